@@ -0,0 +1,53 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use rustboy::{Color, EmulationMode, Emulator, Renderer};
+
+struct NullRenderer;
+
+impl Renderer for NullRenderer {
+  fn draw_pixel(&self, _x: u8, _y: u8, _color: Color, _draw_in_back: bool) {}
+}
+
+// At 0x0150: LD SP,0xDFF0; XOR A; LDH (0x40),A; LD HL,0xC000
+// loop: LD A,(HL); INC A; LD (HL),A; PUSH BC; POP BC; CALL sub; JR loop
+// sub: RET
+const CPU_PROGRAM: [u8; 20] = [
+  0x31, 0xF0, 0xDF, 0xAF, 0xE0, 0x40, 0x21, 0x00, 0xC0,
+  0x7E, 0x3C, 0x77, 0xC5, 0xC1, 0xCD, 0x63, 0x01, 0x18, 0xF6,
+  0xC9,
+];
+
+// At 0x0150: LD SP,0xDFF0; XOR A; LDH (0x40),A
+// loop: LD HL,0xC000; LD DE,0xD000; LD B,0
+// copy: LD A,(HL+); LD (DE),A; INC E; DEC B; JR NZ,copy; JR loop
+const MEMORY_PROGRAM: [u8; 22] = [
+  0x31, 0xF0, 0xDF, 0xAF, 0xE0, 0x40,
+  0x21, 0x00, 0xC0, 0x11, 0x00, 0xD0, 0x06, 0x00,
+  0x2A, 0x12, 0x1C, 0x05, 0x20, 0xFA, 0x18, 0xF0,
+];
+
+fn create_rom(program: &[u8]) -> Vec<u8> {
+  let mut rom = vec![0u8; 0x8000];
+  rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+  rom[0x0150..0x0150 + program.len()].copy_from_slice(program);
+  rom[0x014D] = rom[0x0134..0x014D].iter().fold(0u8, |checksum, byte| checksum.wrapping_sub(*byte).wrapping_sub(1));
+  rom
+}
+
+/// A frame of instructions with the LCD turned off, so the time goes to queueing and executing micro-ops and to the
+/// memory accesses they make.
+fn cpu_frame(c: &mut Criterion) {
+  let rom = create_rom(&CPU_PROGRAM);
+  let mut emulator = Emulator::new(&rom, Box::new(NullRenderer), EmulationMode::Strict).unwrap();
+  c.bench_function("cpu_frame", |b| b.iter(|| emulator.run_frame()));
+}
+
+/// A frame of copying bytes between WRAM banks, so most of the time goes to the CPU's reads and writes through the
+/// memory bus.
+fn memory_frame(c: &mut Criterion) {
+  let rom = create_rom(&MEMORY_PROGRAM);
+  let mut emulator = Emulator::new(&rom, Box::new(NullRenderer), EmulationMode::Strict).unwrap();
+  c.bench_function("memory_frame", |b| b.iter(|| emulator.run_frame()));
+}
+
+criterion_group!(benches, cpu_frame, memory_frame);
+criterion_main!(benches);
@@ -0,0 +1,54 @@
+/* Runs a ROM for 60 frames with Start held, then saves and restores a state.
+   cargo build --release --features ffi
+   cc examples/c/main.c -Iinclude -Ltarget/release -lrustboy -o rustboy_example */
+#include <stdio.h>
+#include <stdlib.h>
+#include "rustboy.h"
+
+int main(int argc, char **argv) {
+  if (argc != 2) {
+    fprintf(stderr, "Usage: %s <rom>\n", argv[0]);
+    return 1;
+  }
+  FILE *file = fopen(argv[1], "rb");
+  if (!file) {
+    perror(argv[1]);
+    return 1;
+  }
+  fseek(file, 0, SEEK_END);
+  long rom_len = ftell(file);
+  rewind(file);
+  uint8_t *rom = malloc(rom_len);
+  fread(rom, 1, rom_len, file);
+  fclose(file);
+
+  RustboyHandle *handle = rustboy_create(rom, rom_len, "{\"lenient\": true}");
+  free(rom);
+  if (!handle) {
+    fprintf(stderr, "The ROM was refused\n");
+    return 1;
+  }
+
+  static uint8_t framebuffer[RUSTBOY_FRAMEBUFFER_SIZE];
+  rustboy_set_button(handle, RUSTBOY_BUTTON_START, true);
+  for (int frame = 0; frame < 60; frame++) {
+    if (rustboy_run_frame(handle, framebuffer) != RUSTBOY_OK) {
+      fprintf(stderr, "The emulator stopped at frame %d\n", frame);
+      rustboy_destroy(handle);
+      return 1;
+    }
+  }
+  rustboy_set_button(handle, RUSTBOY_BUTTON_START, false);
+  printf("Top left pixel: %02x%02x%02x\n", framebuffer[0], framebuffer[1], framebuffer[2]);
+
+  size_t written = 0;
+  uint8_t probe[1];
+  rustboy_save_state(handle, probe, sizeof probe, &written);
+  uint8_t *state = malloc(written);
+  if (rustboy_save_state(handle, state, written, &written) == RUSTBOY_OK) {
+    printf("Saved a %zu byte state, restoring it: %d\n", written, rustboy_load_state(handle, state, written));
+  }
+  free(state);
+  rustboy_destroy(handle);
+  return 0;
+}
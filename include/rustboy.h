@@ -0,0 +1,50 @@
+/* C interface of the rustboy core, exported by the cdylib when it's built with --features ffi. */
+#ifndef RUSTBOY_H
+#define RUSTBOY_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#define RUSTBOY_SCREEN_WIDTH 160
+#define RUSTBOY_SCREEN_HEIGHT 144
+/* RGBA8, row by row from the top left. */
+#define RUSTBOY_FRAMEBUFFER_SIZE (RUSTBOY_SCREEN_WIDTH * RUSTBOY_SCREEN_HEIGHT * 4)
+
+typedef struct RustboyHandle RustboyHandle;
+
+typedef enum RustboyStatus {
+  RUSTBOY_OK = 0,
+  RUSTBOY_NULL_POINTER = 1,
+  RUSTBOY_INVALID_BUTTON = 2,
+  /* For rustboy_save_state, the size the buffer needs is stored in written. */
+  RUSTBOY_BUFFER_TOO_SMALL = 3,
+  RUSTBOY_INVALID_STATE = 4,
+  /* The emulator panicked. Every later call on the handle returns RUSTBOY_PANIC too, so it can only be destroyed. */
+  RUSTBOY_PANIC = 5,
+} RustboyStatus;
+
+typedef enum RustboyButton {
+  RUSTBOY_BUTTON_RIGHT = 0,
+  RUSTBOY_BUTTON_LEFT = 1,
+  RUSTBOY_BUTTON_UP = 2,
+  RUSTBOY_BUTTON_DOWN = 3,
+  RUSTBOY_BUTTON_A = 4,
+  RUSTBOY_BUTTON_B = 5,
+  RUSTBOY_BUTTON_SELECT = 6,
+  RUSTBOY_BUTTON_START = 7,
+} RustboyButton;
+
+/* Starts a ROM, which may be zipped. config_json is null or an object like {"lenient": true}, which tolerates accesses
+   to addresses the cartridge doesn't decode. Returns null if the ROM or the config is refused. */
+RustboyHandle *rustboy_create(const uint8_t *rom, size_t rom_len, const char *config_json);
+void rustboy_destroy(RustboyHandle *handle);
+/* framebuffer is null or RUSTBOY_FRAMEBUFFER_SIZE bytes. */
+RustboyStatus rustboy_run_frame(RustboyHandle *handle, uint8_t *framebuffer);
+RustboyStatus rustboy_set_button(RustboyHandle *handle, uint32_t button, bool pressed);
+/* Interleaved stereo samples. The APU isn't emulated yet, so this always returns 0. */
+size_t rustboy_get_audio(RustboyHandle *handle, int16_t *out, size_t max_samples);
+RustboyStatus rustboy_save_state(RustboyHandle *handle, uint8_t *out, size_t capacity, size_t *written);
+RustboyStatus rustboy_load_state(RustboyHandle *handle, const uint8_t *state, size_t len);
+
+#endif
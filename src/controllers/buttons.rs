@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+use crate::cpu::interrupts::{Interrupt, InterruptController};
+use crate::memory::memory::Memory;
+use crate::util::bit_util::BitUtil;
+
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Debug)]
+pub enum Button {
+  Right,
+  Left,
+  Up,
+  Down,
+  A,
+  B,
+  Select,
+  Start,
+}
+
+impl Button {
+  /// The bit of the button in the pressed mask. Directions use bits 0-3 and actions bits 4-7, so the lower nibble of
+  /// each half matches the bit the button is read from in P1.
+  fn mask_bit(&self) -> u8 {
+    match self {
+      Button::Right => 0,
+      Button::Left => 1,
+      Button::Up => 2,
+      Button::Down => 3,
+      Button::A => 4,
+      Button::B => 5,
+      Button::Select => 6,
+      Button::Start => 7
+    }
+  }
+}
+
+pub trait ButtonController {
+  /// Requests the joypad interrupt if the button is read through a selected line.
+  fn press(&mut self, button: Button, interrupt_controller: &mut dyn InterruptController);
+  fn release(&mut self, button: Button);
+}
+
+/// The joypad register P1. Writing 0 to bit 4 selects the direction buttons, writing 0 to bit 5 the action buttons.
+/// Pressed buttons of the selected lines read as 0 in bits 3-0.
+#[derive(Serialize, Deserialize)]
+pub struct ButtonControllerImpl {
+  pressed: u8,
+  select: u8,
+}
+
+impl ButtonControllerImpl {
+  pub fn new() -> ButtonControllerImpl {
+    ButtonControllerImpl {
+      pressed: 0,
+      select: 0x30,
+    }
+  }
+
+  fn selected_buttons(&self) -> u8 {
+    let mut buttons = 0;
+    if !self.select.get_bit(4) {
+      buttons |= self.pressed & 0x0F;
+    }
+    if !self.select.get_bit(5) {
+      buttons |= self.pressed >> 4;
+    }
+    buttons
+  }
+}
+
+impl ButtonController for ButtonControllerImpl {
+  fn press(&mut self, button: Button, interrupt_controller: &mut dyn InterruptController) {
+    let selected_before = self.selected_buttons();
+    self.pressed |= 1 << button.mask_bit();
+    if self.selected_buttons() != selected_before {
+      interrupt_controller.request_interrupt(Interrupt::ButtonPressed);
+    }
+  }
+
+  fn release(&mut self, button: Button) {
+    self.pressed &= !(1 << button.mask_bit());
+  }
+}
+
+impl Memory for ButtonControllerImpl {
+  fn read(&self, address: u16) -> u8 {
+    match address {
+      0xFF00 => 0xC0 | self.select | (!self.selected_buttons() & 0x0F),
+      _ => panic!("ButtonController can't read address {:#06x}", address)
+    }
+  }
+
+  fn write(&mut self, address: u16, value: u8) {
+    match address {
+      0xFF00 => self.select = value & 0x30,
+      _ => panic!("ButtonController can't write to address {:#06x}", address)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use assert_hex::assert_eq_hex;
+  use crate::cpu::interrupts::InterruptControllerImpl;
+  use super::*;
+
+  #[test]
+  fn selected_line_shows_pressed_buttons() {
+    let mut buttons = ButtonControllerImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    assert_eq_hex!(buttons.read(0xFF00), 0xFF);
+    buttons.write(0xFF00, 0x10);
+    buttons.press(Button::A, &mut interrupt_controller);
+    buttons.press(Button::Down, &mut interrupt_controller);
+    assert_eq_hex!(buttons.read(0xFF00), 0xDE);
+    buttons.write(0xFF00, 0x20);
+    assert_eq_hex!(buttons.read(0xFF00), 0xE7);
+    buttons.write(0xFF00, 0x00);
+    assert_eq_hex!(buttons.read(0xFF00), 0xC6);
+    buttons.release(Button::A);
+    buttons.write(0xFF00, 0x10);
+    assert_eq_hex!(buttons.read(0xFF00), 0xDF);
+  }
+
+  #[test]
+  fn press_on_selected_line_requests_interrupt() {
+    let mut buttons = ButtonControllerImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    buttons.press(Button::Start, &mut interrupt_controller);
+    assert_eq!(interrupt_controller.read(0xFF0F) & 0x10, 0x00);
+    buttons.write(0xFF00, 0x10);
+    buttons.press(Button::Start, &mut interrupt_controller);
+    assert_eq!(interrupt_controller.read(0xFF0F) & 0x10, 0x00);
+    buttons.press(Button::B, &mut interrupt_controller);
+    assert_eq!(interrupt_controller.read(0xFF0F) & 0x10, 0x10);
+  }
+}
@@ -1,5 +1,6 @@
 use std::cell::RefCell;
 use std::rc::Rc;
+use serde::{Deserialize, Serialize};
 use crate::{CPU, MainMemory};
 use crate::controllers::lcd::{LCDMode, LCDController};
 use crate::infrastructure::toggle::Toggle;
@@ -7,7 +8,7 @@ use crate::memory::memory::Memory;
 use crate::time::time::ClockAware;
 use crate::util::bit_util::BitUtil;
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Serialize, Deserialize)]
 enum DMATransferType {
   Inactive,
   Legacy,
@@ -15,6 +16,7 @@ enum DMATransferType {
   HBlank,
 }
 
+#[derive(Serialize, Deserialize)]
 struct DMATransfer {
   transfer_type: DMATransferType,
   source_address: u16,
@@ -24,6 +26,8 @@ struct DMATransfer {
 }
 
 impl DMATransfer {
+  const CHUNK_SIZE: usize = 16;
+
   pub fn inactive() -> DMATransfer {
     DMATransfer {
       transfer_type: DMATransferType::Inactive,
@@ -53,12 +57,25 @@ impl DMATransfer {
       bytes_to_transfer: 0,
     }
   }
+
+  /// HDMA copies its source 16 bytes at a time, like the hardware moves one block per HBlank. The whole chunk is copied
+  /// on its first cycle, but the transfer still takes a cycle per byte.
+  fn copy_chunk(&self, memory: &mut dyn Memory) {
+    let offset = self.bytes_transferred as u16;
+    if offset.is_multiple_of(DMATransfer::CHUNK_SIZE as u16) {
+      let mut chunk = [0; DMATransfer::CHUNK_SIZE];
+      memory.read_block(self.source_address + offset, &mut chunk);
+      memory.write_block(self.destination_address + offset, &chunk);
+    }
+  }
 }
 
 pub trait DMAController {
   fn tick(&mut self, memory: &mut dyn Memory, cpu: &mut dyn CPU, lcd: &dyn LCDController, double_speed: bool);
+  fn active(&self) -> bool;
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct DMAControllerImpl {
   dma: u8,
   high_source_address: u8,
@@ -103,9 +120,8 @@ impl DMAControllerImpl {
     }
     cpu.disable();
     let mut bytes_transferred = self.active_transfer.bytes_transferred as u16;
-    let DMATransfer { source_address, destination_address, bytes_to_transfer, .. } = self.active_transfer;
-    let current_byte = memory.read(source_address + bytes_transferred);
-    memory.write(destination_address + (bytes_transferred as u16), current_byte);
+    let bytes_to_transfer = self.active_transfer.bytes_to_transfer;
+    self.active_transfer.copy_chunk(memory);
     bytes_transferred += 1;
     self.active_transfer.bytes_transferred = bytes_transferred as u8;
     if bytes_transferred == (bytes_to_transfer as u16) {
@@ -130,15 +146,14 @@ impl DMAControllerImpl {
       return;
     }
     let mut bytes_transferred = self.active_transfer.bytes_transferred;
-    let DMATransfer { source_address, destination_address, bytes_to_transfer, .. } = self.active_transfer;
+    let bytes_to_transfer = self.active_transfer.bytes_to_transfer;
     if let LCDMode::HBlank = lcd.get_mode() {
       if self.should_cancel_hblank_transfer(cpu) {
         self.cancel_hblank_transfer();
         return;
       }
       cpu.disable();
-      let current_byte = memory.read(source_address + bytes_transferred as u16);
-      memory.write(destination_address + bytes_transferred as u16, current_byte);
+      self.active_transfer.copy_chunk(memory);
       bytes_transferred += 1;
       self.active_transfer.bytes_transferred = bytes_transferred;
       if bytes_transferred == bytes_to_transfer {
@@ -167,6 +182,10 @@ impl DMAController for DMAControllerImpl {
       DMATransferType::HBlank => self.handle_hblank_transfer(memory, cpu, lcd, double_speed),
     }
   }
+
+  fn active(&self) -> bool {
+    self.active_transfer.transfer_type != DMATransferType::Inactive
+  }
 }
 
 impl Memory for DMAControllerImpl {
@@ -220,10 +239,15 @@ mod tests {
   use crate::{CPUImpl, MockCPU};
   use crate::cpu::interrupts::InterruptControllerImpl;
   use crate::memory::cram::CRAMImpl;
+  use crate::memory::dma::DMAMemoryView;
+  use crate::memory::mbc::Loadable;
+  use crate::memory::mbc5::MBC5;
+  use crate::memory::memory::{RAMSize, ROMSize};
   use crate::memory::memory::CGBMode;
   use crate::memory::memory::test::MockMemory;
   use crate::memory::oam::OAMImpl;
   use crate::memory::vram::VRAMImpl;
+  use crate::memory::wram::WRAM;
   use super::*;
 
   fn create_memory() -> MockMemory {
@@ -253,6 +277,22 @@ mod tests {
     assert_eq_hex!(memory.read(0x8190), 0x0000);
   }
 
+  #[test]
+  fn legacy_dma_transfer_copies_source_as_it_is_when_copied() {
+    let mut dma = DMAControllerImpl::new();
+    let mut memory = create_memory();
+    let mut cpu = MockCPU::new();
+    let lcd = MockLCDController::new();
+    dma.write(0xFF46, 0xC0);
+    dma.tick(&mut memory, &mut cpu, &lcd, false);
+    memory.write(0xC005, 0xAB);
+    for _ in 1..160 {
+      dma.tick(&mut memory, &mut cpu, &lcd, false);
+    }
+    assert_eq_hex!(memory.read(0xFE04), 0x04);
+    assert_eq_hex!(memory.read(0xFE05), 0xAB);
+  }
+
   #[test]
   fn start_general_purpose_dma_transfer() {
     let mut dma = DMAControllerImpl::new();
@@ -267,7 +307,9 @@ mod tests {
     cpu.expect_disable().times(0x70).return_const(());
     cpu.expect_enable().once().return_const(());
     for (index, address) in (0x8120u16..=0x818Fu16).enumerate() {
-      assert_eq_hex!(memory.read(address), 0x0000);
+      if index % 16 == 0 {
+        assert_eq_hex!(memory.read(address), 0x0000);
+      }
       dma.tick(&mut memory, &mut cpu, &mut lcd, false);
       assert_eq_hex!(memory.read(address), index as u8);
     }
@@ -277,6 +319,33 @@ mod tests {
     assert_eq_hex!(memory.read(0x8190), 0x0000);
   }
 
+  #[test]
+  fn general_purpose_dma_transfer_copies_chunks_from_the_mapped_rom_bank() {
+    let mut rom = MBC5::new(ROMSize::KB128, RAMSize::NotAvailable);
+    let bank_3: Vec<u8> = (0..0x20).map(|offset| 0x80 | offset).collect();
+    rom.load_bytes(0xC000, &bank_3);
+    rom.write(0x2000, 0x03);
+    let mut vram = VRAMImpl::new();
+    let wram = WRAM::new();
+    let mut oam = OAMImpl::new();
+    let mut cpu = CPUImpl::new();
+    let lcd = MockLCDController::new();
+    let mut dma = DMAControllerImpl::new();
+    dma.write(0xFF51, 0x40);
+    dma.write(0xFF52, 0x00);
+    dma.write(0xFF53, 0x00);
+    dma.write(0xFF54, 0x00);
+    dma.write(0xFF55, 0x01);
+    let mut tick = |dma: &mut DMAControllerImpl, vram: &mut VRAMImpl| {
+      let mut memory = DMAMemoryView { rom: &rom, vram, wram: &wram, oam: &mut oam };
+      dma.tick(&mut memory, &mut cpu, &lcd, false);
+    };
+    tick(&mut dma, &mut vram);
+    assert_eq!((0x8000..0x8020).map(|address| vram.read(address)).collect::<Vec<u8>>(), [&bank_3[..0x10], &[0; 0x10]].concat());
+    (1..0x11).for_each(|_| tick(&mut dma, &mut vram));
+    assert_eq!((0x8000..0x8020).map(|address| vram.read(address)).collect::<Vec<u8>>(), bank_3);
+  }
+
   #[test]
   fn start_hblank_dma_transfer() {
     let mut dma = DMAControllerImpl::new();
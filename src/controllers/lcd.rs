@@ -5,12 +5,13 @@ use std::thread::current;
 use closure::closure;
 use mockall::automock;
 use web_sys::window;
+use serde::{Deserialize, Serialize};
 
 use crate::cpu::interrupts::{Interrupt, InterruptController, InterruptControllerRef};
 use crate::memory::cram::{CRAM, CRAMImpl};
 use crate::memory::memory::{CGBMode, Memory};
-use crate::memory::oam::{OAM, OAMImpl, OAMObject};
-use crate::memory::vram::{Tile, TileMapView, VRAM, VRAMImpl};
+use crate::memory::oam::{OAM, OAMImpl, OAMObject, ObjectAttributes};
+use crate::memory::vram::{Tile, TileAttributes, TileMapView, VRAM, VRAMImpl};
 use crate::renderer::renderer::{Color, Point, Renderer, TileAddressingMode, TileMapIndex};
 use crate::time::time::ClockAware;
 use crate::util::bit_util::BitUtil;
@@ -18,14 +19,14 @@ use crate::util::bit_util::BitUtil;
 const DOTS_PER_FRAME: u32 = 70224;
 
 pub struct LCDDependencies<'a> {
-  renderer: &'a mut dyn Renderer,
-  interrupt_controller: &'a mut dyn InterruptController,
-  cram: &'a dyn CRAM,
-  oam: &'a dyn OAM,
-  vram: &'a dyn VRAM,
+  pub renderer: &'a mut dyn Renderer,
+  pub interrupt_controller: &'a mut dyn InterruptController,
+  pub cram: &'a dyn CRAM,
+  pub oam: &'a dyn OAM,
+  pub vram: &'a dyn VRAM,
 }
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum LCDMode {
   HBlank,
   VBlank,
@@ -33,6 +34,7 @@ pub enum LCDMode {
   Mode3,
 }
 
+#[derive(Serialize, Deserialize)]
 struct Stat(u8);
 
 impl Stat {
@@ -68,6 +70,7 @@ impl Stat {
   }
 }
 
+#[derive(Serialize, Deserialize)]
 struct LCDC(u8);
 
 impl LCDC {
@@ -113,6 +116,7 @@ pub trait LCDController {
   fn get_mode(&self) -> LCDMode;
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct LCDControllerImpl {
   current_object_index: u8,
   intersecting_object_indices: Vec<u8>,
@@ -184,16 +188,71 @@ impl LCDControllerImpl {
     }
   }
 
-  fn draw_background_line(&self, dependencies: &mut LCDDependencies) {
+  /// The row of 8 OAM bytes the OAM scan is reading, while the LCD is on and scanning.
+  pub fn oam_scan_row(&self) -> Option<usize> {
+    if self.lcdc.lcd_enabled() && self.line < 144 && self.column < 80 {
+      Some(self.column as usize / 4)
+    } else {
+      None
+    }
+  }
+
+  /// DMG games run in compatibility mode, where the PPU behaves like a DMG's.
+  fn dmg_compatible(&self) -> bool {
+    self.cgb_mode == CGBMode::Monochrome
+  }
+
+  /// In compatibility mode the BG map attributes are ignored, so tiles come from bank 0, unflipped.
+  fn compatible_tile(&self, tile: Tile) -> Tile {
+    if self.dmg_compatible() {
+      Tile { chr_code: tile.chr_code, attributes: TileAttributes::default() }
+    } else {
+      tile
+    }
+  }
+
+  /// In compatibility mode the colors come from BGP instead of the CGB palettes.
+  fn background_color(&self, cram: &dyn CRAM, palette_index: u8, color_index: u8) -> Color {
+    if self.dmg_compatible() {
+      Color::from_dmg_shade((self.bgp >> (2 * color_index)) & 0x03)
+    } else {
+      cram.get_background_color(palette_index, color_index)
+    }
+  }
+
+  /// In compatibility mode objects get their colors from OBP0 or OBP1 instead of the CGB palettes.
+  fn object_color(&self, cram: &dyn CRAM, attributes: ObjectAttributes, color_index: u8) -> Color {
+    if self.dmg_compatible() {
+      let palette = if attributes.dmg_palette_index() == 0 { self.obp0 } else { self.obp1 };
+      Color::from_dmg_shade((palette >> (2 * color_index)) & 0x03)
+    } else {
+      cram.get_object_color(attributes.palette_index(), color_index)
+    }
+  }
+
+  /// The objects on the current line, from highest to lowest priority. The CGB goes by OAM index, the DMG by X
+  /// coordinate, then by OAM index.
+  fn objects_by_priority(&self, oam: &dyn OAM) -> Vec<OAMObject> {
+    let mut objects: Vec<OAMObject> = self.intersecting_object_indices.iter()
+      .map(|object_index| oam.get_object(*object_index))
+      .collect();
+    if self.dmg_compatible() {
+      objects.sort_by_key(|object| object.lcd_x());
+    }
+    objects
+  }
+
+  fn draw_background_line(&self, dependencies: &mut LCDDependencies, pixels_to_draw: u8) {
     let tile_map = dependencies.vram.tile_map(self.lcdc.bg_tile_map_index());
     let tile_data_view = dependencies.vram.tile_data(self.lcdc.bg_and_window_tile_addressing_mode());
 
     let tile_column_offset = self.scx / 8;
     let pixel_column_offset = self.scx % 8;
-    let pixel_row = (self.line + self.scy) % 144;
+    let pixel_row = self.line.wrapping_add(self.scy);
     let pixel_row_offset = pixel_row % 8;
 
-    tile_map.row(pixel_row)
+    tile_map.row(pixel_row / 8)
+      .map(|tile| self.compatible_tile(tile))
       .cycle()
       .skip(tile_column_offset as usize)
       .enumerate()
@@ -201,61 +260,105 @@ impl LCDControllerImpl {
         .get_tile_data(attributes.tile_bank_index(), chr_code)
         .get_color_indices(pixel_row_offset, attributes.flip_horizontal(), attributes.flip_vertical())
         .skip(if tile_index == 0 { pixel_column_offset as usize } else { 0 })
-        .map(closure!(ref dependencies, move attributes, |color_index| dependencies.cram.get_background_color(attributes.palette_index(), color_index)))
+        .map(closure!(ref dependencies, move attributes, |color_index| self.background_color(dependencies.cram, attributes.palette_index(), color_index)))
       )
-      .take(160)
+      .take(pixels_to_draw as usize)
       .enumerate()
       .for_each(|(x, color)| dependencies.renderer.draw_pixel(x as u8, self.line, color, false));
   }
 
+  /// The window covers the lines from WY down. At WX = 166 only its first pixel would reach the screen, which the PPU
+  /// doesn't draw, so it's hidden from there on.
   fn should_draw_window_line(&self) -> bool {
-    self.wy >= self.line &&
+    self.line >= self.wy &&
       self.wy < 144 &&
-      self.wx >= 7 &&
-      self.wx - 7 < 160
+      self.wx < 166
   }
 
-  fn draw_window_line(&self, dependencies: &mut LCDDependencies) {
+  /// The column at which the window starts on the current line, if it is visible at all.
+  /// For WX < 7 the window starts at column 0, with its leftmost 7 - WX pixels cut off.
+  fn window_start_column(&self) -> Option<u8> {
     if self.lcdc.windowing_enabled() && self.should_draw_window_line() {
-      let tile_map = dependencies.vram.tile_map(self.lcdc.window_tile_map_index());
-      let tile_data_view = dependencies.vram.tile_data(self.lcdc.bg_and_window_tile_addressing_mode());
-
-      let pixel_row = (self.line - self.wy);
-      let pixel_row_offset = pixel_row % 8;
-      let window_pixel_column = self.wx - 7;
-      let pixels_to_draw = 160 - window_pixel_column;
-
-      tile_map.row(pixel_row)
-        .flat_map(|Tile { chr_code, attributes }| tile_data_view
-          .get_tile_data(attributes.tile_bank_index(), chr_code)
-          .get_color_indices(pixel_row_offset, attributes.flip_horizontal(), attributes.flip_vertical())
-          .map(closure!(ref dependencies, move attributes, |color_index| dependencies.cram.get_background_color(attributes.palette_index(), color_index)))
-        )
-        .take(pixels_to_draw as usize)
-        .enumerate()
-        .for_each(|(x, color)| dependencies.renderer.draw_pixel(window_pixel_column + x as u8, self.line, color, false))
+      Some(self.wx.saturating_sub(7))
+    } else {
+      None
     }
   }
 
-  fn draw_obj_line(&self, dependencies: &mut LCDDependencies) {
-    let tile_data_view = dependencies.vram.tile_data(TileAddressingMode::Mode8000);
+  fn draw_window_line(&self, dependencies: &mut LCDDependencies, window_pixel_column: u8) {
+    let tile_map = dependencies.vram.tile_map(self.lcdc.window_tile_map_index());
+    let tile_data_view = dependencies.vram.tile_data(self.lcdc.bg_and_window_tile_addressing_mode());
 
-    let objects: Vec<OAMObject> = self.intersecting_object_indices.iter()
-      .map(|obj_index| dependencies.oam.get_object(*obj_index))
-      .collect();
-    // objects.sort_by(|a, b| {
-    //
-    // })
+    let pixel_row = self.line - self.wy;
+    let pixel_row_offset = pixel_row % 8;
+    let pixels_to_skip = 7u8.saturating_sub(self.wx);
+    let pixels_to_draw = 160 - window_pixel_column;
 
+    // Window pixels are opaque, so every pixel (including color 0) replaces the background.
+    tile_map.row(pixel_row / 8)
+      .map(|tile| self.compatible_tile(tile))
+      .flat_map(|Tile { chr_code, attributes }| tile_data_view
+        .get_tile_data(attributes.tile_bank_index(), chr_code)
+        .get_color_indices(pixel_row_offset, attributes.flip_horizontal(), attributes.flip_vertical())
+        .map(closure!(ref dependencies, move attributes, |color_index| self.background_color(dependencies.cram, attributes.palette_index(), color_index)))
+      )
+      .skip(pixels_to_skip as usize)
+      .take(pixels_to_draw as usize)
+      .enumerate()
+      .for_each(|(x, color)| dependencies.renderer.draw_pixel(window_pixel_column + x as u8, self.line, color, false))
+  }
 
-    //   .flat_map(|obj| tile_data_view.get_tile_data())
+  /// Each column shows the highest priority object that isn't transparent there. Objects with the BG priority attribute
+  /// are drawn in back, so the renderer keeps background colors 1-3 over them.
+  fn draw_obj_line(&self, dependencies: &mut LCDDependencies) {
+    if !self.lcdc.obj_enabled() {
+      return;
+    }
+    let tile_data_view = dependencies.vram.tile_data(TileAddressingMode::Mode8000);
+    let object_height = if self.lcdc.use_8_x_16_tiles() { 16 } else { 8 };
+    let mut pixels: [Option<(Color, bool)>; 160] = [None; 160];
+
+    for object in self.objects_by_priority(dependencies.oam) {
+      let attributes = object.attributes();
+      let row = self.line + 16 - object.lcd_y();
+      let row = if attributes.flip_vertical() { object_height - 1 - row } else { row };
+      // 8x16 objects use an even tile for the top half and the odd tile after it for the bottom half
+      let tile_index = if object_height == 16 { (object.tile_index() & 0xFE) + row / 8 } else { object.tile_index() };
+      let tile_bank_index = if self.dmg_compatible() { 0 } else { attributes.tile_bank_index() };
+      tile_data_view.get_tile_data(tile_bank_index, tile_index)
+        .get_color_indices(row % 8, attributes.flip_horizontal(), false)
+        .enumerate()
+        .for_each(|(offset, color_index)| {
+          // The object's X coordinate is 8 columns right of its left edge
+          let x = object.lcd_x() as usize + offset;
+          if color_index != 0 && (8..168).contains(&x) && pixels[x - 8].is_none() {
+            let color = self.object_color(dependencies.cram, attributes, color_index);
+            pixels[x - 8] = Some((color, attributes.has_priority_over_oam()));
+          }
+        });
+    }
+
+    pixels.iter().enumerate().for_each(|(x, pixel)| {
+      if let Some((color, draw_in_back)) = pixel {
+        dependencies.renderer.draw_pixel(x as u8, self.line, *color, *draw_in_back);
+      }
+    });
   }
 
   fn draw_line(&self, mut dependencies: LCDDependencies) {
-    // 1) Draw background
-    self.draw_background_line(&mut dependencies);
+    // In compatibility mode, LCDC bit 0 turns the background and window off, leaving the line white
+    if self.dmg_compatible() && !self.lcdc.bg_enabled() {
+      (0..160).for_each(|x| dependencies.renderer.draw_pixel(x, self.line, Color::from_dmg_shade(0), false));
+      self.draw_obj_line(&mut dependencies);
+      return;
+    }
+    let window_start_column = self.window_start_column();
+    // 1) Draw background, up to the column where the window starts
+    self.draw_background_line(&mut dependencies, window_start_column.unwrap_or(160));
     // 2) Draw window line
-    self.draw_window_line(&mut dependencies);
+    if let Some(window_pixel_column) = window_start_column {
+      self.draw_window_line(&mut dependencies, window_pixel_column);
+    }
     // 3) Draw OBJ
     self.draw_obj_line(&mut dependencies);
   }
@@ -311,6 +414,7 @@ impl LCDControllerImpl {
       LCDMode::HBlank => {
         if self.column == 248 {
           self.intersecting_object_indices.clear();
+          self.current_object_index = 0;
         }
       }
       LCDMode::VBlank => {
@@ -322,8 +426,10 @@ impl LCDControllerImpl {
         self.find_intersecting_objects(dependencies)
       }
       LCDMode::Mode3 => {
-        todo!("Either only call this once for the current line or progressively draw the line");
-        self.draw_line(dependencies)
+        // The whole line is drawn at once when entering mode 3
+        if self.column == 80 && self.lcdc.lcd_enabled() {
+          self.draw_line(dependencies)
+        }
       }
     }
   }
@@ -338,6 +444,9 @@ impl Memory for LCDControllerImpl {
       0xFF43 => self.scx,
       0xFF44 => self.line,
       0xFF45 => self.lyc,
+      0xFF47 => self.bgp,
+      0xFF48 => self.obp0,
+      0xFF49 => self.obp1,
       0xFF4A => self.wy,
       0xFF4B => self.wx,
       _ => panic!("Unable to read address {:#x} from LCD Controller", address)
@@ -364,7 +473,317 @@ impl Memory for LCDControllerImpl {
 #[cfg(test)]
 pub mod tests {
   use super::*;
+  use test_case::test_case;
+  use crate::CPUImpl;
+  use crate::controllers::dma::{DMAController, DMAControllerImpl};
+  use crate::cpu::interrupts::InterruptControllerImpl;
+  use crate::memory::dma::DMAMemoryView;
+  use crate::memory::mbc0::MBC0;
+  use crate::memory::wram::WRAM;
+
+  struct RecordingRenderer {
+    pixels: RefCell<Vec<(u8, u8, u8)>>,
+  }
+
+  impl RecordingRenderer {
+    fn new() -> RecordingRenderer {
+      RecordingRenderer {
+        pixels: RefCell::new(vec![]),
+      }
+    }
+
+    fn draws_at(&self, x: u8) -> Vec<u8> {
+      self.pixels.borrow().iter()
+        .filter(|(pixel_x, _, _)| *pixel_x == x)
+        .map(|(_, _, red)| *red)
+        .collect()
+    }
+  }
+
+  impl Renderer for RecordingRenderer {
+    fn draw_pixel(&self, x: u8, y: u8, color: Color, _draw_in_back: bool) {
+      self.pixels.borrow_mut().push((x, y, color.red));
+    }
+  }
+
+  // Background map is filled with tile 1 (color index 1 everywhere), window map with tile 0.
+  // Color 0 of palette 0 is black and color 1 is red.
+  fn window_test_setup(window_tile_row: u8) -> (VRAMImpl, CRAMImpl, OAMImpl) {
+    let mut vram = VRAMImpl::new();
+    let mut cram = CRAMImpl::new();
+    (0..8).for_each(|row| {
+      vram.write(0x8000 + 2 * row, window_tile_row);
+      vram.write(0x8010 + 2 * row, 0xFF);
+    });
+    (0..0x400).for_each(|tile| vram.write(0x9800 + tile, 0x01));
+    cram.write(0xFF68, 0x82);
+    cram.write(0xFF69, 0x1F);
+    (vram, cram, OAMImpl::new())
+  }
+
+  fn draw_first_line(lcd: &LCDControllerImpl, vram: &VRAMImpl, cram: &CRAMImpl, oam: &OAMImpl) -> RecordingRenderer {
+    let mut renderer = RecordingRenderer::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    lcd.draw_line(LCDDependencies {
+      renderer: &mut renderer,
+      interrupt_controller: &mut interrupt_controller,
+      cram,
+      oam,
+      vram,
+    });
+    renderer
+  }
+
+  #[test]
+  fn window_color_0_covers_background() {
+    let (vram, cram, oam) = window_test_setup(0x00);
+    let mut lcd = LCDControllerImpl::new(CGBMode::Color);
+    lcd.write(0xFF40, 0xF1);
+    lcd.write(0xFF4A, 0);
+    lcd.write(0xFF4B, 87);
+    let renderer = draw_first_line(&lcd, &vram, &cram, &oam);
+    assert_eq!(renderer.draws_at(79), vec![0x1F]);
+    assert_eq!(renderer.draws_at(80), vec![0x00]);
+    (0..160).for_each(|x| {
+      assert_eq!(renderer.draws_at(x), vec![if x < 80 { 0x1F } else { 0x00 }]);
+    });
+  }
+
+  #[test]
+  fn window_with_wx_below_7_is_cut_off() {
+    let (vram, cram, oam) = window_test_setup(0x0F);
+    let mut lcd = LCDControllerImpl::new(CGBMode::Color);
+    lcd.write(0xFF40, 0xF1);
+    lcd.write(0xFF4A, 0);
+    lcd.write(0xFF4B, 3);
+    let renderer = draw_first_line(&lcd, &vram, &cram, &oam);
+    // The leftmost 4 window pixels are cut off, so the line starts with the second half of the first tile
+    (0..160).for_each(|x| {
+      assert_eq!(renderer.draws_at(x), vec![if x % 8 < 4 { 0x1F } else { 0x00 }]);
+    });
+  }
+
+  #[test]
+  fn window_at_wy_0_and_wx_7_covers_the_screen() {
+    let (vram, cram, oam) = window_test_setup(0x00);
+    let mut lcd = LCDControllerImpl::new(CGBMode::Color);
+    lcd.write(0xFF40, 0xF1);
+    lcd.write(0xFF4A, 0);
+    lcd.write(0xFF4B, 7);
+    [0, 1, 143].iter().for_each(|line| {
+      lcd.line = *line;
+      let renderer = draw_first_line(&lcd, &vram, &cram, &oam);
+      (0..160).for_each(|x| assert_eq!(renderer.draws_at(x), vec![0x00]));
+    });
+  }
+
+  #[test_case(0, 0x1F; "first line")]
+  #[test_case(99, 0x1F; "line above wy")]
+  #[test_case(100, 0x00; "line at wy")]
+  #[test_case(143, 0x00; "last line")]
+  fn window_starts_at_wy(line: u8, color: u8) {
+    let (vram, cram, oam) = window_test_setup(0x00);
+    let mut lcd = LCDControllerImpl::new(CGBMode::Color);
+    lcd.write(0xFF40, 0xF1);
+    lcd.write(0xFF4A, 100);
+    lcd.write(0xFF4B, 7);
+    lcd.line = line;
+    let renderer = draw_first_line(&lcd, &vram, &cram, &oam);
+    (0..160).for_each(|x| assert_eq!(renderer.draws_at(x), vec![color]));
+  }
+
+  #[test_case(165, 0x00; "wx 165 shows the last two columns")]
+  #[test_case(166, 0x1F; "wx 166 hides the window")]
+  fn window_at_the_right_edge(wx: u8, right_edge_color: u8) {
+    let (vram, cram, oam) = window_test_setup(0x00);
+    let mut lcd = LCDControllerImpl::new(CGBMode::Color);
+    lcd.write(0xFF40, 0xF1);
+    lcd.write(0xFF4A, 0);
+    lcd.write(0xFF4B, wx);
+    let renderer = draw_first_line(&lcd, &vram, &cram, &oam);
+    assert_eq!(renderer.draws_at(157), vec![0x1F]);
+    assert_eq!(renderer.draws_at(158), vec![right_edge_color]);
+    assert_eq!(renderer.draws_at(159), vec![right_edge_color]);
+  }
 
   #[test]
-  fn stat_blocking() {}
-}
\ No newline at end of file
+  fn window_disabled_draws_background_only() {
+    let (vram, cram, oam) = window_test_setup(0x00);
+    let mut lcd = LCDControllerImpl::new(CGBMode::Color);
+    lcd.write(0xFF40, 0xD1);
+    lcd.write(0xFF4A, 0);
+    lcd.write(0xFF4B, 87);
+    let renderer = draw_first_line(&lcd, &vram, &cram, &oam);
+    (0..160).for_each(|x| assert_eq!(renderer.draws_at(x), vec![0x1F]));
+  }
+
+  // Tile 1 has color 1 in the leftmost and color 2 in the rightmost pixel of row 0, and color 3 in the leftmost pixel of row 7.
+  // Tile 2 is filled with color 1. Colors in palette 0 have a red component equal to their color index.
+  fn flip_test_setup(attributes: u8) -> (VRAMImpl, CRAMImpl, OAMImpl) {
+    let mut vram = VRAMImpl::new();
+    let mut cram = CRAMImpl::new();
+    vram.write(0x8010, 0x80);
+    vram.write(0x8011, 0x01);
+    vram.write(0x801E, 0x80);
+    vram.write(0x801F, 0x80);
+    (0..8).for_each(|row| vram.write(0x8020 + 2 * row, 0xFF));
+    [0x9800, 0x9802, 0x9820, 0x9822].iter().for_each(|address| vram.write(*address, 0x02));
+    [0x9801, 0x9821].iter().for_each(|address| vram.write(*address, 0x01));
+    vram.write(0xFF4F, 0x01);
+    [0x9801, 0x9821].iter().for_each(|address| vram.write(*address, attributes));
+    vram.write(0xFF4F, 0x00);
+    cram.write(0xFF68, 0x80);
+    (0..4).for_each(|color_index| {
+      cram.write(0xFF69, color_index);
+      cram.write(0xFF69, 0x00);
+    });
+    (vram, cram, OAMImpl::new())
+  }
+
+  #[test_case(0x00, 0, 1, 2; "no flip")]
+  #[test_case(0x20, 0, 2, 1; "horizontal flip")]
+  #[test_case(0x40, 0, 3, 0; "vertical flip")]
+  #[test_case(0x60, 0, 0, 3; "horizontal and vertical flip")]
+  #[test_case(0x00, 8, 1, 2; "no flip in second tile row")]
+  #[test_case(0x60, 8, 0, 3; "horizontal and vertical flip in second tile row")]
+  fn background_tile_flips(attributes: u8, scy: u8, left_color: u8, right_color: u8) {
+    let (vram, cram, oam) = flip_test_setup(attributes);
+    let mut lcd = LCDControllerImpl::new(CGBMode::Color);
+    lcd.write(0xFF40, 0x91);
+    lcd.write(0xFF42, scy);
+    let renderer = draw_first_line(&lcd, &vram, &cram, &oam);
+    assert_eq!(renderer.draws_at(7), vec![1]);
+    assert_eq!(renderer.draws_at(8), vec![left_color]);
+    (9..15).for_each(|x| assert_eq!(renderer.draws_at(x), vec![0]));
+    assert_eq!(renderer.draws_at(15), vec![right_color]);
+    assert_eq!(renderer.draws_at(16), vec![1]);
+  }
+
+  #[test_case(0x00, 1, 2; "no flip")]
+  #[test_case(0x20, 2, 1; "horizontal flip")]
+  #[test_case(0x40, 3, 0; "vertical flip")]
+  #[test_case(0x60, 0, 3; "horizontal and vertical flip")]
+  fn window_tile_flips(attributes: u8, left_color: u8, right_color: u8) {
+    let (vram, cram, oam) = flip_test_setup(attributes);
+    let mut lcd = LCDControllerImpl::new(CGBMode::Color);
+    lcd.write(0xFF40, 0xB0);
+    lcd.write(0xFF4A, 0);
+    lcd.write(0xFF4B, 7);
+    let renderer = draw_first_line(&lcd, &vram, &cram, &oam);
+    assert_eq!(renderer.draws_at(7), vec![1]);
+    assert_eq!(renderer.draws_at(8), vec![left_color]);
+    assert_eq!(renderer.draws_at(15), vec![right_color]);
+    assert_eq!(renderer.draws_at(16), vec![1]);
+  }
+
+  #[test_case(CGBMode::Color, 0xE4, 1, 2, 1; "cgb uses attributes and cram")]
+  #[test_case(CGBMode::Monochrome, 0xE4, 0x15, 0x15, 0x0A; "dmg ignores attributes")]
+  #[test_case(CGBMode::Monochrome, 0x1B, 0x0A, 0x0A, 0x15; "dmg maps colors through bgp")]
+  fn compatibility_mode_renders_like_a_dmg(cgb_mode: CGBMode, bgp: u8, color_at_7: u8, color_at_8: u8, color_at_15: u8) {
+    let (vram, cram, oam) = flip_test_setup(0x20);
+    let mut lcd = LCDControllerImpl::new(cgb_mode);
+    lcd.write(0xFF40, 0x91);
+    lcd.write(0xFF47, bgp);
+    let renderer = draw_first_line(&lcd, &vram, &cram, &oam);
+    assert_eq!(renderer.draws_at(7), vec![color_at_7]);
+    assert_eq!(renderer.draws_at(8), vec![color_at_8]);
+    assert_eq!(renderer.draws_at(15), vec![color_at_15]);
+  }
+
+  #[test_case(CGBMode::Color, 1; "cgb keeps drawing the background")]
+  #[test_case(CGBMode::Monochrome, 0x1F; "dmg draws white")]
+  fn lcdc_bit_0_disables_background_in_compatibility_mode(cgb_mode: CGBMode, color_at_8: u8) {
+    let (vram, cram, oam) = flip_test_setup(0x00);
+    let mut lcd = LCDControllerImpl::new(cgb_mode);
+    lcd.write(0xFF40, 0x90);
+    lcd.write(0xFF47, 0xE4);
+    let renderer = draw_first_line(&lcd, &vram, &cram, &oam);
+    assert_eq!(renderer.draws_at(8), vec![color_at_8]);
+  }
+
+  #[test_case(CGBMode::Color, vec![50, 20, 20]; "cgb orders by oam index")]
+  #[test_case(CGBMode::Monochrome, vec![20, 20, 50]; "dmg orders by x coordinate")]
+  fn object_priority_follows_mode(cgb_mode: CGBMode, expected_x: Vec<u8>) {
+    let mut oam = OAMImpl::new();
+    [50, 20, 20].iter().enumerate().for_each(|(object_index, x)| {
+      oam.write(0xFE00 + 4 * object_index as u16, 16);
+      oam.write(0xFE01 + 4 * object_index as u16, *x);
+      oam.write(0xFE02 + 4 * object_index as u16, object_index as u8);
+    });
+    let mut lcd = LCDControllerImpl::new(cgb_mode);
+    lcd.intersecting_object_indices = vec![0, 1, 2];
+    let objects = lcd.objects_by_priority(&oam);
+    assert_eq!(objects.iter().map(|object| object.lcd_x()).collect::<Vec<u8>>(), expected_x);
+  }
+
+  // Object 0 is opaque with color 1 and uses OBP1, object 1 is 4 columns to its left with color 3 and uses OBP0. Its
+  // rightmost 2 pixels are transparent. Object colors in palette 0 have a red component equal to their color index.
+  #[test_case(CGBMode::Monochrome, 0x1F, vec![0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0A, 0x0A, 0x0A, 0x0A, 0x0A, 0x0A]; "dmg shows the object further left")]
+  #[test_case(CGBMode::Color, 0x00, vec![3, 3, 3, 3, 1, 1, 1, 1, 1, 1, 1, 1]; "cgb shows the first object in oam")]
+  fn overlapping_objects(cgb_mode: CGBMode, background: u8, object_colors: Vec<u8>) {
+    let mut vram = VRAMImpl::new();
+    let mut cram = CRAMImpl::new();
+    let mut oam = OAMImpl::new();
+    vram.write(0x8010, 0xFF);
+    vram.write(0x8020, 0xFC);
+    vram.write(0x8021, 0xFC);
+    cram.write(0xFF6A, 0x80);
+    (0..4).for_each(|color_index| {
+      cram.write(0xFF6B, color_index);
+      cram.write(0xFF6B, 0x00);
+    });
+    [(24, 0x01, 0x10), (20, 0x02, 0x00)].iter().enumerate().for_each(|(object_index, (x, tile_index, attributes))| {
+      oam.write(0xFE00 + 4 * object_index as u16, 16);
+      oam.write(0xFE01 + 4 * object_index as u16, *x);
+      oam.write(0xFE02 + 4 * object_index as u16, *tile_index);
+      oam.write(0xFE03 + 4 * object_index as u16, *attributes);
+    });
+    let mut lcd = LCDControllerImpl::new(cgb_mode);
+    lcd.write(0xFF40, 0x82);
+    lcd.write(0xFF48, 0xE4);
+    lcd.write(0xFF49, 0x1B);
+    lcd.intersecting_object_indices = vec![0, 1];
+    let renderer = draw_first_line(&lcd, &vram, &cram, &oam);
+    assert_eq!(renderer.draws_at(11), vec![background]);
+    object_colors.iter().enumerate().for_each(|(offset, color)| {
+      assert_eq!(renderer.draws_at(12 + offset as u8), vec![background, *color]);
+    });
+    assert_eq!(renderer.draws_at(24), vec![background]);
+  }
+
+  #[test]
+  fn oam_scan_sees_partially_transferred_oam() {
+    // Before the transfer, odd objects intersect line 0. The transferred table has even objects intersecting line 0 instead.
+    let mut oam = OAMImpl::new();
+    let mut wram = WRAM::new();
+    (0..40u16).for_each(|object_index| {
+      oam.write(0xFE00 + 4 * object_index, if object_index % 2 == 1 { 16 } else { 0 });
+      wram.write(0xC000 + 4 * object_index, if object_index % 2 == 0 { 16 } else { 0 });
+    });
+    let rom = MBC0::new();
+    let mut vram = VRAMImpl::new();
+    let cram = CRAMImpl::new();
+    let mut cpu = CPUImpl::new();
+    let mut dma = DMAControllerImpl::new();
+    let mut lcd = LCDControllerImpl::new(CGBMode::Color);
+    let mut renderer = RecordingRenderer::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    dma.write(0xFF46, 0xC0);
+    for cycle in 0..44 {
+      let mut memory = DMAMemoryView { rom: &rom, vram: &mut vram, wram: &wram, oam: &mut oam };
+      dma.tick(&mut memory, &mut cpu, &lcd, false);
+      // The first 24 bytes (objects 0-5) are transferred before the OAM scan starts
+      if cycle >= 24 {
+        lcd.tick(LCDDependencies {
+          renderer: &mut renderer,
+          interrupt_controller: &mut interrupt_controller,
+          cram: &cram,
+          oam: &oam,
+          vram: &vram,
+        });
+      }
+    }
+    // The scan checks 2 objects per cycle, so it overtakes the transfer after object 6
+    assert_eq!(lcd.intersecting_object_indices, vec![0, 2, 4, 6, 7, 9, 11, 13, 15, 17]);
+  }
+}
@@ -1,3 +1,6 @@
+pub mod buttons;
 pub mod timer;
 pub mod dma;
 pub mod lcd;
+pub mod serial;
+pub mod speed;
@@ -0,0 +1,249 @@
+use std::cell::RefCell;
+use std::rc::Weak;
+use serde::{Deserialize, Serialize};
+use crate::cpu::interrupts::{Interrupt, InterruptController};
+use crate::memory::memory::Memory;
+use crate::util::bit_util::BitUtil;
+
+pub trait SerialController {
+  /// Advances a transfer by one M-cycle and returns whether it completed.
+  fn tick(&mut self, interrupt_controller: &mut dyn InterruptController) -> bool;
+  /// Removes and returns the bytes sent since the last call.
+  fn take_output(&mut self) -> Vec<u8>;
+}
+
+/// The other end of a link cable.
+pub trait SerialLink {
+  /// Called by the side supplying the clock when its transfer completes. Returns the byte the partner shifted out in
+  /// exchange, or None if the partner isn't waiting for a transfer.
+  fn exchange(&mut self, out_byte: u8) -> Option<u8>;
+}
+
+/// Partners are held weakly, so two linked ports don't keep each other alive. A partner that's gone, or busy because
+/// the transfer was started from inside it, isn't waiting for a transfer.
+impl<T: SerialLink> SerialLink for Weak<RefCell<T>> {
+  fn exchange(&mut self, out_byte: u8) -> Option<u8> {
+    self.upgrade().and_then(|partner| partner.try_borrow_mut().ok().and_then(|mut partner| partner.exchange(out_byte)))
+  }
+}
+
+/// The serial port registers SB and SC. With the internal clock, a transfer shifts out SB one bit every 128 M-cycles
+/// (8192 Hz) and shifts in 1 bits, unless a link partner hands over its byte when the transfer completes.
+/// With the external clock, a transfer waits until the partner clocks it. The CGB's fast clock isn't emulated.
+#[derive(Serialize, Deserialize)]
+pub struct SerialControllerImpl {
+  data: u8,
+  control: u8,
+  bits_remaining: u8,
+  cycles_until_shift: u8,
+  sent: u8,
+  clocked_by_partner: bool,
+  #[serde(skip)]
+  output: Vec<u8>,
+  #[serde(skip)]
+  link: Option<Box<dyn SerialLink>>,
+}
+
+impl SerialControllerImpl {
+  const CYCLES_PER_BIT: u8 = 128;
+
+  pub fn new() -> SerialControllerImpl {
+    SerialControllerImpl {
+      data: 0,
+      control: 0,
+      bits_remaining: 0,
+      cycles_until_shift: 0,
+      sent: 0,
+      clocked_by_partner: false,
+      output: vec![],
+      link: None,
+    }
+  }
+
+  pub fn connect(&mut self, link: Box<dyn SerialLink>) {
+    self.link = Some(link);
+  }
+
+  pub fn disconnect(&mut self) {
+    self.link = None;
+  }
+
+  /// Takes over the registers from a save state. The link partner stays connected.
+  pub fn restore(&mut self, state: SerialControllerImpl) {
+    *self = SerialControllerImpl {
+      output: std::mem::take(&mut self.output),
+      link: self.link.take(),
+      ..state
+    };
+  }
+
+  fn transferring(&self) -> bool {
+    self.control.get_bit(7)
+  }
+
+  fn internal_clock(&self) -> bool {
+    self.control.get_bit(0)
+  }
+}
+
+impl SerialController for SerialControllerImpl {
+  fn tick(&mut self, interrupt_controller: &mut dyn InterruptController) -> bool {
+    if self.clocked_by_partner {
+      self.clocked_by_partner = false;
+      interrupt_controller.request_interrupt(Interrupt::SerialIOComplete);
+      return true;
+    }
+    if !self.transferring() || !self.internal_clock() {
+      return false;
+    }
+    self.cycles_until_shift -= 1;
+    if self.cycles_until_shift > 0 {
+      return false;
+    }
+    self.data = (self.data << 1) | 0x01;
+    self.bits_remaining -= 1;
+    self.cycles_until_shift = SerialControllerImpl::CYCLES_PER_BIT;
+    if self.bits_remaining > 0 {
+      return false;
+    }
+    if let Some(received) = self.link.as_mut().and_then(|link| link.exchange(self.sent)) {
+      self.data = received;
+    }
+    self.control = self.control.reset_bit(7);
+    interrupt_controller.request_interrupt(Interrupt::SerialIOComplete);
+    true
+  }
+
+  fn take_output(&mut self) -> Vec<u8> {
+    std::mem::take(&mut self.output)
+  }
+}
+
+impl SerialLink for SerialControllerImpl {
+  /// Completes a transfer waiting for the external clock. The interrupt is requested on the next tick.
+  fn exchange(&mut self, out_byte: u8) -> Option<u8> {
+    if !self.transferring() || self.internal_clock() {
+      return None;
+    }
+    let in_byte = self.data;
+    self.output.push(in_byte);
+    self.data = out_byte;
+    self.control = self.control.reset_bit(7);
+    self.clocked_by_partner = true;
+    Some(in_byte)
+  }
+}
+
+impl Memory for SerialControllerImpl {
+  fn read(&self, address: u16) -> u8 {
+    match address {
+      0xFF01 => self.data,
+      0xFF02 => 0x7E | self.control,
+      _ => panic!("SerialController can't read address {:#06x}", address)
+    }
+  }
+
+  fn write(&mut self, address: u16, value: u8) {
+    match address {
+      0xFF01 => self.data = value,
+      0xFF02 => {
+        self.control = value & 0x81;
+        if self.transferring() {
+          self.bits_remaining = 8;
+          self.cycles_until_shift = SerialControllerImpl::CYCLES_PER_BIT;
+          self.sent = self.data;
+          if self.internal_clock() {
+            self.output.push(self.data);
+          }
+        }
+      }
+      _ => panic!("SerialController can't write to address {:#06x}", address)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::rc::Rc;
+  use assert_hex::assert_eq_hex;
+  use crate::cpu::interrupts::InterruptControllerImpl;
+  use super::*;
+
+  #[test]
+  fn transfers_complete_every_1024_cycles() {
+    let mut serial = SerialControllerImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    for byte in [0x48, 0x69] {
+      serial.write(0xFF01, byte);
+      serial.write(0xFF02, 0x81);
+      assert_eq_hex!(serial.read(0xFF02), 0xFF);
+      let completed: Vec<usize> = (1..=2000).filter(|_| serial.tick(&mut interrupt_controller)).collect();
+      assert_eq!(completed, vec![1024]);
+      assert_eq_hex!(interrupt_controller.read(0xFF0F) & 0x08, 0x08);
+      interrupt_controller.write(0xFF0F, 0x00);
+      assert_eq_hex!(serial.read(0xFF02), 0x7F);
+      assert_eq_hex!(serial.read(0xFF01), 0xFF);
+    }
+    assert_eq!(serial.take_output(), vec![0x48, 0x69]);
+    assert!(serial.take_output().is_empty());
+  }
+
+  #[test]
+  fn external_clock_transfer_waits() {
+    let mut serial = SerialControllerImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    serial.write(0xFF01, 0x42);
+    serial.write(0xFF02, 0x80);
+    assert!((0..2000).all(|_| !serial.tick(&mut interrupt_controller)));
+    assert_eq_hex!(serial.read(0xFF02), 0xFE);
+    assert_eq_hex!(serial.read(0xFF01), 0x42);
+    assert!(serial.take_output().is_empty());
+  }
+
+  fn linked_controllers() -> (Rc<RefCell<SerialControllerImpl>>, Rc<RefCell<SerialControllerImpl>>) {
+    let first = Rc::new(RefCell::new(SerialControllerImpl::new()));
+    let second = Rc::new(RefCell::new(SerialControllerImpl::new()));
+    first.borrow_mut().connect(Box::new(Rc::downgrade(&second)));
+    second.borrow_mut().connect(Box::new(Rc::downgrade(&first)));
+    (first, second)
+  }
+
+  #[test]
+  fn linked_controllers_swap_bytes_with_either_side_clocking() {
+    let (first, second) = linked_controllers();
+    let mut first_interrupts = InterruptControllerImpl::new();
+    let mut second_interrupts = InterruptControllerImpl::new();
+    for (clocking, clocked) in [(&first, &second), (&second, &first)] {
+      clocking.borrow_mut().write(0xFF01, 0x12);
+      clocked.borrow_mut().write(0xFF01, 0x34);
+      clocked.borrow_mut().write(0xFF02, 0x80);
+      clocking.borrow_mut().write(0xFF02, 0x81);
+      for _ in 0..1025 {
+        first.borrow_mut().tick(&mut first_interrupts);
+        second.borrow_mut().tick(&mut second_interrupts);
+      }
+      assert_eq_hex!(clocking.borrow().read(0xFF01), 0x34);
+      assert_eq_hex!(clocked.borrow().read(0xFF01), 0x12);
+      assert_eq_hex!(clocking.borrow().read(0xFF02), 0x7F);
+      assert_eq_hex!(clocked.borrow().read(0xFF02), 0x7E);
+      for interrupt_controller in [&mut first_interrupts, &mut second_interrupts] {
+        assert_eq_hex!(interrupt_controller.read(0xFF0F) & 0x08, 0x08);
+        interrupt_controller.write(0xFF0F, 0x00);
+      }
+    }
+    assert_eq!(first.borrow_mut().take_output(), vec![0x12, 0x34]);
+    assert_eq!(second.borrow_mut().take_output(), vec![0x34, 0x12]);
+  }
+
+  #[test]
+  fn partner_without_pending_transfer_sends_ones() {
+    let (first, second) = linked_controllers();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    second.borrow_mut().write(0xFF01, 0x34);
+    first.borrow_mut().write(0xFF01, 0x12);
+    first.borrow_mut().write(0xFF02, 0x81);
+    (0..1024).for_each(|_| { first.borrow_mut().tick(&mut interrupt_controller); });
+    assert_eq_hex!(first.borrow().read(0xFF01), 0xFF);
+    assert_eq_hex!(second.borrow().read(0xFF01), 0x34);
+  }
+}
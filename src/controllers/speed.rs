@@ -0,0 +1,93 @@
+use serde::{Deserialize, Serialize};
+use crate::memory::memory::{CGBMode, Memory};
+use crate::util::bit_util::BitUtil;
+
+pub trait SpeedController {
+  fn double_speed(&self) -> bool;
+  fn switch_armed(&self) -> bool;
+  /// Toggles between normal and double speed and disarms the switch.
+  fn switch_speed(&mut self);
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SpeedControllerImpl {
+  double_speed: bool,
+  switch_armed: bool,
+  cgb_mode: CGBMode,
+}
+
+impl SpeedControllerImpl {
+  pub fn new(cgb_mode: CGBMode) -> SpeedControllerImpl {
+    SpeedControllerImpl {
+      double_speed: false,
+      switch_armed: false,
+      cgb_mode,
+    }
+  }
+}
+
+impl SpeedController for SpeedControllerImpl {
+  fn double_speed(&self) -> bool {
+    self.double_speed
+  }
+
+  fn switch_armed(&self) -> bool {
+    self.switch_armed
+  }
+
+  fn switch_speed(&mut self) {
+    self.double_speed = !self.double_speed;
+    self.switch_armed = false;
+  }
+}
+
+impl Memory for SpeedControllerImpl {
+  fn read(&self, address: u16) -> u8 {
+    match address {
+      0xFF4D => match self.cgb_mode {
+        CGBMode::Monochrome => 0xFF,
+        _ => ((self.double_speed as u8) << 7) | 0x7E | self.switch_armed as u8
+      },
+      _ => panic!("SpeedController can't read address {:#06x}", address)
+    }
+  }
+
+  fn write(&mut self, address: u16, value: u8) {
+    match address {
+      0xFF4D => if self.cgb_mode != CGBMode::Monochrome {
+        self.switch_armed = value.get_bit(0);
+      },
+      _ => panic!("SpeedController can't write to address {:#06x}", address)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use assert_hex::assert_eq_hex;
+  use super::*;
+
+  #[test]
+  fn arm_and_switch_speed() {
+    let mut speed_controller = SpeedControllerImpl::new(CGBMode::Color);
+    assert_eq_hex!(speed_controller.read(0xFF4D), 0x7E);
+    speed_controller.write(0xFF4D, 0xFF);
+    assert!(speed_controller.switch_armed());
+    assert_eq_hex!(speed_controller.read(0xFF4D), 0x7F);
+    speed_controller.switch_speed();
+    assert!(speed_controller.double_speed());
+    assert_eq_hex!(speed_controller.read(0xFF4D), 0xFE);
+    speed_controller.write(0xFF4D, 0x01);
+    speed_controller.switch_speed();
+    assert!(!speed_controller.double_speed());
+    assert_eq_hex!(speed_controller.read(0xFF4D), 0x7E);
+  }
+
+  #[test]
+  fn key1_is_unavailable_in_monochrome_mode() {
+    let mut speed_controller = SpeedControllerImpl::new(CGBMode::Monochrome);
+    speed_controller.write(0xFF4D, 0x01);
+    assert!(!speed_controller.switch_armed());
+    assert_eq_hex!(speed_controller.read(0xFF4D), 0xFF);
+  }
+}
@@ -1,14 +1,17 @@
 use std::cell::RefCell;
 use std::rc::Rc;
+use serde::{Deserialize, Serialize};
 use crate::time::time::ClockAware;
 use crate::cpu::interrupts::{Interrupt, InterruptController, InterruptControllerRef};
 use crate::memory::memory::Memory;
 use crate::util::bit_util::BitUtil;
 
 pub trait TimerController {
-  fn tick(&mut self, interrupt_controller: &mut dyn InterruptController);
+  /// Advances the timer by one M-cycle and returns whether TIMA overflowed.
+  fn tick(&mut self, interrupt_controller: &mut dyn InterruptController) -> bool;
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct TimerControllerImpl {
   clock_pulse_bit: u8,
   divider: u16,
@@ -32,7 +35,7 @@ impl TimerControllerImpl {
 }
 
 impl TimerController for TimerControllerImpl {
-  fn tick(&mut self, interrupt_controller: &mut dyn InterruptController) {
+  fn tick(&mut self, interrupt_controller: &mut dyn InterruptController) -> bool {
     let old_div = self.divider;
     self.divider = self.divider.wrapping_add(4);
     if self.enabled {
@@ -41,11 +44,13 @@ impl TimerController for TimerControllerImpl {
         if tima_overflowed {
           self.timer_counter = self.timer_modulo;
           interrupt_controller.request_interrupt(Interrupt::TimerOverflow);
+          return true;
         } else {
           self.timer_counter = new_timer_counter;
         }
       }
     }
+    false
   }
 }
 
@@ -81,11 +86,10 @@ impl Memory for TimerControllerImpl {
   }
 }
 
-#[cfg(test)]
-mod tests {
-  use super::*;
-  use test_case::test_case;
-  use crate::cpu::interrupts::InterruptControllerImpl;
+pub mod checks {
+  use crate::cpu::interrupts::{Interrupt, InterruptController, InterruptControllerImpl};
+  use crate::memory::memory::Memory;
+  use super::{TimerController, TimerControllerImpl};
 
   fn timer_ticks(timer: &mut dyn TimerController, interrupt_controller: &mut dyn InterruptController, ticks: usize) {
     for _ in 0..ticks {
@@ -93,63 +97,122 @@ mod tests {
     }
   }
 
-  #[test]
-  fn read_divider() {
+  /// It takes 64 ticks to increment the DIV register by one, so 320 ticks increment it by 5.
+  pub fn read_divider() -> bool {
     let mut interrupt_controller = InterruptControllerImpl::new();
     let mut timer = TimerControllerImpl::new();
-    // It takes 64 ticks to increment the DIV register by one, so 320 ticks should increment it by 5
     timer_ticks(&mut timer, &mut interrupt_controller, 320);
-    assert_eq!(timer.read(0xFF04), 5);
+    timer.read(0xFF04) == 5
   }
 
-  #[test_case(0x04, 256; "Timer @ 4096 Hz")]
-  #[test_case(0x05, 4; "Timer @ 262144 Hz")]
-  #[test_case(0x06, 16; "Timer @ 65536 Hz")]
-  #[test_case(0x07, 64; "Timer @ 16384 Hz")]
-  fn read_tima(tac_register: u8, ticks_per_timer_increment: usize) {
+  pub fn read_tima(tac_register: u8, ticks_per_timer_increment: usize) -> bool {
     let mut interrupt_controller = InterruptControllerImpl::new();
     let mut timer = TimerControllerImpl::new();
     timer.write(0xFF07, tac_register);
     timer_ticks(&mut timer, &mut interrupt_controller, ticks_per_timer_increment - 1);
-    assert_eq!(timer.read(0xFF05), 0u8);
+    let before_first_increment = timer.read(0xFF05);
     timer.tick(&mut interrupt_controller);
-    assert_eq!(timer.read(0xFF05), 1u8);
+    let after_first_increment = timer.read(0xFF05);
     timer_ticks(&mut timer, &mut interrupt_controller, ticks_per_timer_increment);
-    assert_eq!(timer.read(0xFF05), 2u8);
+    before_first_increment == 0 && after_first_increment == 1 && timer.read(0xFF05) == 2
   }
 
-  #[test_case(0x04, 0x10000; "4096 Hz")]
-  #[test_case(0x05, 0x00400; "262144 Hz")]
-  #[test_case(0x06, 0x01000; "65536 Hz")]
-  #[test_case(0x07, 0x04000; "16384 Hz")]
-  fn timer_overflow(tac_register: u8, ticks_per_overflow: usize) {
+  pub fn timer_overflow(tac_register: u8, ticks_per_overflow: usize) -> bool {
     let mut interrupt_controller = InterruptControllerImpl::new();
     interrupt_controller.enable_interrupts();
     interrupt_controller.write(0xFFFF, 0x04);
     let mut timer = TimerControllerImpl::new();
     timer.write(0xFF07, tac_register);
     timer_ticks(&mut timer, &mut interrupt_controller, ticks_per_overflow - 1);
-    assert!(interrupt_controller.get_requested_interrupt().is_none());
+    let requested_early = interrupt_controller.get_requested_interrupt().is_some();
     timer.tick(&mut interrupt_controller);
-    assert!(matches!(interrupt_controller.get_requested_interrupt().unwrap(), Interrupt::TimerOverflow));
+    let first_overflow = interrupt_controller.get_requested_interrupt() == Some(Interrupt::TimerOverflow);
     interrupt_controller.clear_interrupt(Interrupt::TimerOverflow);
-    assert!(interrupt_controller.get_requested_interrupt().is_none());
+    let cleared = interrupt_controller.get_requested_interrupt().is_none();
     timer_ticks(&mut timer, &mut interrupt_controller, ticks_per_overflow);
-    assert!(matches!(interrupt_controller.get_requested_interrupt().unwrap(), Interrupt::TimerOverflow));
+    !requested_early && first_overflow && cleared &&
+      interrupt_controller.get_requested_interrupt() == Some(Interrupt::TimerOverflow)
   }
 
-  #[test_case(0x04, 0x10000; "4096 Hz")]
-  #[test_case(0x05, 0x00400; "262144 Hz")]
-  #[test_case(0x06, 0x01000; "65536 Hz")]
-  #[test_case(0x07, 0x04000; "16384 Hz")]
-  fn timer_modulo(tac_register: u8, ticks_per_overflow: usize) {
+  pub fn timer_modulo(tac_register: u8, ticks_per_overflow: usize) -> bool {
     let mut interrupt_controller = InterruptControllerImpl::new();
     let mut timer = TimerControllerImpl::new();
     timer.write(0xFF06, 0xAB);
     timer.write(0xFF07, tac_register);
     timer_ticks(&mut timer, &mut interrupt_controller, ticks_per_overflow - 1);
-    assert_eq!(timer.read(0xFF05), 0xFF);
+    let before_overflow = timer.read(0xFF05);
+    timer.tick(&mut interrupt_controller);
+    before_overflow == 0xFF && timer.read(0xFF05) == 0xAB
+  }
+
+  /// After an overflow, TIMA reads 0x00 for one M-cycle before TMA is loaded.
+  pub fn tima_reload_delay() -> bool {
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut timer = TimerControllerImpl::new();
+    timer.write(0xFF05, 0xFF);
+    timer.write(0xFF06, 0xAB);
+    timer.write(0xFF07, 0x05);
+    timer_ticks(&mut timer, &mut interrupt_controller, 4);
+    let during_delay = timer.read(0xFF05);
     timer.tick(&mut interrupt_controller);
-    assert_eq!(timer.read(0xFF05), 0xAB);
+    during_delay == 0x00 && timer.read(0xFF05) == 0xAB
   }
-}
\ No newline at end of file
+
+  /// Resetting DIV while the selected divider bit is high produces a falling edge, which increments TIMA.
+  pub fn div_write_falling_edge() -> bool {
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut timer = TimerControllerImpl::new();
+    timer.write(0xFF07, 0x05);
+    timer_ticks(&mut timer, &mut interrupt_controller, 6);
+    let before = timer.read(0xFF05);
+    timer.write(0xFF04, 0x00);
+    timer.read(0xFF05) == before.wrapping_add(1)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use test_case::test_case;
+  use super::checks;
+
+  #[test]
+  fn read_divider() {
+    assert!(checks::read_divider());
+  }
+
+  #[test_case(0x04, 256; "Timer @ 4096 Hz")]
+  #[test_case(0x05, 4; "Timer @ 262144 Hz")]
+  #[test_case(0x06, 16; "Timer @ 65536 Hz")]
+  #[test_case(0x07, 64; "Timer @ 16384 Hz")]
+  fn read_tima(tac_register: u8, ticks_per_timer_increment: usize) {
+    assert!(checks::read_tima(tac_register, ticks_per_timer_increment));
+  }
+
+  #[test_case(0x04, 0x10000; "4096 Hz")]
+  #[test_case(0x05, 0x00400; "262144 Hz")]
+  #[test_case(0x06, 0x01000; "65536 Hz")]
+  #[test_case(0x07, 0x04000; "16384 Hz")]
+  fn timer_overflow(tac_register: u8, ticks_per_overflow: usize) {
+    assert!(checks::timer_overflow(tac_register, ticks_per_overflow));
+  }
+
+  #[test_case(0x04, 0x10000; "4096 Hz")]
+  #[test_case(0x05, 0x00400; "262144 Hz")]
+  #[test_case(0x06, 0x01000; "65536 Hz")]
+  #[test_case(0x07, 0x04000; "16384 Hz")]
+  fn timer_modulo(tac_register: u8, ticks_per_overflow: usize) {
+    assert!(checks::timer_modulo(tac_register, ticks_per_overflow));
+  }
+
+  #[test]
+  #[ignore = "TIMA is reloaded from TMA without the one cycle delay"]
+  fn tima_reload_delay() {
+    assert!(checks::tima_reload_delay());
+  }
+
+  #[test]
+  #[ignore = "Writing DIV doesn't clock TIMA on a falling edge yet"]
+  fn div_write_falling_edge() {
+    assert!(checks::div_write_falling_edge());
+  }
+}
@@ -0,0 +1,76 @@
+use wasm_bindgen::prelude::*;
+
+/// A call that hasn't returned yet. sp points at the pushed return address.
+#[wasm_bindgen]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct CallFrame {
+  pub caller: u16,
+  pub target: u16,
+  pub sp: u16,
+}
+
+/// Reconstructs the call stack from CALL, RST and interrupt dispatches.
+/// Returns aren't tracked directly: a frame is dropped as soon as SP rises above its return address, which also
+/// handles games that discard return addresses by popping them or by loading SP.
+pub struct CallStackTracker {
+  frames: Vec<CallFrame>,
+  pending_caller: Option<u16>,
+}
+
+impl CallStackTracker {
+  pub fn new() -> CallStackTracker {
+    CallStackTracker {
+      frames: vec![],
+      pending_caller: None,
+    }
+  }
+
+  /// Records a call from the given address. Its frame is completed at the next instruction boundary.
+  pub fn call(&mut self, caller: u16) {
+    self.pending_caller = Some(caller);
+  }
+
+  /// Brings the stack up to date at an instruction boundary.
+  pub fn sync(&mut self, pc: u16, sp: u16) {
+    while self.frames.last().is_some_and(|frame| frame.sp < sp) {
+      self.frames.pop();
+    }
+    if let Some(caller) = self.pending_caller.take() {
+      self.frames.push(CallFrame { caller, target: pc, sp });
+    }
+  }
+
+  /// The frames, outermost first.
+  pub fn frames(&self) -> &[CallFrame] {
+    &self.frames
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn call_completes_at_next_boundary() {
+    let mut tracker = CallStackTracker::new();
+    tracker.call(0x0150);
+    assert_eq!(tracker.frames(), &[]);
+    tracker.sync(0x2000, 0xFFFC);
+    assert_eq!(tracker.frames(), &[CallFrame { caller: 0x0150, target: 0x2000, sp: 0xFFFC }]);
+  }
+
+  #[test]
+  fn frames_are_dropped_when_sp_rises_above_them() {
+    let mut tracker = CallStackTracker::new();
+    tracker.call(0x0150);
+    tracker.sync(0x2000, 0xFFFC);
+    tracker.call(0x2010);
+    tracker.sync(0x3000, 0xFFFA);
+    tracker.sync(0x3001, 0xFFF8);
+    assert_eq!(tracker.frames().len(), 2);
+    tracker.sync(0x2013, 0xFFFC);
+    assert_eq!(tracker.frames(), &[CallFrame { caller: 0x0150, target: 0x2000, sp: 0xFFFC }]);
+    tracker.sync(0x0150, 0xFFFE);
+    assert_eq!(tracker.frames(), &[]);
+  }
+}
@@ -1,12 +1,19 @@
 use std::cell::RefCell;
-use std::collections::VecDeque;
 use std::rc::Rc;
 use byteorder::{LittleEndian, ReadBytesExt};
 use mockall::automock;
-use crate::cpu::interrupts::{Interrupt, InterruptController, InterruptControllerImpl, InterruptControllerRef};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::ser::Error;
+use wasm_bindgen::prelude::*;
+use crate::cpu::call_stack::{CallFrame, CallStackTracker};
+use crate::cpu::disassembler::disassemble_instruction;
+use crate::cpu::interrupts::{InterruptController, InterruptControllerImpl, InterruptControllerRef};
 use crate::cpu::opcode::Opcode;
 use crate::cpu::register::{ByteRegister, Registers, WordRegister};
-use crate::memory::memory::Memory;
+use crate::cpu::profiler::Profiler;
+use crate::cpu::trace::{Trace, TraceEntry};
+use crate::memory::memory::{HardwareModel, Memory};
+use crate::memory::oam::OAMCorruption;
 use crate::MainMemory;
 use crate::time::time::ClockAware;
 use crate::util::bit_util::BitUtil;
@@ -50,6 +57,7 @@ struct WordArithmeticParams {
   flag_mask: u8,
 }
 
+#[derive(Serialize, Deserialize)]
 struct InstructionContext {
   opcode: Opcode,
   byte_buffer: u8,
@@ -57,7 +65,97 @@ struct InstructionContext {
   address_buffer: u16,
 }
 
-type Operation = Box<dyn FnOnce(&mut CPUImpl, &mut dyn Memory)>;
+/// A single step of an instruction. Instructions queue one Operation per M-cycle, each made of up to two micro-ops.
+#[derive(Copy, Clone)]
+enum MicroOp {
+  Noop,
+  ExecuteCB,
+  ReadSignedImmediate,
+  TestIndirectHLBit,
+  SetIndirectHLBit,
+  ResetIndirectHLBit,
+  JumpRelative,
+  EnableInterrupts,
+  MoveByte { source: ByteLocation, destination: ByteLocation },
+  MoveWord { source: WordLocation, destination: WordLocation },
+  AddBytes { params: ByteArithmeticParams },
+  AddWords { params: WordArithmeticParams },
+  AddSignedByteToRegSP { destination: WordLocation },
+  SubtractBytes { params: ByteArithmeticParams },
+  AndBytes { first: ByteLocation, second: ByteLocation, destination: ByteLocation },
+  OrBytes { first: ByteLocation, second: ByteLocation, destination: ByteLocation },
+  XorBytes { first: ByteLocation, second: ByteLocation, destination: ByteLocation },
+  RotateByteLeft { source: ByteLocation, destination: ByteLocation, unset_zero: bool },
+  RotateByteLeftThroughCarry { source: ByteLocation, destination: ByteLocation, unset_zero: bool },
+  RotateByteRight { source: ByteLocation, destination: ByteLocation, unset_zero: bool },
+  RotateByteRightThroughCarry { source: ByteLocation, destination: ByteLocation, unset_zero: bool },
+  ShiftByteLeft { source: ByteLocation, destination: ByteLocation },
+  ShiftByteRight { source: ByteLocation, destination: ByteLocation },
+  ShiftByteRightArithmetic { source: ByteLocation, destination: ByteLocation },
+  SwapByte { source: ByteLocation, destination: ByteLocation },
+  IncrementWord { location: WordLocation },
+  DecrementWord { location: WordLocation },
+}
+
+#[derive(Copy, Clone)]
+struct Operation([MicroOp; 2]);
+
+impl From<MicroOp> for Operation {
+  fn from(micro_op: MicroOp) -> Self {
+    Operation([micro_op, MicroOp::Noop])
+  }
+}
+
+impl Operation {
+  fn execute(self, cpu: &mut CPUImpl, memory: &mut dyn Memory) {
+    cpu.read_memory_in_operation = false;
+    for micro_op in self.0 {
+      cpu.execute_micro_op(micro_op, memory);
+    }
+  }
+}
+
+const OPERATION_QUEUE_CAPACITY: usize = 8;
+
+/// Fixed size ring buffer of pending operations, so queueing an instruction doesn't allocate.
+#[derive(Copy, Clone)]
+struct OperationQueue {
+  operations: [Operation; OPERATION_QUEUE_CAPACITY],
+  start: usize,
+  len: usize,
+}
+
+impl OperationQueue {
+  fn new() -> Self {
+    OperationQueue {
+      operations: [Operation::from(MicroOp::Noop); OPERATION_QUEUE_CAPACITY],
+      start: 0,
+      len: 0,
+    }
+  }
+
+  fn push_back(&mut self, operation: Operation) {
+    // The queue only ever holds what's left of the current instruction or interrupt dispatch. Neither takes more than
+    // 6 M-cycles (CALL), so at most 5 operations are pending at once.
+    debug_assert!(self.len < OPERATION_QUEUE_CAPACITY, "Operation queue overflow");
+    self.operations[(self.start + self.len) % OPERATION_QUEUE_CAPACITY] = operation;
+    self.len += 1;
+  }
+
+  fn pop_front(&mut self) -> Option<Operation> {
+    if self.len == 0 {
+      return None;
+    }
+    let operation = self.operations[self.start];
+    self.start = (self.start + 1) % OPERATION_QUEUE_CAPACITY;
+    self.len -= 1;
+    Some(operation)
+  }
+
+  fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+}
 
 #[automock]
 pub trait CPU {
@@ -66,13 +164,78 @@ pub trait CPU {
   fn disable(&mut self);
 }
 
+/// A snapshot of the CPU state for debuggers. cycles counts CPU M-cycles since power on, which take half as long in
+/// double speed mode.
+#[wasm_bindgen]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct CPUInfo {
+  pub af: u16,
+  pub bc: u16,
+  pub de: u16,
+  pub hl: u16,
+  pub sp: u16,
+  pub pc: u16,
+  pub ime: bool,
+  pub halted: bool,
+  pub stopped: bool,
+  pub locked: bool,
+  pub cycles: u64,
+}
+
+/// Why a debugger write to a register was refused.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SetRegisterError {
+  /// Only AF, BC, DE, HL, SP, PC and A, F, B, C, D, E, H, L can be written.
+  UnknownRegister,
+  /// The value doesn't fit in an 8-bit register.
+  ValueOutOfRange,
+  /// The CPU is halfway through an instruction, whose queued operations may depend on the register.
+  InstructionInProgress,
+}
+
+/// The CPU can only be serialized at an instruction boundary, since queued operations aren't serialized.
+#[derive(Serialize, Deserialize)]
 pub struct CPUImpl {
   enabled: bool,
+  halted: bool,
+  halt_bug: bool,
+  stopped: bool,
+  stop_executed: bool,
+  /// Whether the current operation read memory at an address in a register, which decides the kind of OAM corruption.
+  #[serde(skip)]
+  read_memory_in_operation: bool,
+  #[serde(skip)]
+  oam_corruption: Option<OAMCorruption>,
+  locked: bool,
+  dispatching_interrupt: bool,
+  enable_interrupts_after_operation: bool,
+  enable_interrupts_before_instruction: bool,
+  #[serde(skip)]
+  trace: Option<Trace>,
+  #[serde(skip)]
+  profiler: Option<Box<Profiler>>,
+  #[serde(skip)]
+  call_stack: Option<CallStackTracker>,
+  cycles: u64,
   context: InstructionContext,
-  operations: VecDeque<Operation>,
+  #[serde(serialize_with = "serialize_operations", deserialize_with = "deserialize_operations")]
+  operations: OperationQueue,
   registers: Registers,
 }
 
+/// Only records that the queue is empty.
+fn serialize_operations<S: Serializer>(operations: &OperationQueue, serializer: S) -> Result<S::Ok, S::Error> {
+  if !operations.is_empty() {
+    return Err(S::Error::custom("CPU can only be serialized at an instruction boundary"));
+  }
+  serializer.serialize_bool(true)
+}
+
+fn deserialize_operations<'de, D: Deserializer<'de>>(deserializer: D) -> Result<OperationQueue, D::Error> {
+  bool::deserialize(deserializer)?;
+  Ok(OperationQueue::new())
+}
+
 impl CPU for CPUImpl {
   fn enabled(&self) -> bool {
     self.enabled
@@ -91,38 +254,285 @@ impl CPUImpl {
   pub fn new() -> CPUImpl {
     CPUImpl {
       enabled: true,
+      halted: false,
+      halt_bug: false,
+      stopped: false,
+      stop_executed: false,
+      read_memory_in_operation: false,
+      oam_corruption: None,
+      locked: false,
+      dispatching_interrupt: false,
+      enable_interrupts_after_operation: false,
+      enable_interrupts_before_instruction: false,
+      trace: None,
+      profiler: None,
+      call_stack: None,
+      cycles: 0,
       context: InstructionContext {
         opcode: Opcode(0),
         byte_buffer: 0u8,
         word_buffer: 0u16,
         address_buffer: 0u16,
       },
-      operations: VecDeque::with_capacity(5),
+      operations: OperationQueue::new(),
       registers: Registers::new(),
     }
   }
 
+  /// Sets the registers to the values the boot ROM of the given model leaves behind, so execution starts at 0x0100.
+  pub fn init(&mut self, model: HardwareModel) {
+    let (af, bc, de, hl) = match model {
+      HardwareModel::DMG => (0x01B0, 0x0013, 0x00D8, 0x014D),
+      HardwareModel::CGB => (0x1180, 0x0000, 0xFF56, 0x000D),
+    };
+    self.registers.write_word(WordRegister::AF, af);
+    self.registers.write_word(WordRegister::BC, bc);
+    self.registers.write_word(WordRegister::DE, de);
+    self.registers.write_word(WordRegister::HL, hl);
+    self.registers.write_word(WordRegister::SP, 0xFFFE);
+    self.registers.write_word(WordRegister::PC, 0x0100);
+  }
+
+  /// Whether the CPU is suspended by HALT, waiting for an enabled interrupt to be requested.
+  pub fn halted(&self) -> bool {
+    self.halted
+  }
+
+  /// Whether the CPU hung on an illegal opcode. A locked CPU never executes anything again.
+  pub fn locked(&self) -> bool {
+    self.locked
+  }
+
+  /// Leaves STOP mode, where the CPU executes nothing until a button is pressed or the speed switches. Unlike HALT, it
+  /// isn't left on interrupts, so the emulator decides when.
+  pub fn leave_stop(&mut self) {
+    self.stopped = false;
+  }
+
+  /// Whether STOP was executed since the last call. The speed switch it may trigger is handled by the emulator.
+  pub fn take_stop(&mut self) -> bool {
+    std::mem::take(&mut self.stop_executed)
+  }
+
+  /// The OAM corruption a 16-bit increment or decrement caused since the last call. Whether it actually happens
+  /// depends on the hardware and the PPU mode, which the emulator decides.
+  pub fn take_oam_corruption(&mut self) -> Option<OAMCorruption> {
+    self.oam_corruption.take()
+  }
+
+  fn record_oam_corruption(&mut self, word: u16) {
+    if (0xFE00..=0xFEFF).contains(&word) {
+      self.oam_corruption = Some(if self.read_memory_in_operation { OAMCorruption::Read } else { OAMCorruption::Write });
+    }
+  }
+
+  pub fn info(&self, interrupt_controller: &dyn InterruptController) -> CPUInfo {
+    CPUInfo {
+      af: self.registers.read_word(WordRegister::AF),
+      bc: self.registers.read_word(WordRegister::BC),
+      de: self.registers.read_word(WordRegister::DE),
+      hl: self.registers.read_word(WordRegister::HL),
+      sp: self.registers.read_word(WordRegister::SP),
+      pc: self.registers.read_word(WordRegister::PC),
+      ime: interrupt_controller.interrupts_enabled(),
+      halted: self.halted,
+      stopped: self.stopped,
+      locked: self.locked,
+      cycles: self.cycles,
+    }
+  }
+
+  /// Writes a register by name, for debuggers. Bits 3-0 of F stay clear, like they do for any other write.
+  pub fn set_register(&mut self, name: &str, value: u16) -> Result<(), SetRegisterError> {
+    if !self.at_instruction_boundary() {
+      return Err(SetRegisterError::InstructionInProgress);
+    }
+    let word_register = match name.to_ascii_uppercase().as_str() {
+      "AF" => Some(WordRegister::AF),
+      "BC" => Some(WordRegister::BC),
+      "DE" => Some(WordRegister::DE),
+      "HL" => Some(WordRegister::HL),
+      "SP" => Some(WordRegister::SP),
+      "PC" => Some(WordRegister::PC),
+      _ => None
+    };
+    if let Some(register) = word_register {
+      self.registers.write_word(register, value);
+      return Ok(());
+    }
+    let byte_register = match name.to_ascii_uppercase().as_str() {
+      "A" => ByteRegister::A,
+      "F" => ByteRegister::F,
+      "B" => ByteRegister::B,
+      "C" => ByteRegister::C,
+      "D" => ByteRegister::D,
+      "E" => ByteRegister::E,
+      "H" => ByteRegister::UpperHL,
+      "L" => ByteRegister::LowerHL,
+      _ => return Err(SetRegisterError::UnknownRegister)
+    };
+    let value = u8::try_from(value).map_err(|_| SetRegisterError::ValueOutOfRange)?;
+    self.registers.write_byte(byte_register, value);
+    Ok(())
+  }
+
+  pub fn program_counter(&self) -> u16 {
+    self.registers.read_word(WordRegister::PC)
+  }
+
+  pub fn stack_pointer(&self) -> u16 {
+    self.registers.read_word(WordRegister::SP)
+  }
+
+  /// Whether the current instruction or interrupt dispatch has finished, so the next tick starts a new one.
+  pub fn at_instruction_boundary(&self) -> bool {
+    self.operations.is_empty() && !self.dispatching_interrupt
+  }
+
+  /// The number of M-cycles the CPU has been ticked since power on.
+  pub fn cycles(&self) -> u64 {
+    self.cycles
+  }
+
+  /// Ticks until the current instruction has finished and returns the number of M-cycles it took.
+  /// An interrupt dispatch counts as a separate step, and so does a single cycle spent halted.
+  pub fn step_instruction(&mut self, memory: &mut dyn Memory, interrupt_controller: &mut dyn InterruptController) -> u32 {
+    let mut cycles = 0;
+    loop {
+      self.tick(memory, interrupt_controller);
+      cycles += 1;
+      if self.at_instruction_boundary() {
+        return cycles;
+      }
+    }
+  }
+
+  /// Starts recording every executed instruction, keeping the most recent `capacity` ones.
+  pub fn enable_trace(&mut self, capacity: usize) {
+    self.trace = Some(Trace::new(capacity));
+  }
+
+  pub fn disable_trace(&mut self) {
+    self.trace = None;
+  }
+
+  pub fn trace(&self) -> Option<&Trace> {
+    self.trace.as_ref()
+  }
+
+  /// Starts counting executed opcodes, discarding any earlier counts.
+  pub fn enable_profiler(&mut self) {
+    self.profiler = Some(Box::new(Profiler::new()));
+  }
+
+  pub fn disable_profiler(&mut self) {
+    self.profiler = None;
+  }
+
+  pub fn profiler(&self) -> Option<&Profiler> {
+    self.profiler.as_deref()
+  }
+
+  /// Starts tracking calls, beginning with an empty call stack.
+  pub fn enable_call_stack(&mut self) {
+    self.call_stack = Some(CallStackTracker::new());
+  }
+
+  pub fn disable_call_stack(&mut self) {
+    self.call_stack = None;
+  }
+
+  /// The tracked calls, outermost first, or nothing if tracking is disabled.
+  pub fn call_stack(&self) -> &[CallFrame] {
+    self.call_stack.as_ref().map_or(&[], |call_stack| call_stack.frames())
+  }
+
+  fn record_call(&mut self, caller: u16) {
+    if let Some(call_stack) = self.call_stack.as_mut() {
+      call_stack.call(caller);
+    }
+  }
+
+  fn record_trace(&mut self, memory: &dyn Memory) {
+    let pc = self.registers.read_word(WordRegister::PC);
+    let bytes = [0, 1, 2, 3].map(|offset| memory.read(pc.wrapping_add(offset)));
+    let registers = &self.registers;
+    let entry = TraceEntry {
+      a: registers.read_byte(ByteRegister::A),
+      f: registers.read_byte(ByteRegister::F),
+      b: registers.read_byte(ByteRegister::B),
+      c: registers.read_byte(ByteRegister::C),
+      d: registers.read_byte(ByteRegister::D),
+      e: registers.read_byte(ByteRegister::E),
+      h: registers.read_byte(ByteRegister::UpperHL),
+      l: registers.read_byte(ByteRegister::LowerHL),
+      sp: registers.read_word(WordRegister::SP),
+      pc,
+      memory: bytes,
+      mnemonic: disassemble_instruction(&bytes, pc).mnemonic,
+    };
+    if let Some(trace) = self.trace.as_mut() {
+      trace.record(entry);
+    }
+  }
+
   fn ticks(&mut self, memory: &mut dyn Memory, interrupt_controller: &mut dyn InterruptController, number_of_ticks: u32) {
     for _ in 0..number_of_ticks {
       self.tick(memory, interrupt_controller);
     }
   }
 
-  fn tick(&mut self, memory: &mut dyn Memory, interrupt_controller: &mut dyn InterruptController) {
-    if let Some(operation) = self.operations.pop_front() {
-      operation(self, memory);
-    } else if self.enabled {
-      let optional_interrupt = interrupt_controller.get_requested_interrupt();
-      if let Some(interrupt) = optional_interrupt {
-        self.call_interrupt_routine(interrupt, interrupt_controller);
+  /// Executes a single M-cycle and returns whether the CPU did any work during it.
+  pub fn tick(&mut self, memory: &mut dyn Memory, interrupt_controller: &mut dyn InterruptController) -> bool {
+    self.cycles += 1;
+    if self.locked {
+      return false;
+    } else if let Some(operation) = self.operations.pop_front() {
+      // Queued operations include the operand fetch after a CB prefix, so interrupts can't split an instruction.
+      operation.execute(self, memory);
+      if self.enable_interrupts_after_operation {
+        self.enable_interrupts_after_operation = false;
+        interrupt_controller.enable_interrupts();
+      }
+    } else if self.dispatching_interrupt {
+      self.select_interrupt_routine(memory, interrupt_controller);
+    } else if self.enabled && !self.stopped {
+      if self.halted {
+        if !interrupt_controller.interrupt_pending() {
+          return false;
+        }
+        self.halted = false;
+      }
+      if interrupt_controller.get_requested_interrupt().is_some() {
+        self.call_interrupt_routine();
       } else {
+        if self.enable_interrupts_before_instruction {
+          self.enable_interrupts_before_instruction = false;
+          interrupt_controller.enable_interrupts();
+        }
         self.fetch_and_execute_instruction(memory, interrupt_controller);
       }
+    } else {
+      return false;
+    }
+    if let Some(call_stack) = self.call_stack.as_mut() {
+      if self.operations.is_empty() && !self.dispatching_interrupt {
+        call_stack.sync(self.registers.read_word(WordRegister::PC), self.registers.read_word(WordRegister::SP));
+      }
     }
+    true
   }
 
   fn fetch_and_execute_instruction(&mut self, memory: &mut dyn Memory, interrupt_controller: &mut dyn InterruptController) {
+    if self.trace.is_some() {
+      self.record_trace(memory);
+    }
+    // Under the halt bug PC isn't incremented past the opcode, so it's taken before the fetch
+    let pc = self.registers.read_word(WordRegister::PC);
     let opcode_value = self.read_next_byte(memory);
+    if let Some(profiler) = self.profiler.as_mut() {
+      profiler.record_instruction(pc, opcode_value);
+    }
     self.context.opcode = Opcode(opcode_value);
     match opcode_value {
       0x00 => {}
@@ -141,7 +551,7 @@ impl CPUImpl {
       0x0D => self.decrement_reg(memory),
       0x0E => self.immediate_to_reg_ld(),
       0x0F => self.rotate_reg_a_right(memory),
-      0x10 => self.stop(),
+      0x10 => self.stop(memory),
       0x11 => self.immediate_to_reg_pair_ld(),
       0x12 => self.reg_a_to_indirect_de_ld(),
       0x13 => self.increment_reg_pair(memory),
@@ -205,7 +615,7 @@ impl CPUImpl {
       0x6E => self.indirect_to_reg_ld(),
       0x6F => self.reg_to_reg_ld(memory),
       0x70..=0x75 => self.reg_to_indirect_ld(),
-      0x76 => self.halt(),
+      0x76 => self.halt(interrupt_controller),
       0x77 => self.reg_to_indirect_ld(),
       0x78..=0x7D => self.reg_to_reg_ld(memory),
       0x7E => self.indirect_to_reg_ld(),
@@ -258,7 +668,7 @@ impl CPUImpl {
       0xD6 => self.subtract_immediate_from_reg_a_and_write_to_reg_a(false),
       0xD7 => self.restart(),
       0xD8 => self.return_conditionally(),
-      0xD9 => self.return_from_interrupt(interrupt_controller),
+      0xD9 => self.return_from_interrupt(),
       0xDA => self.jump_conditional(),
       0xDC => self.call_conditional(),
       0xDE => self.subtract_immediate_from_reg_a_and_write_to_reg_a(true),
@@ -281,112 +691,119 @@ impl CPUImpl {
       0xF5 => self.push_reg_pair_to_stack(),
       0xF6 => self.or_immediate_with_reg_a_and_write_to_reg_a(),
       0xF7 => self.restart(),
-      0xF8 => self.reg_sp_plus_signed_immediate_to_hl_ld(memory),
+      0xF8 => self.reg_sp_plus_signed_immediate_to_hl_ld(),
       0xF9 => self.reg_hl_to_reg_sp_ld(memory),
       0xFA => self.immediate_indirect_to_reg_a_ld(),
-      0xFB => self.enable_interrupts(interrupt_controller),
+      0xFB => self.enable_interrupts(),
       0xFE => self.compare_immediate_with_reg_a(),
       0xFF => self.restart(),
-      _ => panic!("Unknown opcode"),
+      0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD => self.lock(),
     };
   }
 
   fn execute_cb(&mut self) {
-    self.operations.push_back(Box::new(|this, memory| {
-      let opcode_value = this.read_next_byte(memory);
-      this.context.opcode = Opcode(opcode_value);
-      match opcode_value {
-        0x00..=0x05 => this.rotate_reg_left(memory),
-        0x06 => this.rotate_indirect_hl_left(),
-        0x07 => this.rotate_reg_left(memory),
-        0x08..=0x0D => this.rotate_reg_right(memory),
-        0x0E => this.rotate_indirect_hl_right(),
-        0x0F => this.rotate_reg_right(memory),
-        0x10..=0x15 => this.rotate_reg_left_through_carry(memory),
-        0x16 => this.rotate_indirect_hl_left_through_carry(),
-        0x17 => this.rotate_reg_left_through_carry(memory),
-        0x18..=0x1D => this.rotate_reg_right_through_carry(memory),
-        0x1E => this.rotate_indirect_hl_right_through_carry(),
-        0x1F => this.rotate_reg_right_through_carry(memory),
-        0x20..=0x25 => this.shift_reg_left(memory),
-        0x26 => this.shift_indirect_hl_left(),
-        0x27 => this.shift_reg_left(memory),
-        0x28..=0x2D => this.shift_reg_right_arithmetic(memory),
-        0x2E => this.shift_indirect_hl_right_arithmetic(),
-        0x2F => this.shift_reg_right_arithmetic(memory),
-        0x30..=0x35 => this.swap_reg(memory),
-        0x36 => this.swap_indirect_hl(),
-        0x37 => this.swap_reg(memory),
-        0x38..=0x3D => this.shift_reg_right(memory),
-        0x3E => this.shift_indirect_hl_right(),
-        0x3F => this.shift_reg_right(memory),
-        0x40..=0x45 => this.get_reg_bit(),
-        0x46 => this.get_indirect_hl_bit(),
-        0x47..=0x4D => this.get_reg_bit(),
-        0x4E => this.get_indirect_hl_bit(),
-        0x4F..=0x55 => this.get_reg_bit(),
-        0x56 => this.get_indirect_hl_bit(),
-        0x57..=0x5D => this.get_reg_bit(),
-        0x5E => this.get_indirect_hl_bit(),
-        0x5F..=0x65 => this.get_reg_bit(),
-        0x66 => this.get_indirect_hl_bit(),
-        0x67..=0x6D => this.get_reg_bit(),
-        0x6E => this.get_indirect_hl_bit(),
-        0x6F..=0x75 => this.get_reg_bit(),
-        0x76 => this.get_indirect_hl_bit(),
-        0x77..=0x7D => this.get_reg_bit(),
-        0x7E => this.get_indirect_hl_bit(),
-        0x7F => this.get_reg_bit(),
-        0x80..=0x85 => this.reset_reg_bit(),
-        0x86 => this.reset_indirect_hl_bit(),
-        0x87..=0x8D => this.reset_reg_bit(),
-        0x8E => this.reset_indirect_hl_bit(),
-        0x8F..=0x95 => this.reset_reg_bit(),
-        0x96 => this.reset_indirect_hl_bit(),
-        0x97..=0x9D => this.reset_reg_bit(),
-        0x9E => this.reset_indirect_hl_bit(),
-        0x9F..=0xA5 => this.reset_reg_bit(),
-        0xA6 => this.reset_indirect_hl_bit(),
-        0xA7..=0xAD => this.reset_reg_bit(),
-        0xAE => this.reset_indirect_hl_bit(),
-        0xAF..=0xB5 => this.reset_reg_bit(),
-        0xB6 => this.reset_indirect_hl_bit(),
-        0xB7..=0xBD => this.reset_reg_bit(),
-        0xBE => this.reset_indirect_hl_bit(),
-        0xBF => this.reset_reg_bit(),
-        0xC0..=0xC5 => this.set_reg_bit(),
-        0xC6 => this.set_indirect_hl_bit(),
-        0xC7..=0xCD => this.set_reg_bit(),
-        0xCE => this.set_indirect_hl_bit(),
-        0xCF..=0xD5 => this.set_reg_bit(),
-        0xD6 => this.set_indirect_hl_bit(),
-        0xD7..=0xDD => this.set_reg_bit(),
-        0xDE => this.set_indirect_hl_bit(),
-        0xDF..=0xE5 => this.set_reg_bit(),
-        0xE6 => this.set_indirect_hl_bit(),
-        0xE7..=0xED => this.set_reg_bit(),
-        0xEE => this.set_indirect_hl_bit(),
-        0xEF..=0xF5 => this.set_reg_bit(),
-        0xF6 => this.set_indirect_hl_bit(),
-        0xF7..=0xFD => this.set_reg_bit(),
-        0xFE => this.set_indirect_hl_bit(),
-        0xFF => this.set_reg_bit(),
-        _ => panic!("Unknown opcode"),
-      };
-    }));
+    self.operations.push_back(Operation::from(MicroOp::ExecuteCB));
+  }
+
+  fn execute_cb_opcode(&mut self, memory: &mut dyn Memory) {
+    let opcode_value = self.read_next_byte(memory);
+    if let Some(profiler) = self.profiler.as_mut() {
+      profiler.record_cb_instruction(opcode_value);
+    }
+    self.context.opcode = Opcode(opcode_value);
+    match opcode_value {
+      0x00..=0x05 => self.rotate_reg_left(memory),
+      0x06 => self.rotate_indirect_hl_left(),
+      0x07 => self.rotate_reg_left(memory),
+      0x08..=0x0D => self.rotate_reg_right(memory),
+      0x0E => self.rotate_indirect_hl_right(),
+      0x0F => self.rotate_reg_right(memory),
+      0x10..=0x15 => self.rotate_reg_left_through_carry(memory),
+      0x16 => self.rotate_indirect_hl_left_through_carry(),
+      0x17 => self.rotate_reg_left_through_carry(memory),
+      0x18..=0x1D => self.rotate_reg_right_through_carry(memory),
+      0x1E => self.rotate_indirect_hl_right_through_carry(),
+      0x1F => self.rotate_reg_right_through_carry(memory),
+      0x20..=0x25 => self.shift_reg_left(memory),
+      0x26 => self.shift_indirect_hl_left(),
+      0x27 => self.shift_reg_left(memory),
+      0x28..=0x2D => self.shift_reg_right_arithmetic(memory),
+      0x2E => self.shift_indirect_hl_right_arithmetic(),
+      0x2F => self.shift_reg_right_arithmetic(memory),
+      0x30..=0x35 => self.swap_reg(memory),
+      0x36 => self.swap_indirect_hl(),
+      0x37 => self.swap_reg(memory),
+      0x38..=0x3D => self.shift_reg_right(memory),
+      0x3E => self.shift_indirect_hl_right(),
+      0x3F => self.shift_reg_right(memory),
+      0x40..=0x45 => self.get_reg_bit(),
+      0x46 => self.get_indirect_hl_bit(),
+      0x47..=0x4D => self.get_reg_bit(),
+      0x4E => self.get_indirect_hl_bit(),
+      0x4F..=0x55 => self.get_reg_bit(),
+      0x56 => self.get_indirect_hl_bit(),
+      0x57..=0x5D => self.get_reg_bit(),
+      0x5E => self.get_indirect_hl_bit(),
+      0x5F..=0x65 => self.get_reg_bit(),
+      0x66 => self.get_indirect_hl_bit(),
+      0x67..=0x6D => self.get_reg_bit(),
+      0x6E => self.get_indirect_hl_bit(),
+      0x6F..=0x75 => self.get_reg_bit(),
+      0x76 => self.get_indirect_hl_bit(),
+      0x77..=0x7D => self.get_reg_bit(),
+      0x7E => self.get_indirect_hl_bit(),
+      0x7F => self.get_reg_bit(),
+      0x80..=0x85 => self.reset_reg_bit(),
+      0x86 => self.reset_indirect_hl_bit(),
+      0x87..=0x8D => self.reset_reg_bit(),
+      0x8E => self.reset_indirect_hl_bit(),
+      0x8F..=0x95 => self.reset_reg_bit(),
+      0x96 => self.reset_indirect_hl_bit(),
+      0x97..=0x9D => self.reset_reg_bit(),
+      0x9E => self.reset_indirect_hl_bit(),
+      0x9F..=0xA5 => self.reset_reg_bit(),
+      0xA6 => self.reset_indirect_hl_bit(),
+      0xA7..=0xAD => self.reset_reg_bit(),
+      0xAE => self.reset_indirect_hl_bit(),
+      0xAF..=0xB5 => self.reset_reg_bit(),
+      0xB6 => self.reset_indirect_hl_bit(),
+      0xB7..=0xBD => self.reset_reg_bit(),
+      0xBE => self.reset_indirect_hl_bit(),
+      0xBF => self.reset_reg_bit(),
+      0xC0..=0xC5 => self.set_reg_bit(),
+      0xC6 => self.set_indirect_hl_bit(),
+      0xC7..=0xCD => self.set_reg_bit(),
+      0xCE => self.set_indirect_hl_bit(),
+      0xCF..=0xD5 => self.set_reg_bit(),
+      0xD6 => self.set_indirect_hl_bit(),
+      0xD7..=0xDD => self.set_reg_bit(),
+      0xDE => self.set_indirect_hl_bit(),
+      0xDF..=0xE5 => self.set_reg_bit(),
+      0xE6 => self.set_indirect_hl_bit(),
+      0xE7..=0xED => self.set_reg_bit(),
+      0xEE => self.set_indirect_hl_bit(),
+      0xEF..=0xF5 => self.set_reg_bit(),
+      0xF6 => self.set_indirect_hl_bit(),
+      0xF7..=0xFD => self.set_reg_bit(),
+      0xFE => self.set_indirect_hl_bit(),
+      0xFF => self.set_reg_bit(),
+      _ => panic!("Unknown opcode"),
+    };
   }
 
+  /// PC wraps around from 0xFFFF to 0x0000, so the byte at 0xFFFF (IE) is fetched like any other.
   fn read_next_byte(&mut self, memory: &dyn Memory) -> u8 {
     let address = self.registers.read_word(WordRegister::PC);
-    self.registers.write_word(WordRegister::PC, address + 1);
+    if self.halt_bug {
+      self.halt_bug = false;
+    } else {
+      self.registers.write_word(WordRegister::PC, address.wrapping_add(1));
+    }
     memory.read(address)
   }
 
   fn combine_operations(operation1: Operation, operation2: Operation) -> Operation {
-    Box::new(|this, memory| {
-      operation1(this, memory);
-      operation2(this, memory);
-    })
+    Operation([operation1.0[0], operation2.0[0]])
   }
 
   fn read_byte(&mut self, memory: &dyn Memory, location: ByteLocation) -> u8 {
@@ -399,7 +816,10 @@ impl CPUImpl {
       ByteLocation::LowerWordBuffer => self.context.word_buffer as u8,
       ByteLocation::UpperWordBuffer => (self.context.word_buffer >> 8) as u8,
       ByteLocation::MemoryReferencedByAddressBuffer => memory.read(self.context.address_buffer),
-      ByteLocation::MemoryReferencedByRegister(register) => memory.read(self.registers.read_word(register)),
+      ByteLocation::MemoryReferencedByRegister(register) => {
+        self.read_memory_in_operation = true;
+        memory.read(self.registers.read_word(register))
+      }
       ByteLocation::NextMemoryByte => self.read_next_byte(memory),
     }
   }
@@ -437,236 +857,305 @@ impl CPUImpl {
     }
   }
 
+  fn execute_micro_op(&mut self, micro_op: MicroOp, memory: &mut dyn Memory) {
+    match micro_op {
+      MicroOp::Noop => {}
+      MicroOp::ExecuteCB => self.execute_cb_opcode(memory),
+      MicroOp::ReadSignedImmediate => {
+        self.context.word_buffer = self.read_next_byte(memory) as i8 as u16;
+      }
+      MicroOp::TestIndirectHLBit => {
+        let address = self.registers.read_word(WordRegister::HL);
+        let value = memory.read(address);
+        let bit = self.context.opcode.y_bits();
+        self.registers.write_byte_masked(ByteRegister::F, u8::compose(&[(!value.get_bit(bit), 7), (false, 6), (true, 5)]), 0xE0);
+      }
+      MicroOp::SetIndirectHLBit => {
+        let bit = self.context.opcode.y_bits();
+        let address = self.registers.read_word(WordRegister::HL);
+        memory.write(address, self.context.byte_buffer.set_bit(bit));
+      }
+      MicroOp::ResetIndirectHLBit => {
+        let bit = self.context.opcode.y_bits();
+        let address = self.registers.read_word(WordRegister::HL);
+        memory.write(address, self.context.byte_buffer.reset_bit(bit));
+      }
+      MicroOp::JumpRelative => {
+        self.registers.write_word(WordRegister::PC, self.registers.read_word(WordRegister::PC).wrapping_add(self.context.byte_buffer as i8 as u16));
+      }
+      MicroOp::EnableInterrupts => self.enable_interrupts_after_operation = true,
+      MicroOp::MoveByte { source, destination } => {
+        let byte = self.read_byte(memory, source);
+        self.write_byte(memory, destination, byte);
+      }
+      MicroOp::MoveWord { source, destination } => {
+        let word = self.read_word(source);
+        self.write_word(destination, word);
+      }
+      MicroOp::AddBytes { params } => {
+        let first_value = self.read_byte(memory, params.first) as u16;
+        let second_value = self.read_byte(memory, params.second) as u16;
+        let carry = if params.use_carry { self.registers.read_byte(ByteRegister::F).get_bit(4) as u16 } else { 0u16 };
+        let result = first_value + second_value + carry;
+        let carry_result = first_value ^ second_value ^ result;
+        let truncated_result = result as u8;
+        let zero = truncated_result == 0;
+        if params.flag_mask != 0 {
+          let flag =
+            ((zero as u8) << 7) |
+              ((carry_result.get_bit(4) as u8) << 5) |
+              ((carry_result.get_bit(8) as u8) << 4);
+          self.registers.write_byte_masked(ByteRegister::F, flag, params.flag_mask);
+        }
+        self.write_byte(memory, params.destination, truncated_result);
+      }
+      MicroOp::AddWords { params } => {
+        let first_value = self.read_word(params.first);
+        let second_value = self.read_word(params.second);
+        let le_bytes1 = first_value.to_le_bytes();
+        let le_bytes2 = second_value.to_le_bytes();
+        let (result1, carry1) = le_bytes1[0].overflowing_add(le_bytes2[0]);
+        let result2 = (le_bytes1[1] as u16) + (le_bytes2[1] as u16) + (carry1 as u16);
+        let carry_result2 = (le_bytes1[1] as u16) ^ (le_bytes2[1] as u16) ^ result2;
+        let result = (&[result1, result2 as u8][..]).read_u16::<LittleEndian>().unwrap();
+        let zero = result == 0;
+        if params.flag_mask != 0 {
+          let flag =
+            ((zero as u8) << 7) |
+              ((carry_result2.get_bit(4) as u8) << 5) |
+              ((carry_result2.get_bit(8) as u8) << 4);
+          self.registers.write_byte_masked(ByteRegister::F, flag, params.flag_mask);
+        }
+        self.write_word(params.destination, result);
+      }
+      MicroOp::AddSignedByteToRegSP { destination } => {
+        let sp = self.read_word(WordLocation::Register(WordRegister::SP));
+        let offset = self.read_word(WordLocation::WordBuffer);
+        let result = sp.wrapping_add(offset);
+        let carry_result = sp ^ offset ^ result;
+        let flag = ((carry_result.get_bit(4) as u8) << 5) | ((carry_result.get_bit(8) as u8) << 4);
+        self.registers.write_byte(ByteRegister::F, flag);
+        self.write_word(destination, result);
+      }
+      MicroOp::SubtractBytes { params } => {
+        let first_value = self.read_byte(memory, params.first);
+        let second_value = self.read_byte(memory, params.second);
+        let borrow = if params.use_carry { self.registers.read_byte(ByteRegister::F).get_bit(4) as u16 } else { 0u16 };
+        let result = 0x100u16 + (first_value as u16) - (second_value as u16) - borrow;
+        let borrow_result = (0x100u16 + first_value as u16) ^ (second_value as u16) ^ result;
+        let truncated_result = result as u8;
+        let zero = truncated_result == 0;
+        if params.flag_mask != 0 {
+          let flag =
+            ((zero as u8) << 7) |
+              (1u8 << 6) |
+              ((borrow_result.get_bit(4) as u8) << 5) |
+              ((borrow_result.get_bit(8) as u8) << 4);
+          self.registers.write_byte_masked(ByteRegister::F, flag, params.flag_mask);
+        }
+        self.write_byte(memory, params.destination, truncated_result);
+      }
+      MicroOp::AndBytes { first, second, destination } => {
+        let first_value = self.read_byte(memory, first);
+        let second_value = self.read_byte(memory, second);
+        let result = first_value & second_value;
+        let zero = result == 0;
+        let flag = ((zero as u8) << 7) | (1u8 << 5);
+        self.registers.write_byte(ByteRegister::F, flag);
+        self.write_byte(memory, destination, result);
+      }
+      MicroOp::OrBytes { first, second, destination } => {
+        let first_value = self.read_byte(memory, first);
+        let second_value = self.read_byte(memory, second);
+        let result = first_value | second_value;
+        let flag = if result == 0 { 0x80u8 } else { 0x00u8 };
+        self.registers.write_byte(ByteRegister::F, flag);
+        self.write_byte(memory, destination, result);
+      }
+      MicroOp::XorBytes { first, second, destination } => {
+        let first_value = self.read_byte(memory, first);
+        let second_value = self.read_byte(memory, second);
+        let result = first_value ^ second_value;
+        let flag = if result == 0 { 0x80u8 } else { 0x00u8 };
+        self.registers.write_byte(ByteRegister::F, flag);
+        self.write_byte(memory, destination, result);
+      }
+      MicroOp::RotateByteLeft { source, destination, unset_zero } => {
+        let value = self.read_byte(memory, source);
+        let result = value.rotate_left(1);
+        let zero = !unset_zero && result == 0;
+        let flag =
+          ((zero as u8) << 7) | ((value.get_bit(7) as u8) << 4);
+        self.registers.write_byte(ByteRegister::F, flag);
+        self.write_byte(memory, destination, result);
+      }
+      MicroOp::RotateByteLeftThroughCarry { source, destination, unset_zero } => {
+        let value = self.read_byte(memory, source);
+        let carry = self.registers.read_byte(ByteRegister::F).get_bit(4);
+        let result = (value << 1) | (carry as u8);
+        let zero = !unset_zero && result == 0;
+        let flag =
+          ((zero as u8) << 7) | ((value.get_bit(7) as u8) << 4);
+        self.registers.write_byte(ByteRegister::F, flag);
+        self.write_byte(memory, destination, result);
+      }
+      MicroOp::RotateByteRight { source, destination, unset_zero } => {
+        let value = self.read_byte(memory, source);
+        let result = value.rotate_right(1);
+        let zero = !unset_zero && result == 0;
+        let flag =
+          ((zero as u8) << 7) | ((value.get_bit(0) as u8) << 4);
+        self.registers.write_byte(ByteRegister::F, flag);
+        self.write_byte(memory, destination, result);
+      }
+      MicroOp::RotateByteRightThroughCarry { source, destination, unset_zero } => {
+        let value = self.read_byte(memory, source);
+        let carry = self.registers.read_byte(ByteRegister::F).get_bit(4);
+        let result = (value >> 1) | (if carry { 0x80u8 } else { 0x00u8 });
+        let zero = !unset_zero && result == 0;
+        let flag =
+          ((zero as u8) << 7) | ((value.get_bit(0) as u8) << 4);
+        self.registers.write_byte(ByteRegister::F, flag);
+        self.write_byte(memory, destination, result);
+      }
+      MicroOp::ShiftByteLeft { source, destination } => {
+        let value = self.read_byte(memory, source);
+        let result = value << 1;
+        let zero = result == 0;
+        let flag =
+          ((zero as u8) << 7) | ((value.get_bit(7) as u8) << 4);
+        self.registers.write_byte(ByteRegister::F, flag);
+        self.write_byte(memory, destination, result);
+      }
+      MicroOp::ShiftByteRight { source, destination } => {
+        let value = self.read_byte(memory, source);
+        let result = value >> 1;
+        let zero = result == 0;
+        let flag =
+          ((zero as u8) << 7) | ((value.get_bit(0) as u8) << 4);
+        self.registers.write_byte(ByteRegister::F, flag);
+        self.write_byte(memory, destination, result);
+      }
+      MicroOp::ShiftByteRightArithmetic { source, destination } => {
+        let value = self.read_byte(memory, source);
+        let result = (value >> 1) | (value & 0x80);
+        let zero = result == 0;
+        let flag =
+          ((zero as u8) << 7) | ((value.get_bit(0) as u8) << 4);
+        self.registers.write_byte(ByteRegister::F, flag);
+        self.write_byte(memory, destination, result);
+      }
+      MicroOp::SwapByte { source, destination } => {
+        let value = self.read_byte(memory, source);
+        let result = value.rotate_left(4);
+        let flag = if result == 0 { 0x80u8 } else { 0x00u8 };
+        self.registers.write_byte(ByteRegister::F, flag);
+        self.write_byte(memory, destination, result);
+      }
+      MicroOp::IncrementWord { location } => {
+        let word = self.read_word(location);
+        if let WordLocation::Register(WordRegister::SP | WordRegister::HL) = location {
+          self.record_oam_corruption(word);
+        }
+        self.write_word(location, word.wrapping_add(1));
+      }
+      MicroOp::DecrementWord { location } => {
+        let word = self.read_word(location);
+        if let WordLocation::Register(WordRegister::SP | WordRegister::HL) = location {
+          self.record_oam_corruption(word);
+        }
+        self.write_word(location, word.wrapping_sub(1));
+      }
+    }
+  }
+
   fn noop() -> Operation {
-    Box::new(|_this, _memory| {})
+    Operation::from(MicroOp::Noop)
   }
 
   fn move_byte(source: ByteLocation, destination: ByteLocation) -> Operation {
-    Box::new(move |this, memory| {
-      let byte = this.read_byte(memory, source);
-      this.write_byte(memory, destination, byte);
-    })
+    Operation::from(MicroOp::MoveByte { source, destination })
   }
 
   fn move_word(source: WordLocation, destination: WordLocation) -> Operation {
-    Box::new(move |this, _memory| {
-      let word = this.read_word(source);
-      this.write_word(destination, word);
-    })
+    Operation::from(MicroOp::MoveWord { source, destination })
   }
 
   fn add_bytes(params: ByteArithmeticParams) -> Operation {
-    Box::new(move |this, memory| {
-      let first_value = this.read_byte(memory, params.first) as u16;
-      let second_value = this.read_byte(memory, params.second) as u16;
-      let carry = if params.use_carry { this.registers.read_byte(ByteRegister::F).get_bit(4) as u16 } else { 0u16 };
-      let result = first_value + second_value + carry;
-      let carry_result = first_value ^ second_value ^ result;
-      let truncated_result = result as u8;
-      let zero = truncated_result == 0;
-      if params.flag_mask != 0 {
-        let flag =
-          ((zero as u8) << 7) |
-            ((carry_result.get_bit(4) as u8) << 5) |
-            ((carry_result.get_bit(8) as u8) << 4);
-        this.registers.write_byte_masked(ByteRegister::F, flag, params.flag_mask);
-      }
-      this.write_byte(memory, params.destination, truncated_result);
-    })
+    Operation::from(MicroOp::AddBytes { params })
   }
 
   fn add_words(params: WordArithmeticParams) -> Operation {
-    Box::new(move |this, _memory| {
-      let first_value = this.read_word(params.first);
-      let second_value = this.read_word(params.second);
-      let le_bytes1 = first_value.to_le_bytes();
-      let le_bytes2 = second_value.to_le_bytes();
-      let (result1, carry1) = le_bytes1[0].overflowing_add(le_bytes2[0]);
-      let result2 = (le_bytes1[1] as u16) + (le_bytes2[1] as u16) + (carry1 as u16);
-      let carry_result2 = (le_bytes1[1] as u16) ^ (le_bytes2[1] as u16) ^ result2;
-      let result = (&[result1, result2 as u8][..]).read_u16::<LittleEndian>().unwrap();
-      let zero = result == 0;
-      if params.flag_mask != 0 {
-        let flag =
-          ((zero as u8) << 7) |
-            ((carry_result2.get_bit(4) as u8) << 5) |
-            ((carry_result2.get_bit(8) as u8) << 4);
-        this.registers.write_byte_masked(ByteRegister::F, flag, params.flag_mask);
-      }
-      this.write_word(params.destination, result);
-    })
+    Operation::from(MicroOp::AddWords { params })
+  }
+
+  /// Adds the sign extended byte in the word buffer to SP. H and C come from the unsigned addition of the lower bytes,
+  /// Z and N are always reset.
+  fn add_signed_byte_to_reg_sp(destination: WordLocation) -> Operation {
+    Operation::from(MicroOp::AddSignedByteToRegSP { destination })
   }
 
   fn subtract_bytes(params: ByteArithmeticParams) -> Operation {
-    Box::new(move |this, memory| {
-      let first_value = this.read_byte(memory, params.first);
-      let second_value = this.read_byte(memory, params.second);
-      let borrow = if params.use_carry { this.registers.read_byte(ByteRegister::F).get_bit(4) as u16 } else { 0u16 };
-      let result = 0x100u16 + (first_value as u16) - (second_value as u16) - borrow;
-      let borrow_result = (0x100u16 + first_value as u16) ^ (second_value as u16) ^ result;
-      let truncated_result = result as u8;
-      let zero = truncated_result == 0;
-      if params.flag_mask != 0 {
-        let flag =
-          ((zero as u8) << 7) |
-            (1u8 << 6) |
-            ((borrow_result.get_bit(4) as u8) << 5) |
-            ((borrow_result.get_bit(8) as u8) << 4);
-        this.registers.write_byte_masked(ByteRegister::F, flag, params.flag_mask);
-      }
-      this.write_byte(memory, params.destination, truncated_result);
-    })
+    Operation::from(MicroOp::SubtractBytes { params })
   }
 
   fn and_bytes(first: ByteLocation, second: ByteLocation, destination: ByteLocation) -> Operation {
-    Box::new(move |this, memory| {
-      let first_value = this.read_byte(memory, first);
-      let second_value = this.read_byte(memory, second);
-      let result = first_value & second_value;
-      let zero = result == 0;
-      let flag = ((zero as u8) << 7) | (1u8 << 5);
-      this.registers.write_byte(ByteRegister::F, flag);
-      this.write_byte(memory, destination, result);
-    })
+    Operation::from(MicroOp::AndBytes { first, second, destination })
   }
 
   fn or_bytes(first: ByteLocation, second: ByteLocation, destination: ByteLocation) -> Operation {
-    Box::new(move |this, memory| {
-      let first_value = this.read_byte(memory, first);
-      let second_value = this.read_byte(memory, second);
-      let result = first_value | second_value;
-      let flag = if result == 0 { 0x80u8 } else { 0x00u8 };
-      this.registers.write_byte(ByteRegister::F, flag);
-      this.write_byte(memory, destination, result);
-    })
+    Operation::from(MicroOp::OrBytes { first, second, destination })
   }
 
   fn xor_bytes(first: ByteLocation, second: ByteLocation, destination: ByteLocation) -> Operation {
-    Box::new(move |this, memory| {
-      let first_value = this.read_byte(memory, first);
-      let second_value = this.read_byte(memory, second);
-      let result = first_value ^ second_value;
-      let flag = if result == 0 { 0x80u8 } else { 0x00u8 };
-      this.registers.write_byte(ByteRegister::F, flag);
-      this.write_byte(memory, destination, result);
-    })
+    Operation::from(MicroOp::XorBytes { first, second, destination })
   }
 
   fn rotate_byte_left(source: ByteLocation, destination: ByteLocation, unset_zero: bool) -> Operation {
-    Box::new(move |this, memory| {
-      let value = this.read_byte(memory, source);
-      let result = value.rotate_left(1);
-      let zero = !unset_zero && result == 0;
-      let flag =
-        ((zero as u8) << 7) | ((value.get_bit(7) as u8) << 4);
-      this.registers.write_byte(ByteRegister::F, flag);
-      this.write_byte(memory, destination, result);
-    })
+    Operation::from(MicroOp::RotateByteLeft { source, destination, unset_zero })
   }
 
   fn rotate_byte_left_through_carry(source: ByteLocation, destination: ByteLocation, unset_zero: bool) -> Operation {
-    Box::new(move |this, memory| {
-      let value = this.read_byte(memory, source);
-      let carry = this.registers.read_byte(ByteRegister::F).get_bit(4);
-      let result = (value << 1) | (carry as u8);
-      let zero = !unset_zero && result == 0;
-      let flag =
-        ((zero as u8) << 7) | ((value.get_bit(7) as u8) << 4);
-      this.registers.write_byte(ByteRegister::F, flag);
-      this.write_byte(memory, destination, result);
-    })
+    Operation::from(MicroOp::RotateByteLeftThroughCarry { source, destination, unset_zero })
   }
 
   fn rotate_byte_right(source: ByteLocation, destination: ByteLocation, unset_zero: bool) -> Operation {
-    Box::new(move |this, memory| {
-      let value = this.read_byte(memory, source);
-      let result = value.rotate_right(1);
-      let zero = !unset_zero && result == 0;
-      let flag =
-        ((zero as u8) << 7) | ((value.get_bit(0) as u8) << 4);
-      this.registers.write_byte(ByteRegister::F, flag);
-      this.write_byte(memory, destination, result);
-    })
+    Operation::from(MicroOp::RotateByteRight { source, destination, unset_zero })
   }
 
   fn rotate_byte_right_through_carry(source: ByteLocation, destination: ByteLocation, unset_zero: bool) -> Operation {
-    Box::new(move |this, memory| {
-      let value = this.read_byte(memory, source);
-      let carry = this.registers.read_byte(ByteRegister::F).get_bit(4);
-      let result = (value >> 1) | (if carry { 0x80u8 } else { 0x00u8 });
-      let zero = !unset_zero && result == 0;
-      let flag =
-        ((zero as u8) << 7) | ((value.get_bit(0) as u8) << 4);
-      this.registers.write_byte(ByteRegister::F, flag);
-      this.write_byte(memory, destination, result);
-    })
+    Operation::from(MicroOp::RotateByteRightThroughCarry { source, destination, unset_zero })
   }
 
   fn shift_byte_left(source: ByteLocation, destination: ByteLocation) -> Operation {
-    Box::new(move |this, memory| {
-      let value = this.read_byte(memory, source);
-      let result = value << 1;
-      let zero = result == 0;
-      let flag =
-        ((zero as u8) << 7) | ((value.get_bit(7) as u8) << 4);
-      this.registers.write_byte(ByteRegister::F, flag);
-      this.write_byte(memory, destination, result);
-    })
+    Operation::from(MicroOp::ShiftByteLeft { source, destination })
   }
 
   fn shift_byte_right(source: ByteLocation, destination: ByteLocation) -> Operation {
-    Box::new(move |this, memory| {
-      let value = this.read_byte(memory, source);
-      let result = value >> 1;
-      let zero = result == 0;
-      let flag =
-        ((zero as u8) << 7) | ((value.get_bit(0) as u8) << 4);
-      this.registers.write_byte(ByteRegister::F, flag);
-      this.write_byte(memory, destination, result);
-    })
+    Operation::from(MicroOp::ShiftByteRight { source, destination })
   }
 
   fn shift_byte_right_arithmetic(source: ByteLocation, destination: ByteLocation) -> Operation {
-    Box::new(move |this, memory| {
-      let value = this.read_byte(memory, source);
-      let result = (value >> 1) | (value & 0x80);
-      let zero = result == 0;
-      let flag =
-        ((zero as u8) << 7) | ((value.get_bit(0) as u8) << 4);
-      this.registers.write_byte(ByteRegister::F, flag);
-      this.write_byte(memory, destination, result);
-    })
+    Operation::from(MicroOp::ShiftByteRightArithmetic { source, destination })
   }
 
   fn swap_byte(source: ByteLocation, destination: ByteLocation) -> Operation {
-    Box::new(move |this, memory| {
-      let value = this.read_byte(memory, source);
-      let result = value.rotate_left(4);
-      let flag = if result == 0 { 0x80u8 } else { 0x00u8 };
-      this.registers.write_byte(ByteRegister::F, flag);
-      this.write_byte(memory, destination, result);
-    })
+    Operation::from(MicroOp::SwapByte { source, destination })
   }
 
   fn increment_word(location: WordLocation) -> Operation {
-    Box::new(move |this, _memory| {
-      let word = this.read_word(location);
-      this.write_word(location, word.wrapping_add(1));
-    })
+    Operation::from(MicroOp::IncrementWord { location })
   }
 
   fn decrement_word(location: WordLocation) -> Operation {
-    Box::new(move |this, _memory| {
-      let word = this.read_word(location);
-      this.write_word(location, word.wrapping_sub(1));
-    })
+    Operation::from(MicroOp::DecrementWord { location })
   }
 
   fn reg_to_reg_ld(&mut self, memory: &mut dyn Memory) {
     CPUImpl::move_byte(
       ByteLocation::Register(ByteRegister::from_r_bits(self.context.opcode.z_bits())),
       ByteLocation::Register(ByteRegister::from_r_bits(self.context.opcode.y_bits())),
-    )(self, memory);
+    ).execute(self, memory);
   }
 
   fn immediate_to_reg_ld(&mut self) {
@@ -730,8 +1219,8 @@ impl CPUImpl {
   }
 
   fn indirect_c_with_offset_to_reg_a_ld(&mut self, memory: &mut dyn Memory) {
-    CPUImpl::move_byte(ByteLocation::Value(0xFF), ByteLocation::UpperAddressBuffer)(self, memory);
-    CPUImpl::move_byte(ByteLocation::Register(ByteRegister::C), ByteLocation::LowerAddressBuffer)(self, memory);
+    CPUImpl::move_byte(ByteLocation::Value(0xFF), ByteLocation::UpperAddressBuffer).execute(self, memory);
+    CPUImpl::move_byte(ByteLocation::Register(ByteRegister::C), ByteLocation::LowerAddressBuffer).execute(self, memory);
     self.operations.push_back(
       CPUImpl::move_byte(
         ByteLocation::MemoryReferencedByAddressBuffer,
@@ -741,8 +1230,8 @@ impl CPUImpl {
   }
 
   fn reg_a_to_indirect_c_ld(&mut self, memory: &mut dyn Memory) {
-    CPUImpl::move_byte(ByteLocation::Value(0xFF), ByteLocation::UpperAddressBuffer)(self, memory);
-    CPUImpl::move_byte(ByteLocation::Register(ByteRegister::C), ByteLocation::LowerAddressBuffer)(self, memory);
+    CPUImpl::move_byte(ByteLocation::Value(0xFF), ByteLocation::UpperAddressBuffer).execute(self, memory);
+    CPUImpl::move_byte(ByteLocation::Register(ByteRegister::C), ByteLocation::LowerAddressBuffer).execute(self, memory);
     self.operations.push_back(
       CPUImpl::move_byte(
         ByteLocation::Register(ByteRegister::A),
@@ -920,7 +1409,7 @@ impl CPUImpl {
     CPUImpl::move_byte(
       ByteLocation::Register(ByteRegister::LowerHL),
       ByteLocation::Register(ByteRegister::LowerSP),
-    )(self, memory);
+    ).execute(self, memory);
     self.operations.push_back(
       CPUImpl::move_byte(
         ByteLocation::Register(ByteRegister::UpperHL),
@@ -974,23 +1463,9 @@ impl CPUImpl {
     );
   }
 
-  // TODO: Do a more thorough check to see if this is correct. There seems to be a lot of confusion surrounding the (half) carry bits
-  fn reg_sp_plus_signed_immediate_to_hl_ld(&mut self, memory: &mut dyn Memory) {
-    CPUImpl::move_byte(
-      ByteLocation::Value(0x00),
-      ByteLocation::Register(ByteRegister::F),
-    )(self, memory);
-    self.operations.push_back(Box::new(|this, memory| {
-      this.context.word_buffer = this.read_next_byte(memory) as i8 as u16;
-    }));
-    self.operations.push_back(
-      CPUImpl::add_words(WordArithmeticParams {
-        first: WordLocation::Register(WordRegister::SP),
-        second: WordLocation::WordBuffer,
-        destination: WordLocation::Register(WordRegister::HL),
-        flag_mask: 0x30,
-      })
-    );
+  fn reg_sp_plus_signed_immediate_to_hl_ld(&mut self) {
+    self.operations.push_back(Operation::from(MicroOp::ReadSignedImmediate));
+    self.operations.push_back(CPUImpl::add_signed_byte_to_reg_sp(WordLocation::Register(WordRegister::HL)));
   }
 
   fn reg_sp_to_immediate_indirect_ld(&mut self) {
@@ -1029,7 +1504,7 @@ impl CPUImpl {
       destination: ByteLocation::Register(ByteRegister::A),
       use_carry,
       flag_mask: 0xF0,
-    })(self, memory);
+    }).execute(self, memory);
   }
 
   fn add_immediate_to_reg_a_and_write_to_reg_a(&mut self, use_carry: bool) {
@@ -1063,7 +1538,7 @@ impl CPUImpl {
       destination: ByteLocation::Register(ByteRegister::A),
       use_carry,
       flag_mask: 0xF0,
-    })(self, memory);
+    }).execute(self, memory);
   }
 
   fn subtract_immediate_from_reg_a_and_write_to_reg_a(&mut self, use_carry: bool) {
@@ -1095,7 +1570,7 @@ impl CPUImpl {
       ByteLocation::Register(ByteRegister::from_r_bits(self.context.opcode.z_bits())),
       ByteLocation::Register(ByteRegister::A),
       ByteLocation::Register(ByteRegister::A),
-    )(self, memory);
+    ).execute(self, memory);
   }
 
   fn and_immediate_with_reg_a_and_write_to_reg_a(&mut self) {
@@ -1123,7 +1598,7 @@ impl CPUImpl {
       ByteLocation::Register(ByteRegister::from_r_bits(self.context.opcode.z_bits())),
       ByteLocation::Register(ByteRegister::A),
       ByteLocation::Register(ByteRegister::A),
-    )(self, memory);
+    ).execute(self, memory);
   }
 
   fn or_immediate_with_reg_a_and_write_to_reg_a(&mut self) {
@@ -1151,7 +1626,7 @@ impl CPUImpl {
       ByteLocation::Register(ByteRegister::from_r_bits(self.context.opcode.z_bits())),
       ByteLocation::Register(ByteRegister::A),
       ByteLocation::Register(ByteRegister::A),
-    )(self, memory);
+    ).execute(self, memory);
   }
 
   fn xor_immediate_with_reg_a_and_write_to_reg_a(&mut self) {
@@ -1181,7 +1656,7 @@ impl CPUImpl {
       destination: ByteLocation::ByteBuffer,
       use_carry: false,
       flag_mask: 0xF0,
-    })(self, memory);
+    }).execute(self, memory);
   }
 
   fn compare_immediate_with_reg_a(&mut self) {
@@ -1216,7 +1691,7 @@ impl CPUImpl {
       destination: ByteLocation::Register(register),
       use_carry: false,
       flag_mask: 0xE0,
-    })(self, memory);
+    }).execute(self, memory);
   }
 
   fn increment_indirect_hl(&mut self) {
@@ -1245,7 +1720,7 @@ impl CPUImpl {
       destination: ByteLocation::Register(register),
       use_carry: false,
       flag_mask: 0xE0,
-    })(self, memory);
+    }).execute(self, memory);
   }
 
   fn decrement_indirect_hl(&mut self) {
@@ -1272,12 +1747,13 @@ impl CPUImpl {
       first: WordLocation::Register(register),
       second: WordLocation::Register(WordRegister::HL),
       destination: WordLocation::WordBuffer,
+      // Z is left untouched. N is included so that it's reset, since add_words never sets bit 6.
       flag_mask: 0x70,
-    })(self, memory);
+    }).execute(self, memory);
     CPUImpl::move_byte(
       ByteLocation::LowerWordBuffer,
       ByteLocation::Register(ByteRegister::LowerHL),
-    )(self, memory);
+    ).execute(self, memory);
     self.operations.push_back(
       CPUImpl::move_byte(
         ByteLocation::UpperWordBuffer,
@@ -1286,19 +1762,11 @@ impl CPUImpl {
     );
   }
 
-  //TODO: Check whether the flags are set correctly
   fn add_immediate_to_reg_sp(&mut self) {
-    self.operations.push_back(Box::new(|this, memory| {
-      this.context.word_buffer = this.read_next_byte(memory) as i8 as u16;
-    }));
+    self.operations.push_back(Operation::from(MicroOp::ReadSignedImmediate));
     self.operations.push_back(
       CPUImpl::combine_operations(
-        CPUImpl::add_words(WordArithmeticParams {
-          first: WordLocation::Register(WordRegister::SP),
-          second: WordLocation::WordBuffer,
-          destination: WordLocation::WordBuffer,
-          flag_mask: 0x30,
-        }),
+        CPUImpl::add_signed_byte_to_reg_sp(WordLocation::WordBuffer),
         CPUImpl::move_byte(
           ByteLocation::LowerWordBuffer,
           ByteLocation::Register(ByteRegister::LowerSP),
@@ -1315,15 +1783,16 @@ impl CPUImpl {
 
   fn increment_reg_pair(&mut self, memory: &mut dyn Memory) {
     let register = WordRegister::from_dd_bits(self.context.opcode.dd_bits());
+    self.record_oam_corruption(self.registers.read_word(register));
     CPUImpl::move_word(
       WordLocation::Register(register),
       WordLocation::WordBuffer,
-    )(self, memory);
-    CPUImpl::increment_word(WordLocation::WordBuffer)(self, memory);
+    ).execute(self, memory);
+    CPUImpl::increment_word(WordLocation::WordBuffer).execute(self, memory);
     CPUImpl::move_byte(
       ByteLocation::LowerWordBuffer,
       ByteLocation::Register(register.get_lower_byte_register()),
-    )(self, memory);
+    ).execute(self, memory);
     self.operations.push_back(
       CPUImpl::move_byte(
         ByteLocation::UpperWordBuffer,
@@ -1334,15 +1803,16 @@ impl CPUImpl {
 
   fn decrement_reg_pair(&mut self, memory: &mut dyn Memory) {
     let register = WordRegister::from_dd_bits(self.context.opcode.dd_bits());
+    self.record_oam_corruption(self.registers.read_word(register));
     CPUImpl::move_word(
       WordLocation::Register(register),
       WordLocation::WordBuffer,
-    )(self, memory);
-    CPUImpl::decrement_word(WordLocation::WordBuffer)(self, memory);
+    ).execute(self, memory);
+    CPUImpl::decrement_word(WordLocation::WordBuffer).execute(self, memory);
     CPUImpl::move_byte(
       ByteLocation::LowerWordBuffer,
       ByteLocation::Register(register.get_lower_byte_register()),
-    )(self, memory);
+    ).execute(self, memory);
     self.operations.push_back(
       CPUImpl::move_byte(
         ByteLocation::UpperWordBuffer,
@@ -1356,7 +1826,7 @@ impl CPUImpl {
       ByteLocation::Register(ByteRegister::A),
       ByteLocation::Register(ByteRegister::A),
       true,
-    )(self, memory);
+    ).execute(self, memory);
   }
 
   fn rotate_reg_left(&mut self, memory: &mut dyn Memory) {
@@ -1365,7 +1835,7 @@ impl CPUImpl {
       ByteLocation::Register(register),
       ByteLocation::Register(register),
       false,
-    )(self, memory);
+    ).execute(self, memory);
   }
 
   fn rotate_indirect_hl_left(&mut self) {
@@ -1389,7 +1859,7 @@ impl CPUImpl {
       ByteLocation::Register(ByteRegister::A),
       ByteLocation::Register(ByteRegister::A),
       true,
-    )(self, memory);
+    ).execute(self, memory);
   }
 
   fn rotate_reg_left_through_carry(&mut self, memory: &mut dyn Memory) {
@@ -1398,7 +1868,7 @@ impl CPUImpl {
       ByteLocation::Register(register),
       ByteLocation::Register(register),
       false,
-    )(self, memory);
+    ).execute(self, memory);
   }
 
   fn rotate_indirect_hl_left_through_carry(&mut self) {
@@ -1422,7 +1892,7 @@ impl CPUImpl {
       ByteLocation::Register(ByteRegister::A),
       ByteLocation::Register(ByteRegister::A),
       true,
-    )(self, memory);
+    ).execute(self, memory);
   }
 
   fn rotate_reg_right(&mut self, memory: &mut dyn Memory) {
@@ -1431,7 +1901,7 @@ impl CPUImpl {
       ByteLocation::Register(register),
       ByteLocation::Register(register),
       false,
-    )(self, memory);
+    ).execute(self, memory);
   }
 
   fn rotate_indirect_hl_right(&mut self) {
@@ -1455,7 +1925,7 @@ impl CPUImpl {
       ByteLocation::Register(ByteRegister::A),
       ByteLocation::Register(ByteRegister::A),
       true,
-    )(self, memory);
+    ).execute(self, memory);
   }
 
   fn rotate_reg_right_through_carry(&mut self, memory: &mut dyn Memory) {
@@ -1464,7 +1934,7 @@ impl CPUImpl {
       ByteLocation::Register(register),
       ByteLocation::Register(register),
       false,
-    )(self, memory);
+    ).execute(self, memory);
   }
 
   fn rotate_indirect_hl_right_through_carry(&mut self) {
@@ -1488,7 +1958,7 @@ impl CPUImpl {
     CPUImpl::shift_byte_left(
       ByteLocation::Register(register),
       ByteLocation::Register(register),
-    )(self, memory);
+    ).execute(self, memory);
   }
 
   fn shift_reg_right(&mut self, memory: &mut dyn Memory) {
@@ -1496,7 +1966,7 @@ impl CPUImpl {
     CPUImpl::shift_byte_right(
       ByteLocation::Register(register),
       ByteLocation::Register(register),
-    )(self, memory);
+    ).execute(self, memory);
   }
 
   fn shift_reg_right_arithmetic(&mut self, memory: &mut dyn Memory) {
@@ -1504,7 +1974,7 @@ impl CPUImpl {
     CPUImpl::shift_byte_right_arithmetic(
       ByteLocation::Register(register),
       ByteLocation::Register(register),
-    )(self, memory);
+    ).execute(self, memory);
   }
 
   fn shift_indirect_hl_left(&mut self) {
@@ -1557,7 +2027,7 @@ impl CPUImpl {
     CPUImpl::swap_byte(
       ByteLocation::Register(register),
       ByteLocation::Register(register),
-    )(self, memory);
+    ).execute(self, memory);
   }
 
   fn swap_indirect_hl(&mut self) {
@@ -1582,12 +2052,7 @@ impl CPUImpl {
   }
 
   fn get_indirect_hl_bit(&mut self) {
-    self.operations.push_back(Box::new(|this, memory| {
-      let address = this.registers.read_word(WordRegister::HL);
-      let value = memory.read(address);
-      let bit = this.context.opcode.y_bits();
-      this.registers.write_byte_masked(ByteRegister::F, u8::compose(&[(!value.get_bit(bit), 7), (false, 6), (true, 5)]), 0xE0);
-    }));
+    self.operations.push_back(Operation::from(MicroOp::TestIndirectHLBit));
   }
 
   fn set_reg_bit(&mut self) {
@@ -1604,15 +2069,7 @@ impl CPUImpl {
         ByteLocation::ByteBuffer,
       )
     );
-    self.operations.push_back(
-      Box::new(|this, memory| {
-        let bit = this.context.opcode.y_bits();
-        CPUImpl::move_byte(
-          ByteLocation::Value(this.context.byte_buffer.set_bit(bit)),
-          ByteLocation::MemoryReferencedByRegister(WordRegister::HL),
-        )(this, memory);
-      })
-    );
+    self.operations.push_back(Operation::from(MicroOp::SetIndirectHLBit));
   }
 
   fn reset_reg_bit(&mut self) {
@@ -1629,15 +2086,7 @@ impl CPUImpl {
         ByteLocation::ByteBuffer,
       )
     );
-    self.operations.push_back(
-      Box::new(|this, memory| {
-        let bit = this.context.opcode.y_bits();
-        CPUImpl::move_byte(
-          ByteLocation::Value(this.context.byte_buffer.reset_bit(bit)),
-          ByteLocation::MemoryReferencedByRegister(WordRegister::HL),
-        )(this, memory);
-      })
-    );
+    self.operations.push_back(Operation::from(MicroOp::ResetIndirectHLBit));
   }
 
   fn jump(&mut self) {
@@ -1702,11 +2151,7 @@ impl CPUImpl {
         ByteLocation::ByteBuffer,
       )
     );
-    self.operations.push_back(
-      Box::new(|this, _memory| {
-        this.registers.write_word(WordRegister::PC, this.registers.read_word(WordRegister::PC).wrapping_add(this.context.byte_buffer as i8 as u16));
-      })
-    );
+    self.operations.push_back(Operation::from(MicroOp::JumpRelative));
   }
 
   fn jump_conditional_relative(&mut self) {
@@ -1717,11 +2162,7 @@ impl CPUImpl {
       )
     );
     if self.satisfies_condition(self.context.opcode) {
-      self.operations.push_back(
-        Box::new(|this, _memory| {
-          this.registers.write_word(WordRegister::PC, this.registers.read_word(WordRegister::PC).wrapping_add(this.context.byte_buffer as i8 as u16));
-        })
-      );
+      self.operations.push_back(Operation::from(MicroOp::JumpRelative));
     }
   }
 
@@ -1729,12 +2170,12 @@ impl CPUImpl {
     CPUImpl::move_word(
       WordLocation::Register(WordRegister::HL),
       WordLocation::Register(WordRegister::PC),
-    )(self, memory);
+    ).execute(self, memory);
   }
 
-  fn call_interrupt_routine(&mut self, interrupt: Interrupt, interrupt_controller: &mut dyn InterruptController) {
-    interrupt_controller.clear_interrupt(interrupt);
-    interrupt_controller.disable_interrupts();
+  fn call_interrupt_routine(&mut self) {
+    self.dispatching_interrupt = true;
+    self.record_call(self.registers.read_word(WordRegister::PC));
     self.operations.push_back(CPUImpl::noop());
     self.operations.push_back(
       CPUImpl::combine_operations(
@@ -1745,24 +2186,35 @@ impl CPUImpl {
         ),
       )
     );
-    self.operations.push_back(
-      CPUImpl::combine_operations(
-        CPUImpl::decrement_word(WordLocation::Register(WordRegister::SP)),
-        CPUImpl::move_byte(
-          ByteLocation::Register(ByteRegister::LowerPC),
-          ByteLocation::MemoryReferencedByRegister(WordRegister::SP),
-        ),
-      )
-    );
+  }
+
+  /// The interrupt to service is only selected after the upper byte of PC has been pushed.
+  /// If that push overwrote IE (SP = 0x0000) and no enabled interrupt remains, the dispatch is cancelled and execution
+  /// continues at 0x0000 without clearing any IF bit.
+  fn select_interrupt_routine(&mut self, memory: &mut dyn Memory, interrupt_controller: &mut dyn InterruptController) {
+    self.dispatching_interrupt = false;
+    let optional_interrupt = interrupt_controller.get_requested_interrupt();
+    interrupt_controller.disable_interrupts();
+    if let Some(interrupt) = optional_interrupt {
+      interrupt_controller.clear_interrupt(interrupt);
+    }
+    CPUImpl::combine_operations(
+      CPUImpl::decrement_word(WordLocation::Register(WordRegister::SP)),
+      CPUImpl::move_byte(
+        ByteLocation::Register(ByteRegister::LowerPC),
+        ByteLocation::MemoryReferencedByRegister(WordRegister::SP),
+      ),
+    ).execute(self, memory);
     self.operations.push_back(
       CPUImpl::move_word(
-        WordLocation::Value(interrupt.get_routine_address()),
+        WordLocation::Value(optional_interrupt.map_or(0x0000, |interrupt| interrupt.get_routine_address())),
         WordLocation::Register(WordRegister::PC),
       )
     );
   }
 
   fn call(&mut self) {
+    self.record_call(self.registers.read_word(WordRegister::PC).wrapping_sub(1));
     self.operations.push_back(
       CPUImpl::move_byte(
         ByteLocation::NextMemoryByte,
@@ -1815,6 +2267,7 @@ impl CPUImpl {
       )
     );
     if self.satisfies_condition(self.context.opcode) {
+      self.record_call(self.registers.read_word(WordRegister::PC).wrapping_sub(1));
       self.operations.push_back(
         CPUImpl::combine_operations(
           CPUImpl::decrement_word(WordLocation::Register(WordRegister::SP)),
@@ -1843,6 +2296,16 @@ impl CPUImpl {
   }
 
   fn return_from_call(&mut self) {
+    self.pop_return_address();
+    self.operations.push_back(
+      CPUImpl::move_word(
+        WordLocation::WordBuffer,
+        WordLocation::Register(WordRegister::PC),
+      )
+    );
+  }
+
+  fn pop_return_address(&mut self) {
     self.operations.push_back(
       CPUImpl::combine_operations(
         CPUImpl::move_byte(
@@ -1861,27 +2324,27 @@ impl CPUImpl {
         CPUImpl::increment_word(WordLocation::Register(WordRegister::SP)),
       )
     );
+  }
+
+  /// Unlike EI, RETI sets IME in its last cycle, so a pending interrupt is dispatched right after it.
+  fn return_from_interrupt(&mut self) {
+    self.pop_return_address();
     self.operations.push_back(
-      CPUImpl::move_word(
-        WordLocation::WordBuffer,
-        WordLocation::Register(WordRegister::PC),
+      CPUImpl::combine_operations(
+        CPUImpl::move_word(
+          WordLocation::WordBuffer,
+          WordLocation::Register(WordRegister::PC),
+        ),
+        Operation::from(MicroOp::EnableInterrupts),
       )
     );
   }
 
-  fn return_from_interrupt(&mut self, interrupt_controller: &mut dyn InterruptController) {
-    self.return_from_call();
-    self.enable_interrupts(interrupt_controller);
-  }
-
   fn return_conditionally(&mut self) {
-    self.operations.push_back(
-      Box::new(|this, _memory| {
-        if this.satisfies_condition(this.context.opcode) {
-          this.return_from_call();
-        }
-      })
-    );
+    self.operations.push_back(CPUImpl::noop());
+    if self.satisfies_condition(self.context.opcode) {
+      self.return_from_call();
+    }
   }
 
   fn restart(&mut self) {
@@ -1896,6 +2359,7 @@ impl CPUImpl {
       7 => 0x0038u16,
       _ => panic!("{} is not a valid restart code", self.context.opcode.y_bits())
     };
+    self.record_call(self.registers.read_word(WordRegister::PC).wrapping_sub(1));
     self.operations.push_back(
       CPUImpl::combine_operations(
         CPUImpl::decrement_word(WordLocation::Register(WordRegister::SP)),
@@ -1937,7 +2401,7 @@ impl CPUImpl {
         destination: ByteLocation::Register(ByteRegister::A),
         use_carry: false,
         flag_mask: 0xB0,
-      })(self, memory);
+      }).execute(self, memory);
     } else {
       let lower = if half_carry || ((a & 0x0F) >= 0x0A) { 6u8 } else { 0u8 };
       let upper = if carry || (a > 0x99) { 0x60u8 } else { 0u8 };
@@ -1947,7 +2411,7 @@ impl CPUImpl {
         destination: ByteLocation::Register(ByteRegister::A),
         use_carry: false,
         flag_mask: 0xB0,
-      })(self, memory);
+      }).execute(self, memory);
     };
     if carry {
       self.registers.write_byte_masked(ByteRegister::F, 0x10, 0x30);
@@ -1970,29 +2434,135 @@ impl CPUImpl {
   }
 
   fn disable_interrupts(&mut self, interrupt_controller: &mut dyn InterruptController) {
+    self.enable_interrupts_before_instruction = false;
     interrupt_controller.disable_interrupts();
   }
 
-  fn enable_interrupts(&mut self, interrupt_controller: &mut dyn InterruptController) {
-    interrupt_controller.enable_interrupts();
+  /// IME is only set once the next instruction starts, so no interrupt is dispatched before that instruction finishes,
+  /// and a DI right after EI keeps interrupts disabled throughout.
+  fn enable_interrupts(&mut self) {
+    self.enable_interrupts_before_instruction = true;
+  }
+
+  /// If IME is disabled and an interrupt is already pending, HALT doesn't suspend the CPU,
+  /// but PC fails to increment after the next byte is read, so that byte is read twice.
+  fn halt(&mut self, interrupt_controller: &mut dyn InterruptController) {
+    if !interrupt_controller.interrupts_enabled() && interrupt_controller.interrupt_pending() {
+      self.halt_bug = true;
+    } else {
+      self.halted = true;
+    }
   }
 
-  fn halt(&mut self) {
-    //TODO: Implement halt
+  fn lock(&mut self) {
+    self.locked = true;
+    #[cfg(target_arch = "wasm32")]
+    web_sys::console::warn_1(&format!("CPU locked up on illegal opcode {:#04x}", self.context.opcode.0).into());
   }
 
-  fn stop(&mut self) {
-    // TODO: Implement stop
+  /// STOP is followed by a byte that is skipped.
+  fn stop(&mut self, memory: &mut dyn Memory) {
+    self.read_next_byte(memory);
+    self.stopped = true;
+    self.stop_executed = true;
   }
 }
 
-#[cfg(test)]
-pub mod test {
-  use assert_hex::assert_eq_hex;
-  use super::*;
-  use crate::memory::memory::test::MockMemory;
-  use test_case::test_case;
-  use crate::cpu::interrupts::InterruptControllerImpl;
+pub mod checks {
+  use crate::cpu::interrupts::{Interrupt, InterruptController, InterruptControllerImpl};
+  use crate::cpu::register::{ByteRegister, WordRegister};
+  use crate::memory::linear_memory::LinearMemory;
+  use crate::memory::memory::Memory;
+  use crate::util::bit_util::BitUtil;
+  use super::CPUImpl;
+
+  fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+  }
+
+  fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+  }
+
+  /// Runs the opcode on A and D, followed by DAA, for every pair of two digit BCD operands, and compares A and the Z, H
+  /// and C flags with the decimal result and carry.
+  fn decimal_adjust_reg_a(opcode: u8, expected: fn(u8, u8) -> (u8, bool)) -> bool {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = Box::new(LinearMemory::<0x10000, 0>::new());
+    let mut instruction_index = 0u16;
+    (0u8..99u8).all(|x| {
+      (0u8..99u8).all(|y| {
+        let (result, carry) = expected(x, y);
+        cpu.registers.write_byte(ByteRegister::A, to_bcd(x));
+        cpu.registers.write_byte(ByteRegister::D, to_bcd(y));
+        memory.write(instruction_index, opcode);
+        memory.write(instruction_index + 1, 0x27);
+        instruction_index += 2;
+        cpu.tick(memory.as_mut(), &mut interrupt_controller);
+        cpu.tick(memory.as_mut(), &mut interrupt_controller);
+        from_bcd(cpu.registers.read_byte(ByteRegister::A)) == result &&
+          cpu.registers.read_byte(ByteRegister::F) & 0xB0 == u8::compose(&[(result == 0, 7), (carry, 4)])
+      })
+    })
+  }
+
+  pub fn decimal_adjust_after_addition() -> bool {
+    // ADD A,D
+    decimal_adjust_reg_a(0x82, |x, y| ((x + y) % 100, x + y >= 100))
+  }
+
+  pub fn decimal_adjust_after_subtraction() -> bool {
+    // SUB A,D
+    decimal_adjust_reg_a(0x92, |x, y| ((100 + x - y) % 100, x < y))
+  }
+
+  /// HALT with IME disabled and an interrupt pending fails to increment PC, so the next byte is read twice.
+  pub fn halt_bug_reads_next_byte_twice() -> bool {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = Box::new(LinearMemory::<0x10000, 0>::new());
+    interrupt_controller.write(0xFFFF, 0x04);
+    interrupt_controller.request_interrupt(Interrupt::TimerOverflow);
+    // HALT; INC A
+    memory.write(0x0000, 0x76);
+    memory.write(0x0001, 0x3C);
+    cpu.ticks(memory.as_mut(), &mut interrupt_controller, 3);
+    !cpu.halted() &&
+      cpu.registers.read_byte(ByteRegister::A) == 0x02 &&
+      cpu.registers.read_word(WordRegister::PC) == 0x0002
+  }
+
+  /// EI only takes effect after the instruction following it, so the first INC A runs before the interrupt is serviced.
+  pub fn enable_interrupts_takes_effect_after_next_instruction() -> bool {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = Box::new(LinearMemory::<0x10000, 0>::new());
+    cpu.registers.write_word(WordRegister::SP, 0xFFFE);
+    interrupt_controller.write(0xFFFF, 0x01);
+    interrupt_controller.request_interrupt(Interrupt::VerticalBlank);
+    // EI; INC A; INC A
+    [0xFB, 0x3C, 0x3C].iter().enumerate()
+      .for_each(|(address, value)| memory.write(address as u16, *value));
+    cpu.step_instruction(memory.as_mut(), &mut interrupt_controller);
+    cpu.step_instruction(memory.as_mut(), &mut interrupt_controller);
+    let after_first_increment = cpu.registers.read_byte(ByteRegister::A);
+    let dispatch_cycles = cpu.step_instruction(memory.as_mut(), &mut interrupt_controller);
+    after_first_increment == 0x01 && dispatch_cycles == 5 &&
+      cpu.registers.read_word(WordRegister::PC) == 0x0040 &&
+      cpu.registers.read_byte(ByteRegister::A) == 0x01
+  }
+}
+
+#[cfg(test)]
+pub mod test {
+  use assert_hex::assert_eq_hex;
+  use super::*;
+  use crate::memory::memory::test::MockMemory;
+  use crate::controllers::timer::{TimerController, TimerControllerImpl};
+  use test_case::test_case;
+  use crate::cpu::interrupts::{Interrupt, InterruptControllerImpl};
+  use crate::cpu::sm83_json::Json;
 
   #[test]
   fn reg_to_reg_ld() {
@@ -2294,8 +2864,12 @@ pub mod test {
   }
 
   #[test_case(0x0FF8, 0x07, 0x00; "no flags")]
-  #[test_case(0x0FF8, 0x08, 0x20; "only half carry")]
+  #[test_case(0x0008, 0x08, 0x20; "only half carry")]
+  #[test_case(0x0FF8, 0x08, 0x30; "both carry flags from lower byte")]
   #[test_case(0xFFF8, 0x08, 0x30; "both carry flags")]
+  #[test_case(0x0000, 0xFF, 0x00; "negative offset without carries")]
+  #[test_case(0x0001, 0xFF, 0x30; "negative offset with carries")]
+  #[test_case(0x0F00, 0x80, 0x00; "upper byte borrow doesn't affect flags")]
   fn reg_sp_plus_signed_immediate_to_hl_ld_writes_correct_flags(sp: u16, e: u8, f: u8) {
     let mut cpu = CPUImpl::new();
     let mut interrupt_controller = InterruptControllerImpl::new();
@@ -2759,6 +3333,12 @@ pub mod test {
 
   #[test_case(0xF01E, 0xF028, 0xE046, 0x80, 0x90; "carry set correctly and zero flag not affected")]
   #[test_case(0x1E1E, 0x2828, 0x4646, 0x80, 0xA0; "half carry set correctly")]
+  #[test_case(0x0000, 0x0000, 0x0000, 0xF0, 0x80; "zero result with all flags set keeps only zero flag")]
+  #[test_case(0x8000, 0x8000, 0x0000, 0x00, 0x10; "zero result doesn't set zero flag")]
+  #[test_case(0x0FFF, 0x0001, 0x1000, 0xF0, 0xA0; "half carry from bit 11 with all flags set")]
+  #[test_case(0x00FF, 0x0001, 0x0100, 0x00, 0x00; "carry from bit 7 doesn't set half carry")]
+  #[test_case(0xFFFF, 0x0001, 0x0000, 0x00, 0x30; "both carries with no flags set")]
+  #[test_case(0x0001, 0x0001, 0x0002, 0x70, 0x00; "subtract flag is reset")]
   fn add_reg_pair_to_reg_hl(hl: u16, value: u16, result: u16, f_old: u8, f_new: u8) {
     let mut cpu = CPUImpl::new();
     let mut interrupt_controller = InterruptControllerImpl::new();
@@ -2773,7 +3353,10 @@ pub mod test {
   }
 
   #[test_case(0xFFDA, 0x26, 0x0000, 0x30; "carry set correctly and zero flag set to zero")]
-  #[test_case(0x0FDA, 0x26, 0x1000, 0x20; "half carry set correctly")]
+  #[test_case(0x0F0A, 0x06, 0x0F10, 0x20; "half carry set correctly")]
+  #[test_case(0x0FDA, 0x26, 0x1000, 0x30; "carry from lower byte only")]
+  #[test_case(0x0000, 0xFF, 0xFFFF, 0x00; "negative offset without carries")]
+  #[test_case(0x0005, 0xFD, 0x0002, 0x30; "negative offset with carries")]
   fn add_immediate_to_reg_sp(sp: u16, value: u8, result: u16, f: u8) {
     let mut cpu = CPUImpl::new();
     let mut interrupt_controller = InterruptControllerImpl::new();
@@ -2800,6 +3383,34 @@ pub mod test {
     assert_eq!(cpu.registers.read_byte(ByteRegister::F), 0xF0);
   }
 
+  // INC HL, DEC HL and LD A,(HL+)
+  #[test_case(0x23, 0xFE10, Some(OAMCorruption::Write); "increment in oam")]
+  #[test_case(0x2B, 0xFEFF, Some(OAMCorruption::Write); "decrement in oam")]
+  #[test_case(0x23, 0xFF00, None; "increment above oam")]
+  #[test_case(0x2B, 0xFDFF, None; "decrement below oam")]
+  #[test_case(0x2A, 0xFE10, Some(OAMCorruption::Read); "increment while reading")]
+  fn word_increment_in_oam_range_records_corruption(opcode: u8, hl: u16, corruption: Option<OAMCorruption>) {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    cpu.registers.write_word(WordRegister::HL, hl);
+    memory.write(0x0000, opcode);
+    cpu.ticks(&mut memory, &mut interrupt_controller, 2);
+    assert_eq!(cpu.take_oam_corruption(), corruption);
+    assert_eq!(cpu.take_oam_corruption(), None);
+  }
+
+  #[test]
+  fn pop_in_oam_range_records_read_corruption() {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    cpu.registers.write_word(WordRegister::SP, 0xFE20);
+    memory.write(0x0000, 0xD1);
+    cpu.ticks(&mut memory, &mut interrupt_controller, 3);
+    assert_eq!(cpu.take_oam_corruption(), Some(OAMCorruption::Read));
+  }
+
   #[test_case(0x0000, 0xFFFF; "performs wrapping correctly")]
   #[test_case(0x0FDA, 0x0FD9; "decrements correctly")]
   fn decrement_reg_pair(sp: u16, result: u16) {
@@ -3375,6 +3986,34 @@ pub mod test {
     assert_eq!(cpu.registers.read_word(WordRegister::SP), 0xFFFE);
   }
 
+  #[test]
+  fn return_from_interrupt_dispatches_pending_interrupt_once_after_returning() {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    cpu.registers.write_word(WordRegister::SP, 0xFFFC);
+    memory.write(0xFFFC, 0x34);
+    memory.write(0xFFFD, 0x12);
+    interrupt_controller.write(0xFFFF, 0x01);
+    interrupt_controller.request_interrupt(Interrupt::VerticalBlank);
+    // RETI
+    memory.write(0x0000, 0xD9);
+    for _ in 0..3 {
+      cpu.tick(&mut memory, &mut interrupt_controller);
+      assert!(!interrupt_controller.interrupts_enabled());
+    }
+    cpu.tick(&mut memory, &mut interrupt_controller);
+    assert!(interrupt_controller.interrupts_enabled());
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x1234);
+    assert_eq!(cpu.step_instruction(&mut memory, &mut interrupt_controller), 5);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0040);
+    assert_eq_hex!(memory.read(0xFFFD), 0x12);
+    assert_eq_hex!(memory.read(0xFFFC), 0x34);
+    assert!(interrupt_controller.get_requested_interrupt().is_none());
+    assert_eq!(cpu.step_instruction(&mut memory, &mut interrupt_controller), 1);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0041);
+  }
+
   #[test_case(0x00, 0x70; "returns when zero flag not set")]
   #[test_case(0x01, 0x80; "returns when zero flag set")]
   #[test_case(0x02, 0xE0; "returns when carry not set")]
@@ -3428,49 +4067,8 @@ pub mod test {
 
   #[test]
   fn decimal_adjust_reg_a() {
-    let mut cpu = CPUImpl::new();
-    let mut interrupt_controller = InterruptControllerImpl::new();
-    let mut memory = MockMemory::new(0x10000);
-    let mut instruction_index = 0u16;
-    (0u8..99u8).for_each(|x| {
-      (0u8..99u8).for_each(|y| {
-        let sum = x + y;
-        let difference = 100 + x - y;
-        let a = (x % 10) | ((x / 10) << 4);
-        let d = (y % 10) | ((y / 10) << 4);
-        let f = u8::compose(&[(sum % 100 == 0, 7), (sum >= 100, 4)]);
-        if a == 0x1 && d == 0x9 {
-          println!("Invalid result. A: {:#x}, D: {:#x}, F: {:#x}", a, d, f);
-        }
-
-        cpu.registers.write_byte(ByteRegister::A, a);
-        cpu.registers.write_byte(ByteRegister::D, d);
-        memory.write(instruction_index, 0x82);
-        instruction_index += 1;
-        cpu.tick(&mut memory, &mut interrupt_controller);
-        memory.write(instruction_index, 0x27);
-        instruction_index += 1;
-        cpu.tick(&mut memory, &mut interrupt_controller);
-        let result_bcd_sum = cpu.registers.read_byte(ByteRegister::A);
-        let result_decimal_sum = ((result_bcd_sum & 0xF0) >> 4) * 10 + (result_bcd_sum & 0x0F);
-        assert_eq!(result_decimal_sum, sum % 100);
-        assert_eq_hex!(cpu.registers.read_byte(ByteRegister::F) & 0xB0, f);
-
-        cpu.registers.write_byte(ByteRegister::A, a);
-        cpu.registers.write_byte(ByteRegister::D, d);
-        memory.write(instruction_index, 0x92);
-        instruction_index += 1;
-        cpu.tick(&mut memory, &mut interrupt_controller);
-        memory.write(instruction_index, 0x27);
-        instruction_index += 1;
-        cpu.tick(&mut memory, &mut interrupt_controller);
-        let result_bcd_diff = cpu.registers.read_byte(ByteRegister::A);
-        let result_decimal_diff = ((result_bcd_diff & 0xF0) >> 4) * 10 + (result_bcd_diff & 0x0F);
-        let f = u8::compose(&[(difference % 100 == 0, 7), (difference < 100, 4)]);
-        assert_eq_hex!(cpu.registers.read_byte(ByteRegister::F) & 0xB0, f);
-        assert_eq!(result_decimal_diff, difference % 100);
-      })
-    })
+    assert!(checks::decimal_adjust_after_addition());
+    assert!(checks::decimal_adjust_after_subtraction());
   }
 
   #[test]
@@ -3523,6 +4121,775 @@ pub mod test {
     cpu.tick(&mut memory, &mut interrupt_controller);
     assert_eq!(interrupt_controller.interrupts_enabled(), false);
     cpu.tick(&mut memory, &mut interrupt_controller);
+    assert!(!interrupt_controller.interrupts_enabled());
+    cpu.tick(&mut memory, &mut interrupt_controller);
     assert_eq!(interrupt_controller.interrupts_enabled(), true);
   }
+
+  #[test]
+  fn enable_interrupts_takes_effect_after_next_instruction() {
+    assert!(checks::enable_interrupts_takes_effect_after_next_instruction());
+  }
+
+  #[test]
+  fn disable_interrupts_right_after_enable_cancels_it() {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    cpu.registers.write_word(WordRegister::SP, 0xFFFE);
+    interrupt_controller.write(0xFFFF, 0x01);
+    interrupt_controller.request_interrupt(Interrupt::VerticalBlank);
+    // EI; DI; NOP x10
+    memory.write(0x0000, 0xFB);
+    memory.write(0x0001, 0xF3);
+    for _ in 0..12 {
+      cpu.step_instruction(&mut memory, &mut interrupt_controller);
+      assert!(!interrupt_controller.interrupts_enabled());
+    }
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x000C);
+    assert_eq_hex!(interrupt_controller.read(0xFF0F) & 0x01, 0x01);
+  }
+
+  struct InterruptMappedMemory {
+    memory: MockMemory,
+    interrupt_controller: InterruptControllerRef,
+  }
+
+  impl Memory for InterruptMappedMemory {
+    fn read(&self, address: u16) -> u8 {
+      match address {
+        0xFF0F | 0xFFFF => self.interrupt_controller.read(address),
+        _ => self.memory.read(address)
+      }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+      match address {
+        0xFF0F | 0xFFFF => self.interrupt_controller.write(address, value),
+        _ => self.memory.write(address, value)
+      }
+    }
+  }
+
+  #[test_case(0x0400, 0x0000, 0x04, 0x04, 0x0050, 0x00; "upper PC byte keeps interrupt enabled")]
+  #[test_case(0x0023, 0x0000, 0x04, 0x04, 0x0000, 0x04; "upper PC byte disables interrupt")]
+  #[test_case(0x0423, 0x0000, 0x05, 0x05, 0x0050, 0x01; "upper PC byte disables higher priority interrupt")]
+  #[test_case(0x0023, 0x0001, 0x04, 0x04, 0x0050, 0x00; "lower PC byte overwrites IE after selection")]
+  fn interrupt_dispatch_ie_push(pc: u16, sp: u16, interrupt_enable: u8, interrupt_request: u8, expected_pc: u16, expected_interrupt_request: u8) {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller: InterruptControllerRef = Rc::new(RefCell::new(InterruptControllerImpl::new()));
+    let mut memory = InterruptMappedMemory {
+      memory: MockMemory::new(0x10000),
+      interrupt_controller: Rc::clone(&interrupt_controller),
+    };
+    interrupt_controller.enable_interrupts();
+    interrupt_controller.write(0xFFFF, interrupt_enable);
+    interrupt_controller.write(0xFF0F, interrupt_request);
+    cpu.registers.write_word(WordRegister::PC, pc);
+    cpu.registers.write_word(WordRegister::SP, sp);
+    cpu.ticks(&mut memory, &mut interrupt_controller, 5);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), expected_pc);
+    assert_eq_hex!(interrupt_controller.read(0xFF0F) & 0x1F, expected_interrupt_request);
+    assert!(!interrupt_controller.interrupts_enabled());
+  }
+
+  #[test]
+  fn interrupt_dispatch_pushes_pc_and_jumps_to_vector() {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    interrupt_controller.enable_interrupts();
+    interrupt_controller.write(0xFFFF, 0x1F);
+    interrupt_controller.request_interrupt(Interrupt::Stat);
+    interrupt_controller.request_interrupt(Interrupt::ButtonPressed);
+    cpu.registers.write_word(WordRegister::PC, 0x1234);
+    cpu.registers.write_word(WordRegister::SP, 0xFFFE);
+    cpu.ticks(&mut memory, &mut interrupt_controller, 4);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x1234);
+    cpu.tick(&mut memory, &mut interrupt_controller);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0048);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::SP), 0xFFFC);
+    assert_eq_hex!(memory.read(0xFFFD), 0x12);
+    assert_eq_hex!(memory.read(0xFFFC), 0x34);
+    assert_eq_hex!(interrupt_controller.read(0xFF0F) & 0x1F, 0x10);
+  }
+
+  #[test_case(Interrupt::VerticalBlank, 0x0040)]
+  #[test_case(Interrupt::Stat, 0x0048)]
+  #[test_case(Interrupt::TimerOverflow, 0x0050)]
+  #[test_case(Interrupt::SerialIOComplete, 0x0058)]
+  #[test_case(Interrupt::ButtonPressed, 0x0060)]
+  fn interrupt_dispatch_takes_vector(interrupt: Interrupt, vector: u16) {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    interrupt_controller.enable_interrupts();
+    interrupt_controller.write(0xFFFF, 0x1F);
+    interrupt_controller.request_interrupt(interrupt);
+    cpu.registers.write_word(WordRegister::SP, 0xFFFE);
+    cpu.ticks(&mut memory, &mut interrupt_controller, 5);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), vector);
+    assert_eq_hex!(interrupt_controller.read(0xFF0F) & 0x1F, 0x00);
+  }
+
+  #[test]
+  fn lower_priority_interrupt_waits_until_ime_is_set_again() {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    interrupt_controller.enable_interrupts();
+    interrupt_controller.write(0xFFFF, 0x1F);
+    interrupt_controller.request_interrupt(Interrupt::TimerOverflow);
+    interrupt_controller.request_interrupt(Interrupt::VerticalBlank);
+    cpu.registers.write_word(WordRegister::PC, 0x1234);
+    cpu.registers.write_word(WordRegister::SP, 0xFFFE);
+    // RETI
+    memory.write(0x0040, 0xD9);
+    cpu.ticks(&mut memory, &mut interrupt_controller, 5);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0040);
+    assert_eq_hex!(interrupt_controller.read(0xFF0F) & 0x1F, 0x04);
+    cpu.ticks(&mut memory, &mut interrupt_controller, 4);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x1234);
+    assert!(interrupt_controller.interrupts_enabled());
+    cpu.ticks(&mut memory, &mut interrupt_controller, 5);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0050);
+    assert_eq_hex!(memory.read(0xFFFD), 0x12);
+    assert_eq_hex!(memory.read(0xFFFC), 0x34);
+    assert_eq_hex!(interrupt_controller.read(0xFF0F) & 0x1F, 0x00);
+  }
+
+  #[test]
+  fn halt_waits_for_timer_interrupt() {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut timer = TimerControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    interrupt_controller.enable_interrupts();
+    interrupt_controller.write(0xFFFF, 0x04);
+    cpu.registers.write_word(WordRegister::SP, 0xFFFE);
+    memory.write(0x0000, 0x76);
+    cpu.ticks(&mut memory, &mut interrupt_controller, 10);
+    assert!(cpu.halted());
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0001);
+    timer.write(0xFF05, 0xFF);
+    timer.write(0xFF07, 0x05);
+    let mut cycles = 0;
+    while !interrupt_controller.interrupt_pending() {
+      assert!(!cpu.tick(&mut memory, &mut interrupt_controller));
+      timer.tick(&mut interrupt_controller);
+      cycles += 1;
+    }
+    assert_eq!(cycles, 4);
+    assert!(cpu.halted());
+    cpu.ticks(&mut memory, &mut interrupt_controller, 5);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0050);
+    assert_eq_hex!(memory.read(0xFFFC), 0x01);
+  }
+
+  #[test]
+  fn halt_wakes_on_joypad_interrupt_with_interrupts_disabled() {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    interrupt_controller.write(0xFFFF, 0x10);
+    memory.write(0x0000, 0x76);
+    memory.write(0x0001, 0x3C);
+    cpu.ticks(&mut memory, &mut interrupt_controller, 10);
+    assert!(cpu.halted());
+    assert_eq_hex!(cpu.registers.read_byte(ByteRegister::A), 0x00);
+    interrupt_controller.request_interrupt(Interrupt::ButtonPressed);
+    cpu.tick(&mut memory, &mut interrupt_controller);
+    assert!(!cpu.halted());
+    assert_eq_hex!(cpu.registers.read_byte(ByteRegister::A), 0x01);
+    assert_eq_hex!(interrupt_controller.read(0xFF0F) & 0x1F, 0x10);
+  }
+
+  #[test]
+  fn halt_ignores_interrupts_that_are_not_enabled() {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    interrupt_controller.enable_interrupts();
+    interrupt_controller.write(0xFFFF, 0x04);
+    interrupt_controller.request_interrupt(Interrupt::VerticalBlank);
+    memory.write(0x0000, 0x76);
+    cpu.ticks(&mut memory, &mut interrupt_controller, 10);
+    assert!(cpu.halted());
+  }
+
+  #[test]
+  fn halt_bug_reads_next_byte_twice() {
+    assert!(checks::halt_bug_reads_next_byte_twice());
+  }
+
+  #[test]
+  fn profiler_counts_the_byte_read_twice_by_the_halt_bug_at_its_address() {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    interrupt_controller.write(0xFFFF, 0x04);
+    interrupt_controller.request_interrupt(Interrupt::TimerOverflow);
+    // HALT at the end of the first 4KB region, INC A at the start of the second
+    memory.write(0x0FFF, 0x76);
+    memory.write(0x1000, 0x3C);
+    cpu.registers.write_word(WordRegister::PC, 0x0FFF);
+    cpu.enable_profiler();
+    cpu.ticks(&mut memory, &mut interrupt_controller, 3);
+    let report = cpu.profiler().unwrap().report();
+    assert!(report.contains("\n1000-1FFF  2\n"));
+    assert!(report.contains("\n0000-0FFF  1\n"));
+  }
+
+  #[test_case(true; "interrupts enabled")]
+  #[test_case(false; "interrupts disabled")]
+  fn halt_without_pending_interrupt_reads_next_byte_once(interrupts_enabled: bool) {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    if interrupts_enabled {
+      interrupt_controller.enable_interrupts();
+    }
+    interrupt_controller.write(0xFFFF, 0x04);
+    cpu.registers.write_word(WordRegister::SP, 0xFFFE);
+    memory.write(0x0000, 0x76);
+    memory.write(0x0001, 0x3C);
+    memory.write(0x0050, 0xD9);
+    cpu.tick(&mut memory, &mut interrupt_controller);
+    assert!(cpu.halted());
+    interrupt_controller.request_interrupt(Interrupt::TimerOverflow);
+    // Wake up, dispatch if enabled and return with RETI
+    cpu.ticks(&mut memory, &mut interrupt_controller, if interrupts_enabled { 10 } else { 1 });
+    assert_eq_hex!(cpu.registers.read_byte(ByteRegister::A), 0x01);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0002);
+  }
+
+  #[test]
+  fn stop_skips_next_byte() {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    memory.write(0x0000, 0x10);
+    memory.write(0x0001, 0x3C);
+    cpu.tick(&mut memory, &mut interrupt_controller);
+    assert!(cpu.take_stop());
+    assert!(!cpu.take_stop());
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0002);
+    assert_eq_hex!(cpu.registers.read_byte(ByteRegister::A), 0x00);
+  }
+
+  #[test]
+  fn stop_suspends_cpu_until_left() {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    interrupt_controller.enable_interrupts();
+    interrupt_controller.write(0xFFFF, 0x04);
+    // STOP; INC A
+    memory.write(0x0000, 0x10);
+    memory.write(0x0002, 0x3C);
+    cpu.tick(&mut memory, &mut interrupt_controller);
+    // Unlike HALT, STOP isn't left when an interrupt is requested
+    interrupt_controller.request_interrupt(Interrupt::TimerOverflow);
+    cpu.ticks(&mut memory, &mut interrupt_controller, 10);
+    let info = cpu.info(&interrupt_controller);
+    assert!(info.stopped);
+    assert_eq!(info.cycles, 11);
+    assert_eq_hex!(info.pc, 0x0002);
+    interrupt_controller.clear_interrupt(Interrupt::TimerOverflow);
+    cpu.leave_stop();
+    cpu.tick(&mut memory, &mut interrupt_controller);
+    assert!(!cpu.info(&interrupt_controller).stopped);
+    assert_eq_hex!(cpu.registers.read_byte(ByteRegister::A), 0x01);
+  }
+
+  #[test_case(0xD3)]
+  #[test_case(0xDB)]
+  #[test_case(0xDD)]
+  #[test_case(0xE3)]
+  #[test_case(0xE4)]
+  #[test_case(0xEB)]
+  #[test_case(0xEC)]
+  #[test_case(0xED)]
+  #[test_case(0xF4)]
+  #[test_case(0xFC)]
+  #[test_case(0xFD)]
+  fn illegal_opcode_locks_cpu(opcode: u8) {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    interrupt_controller.enable_interrupts();
+    interrupt_controller.write(0xFFFF, 0x1F);
+    memory.write(0x0000, opcode);
+    cpu.tick(&mut memory, &mut interrupt_controller);
+    assert!(cpu.locked());
+    interrupt_controller.request_interrupt(Interrupt::VerticalBlank);
+    (0..10).for_each(|_| assert!(!cpu.tick(&mut memory, &mut interrupt_controller)));
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0001);
+  }
+
+  #[test]
+  fn pop_af_clears_lower_flag_bits() {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    cpu.registers.write_word(WordRegister::SP, 0xFFFE);
+    cpu.registers.write_word(WordRegister::BC, 0x12FF);
+    // PUSH BC; POP AF
+    memory.write(0x0000, 0xC5);
+    memory.write(0x0001, 0xF1);
+    cpu.ticks(&mut memory, &mut interrupt_controller, 7);
+    assert_eq_hex!(cpu.registers.read_byte(ByteRegister::A), 0x12);
+    assert_eq_hex!(cpu.registers.read_byte(ByteRegister::F), 0xF0);
+  }
+
+  #[test]
+  fn af_round_trip_through_stack_keeps_lower_flag_bits_clear() {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    cpu.registers.write_word(WordRegister::SP, 0xFFFC);
+    memory.write(0xFFFC, 0xFF);
+    memory.write(0xFFFD, 0x12);
+    // POP AF; PUSH AF
+    memory.write(0x0000, 0xF1);
+    memory.write(0x0001, 0xF5);
+    cpu.ticks(&mut memory, &mut interrupt_controller, 3);
+    assert_eq_hex!(cpu.registers.read_byte(ByteRegister::F), 0xF0);
+    cpu.ticks(&mut memory, &mut interrupt_controller, 4);
+    assert_eq_hex!(memory.read(0xFFFC), 0xF0);
+    assert_eq_hex!(memory.read(0xFFFD), 0x12);
+  }
+
+  #[test]
+  fn set_register_writes_registers_by_name() {
+    let mut cpu = CPUImpl::new();
+    assert_eq!(cpu.set_register("PC", 0x1234), Ok(()));
+    assert_eq!(cpu.set_register("af", 0x12FF), Ok(()));
+    assert_eq!(cpu.set_register("H", 0x00AB), Ok(()));
+    assert_eq!(cpu.set_register("L", 0x00CD), Ok(()));
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x1234);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::AF), 0x12F0);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::HL), 0xABCD);
+    assert_eq!(cpu.set_register("IX", 0x0000), Err(SetRegisterError::UnknownRegister));
+    assert_eq!(cpu.set_register("A", 0x0100), Err(SetRegisterError::ValueOutOfRange));
+  }
+
+  #[test_case(HardwareModel::DMG, 0x01B0, 0x0013, 0x00D8, 0x014D; "dmg")]
+  #[test_case(HardwareModel::CGB, 0x1180, 0x0000, 0xFF56, 0x000D; "cgb")]
+  fn init_sets_post_boot_registers(model: HardwareModel, af: u16, bc: u16, de: u16, hl: u16) {
+    let mut cpu = CPUImpl::new();
+    cpu.init(model);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::AF), af);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::BC), bc);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::DE), de);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::HL), hl);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::SP), 0xFFFE);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0100);
+  }
+
+  #[test]
+  fn program_counter_wraps_around_top_of_memory() {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    cpu.registers.write_word(WordRegister::PC, 0xFFFE);
+    // LD A,0x42 across the top of memory; INC A
+    memory.write(0xFFFE, 0x3E);
+    memory.write(0xFFFF, 0x42);
+    memory.write(0x0000, 0x3C);
+    cpu.step_instruction(&mut memory, &mut interrupt_controller);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0000);
+    assert_eq_hex!(cpu.registers.read_byte(ByteRegister::A), 0x42);
+    cpu.step_instruction(&mut memory, &mut interrupt_controller);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0001);
+    assert_eq_hex!(cpu.registers.read_byte(ByteRegister::A), 0x43);
+  }
+
+  #[test]
+  fn step_instruction_runs_whole_instructions() {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    cpu.registers.write_word(WordRegister::SP, 0xFFFE);
+    // NOP; LD BC,0x1234; PUSH BC; CALL 0x0010
+    [0x00, 0x01, 0x34, 0x12, 0xC5, 0xCD, 0x10, 0x00].iter().enumerate()
+      .for_each(|(address, value)| memory.write(address as u16, *value));
+    assert_eq!(cpu.step_instruction(&mut memory, &mut interrupt_controller), 1);
+    assert_eq!(cpu.step_instruction(&mut memory, &mut interrupt_controller), 3);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::BC), 0x1234);
+    assert_eq!(cpu.step_instruction(&mut memory, &mut interrupt_controller), 4);
+    assert_eq!(cpu.step_instruction(&mut memory, &mut interrupt_controller), 6);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0010);
+  }
+
+  #[test]
+  fn step_instruction_treats_interrupt_dispatch_as_a_step() {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    cpu.registers.write_word(WordRegister::SP, 0xFFFE);
+    interrupt_controller.enable_interrupts();
+    interrupt_controller.write(0xFFFF, 0x01);
+    interrupt_controller.request_interrupt(Interrupt::VerticalBlank);
+    assert_eq!(cpu.step_instruction(&mut memory, &mut interrupt_controller), 5);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0040);
+    assert_eq!(cpu.step_instruction(&mut memory, &mut interrupt_controller), 1);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0041);
+  }
+
+  #[test]
+  fn interrupt_is_not_taken_between_cb_prefix_and_operand() {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    cpu.registers.write_word(WordRegister::SP, 0xFFFE);
+    cpu.registers.write_byte(ByteRegister::A, 0x12);
+    interrupt_controller.enable_interrupts();
+    interrupt_controller.write(0xFFFF, 0x01);
+    // SWAP A
+    memory.write(0x0000, 0xCB);
+    memory.write(0x0001, 0x37);
+    cpu.tick(&mut memory, &mut interrupt_controller);
+    interrupt_controller.request_interrupt(Interrupt::VerticalBlank);
+    cpu.tick(&mut memory, &mut interrupt_controller);
+    assert_eq_hex!(cpu.registers.read_byte(ByteRegister::A), 0x21);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0002);
+    assert_eq!(cpu.step_instruction(&mut memory, &mut interrupt_controller), 5);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0040);
+    assert_eq_hex!(memory.read(0xFFFD), 0x00);
+    assert_eq_hex!(memory.read(0xFFFC), 0x02);
+  }
+
+  #[test]
+  fn interrupt_waits_for_indirect_cb_instruction_to_finish() {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    cpu.registers.write_word(WordRegister::SP, 0xFFFE);
+    cpu.registers.write_word(WordRegister::HL, 0xC000);
+    memory.write(0xC000, 0x00);
+    interrupt_controller.enable_interrupts();
+    interrupt_controller.write(0xFFFF, 0x01);
+    // SET 7,(HL)
+    memory.write(0x0000, 0xCB);
+    memory.write(0x0001, 0xFE);
+    cpu.tick(&mut memory, &mut interrupt_controller);
+    interrupt_controller.request_interrupt(Interrupt::VerticalBlank);
+    cpu.ticks(&mut memory, &mut interrupt_controller, 3);
+    assert_eq_hex!(memory.read(0xC000), 0x80);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0002);
+    assert!(cpu.at_instruction_boundary());
+    cpu.step_instruction(&mut memory, &mut interrupt_controller);
+    assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0040);
+  }
+
+  #[test]
+  fn trace_records_state_before_each_instruction() {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    cpu.registers.write_word(WordRegister::SP, 0xFFFE);
+    // LD BC,0x1234; INC B
+    [0x01, 0x34, 0x12, 0x04].iter().enumerate()
+      .for_each(|(address, value)| memory.write(address as u16, *value));
+    cpu.enable_trace(16);
+    cpu.ticks(&mut memory, &mut interrupt_controller, 4);
+    let entries: Vec<&TraceEntry> = cpu.trace().unwrap().entries().collect();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].mnemonic, "LD BC,$1234");
+    assert_eq!(entries[1].mnemonic, "INC B");
+    assert_eq!(entries[1].to_string(), "A:00 F:00 B:12 C:34 D:00 E:00 H:00 L:00 SP:FFFE PC:0003 PCMEM:04,00,00,00");
+    cpu.disable_trace();
+    assert!(cpu.trace().is_none());
+  }
+
+  #[test]
+  fn info_counts_cycles_per_instruction() {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    cpu.registers.write_word(WordRegister::SP, 0xFFFE);
+    // NOP; LD BC,0x1234; CALL 0x0010; ...; 0x0010: PUSH BC; RET (which returns to 0x1234)
+    [0x00, 0x01, 0x34, 0x12, 0xCD, 0x10, 0x00].iter().enumerate()
+      .for_each(|(address, value)| memory.write(address as u16, *value));
+    memory.write(0x0010, 0xC5);
+    memory.write(0x0011, 0xC9);
+    let cycles: Vec<u64> = (0..5).map(|_| {
+      cpu.step_instruction(&mut memory, &mut interrupt_controller);
+      cpu.info(&interrupt_controller).cycles
+    }).collect();
+    assert_eq!(cycles, vec![1, 4, 10, 14, 18]);
+    let info = cpu.info(&interrupt_controller);
+    assert_eq!(info, CPUInfo { af: 0x0000, bc: 0x1234, de: 0x0000, hl: 0x0000, sp: 0xFFFC, pc: 0x1234, ime: false, halted: false, stopped: false, locked: false, cycles: 18 });
+  }
+
+  /// M-cycles of every base opcode, with conditional branches not taken. Zero marks opcodes that aren't timed here:
+  /// STOP, HALT, the CB prefix and the unused opcodes.
+  const BASE_CYCLES: [u64; 0x100] = [
+    1, 3, 2, 2, 1, 1, 2, 1, 5, 2, 2, 2, 1, 1, 2, 1,
+    0, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1,
+    2, 3, 2, 2, 1, 1, 2, 1, 2, 2, 2, 2, 1, 1, 2, 1,
+    2, 3, 2, 2, 3, 3, 3, 1, 2, 2, 2, 2, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    2, 2, 2, 2, 2, 2, 0, 2, 1, 1, 1, 1, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    2, 3, 3, 4, 3, 4, 2, 4, 2, 4, 3, 0, 3, 6, 2, 4,
+    2, 3, 3, 0, 3, 4, 2, 4, 2, 4, 3, 0, 3, 0, 2, 4,
+    3, 3, 2, 0, 0, 4, 2, 4, 4, 1, 4, 0, 0, 0, 2, 4,
+    3, 3, 2, 1, 0, 4, 2, 4, 3, 2, 4, 1, 0, 0, 2, 4,
+  ];
+
+  /// M-cycles of every CB-prefixed opcode, including the prefix.
+  const CB_CYCLES: [u64; 0x100] = [
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2,
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2,
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2,
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2,
+    2, 2, 2, 2, 2, 2, 3, 2, 2, 2, 2, 2, 2, 2, 3, 2,
+    2, 2, 2, 2, 2, 2, 3, 2, 2, 2, 2, 2, 2, 2, 3, 2,
+    2, 2, 2, 2, 2, 2, 3, 2, 2, 2, 2, 2, 2, 2, 3, 2,
+    2, 2, 2, 2, 2, 2, 3, 2, 2, 2, 2, 2, 2, 2, 3, 2,
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2,
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2,
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2,
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2,
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2,
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2,
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2,
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2,
+  ];
+
+  /// M-cycles of the conditional branches when taken.
+  const TAKEN_CYCLES: [(u8, u64); 16] = [
+    (0x20, 3), (0x28, 3), (0x30, 3), (0x38, 3),
+    (0xC0, 5), (0xC8, 5), (0xD0, 5), (0xD8, 5),
+    (0xC2, 4), (0xCA, 4), (0xD2, 4), (0xDA, 4),
+    (0xC4, 6), (0xCC, 6), (0xD4, 6), (0xDC, 6),
+  ];
+
+  fn measure_cycles(program: &[u8], flags: u8) -> u64 {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    cpu.registers.write_word(WordRegister::SP, 0xFFFE);
+    cpu.registers.write_word(WordRegister::BC, 0xC000);
+    cpu.registers.write_word(WordRegister::HL, 0xC000);
+    cpu.registers.write_byte(ByteRegister::F, flags);
+    program.iter().enumerate()
+      .for_each(|(address, value)| memory.write(address as u16, *value));
+    let cycles_before = cpu.cycles();
+    cpu.step_instruction(&mut memory, &mut interrupt_controller);
+    cpu.cycles() - cycles_before
+  }
+
+  /// Whether the condition in bits 3-4 of a conditional branch (NZ, Z, NC, C) holds for the given flags.
+  fn condition_holds(opcode: u8, flags: u8) -> bool {
+    match (opcode >> 3) & 0x03 {
+      0 => flags & 0x80 == 0,
+      1 => flags & 0x80 != 0,
+      2 => flags & 0x10 == 0,
+      _ => flags & 0x10 != 0
+    }
+  }
+
+  #[test]
+  fn base_instructions_take_documented_cycles() {
+    let mut mismatches = vec![];
+    for opcode in 0..=0xFFu8 {
+      if BASE_CYCLES[opcode as usize] == 0 {
+        continue;
+      }
+      for flags in [0x00, 0xF0] {
+        let expected = match TAKEN_CYCLES.iter().find(|(taken_opcode, _)| *taken_opcode == opcode) {
+          Some((_, taken)) if condition_holds(opcode, flags) => *taken,
+          _ => BASE_CYCLES[opcode as usize]
+        };
+        let actual = measure_cycles(&[opcode, 0x00, 0xC0], flags);
+        if actual != expected {
+          mismatches.push((opcode, flags, expected, actual));
+        }
+      }
+    }
+    assert_eq!(mismatches, vec![]);
+  }
+
+  #[test]
+  fn cb_instructions_take_documented_cycles() {
+    let mismatches: Vec<(u8, u64, u64)> = (0..=0xFFu8)
+      .map(|opcode| (opcode, CB_CYCLES[opcode as usize], measure_cycles(&[0xCB, opcode], 0x00)))
+      .filter(|(_, expected, actual)| expected != actual)
+      .collect();
+    assert_eq!(mismatches, vec![]);
+  }
+
+  #[test]
+  fn info_reports_halt_and_keeps_counting() {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    interrupt_controller.enable_interrupts();
+    memory.write(0x0000, 0x76);
+    cpu.ticks(&mut memory, &mut interrupt_controller, 10);
+    let info = cpu.info(&interrupt_controller);
+    assert!(info.halted);
+    assert!(info.ime);
+    assert_eq!(info.cycles, 10);
+  }
+
+  fn round_trip_program() -> MockMemory {
+    let mut memory = MockMemory::new(0x10000);
+    // LD SP,0xFFFE; LD HL,0xC000; INC A; LD (HL+),A; PUSH AF; ADD HL,SP; JR -7
+    [0x31, 0xFE, 0xFF, 0x21, 0x00, 0xC0, 0x3C, 0x22, 0xF5, 0x39, 0x18, 0xF9].iter().enumerate()
+      .for_each(|(address, value)| memory.write(address as u16, *value));
+    memory
+  }
+
+  #[test_case(1)]
+  #[test_case(7)]
+  #[test_case(40)]
+  fn serialization_round_trip(instructions: usize) {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = round_trip_program();
+    (0..instructions).for_each(|_| { cpu.step_instruction(&mut memory, &mut interrupt_controller); });
+    let mut restored_cpu: CPUImpl = serde_json::from_str(&serde_json::to_string(&cpu).unwrap()).unwrap();
+    let mut restored_memory = memory.clone();
+    (0..50).for_each(|_| {
+      cpu.tick(&mut memory, &mut interrupt_controller);
+      restored_cpu.tick(&mut restored_memory, &mut interrupt_controller);
+    });
+    assert_eq!(restored_cpu.info(&interrupt_controller), cpu.info(&interrupt_controller));
+  }
+
+  #[test]
+  fn serialization_fails_between_instruction_boundaries() {
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = round_trip_program();
+    cpu.tick(&mut memory, &mut interrupt_controller);
+    assert!(!cpu.at_instruction_boundary());
+    assert!(serde_json::to_string(&cpu).is_err());
+  }
+
+  const SM83_BYTE_REGISTERS: [(ByteRegister, &str); 8] = [
+    (ByteRegister::A, "a"),
+    (ByteRegister::F, "f"),
+    (ByteRegister::B, "b"),
+    (ByteRegister::C, "c"),
+    (ByteRegister::D, "d"),
+    (ByteRegister::E, "e"),
+    (ByteRegister::UpperHL, "h"),
+    (ByteRegister::LowerHL, "l"),
+  ];
+
+  fn sm83_value(state: &Json, key: &str) -> u16 {
+    state.get(key).and_then(Json::as_u64).unwrap_or_else(|| panic!("Missing {} in test state", key)) as u16
+  }
+
+  fn sm83_ram(state: &Json) -> Vec<(u16, u8)> {
+    state.get("ram").and_then(Json::as_array).unwrap().iter()
+      .map(|entry| {
+        let entry = entry.as_array().unwrap();
+        (entry[0].as_u64().unwrap() as u16, entry[1].as_u64().unwrap() as u8)
+      })
+      .collect()
+  }
+
+  /// Runs a single test case and returns a description of every mismatch.
+  fn run_sm83_case(case: &Json) -> Vec<String> {
+    let initial = case.get("initial").unwrap();
+    let expected = case.get("final").unwrap();
+    let mut cpu = CPUImpl::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    let mut memory = MockMemory::new(0x10000);
+    SM83_BYTE_REGISTERS.iter().for_each(|(register, key)| cpu.registers.write_byte(*register, sm83_value(initial, key) as u8));
+    cpu.registers.write_word(WordRegister::SP, sm83_value(initial, "sp"));
+    cpu.registers.write_word(WordRegister::PC, sm83_value(initial, "pc"));
+    if sm83_value(initial, "ime") == 1 {
+      interrupt_controller.enable_interrupts();
+    }
+    interrupt_controller.write(0xFFFF, sm83_value(initial, "ie") as u8);
+    sm83_ram(initial).into_iter().for_each(|(address, value)| memory.write(address, value));
+
+    let cycles = cpu.step_instruction(&mut memory, &mut interrupt_controller) as usize;
+
+    let mut mismatches = vec![];
+    SM83_BYTE_REGISTERS.iter().for_each(|(register, key)| {
+      let (actual, expected) = (cpu.registers.read_byte(*register), sm83_value(expected, key) as u8);
+      if actual != expected {
+        mismatches.push(format!("{}: {:#04x} != {:#04x}", key, actual, expected));
+      }
+    });
+    [(WordRegister::SP, "sp"), (WordRegister::PC, "pc")].iter().for_each(|(register, key)| {
+      let (actual, expected) = (cpu.registers.read_word(*register), sm83_value(expected, key));
+      if actual != expected {
+        mismatches.push(format!("{}: {:#06x} != {:#06x}", key, actual, expected));
+      }
+    });
+    let ime = interrupt_controller.interrupts_enabled() as u16;
+    if ime != sm83_value(expected, "ime") {
+      mismatches.push(format!("ime: {} != {}", ime, sm83_value(expected, "ime")));
+    }
+    let expected_cycles = case.get("cycles").and_then(Json::as_array).unwrap().len();
+    if cycles != expected_cycles {
+      mismatches.push(format!("cycles: {} != {}", cycles, expected_cycles));
+    }
+    sm83_ram(expected).into_iter().for_each(|(address, expected)| {
+      let actual = memory.read(address);
+      if actual != expected {
+        mismatches.push(format!("({:#06x}): {:#04x} != {:#04x}", address, actual, expected));
+      }
+    });
+    mismatches
+  }
+
+  #[test]
+  fn sm83_case_reports_mismatches() {
+    let case = |final_a: u8| Json::parse(&format!(r#"{{
+      "name": "3c 0000",
+      "initial": {{"pc": 256, "sp": 65534, "a": 1, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0,
+        "ie": 0, "ram": [[256, 60]]}},
+      "final": {{"pc": 257, "sp": 65534, "a": {}, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0,
+        "ram": [[256, 60]]}},
+      "cycles": [[256, 60, "r-m"]]
+    }}"#, final_a)).unwrap();
+    assert_eq!(run_sm83_case(&case(2)), Vec::<String>::new());
+    assert_eq!(run_sm83_case(&case(3)), vec!["a: 0x02 != 0x03"]);
+  }
+
+  /// Runs the SM83 single instruction tests from https://github.com/SingleStepTests/sm83.
+  /// Copy the JSON files of the suite (named after their opcode, like `00.json` or `cb 00.json`) into `tests/sm83/v1`,
+  /// or point SM83_TESTS_DIR at a directory containing them, and run `cargo test sm83 -- --ignored`.
+  #[test]
+  #[ignore]
+  fn sm83_single_instruction_tests() {
+    let directory = std::env::var("SM83_TESTS_DIR").unwrap_or_else(|_| String::from("tests/sm83/v1"));
+    let mut paths: Vec<std::path::PathBuf> = std::fs::read_dir(&directory)
+      .unwrap_or_else(|error| panic!("Can't read SM83 tests from {}: {}", directory, error))
+      .map(|entry| entry.unwrap().path())
+      .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+      .collect();
+    paths.sort();
+    let mut failures = vec![];
+    let mut total = 0;
+    for path in paths {
+      let cases = Json::parse(&std::fs::read_to_string(&path).unwrap())
+        .unwrap_or_else(|error| panic!("Can't parse {}: {}", path.display(), error));
+      let cases = cases.as_array().unwrap();
+      total += cases.len();
+      for case in cases {
+        let mismatches = run_sm83_case(case);
+        if !mismatches.is_empty() {
+          failures.push(format!("{}: {}", case.get("name").and_then(Json::as_str).unwrap_or("?"), mismatches.join(", ")));
+        }
+      }
+    }
+    assert!(total > 0, "No SM83 tests found in {}", directory);
+    assert!(failures.is_empty(), "{} of {} cases failed, first failures:\n{}", failures.len(), total, failures.iter().take(20).cloned().collect::<Vec<String>>().join("\n"));
+  }
 }
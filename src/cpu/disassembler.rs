@@ -0,0 +1,242 @@
+use crate::cpu::opcode::Opcode;
+
+const REGISTERS: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const REGISTER_PAIRS: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const STACK_REGISTER_PAIRS: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const CONDITIONS: [&str; 4] = ["NZ", "Z", "NC", "C"];
+const ALU_OPERATIONS: [&str; 8] = ["ADD A,", "ADC A,", "SUB ", "SBC A,", "AND ", "XOR ", "OR ", "CP "];
+const ROTATIONS: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct DisassembledInstruction {
+  pub address: u16,
+  /// The bytes of the instruction. This is shorter than length if the instruction is cut off at the end of the input.
+  pub bytes: Vec<u8>,
+  pub mnemonic: String,
+  pub length: u16,
+}
+
+/// Disassembles consecutive instructions, the first of which is located at base_address.
+pub fn disassemble(bytes: &[u8], base_address: u16) -> Vec<DisassembledInstruction> {
+  disassemble_with_labels(bytes, base_address, &|_| None)
+}
+
+/// Like disassemble, but shows call and jump targets by the label returned for them, if any.
+pub fn disassemble_with_labels(bytes: &[u8], base_address: u16, labels: &dyn Fn(u16) -> Option<String>) -> Vec<DisassembledInstruction> {
+  let mut instructions = vec![];
+  let mut offset = 0;
+  while offset < bytes.len() {
+    let instruction = disassemble_labelled_instruction(&bytes[offset..], base_address.wrapping_add(offset as u16), labels);
+    offset += instruction.length as usize;
+    instructions.push(instruction);
+  }
+  instructions
+}
+
+/// Disassembles the single instruction at the start of bytes. Missing operand bytes are treated as 0.
+pub fn disassemble_instruction(bytes: &[u8], address: u16) -> DisassembledInstruction {
+  disassemble_labelled_instruction(bytes, address, &|_| None)
+}
+
+fn disassemble_labelled_instruction(bytes: &[u8], address: u16, labels: &dyn Fn(u16) -> Option<String>) -> DisassembledInstruction {
+  let length = instruction_length(bytes[0]);
+  let available = bytes.len().min(length as usize);
+  let mut padded_bytes = [0u8; 3];
+  padded_bytes[..available].copy_from_slice(&bytes[..available]);
+  DisassembledInstruction {
+    address,
+    bytes: bytes[..available].to_vec(),
+    mnemonic: mnemonic(address, padded_bytes, labels),
+    length,
+  }
+}
+
+pub fn instruction_length(opcode_value: u8) -> u16 {
+  let opcode = Opcode(opcode_value);
+  let (y, q) = (opcode.y_bits(), opcode.y_bits() & 1);
+  match (opcode.x_bits(), opcode.z_bits()) {
+    (0, 0) => match y {
+      0 => 1,
+      1 => 3,
+      _ => 2
+    },
+    (0, 1) if q == 0 => 3,
+    (0, 6) => 2,
+    (3, 0) if y >= 4 => 2,
+    (3, 2) if y < 4 || y == 5 || y == 7 => 3,
+    (3, 3) if y == 0 => 3,
+    (3, 3) if y == 1 => 2,
+    (3, 4) if y < 4 => 3,
+    (3, 5) if y == 1 => 3,
+    (3, 6) => 2,
+    _ => 1
+  }
+}
+
+fn mnemonic(address: u16, bytes: [u8; 3], labels: &dyn Fn(u16) -> Option<String>) -> String {
+  let opcode = Opcode(bytes[0]);
+  let (x, y, z) = (opcode.x_bits(), opcode.y_bits(), opcode.z_bits());
+  let (p, q) = ((y >> 1) as usize, y & 1);
+  let n = format!("${:02X}", bytes[1]);
+  let nn = format!("${:04X}", u16::from_le_bytes([bytes[1], bytes[2]]));
+  let target = |address: u16| labels(address).unwrap_or_else(|| format!("${:04X}", address));
+  let absolute_target = target(u16::from_le_bytes([bytes[1], bytes[2]]));
+  let relative_target = target(address.wrapping_add(2).wrapping_add(bytes[1] as i8 as u16));
+  let signed_offset = bytes[1] as i8;
+  let r = |index: u8| REGISTERS[index as usize];
+  match (x, z) {
+    (0, 0) => match y {
+      0 => "NOP".to_string(),
+      1 => format!("LD ({}),SP", nn),
+      2 => "STOP".to_string(),
+      3 => format!("JR {}", relative_target),
+      _ => format!("JR {},{}", CONDITIONS[y as usize - 4], relative_target)
+    },
+    (0, 1) if q == 0 => format!("LD {},{}", REGISTER_PAIRS[p], nn),
+    (0, 1) => format!("ADD HL,{}", REGISTER_PAIRS[p]),
+    (0, 2) => {
+      let indirect = ["(BC)", "(DE)", "(HL+)", "(HL-)"][p];
+      if q == 0 { format!("LD {},A", indirect) } else { format!("LD A,{}", indirect) }
+    }
+    (0, 3) => format!("{} {}", if q == 0 { "INC" } else { "DEC" }, REGISTER_PAIRS[p]),
+    (0, 4) => format!("INC {}", r(y)),
+    (0, 5) => format!("DEC {}", r(y)),
+    (0, 6) => format!("LD {},{}", r(y), n),
+    (0, 7) => ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"][y as usize].to_string(),
+    (1, 6) if y == 6 => "HALT".to_string(),
+    (1, _) => format!("LD {},{}", r(y), r(z)),
+    (2, _) => format!("{}{}", ALU_OPERATIONS[y as usize], r(z)),
+    (3, 0) => match y {
+      0..=3 => format!("RET {}", CONDITIONS[y as usize]),
+      4 => format!("LDH ({}),A", n),
+      5 => format!("ADD SP,{}", signed_offset),
+      6 => format!("LDH A,({})", n),
+      _ => format!("LD HL,SP{:+}", signed_offset)
+    },
+    (3, 1) if q == 0 => format!("POP {}", STACK_REGISTER_PAIRS[p]),
+    (3, 1) => ["RET", "RETI", "JP HL", "LD SP,HL"][p].to_string(),
+    (3, 2) => match y {
+      0..=3 => format!("JP {},{}", CONDITIONS[y as usize], absolute_target),
+      4 => "LD (C),A".to_string(),
+      5 => format!("LD ({}),A", nn),
+      6 => "LD A,(C)".to_string(),
+      _ => format!("LD A,({})", nn)
+    },
+    (3, 3) => match y {
+      0 => format!("JP {}", absolute_target),
+      1 => prefixed_mnemonic(bytes[1]),
+      6 => "DI".to_string(),
+      7 => "EI".to_string(),
+      _ => format!("ILLEGAL ${:02X}", bytes[0])
+    },
+    (3, 4) if y < 4 => format!("CALL {},{}", CONDITIONS[y as usize], absolute_target),
+    (3, 5) if q == 0 => format!("PUSH {}", STACK_REGISTER_PAIRS[p]),
+    (3, 5) if p == 0 => format!("CALL {}", absolute_target),
+    (3, 6) => format!("{}{}", ALU_OPERATIONS[y as usize], n),
+    (3, 7) => format!("RST ${:02X}", y * 8),
+    _ => format!("ILLEGAL ${:02X}", bytes[0])
+  }
+}
+
+fn prefixed_mnemonic(value: u8) -> String {
+  let opcode = Opcode(value);
+  let (y, register) = (opcode.y_bits(), REGISTERS[opcode.z_bits() as usize]);
+  match opcode.x_bits() {
+    0 => format!("{} {}", ROTATIONS[y as usize], register),
+    1 => format!("BIT {},{}", y, register),
+    2 => format!("RES {},{}", y, register),
+    _ => format!("SET {},{}", y, register)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use test_case::test_case;
+  use super::*;
+
+  #[test_case([0x00, 0x00, 0x00], "NOP")]
+  #[test_case([0x01, 0x34, 0x12], "LD BC,$1234")]
+  #[test_case([0x08, 0x00, 0xC0], "LD ($C000),SP")]
+  #[test_case([0x18, 0xFE, 0x00], "JR $0100")]
+  #[test_case([0x20, 0x05, 0x00], "JR NZ,$0107")]
+  #[test_case([0x22, 0x00, 0x00], "LD (HL+),A")]
+  #[test_case([0x3A, 0x00, 0x00], "LD A,(HL-)")]
+  #[test_case([0x36, 0x42, 0x00], "LD (HL),$42")]
+  #[test_case([0x76, 0x00, 0x00], "HALT")]
+  #[test_case([0x78, 0x00, 0x00], "LD A,B")]
+  #[test_case([0x9E, 0x00, 0x00], "SBC A,(HL)")]
+  #[test_case([0xAF, 0x00, 0x00], "XOR A")]
+  #[test_case([0xC3, 0x50, 0x01], "JP $0150")]
+  #[test_case([0xCB, 0x37, 0x00], "SWAP A")]
+  #[test_case([0xCB, 0x7E, 0x00], "BIT 7,(HL)")]
+  #[test_case([0xCB, 0xC1, 0x00], "SET 0,C")]
+  #[test_case([0xCD, 0x00, 0x40], "CALL $4000")]
+  #[test_case([0xD3, 0x00, 0x00], "ILLEGAL $D3")]
+  #[test_case([0xE0, 0x40, 0x00], "LDH ($40),A")]
+  #[test_case([0xE2, 0x00, 0x00], "LD (C),A")]
+  #[test_case([0xE8, 0xFE, 0x00], "ADD SP,-2")]
+  #[test_case([0xF1, 0x00, 0x00], "POP AF")]
+  #[test_case([0xF8, 0x05, 0x00], "LD HL,SP+5")]
+  #[test_case([0xFE, 0x90, 0x00], "CP $90")]
+  #[test_case([0xFF, 0x00, 0x00], "RST $38")]
+  fn disassemble_instruction_mnemonic(bytes: [u8; 3], mnemonic: &str) {
+    assert_eq!(disassemble_instruction(&bytes, 0x0100).mnemonic, mnemonic);
+  }
+
+  #[test_case(0x00, 1)]
+  #[test_case(0x01, 3)]
+  #[test_case(0x08, 3)]
+  #[test_case(0x10, 2)]
+  #[test_case(0x18, 2)]
+  #[test_case(0x3E, 2)]
+  #[test_case(0xC3, 3)]
+  #[test_case(0xC4, 3)]
+  #[test_case(0xCB, 2)]
+  #[test_case(0xCD, 3)]
+  #[test_case(0xD3, 1)]
+  #[test_case(0xE0, 2)]
+  #[test_case(0xE2, 1)]
+  #[test_case(0xE8, 2)]
+  #[test_case(0xEA, 3)]
+  #[test_case(0xFE, 2)]
+  fn instruction_lengths(opcode: u8, length: u16) {
+    assert_eq!(instruction_length(opcode), length);
+  }
+
+  #[test]
+  fn lengths_walk_the_whole_buffer() {
+    let bytes: Vec<u8> = (0x00..=0xFF).collect();
+    let instructions = disassemble(&bytes, 0x4000);
+    instructions.windows(2).for_each(|pair| assert_eq!(pair[1].address, pair[0].address + pair[0].length));
+    let last = instructions.last().unwrap();
+    assert!(last.address - 0x4000 + last.length >= 0x100);
+    assert_eq!(instructions.iter().map(|instruction| instruction.bytes.len()).sum::<usize>(), 0x100);
+  }
+
+  #[test]
+  fn every_opcode_is_visited_once_when_operands_are_nops() {
+    let bytes: Vec<u8> = (0x00..=0xFF).flat_map(|opcode| {
+      let mut instruction = vec![0x00; instruction_length(opcode) as usize];
+      instruction[0] = opcode;
+      instruction
+    }).collect();
+    let instructions = disassemble(&bytes, 0x0000);
+    assert_eq!(instructions.len(), 0x100);
+    instructions.iter().enumerate().for_each(|(opcode, instruction)| assert_eq!(instruction.bytes[0], opcode as u8));
+  }
+
+  #[test]
+  fn labels_replace_call_and_jump_targets() {
+    let labels = |address: u16| if address == 0x4123 { Some("MyFunction".to_string()) } else { None };
+    // CALL $4123; JP NZ,$4123; JR $4123; LD ($4123),A; CALL $4000
+    let bytes = [0xCD, 0x23, 0x41, 0xC2, 0x23, 0x41, 0x18, 0x1B, 0xEA, 0x23, 0x41, 0xCD, 0x00, 0x40];
+    let mnemonics: Vec<String> = disassemble_with_labels(&bytes, 0x4100, &labels).into_iter().map(|instruction| instruction.mnemonic).collect();
+    assert_eq!(mnemonics, vec!["CALL MyFunction", "JP NZ,MyFunction", "JR MyFunction", "LD ($4123),A", "CALL $4000"]);
+  }
+
+  #[test]
+  fn truncated_instruction_keeps_its_length() {
+    let instructions = disassemble(&[0x00, 0xC3, 0x50], 0x0100);
+    assert_eq!(instructions[1], DisassembledInstruction { address: 0x0101, bytes: vec![0xC3, 0x50], mnemonic: "JP $0050".to_string(), length: 3 });
+  }
+}
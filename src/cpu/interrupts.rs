@@ -1,5 +1,6 @@
 use std::cell::RefCell;
 use std::rc::Rc;
+use serde::{Deserialize, Serialize};
 use crate::memory::memory::Memory;
 use crate::util::bit_util::BitUtil;
 
@@ -49,6 +50,8 @@ impl Interrupt {
 
 pub trait InterruptController {
   fn get_requested_interrupt(&self) -> Option<Interrupt>;
+  /// Whether any enabled interrupt is requested, regardless of IME.
+  fn interrupt_pending(&self) -> bool;
   fn interrupts_enabled(&self) -> bool;
   fn enable_interrupts(&mut self);
   fn disable_interrupts(&mut self);
@@ -56,6 +59,7 @@ pub trait InterruptController {
   fn clear_interrupt(&mut self, interrupt: Interrupt);
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct InterruptControllerImpl {
   interrupt_request: u8,
   interrupt_enable: u8,
@@ -86,6 +90,10 @@ impl InterruptController for InterruptControllerImpl {
     }
   }
 
+  fn interrupt_pending(&self) -> bool {
+    0x1F & self.interrupt_enable & self.interrupt_request != 0
+  }
+
   fn interrupts_enabled(&self) -> bool {
     self.interrupt_master_enable
   }
@@ -110,7 +118,7 @@ impl InterruptController for InterruptControllerImpl {
 impl Memory for InterruptControllerImpl {
   fn read(&self, address: u16) -> u8 {
     match address {
-      0xFF0F => self.interrupt_request,
+      0xFF0F => self.interrupt_request | 0xE0,
       0xFFFF => self.interrupt_enable,
       _ => panic!("InterruptController can't read address {}", address)
     }
@@ -118,29 +126,100 @@ impl Memory for InterruptControllerImpl {
 
   fn write(&mut self, address: u16, value: u8) {
     match address {
-      0xFF0F => self.interrupt_request = value,
+      0xFF0F => self.interrupt_request = value & 0x1F,
       0xFFFF => self.interrupt_enable = value,
       _ => panic!("InterruptController can't write to address {}", address)
     }
   }
 }
 
-#[cfg(test)]
-mod tests {
-  use super::*;
+impl InterruptController for InterruptControllerRef {
+  fn get_requested_interrupt(&self) -> Option<Interrupt> {
+    self.borrow().get_requested_interrupt()
+  }
 
-  #[test]
-  fn get_requested_interrupt_returns_highest_priority() {
+  fn interrupt_pending(&self) -> bool {
+    self.borrow().interrupt_pending()
+  }
+
+  fn interrupts_enabled(&self) -> bool {
+    self.borrow().interrupts_enabled()
+  }
+
+  fn enable_interrupts(&mut self) {
+    self.borrow_mut().enable_interrupts()
+  }
+
+  fn disable_interrupts(&mut self) {
+    self.borrow_mut().disable_interrupts()
+  }
+
+  fn request_interrupt(&mut self, interrupt: Interrupt) {
+    self.borrow_mut().request_interrupt(interrupt)
+  }
+
+  fn clear_interrupt(&mut self, interrupt: Interrupt) {
+    self.borrow_mut().clear_interrupt(interrupt)
+  }
+}
+
+impl Memory for InterruptControllerRef {
+  fn read(&self, address: u16) -> u8 {
+    self.borrow().read(address)
+  }
+
+  fn write(&mut self, address: u16, value: u8) {
+    self.borrow_mut().write(address, value)
+  }
+}
+
+pub mod checks {
+  use crate::memory::memory::Memory;
+  use super::{Interrupt, InterruptController, InterruptControllerImpl};
+
+  pub fn get_requested_interrupt_returns_highest_priority() -> bool {
     let mut interrupt_controller = InterruptControllerImpl::new();
     interrupt_controller.request_interrupt(Interrupt::SerialIOComplete);
     interrupt_controller.request_interrupt(Interrupt::Stat);
     interrupt_controller.enable_interrupts();
     interrupt_controller.write(0xFFFF, 0xFF);
-    assert_eq!(interrupt_controller.get_requested_interrupt(), Some(Interrupt::Stat));
+    let first = interrupt_controller.get_requested_interrupt();
     interrupt_controller.clear_interrupt(Interrupt::Stat);
-    assert_eq!(interrupt_controller.get_requested_interrupt(), Some(Interrupt::SerialIOComplete));
+    let second = interrupt_controller.get_requested_interrupt();
     interrupt_controller.clear_interrupt(Interrupt::SerialIOComplete);
-    assert_eq!(interrupt_controller.get_requested_interrupt(), None);
+    first == Some(Interrupt::Stat) && second == Some(Interrupt::SerialIOComplete) &&
+      interrupt_controller.get_requested_interrupt().is_none()
+  }
+
+  pub fn interrupt_request_upper_bits_read_as_one() -> bool {
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    interrupt_controller.write(0xFF0F, 0xFF);
+    let all_set = interrupt_controller.read(0xFF0F);
+    interrupt_controller.write(0xFF0F, 0x00);
+    let cleared = interrupt_controller.read(0xFF0F);
+    interrupt_controller.request_interrupt(Interrupt::TimerOverflow);
+    all_set == 0xFF && cleared == 0xE0 && interrupt_controller.read(0xFF0F) == 0xE4
+  }
+
+  pub fn interrupt_enable_upper_bits_do_not_cause_interrupts() -> bool {
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    interrupt_controller.enable_interrupts();
+    interrupt_controller.write(0xFF0F, 0xFF);
+    interrupt_controller.write(0xFFFF, 0xE0);
+    let upper_bits_only = interrupt_controller.read(0xFFFF) == 0xE0 && interrupt_controller.get_requested_interrupt().is_none();
+    interrupt_controller.write(0xFFFF, 0xE4);
+    upper_bits_only && interrupt_controller.read(0xFFFF) == 0xE4 &&
+      interrupt_controller.get_requested_interrupt() == Some(Interrupt::TimerOverflow)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn get_requested_interrupt_returns_highest_priority() {
+    assert!(checks::get_requested_interrupt_returns_highest_priority());
   }
 
   #[test]
@@ -168,5 +247,23 @@ mod tests {
     interrupt_controller.enable_interrupts();
     assert_eq!(interrupt_controller.get_requested_interrupt(), Some(Interrupt::Stat));
   }
-}
 
+  #[test]
+  fn interrupt_request_upper_bits_read_as_one() {
+    assert!(checks::interrupt_request_upper_bits_read_as_one());
+  }
+
+  #[test]
+  fn interrupt_enable_stores_all_bits() {
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    interrupt_controller.write(0xFFFF, 0xFF);
+    assert_eq!(interrupt_controller.read(0xFFFF), 0xFF);
+    interrupt_controller.write(0xFFFF, 0x00);
+    assert_eq!(interrupt_controller.read(0xFFFF), 0x00);
+  }
+
+  #[test]
+  fn interrupt_enable_upper_bits_do_not_cause_interrupts() {
+    assert!(checks::interrupt_enable_upper_bits_do_not_cause_interrupts());
+  }
+}
@@ -1,4 +1,10 @@
 mod opcode;
 mod register;
+pub mod call_stack;
 pub mod cpu;
+pub mod disassembler;
 pub mod interrupts;
+pub mod profiler;
+#[cfg(test)]
+mod sm83_json;
+pub mod trace;
@@ -0,0 +1,95 @@
+use std::fmt::Write;
+
+const REGION_SIZE: usize = 0x1000;
+
+/// Counts how often every opcode is executed, and how many instructions start in every 4KB region of the address
+/// space. CB-prefixed opcodes are counted separately from the prefix itself.
+pub struct Profiler {
+  opcodes: [u64; 512],
+  regions: [u64; 0x10000 / REGION_SIZE],
+}
+
+impl Profiler {
+  pub fn new() -> Profiler {
+    Profiler {
+      opcodes: [0; 512],
+      regions: [0; 0x10000 / REGION_SIZE],
+    }
+  }
+
+  pub fn record_instruction(&mut self, pc: u16, opcode: u8) {
+    self.opcodes[opcode as usize] += 1;
+    self.regions[pc as usize / REGION_SIZE] += 1;
+  }
+
+  pub fn record_cb_instruction(&mut self, opcode: u8) {
+    self.opcodes[0x100 + opcode as usize] += 1;
+  }
+
+  pub fn opcode_count(&self, opcode: u8) -> u64 {
+    self.opcodes[opcode as usize]
+  }
+
+  pub fn cb_opcode_count(&self, opcode: u8) -> u64 {
+    self.opcodes[0x100 + opcode as usize]
+  }
+
+  /// Lists the executed opcodes and the regions instructions were executed from, most frequent first.
+  pub fn report(&self) -> String {
+    let mut report = String::from("opcode  count\n");
+    for (index, count) in Profiler::sorted_counts(&self.opcodes) {
+      let opcode = if index < 0x100 { format!("{:02X}", index) } else { format!("CB {:02X}", index - 0x100) };
+      writeln!(report, "{:<8}{}", opcode, count).unwrap();
+    }
+    report.push_str("\nregion     count\n");
+    for (index, count) in Profiler::sorted_counts(&self.regions) {
+      let start = index * REGION_SIZE;
+      writeln!(report, "{:04X}-{:04X}  {}", start, start + REGION_SIZE - 1, count).unwrap();
+    }
+    report
+  }
+
+  fn sorted_counts(counts: &[u64]) -> Vec<(usize, u64)> {
+    let mut sorted: Vec<(usize, u64)> = counts.iter().copied().enumerate().filter(|(_, count)| *count > 0).collect();
+    sorted.sort_by(|(first_index, first_count), (second_index, second_count)| {
+      second_count.cmp(first_count).then(first_index.cmp(second_index))
+    });
+    sorted
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn counts_opcodes_and_regions() {
+    let mut profiler = Profiler::new();
+    profiler.record_instruction(0x0150, 0x3C);
+    profiler.record_instruction(0x0151, 0x3C);
+    profiler.record_instruction(0xC000, 0xCB);
+    profiler.record_cb_instruction(0x37);
+    assert_eq!(profiler.opcode_count(0x3C), 2);
+    assert_eq!(profiler.opcode_count(0xCB), 1);
+    assert_eq!(profiler.cb_opcode_count(0x37), 1);
+    assert_eq!(profiler.cb_opcode_count(0x3C), 0);
+  }
+
+  #[test]
+  fn report_sorts_by_count() {
+    let mut profiler = Profiler::new();
+    profiler.record_instruction(0xC000, 0xCB);
+    profiler.record_cb_instruction(0x37);
+    (0..3).for_each(|_| profiler.record_instruction(0x0150, 0x3C));
+    assert_eq!(profiler.report(), "\
+opcode  count
+3C      3
+CB      1
+CB 37   1
+
+region     count
+0000-0FFF  3
+C000-CFFF  1
+");
+  }
+}
@@ -1,4 +1,5 @@
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use serde::{Deserialize, Serialize};
 
 #[derive(Copy, Clone, Debug)]
 pub enum WordRegister {
@@ -115,6 +116,7 @@ impl ByteRegister {
   }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Registers([u8; 12]);
 
 impl Registers {
@@ -128,10 +130,12 @@ impl Registers {
 
   pub fn write_byte(&mut self, register: ByteRegister, value: u8) {
     self.0[register.offset()] = value;
+    self.clear_lower_flag_bits();
   }
 
   pub fn write_byte_masked(&mut self, register: ByteRegister, value: u8, mask: u8) {
     self.0[register.offset()] = (!mask & self.0[register.offset()]) | (mask & value);
+    self.clear_lower_flag_bits();
   }
 
   pub fn read_word(&self, register: WordRegister) -> u16 {
@@ -140,6 +144,12 @@ impl Registers {
 
   pub fn write_word(&mut self, register: WordRegister, value: u16) {
     (&mut self.0[register.offset()..]).write_u16::<BigEndian>(value).unwrap();
+    self.clear_lower_flag_bits();
+  }
+
+  /// Bits 3-0 of F don't exist and always read 0
+  fn clear_lower_flag_bits(&mut self) {
+    self.0[ByteRegister::F.offset()] &= 0xF0;
   }
 }
 
@@ -162,4 +172,15 @@ mod tests {
     assert_eq!(registers.read_byte(ByteRegister::C), 0xCD);
     assert_eq!(registers.read_byte(ByteRegister::B), 0xAB);
   }
+
+  #[test]
+  fn lower_flag_bits_always_read_zero() {
+    let mut registers = Registers::new();
+    registers.write_byte(ByteRegister::F, 0xFF);
+    assert_eq!(registers.read_byte(ByteRegister::F), 0xF0);
+    registers.write_byte_masked(ByteRegister::F, 0x0F, 0x0F);
+    assert_eq!(registers.read_byte(ByteRegister::F), 0xF0);
+    registers.write_word(WordRegister::AF, 0x12FF);
+    assert_eq!(registers.read_word(WordRegister::AF), 0x12F0);
+  }
 }
@@ -0,0 +1,170 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// A JSON value from the SM83 single instruction tests. The files only contain unsigned integers, strings without
+/// escapes other than \" and \\, null, and arrays and objects of those, so that's all the parser understands.
+#[derive(PartialEq, Debug)]
+pub enum Json {
+  Null,
+  Number(u64),
+  String(String),
+  Array(Vec<Json>),
+  Object(Vec<(String, Json)>),
+}
+
+impl Json {
+  pub fn parse(text: &str) -> Result<Json, String> {
+    let mut chars = text.chars().peekable();
+    let value = Json::parse_value(&mut chars)?;
+    match Json::skip_whitespace(&mut chars) {
+      None => Ok(value),
+      Some(c) => Err(format!("Unexpected {:?} after the value", c)),
+    }
+  }
+
+  pub fn get(&self, key: &str) -> Option<&Json> {
+    match self {
+      Json::Object(entries) => entries.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+      _ => None,
+    }
+  }
+
+  pub fn as_u64(&self) -> Option<u64> {
+    match self {
+      Json::Number(number) => Some(*number),
+      _ => None,
+    }
+  }
+
+  pub fn as_str(&self) -> Option<&str> {
+    match self {
+      Json::String(string) => Some(string),
+      _ => None,
+    }
+  }
+
+  pub fn as_array(&self) -> Option<&[Json]> {
+    match self {
+      Json::Array(values) => Some(values),
+      _ => None,
+    }
+  }
+
+  fn skip_whitespace(chars: &mut Peekable<Chars>) -> Option<char> {
+    while chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+    chars.peek().copied()
+  }
+
+  fn expect(chars: &mut Peekable<Chars>, expected: char) -> Result<(), String> {
+    match Json::skip_whitespace(chars) {
+      Some(c) if c == expected => {
+        chars.next();
+        Ok(())
+      }
+      c => Err(format!("Expected {:?} but found {:?}", expected, c)),
+    }
+  }
+
+  fn parse_value(chars: &mut Peekable<Chars>) -> Result<Json, String> {
+    match Json::skip_whitespace(chars) {
+      Some('{') => Json::parse_object(chars),
+      Some('[') => Json::parse_array(chars),
+      Some('"') => Json::parse_string(chars).map(Json::String),
+      Some('n') => {
+        "null".chars().try_for_each(|c| Json::expect(chars, c))?;
+        Ok(Json::Null)
+      }
+      Some(c) if c.is_ascii_digit() => {
+        let mut number = 0u64;
+        while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+          number = number.checked_mul(10)
+            .and_then(|number| number.checked_add(digit.to_digit(10).unwrap() as u64))
+            .ok_or("Number out of range")?;
+        }
+        Ok(Json::Number(number))
+      }
+      c => Err(format!("Unexpected {:?}", c)),
+    }
+  }
+
+  fn parse_string(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    Json::expect(chars, '"')?;
+    let mut string = String::new();
+    loop {
+      match chars.next() {
+        Some('"') => return Ok(string),
+        Some('\\') => match chars.next() {
+          Some(c @ ('"' | '\\')) => string.push(c),
+          c => return Err(format!("Unsupported escape {:?}", c)),
+        },
+        Some(c) => string.push(c),
+        None => return Err(String::from("Unterminated string")),
+      }
+    }
+  }
+
+  /// Parses the elements between the brackets, calling parse_element on each.
+  fn parse_elements<T>(
+    chars: &mut Peekable<Chars>,
+    open: char,
+    close: char,
+    mut parse_element: impl FnMut(&mut Peekable<Chars>) -> Result<T, String>,
+  ) -> Result<Vec<T>, String> {
+    Json::expect(chars, open)?;
+    let mut elements = vec![];
+    if Json::skip_whitespace(chars) == Some(close) {
+      chars.next();
+      return Ok(elements);
+    }
+    loop {
+      elements.push(parse_element(chars)?);
+      match Json::skip_whitespace(chars) {
+        Some(',') => { chars.next(); }
+        Some(c) if c == close => {
+          chars.next();
+          return Ok(elements);
+        }
+        c => return Err(format!("Expected ',' or {:?} but found {:?}", close, c)),
+      }
+    }
+  }
+
+  fn parse_array(chars: &mut Peekable<Chars>) -> Result<Json, String> {
+    Json::parse_elements(chars, '[', ']', Json::parse_value).map(Json::Array)
+  }
+
+  fn parse_object(chars: &mut Peekable<Chars>) -> Result<Json, String> {
+    Json::parse_elements(chars, '{', '}', |chars| {
+      let key = Json::parse_string(chars)?;
+      Json::expect(chars, ':')?;
+      Ok((key, Json::parse_value(chars)?))
+    }).map(Json::Object)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use test_case::test_case;
+  use super::*;
+
+  #[test]
+  fn parses_a_test_case() {
+    let text = r#"{"name": "00 \"0\"", "initial": {"pc": 256, "ram": [[256, 0]]}, "cycles": [[256, 0, "r-m"], null]}"#;
+    let case = Json::parse(text).unwrap();
+    assert_eq!(case.get("name").and_then(Json::as_str), Some("00 \"0\""));
+    assert_eq!(case.get("initial").and_then(|initial| initial.get("pc")).and_then(Json::as_u64), Some(256));
+    assert_eq!(case.get("cycles").and_then(Json::as_array).map(|cycles| cycles.len()), Some(2));
+    assert_eq!(case.get("final"), None);
+    assert_eq!(Json::parse("[]"), Ok(Json::Array(vec![])));
+  }
+
+  #[test_case("{\"a\": 1"; "unterminated object")]
+  #[test_case("[1 2]"; "missing comma")]
+  #[test_case("-1"; "negative number")]
+  #[test_case("\"\\n\""; "unsupported escape")]
+  #[test_case("1 1"; "trailing value")]
+  #[test_case("18446744073709551616"; "number out of range")]
+  fn refuses_what_it_does_not_understand(text: &str) {
+    assert!(Json::parse(text).is_err());
+  }
+}
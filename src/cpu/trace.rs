@@ -0,0 +1,83 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+
+pub const DEFAULT_TRACE_CAPACITY: usize = 0x10000;
+
+/// The state of the CPU right before it executes an instruction.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TraceEntry {
+  pub a: u8,
+  pub f: u8,
+  pub b: u8,
+  pub c: u8,
+  pub d: u8,
+  pub e: u8,
+  pub h: u8,
+  pub l: u8,
+  pub sp: u16,
+  pub pc: u16,
+  pub memory: [u8; 4],
+  pub mnemonic: String,
+}
+
+/// Formats the entry as a gameboy-doctor log line, so traces can be compared against reference logs.
+impl Display for TraceEntry {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+      self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l, self.sp, self.pc,
+      self.memory[0], self.memory[1], self.memory[2], self.memory[3]
+    )
+  }
+}
+
+/// A ring buffer holding the most recently executed instructions.
+pub struct Trace {
+  capacity: usize,
+  entries: VecDeque<TraceEntry>,
+}
+
+impl Trace {
+  pub fn new(capacity: usize) -> Trace {
+    Trace {
+      capacity,
+      entries: VecDeque::with_capacity(capacity),
+    }
+  }
+
+  pub fn record(&mut self, entry: TraceEntry) {
+    if self.capacity == 0 {
+      return;
+    }
+    if self.entries.len() == self.capacity {
+      self.entries.pop_front();
+    }
+    self.entries.push_back(entry);
+  }
+
+  pub fn entries(&self) -> impl Iterator<Item=&TraceEntry> {
+    self.entries.iter()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn entry(pc: u16) -> TraceEntry {
+    TraceEntry { a: 0x01, f: 0xB0, b: 0x00, c: 0x13, d: 0x00, e: 0xD8, h: 0x01, l: 0x4D, sp: 0xFFFE, pc, memory: [0x00, 0xC3, 0x13, 0x02], mnemonic: "NOP".to_string() }
+  }
+
+  #[test]
+  fn entry_uses_gameboy_doctor_format() {
+    assert_eq!(entry(0x0100).to_string(), "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02");
+  }
+
+  #[test]
+  fn trace_keeps_most_recent_entries() {
+    let mut trace = Trace::new(2);
+    (0..3).for_each(|pc| trace.record(entry(pc)));
+    assert_eq!(trace.entries().map(|entry| entry.pc).collect::<Vec<u16>>(), vec![1, 2]);
+  }
+}
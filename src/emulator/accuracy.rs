@@ -0,0 +1,9 @@
+use serde::{Deserialize, Serialize};
+
+/// Hardware quirks that are only emulated on request, since they cost time and few games depend on them.
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Debug, Default)]
+pub struct Accuracy {
+  /// On the DMG, 16-bit increments and decrements of a register pointing into 0xFE00-0xFEFF corrupt the OAM row the
+  /// PPU is scanning.
+  pub oam_bug: bool,
+}
@@ -1,24 +1,2436 @@
 use std::cell::RefCell;
 use std::rc::Rc;
+use crate::cpu::call_stack::CallFrame;
+use crate::cpu::cpu::{CPUImpl, CPUInfo, SetRegisterError};
+use crate::cpu::disassembler::{disassemble_with_labels, DisassembledInstruction};
 use crate::cpu::interrupts::{InterruptControllerImpl, InterruptControllerRef};
-use crate::controllers::dma::{DMAControllerImpl};
-use crate::memory::oam::OAMImpl;
-use crate::controllers::timer::TimerController;
+use crate::controllers::buttons::{Button, ButtonController, ButtonControllerImpl};
+use crate::controllers::dma::{DMAController, DMAControllerImpl};
+use crate::controllers::lcd::{LCDController, LCDControllerImpl, LCDDependencies, LCDMode};
+use std::fmt::{Display, Formatter};
+use std::ops::RangeInclusive;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use wasm_bindgen::prelude::*;
+use crate::emulator::replay::{state_hash, InputEvent, NullRenderer, ReplayError, ReplayPosition, ReplayRecording};
+use crate::emulator::save_slots::{SaveSlots, SlotInfo};
+use crate::emulator::symbols::SymbolTable;
+use crate::infrastructure::time::clock::unix_time;
+use crate::emulator::watchpoint::{WatchedMemory, Watchpoint, WatchpointHit};
+use crate::controllers::serial::{SerialController, SerialControllerImpl, SerialLink};
+use crate::controllers::speed::{SpeedController, SpeedControllerImpl};
+use crate::controllers::timer::{TimerController, TimerControllerImpl};
+use crate::emulator::accuracy::Accuracy;
+use crate::memory::boot_rom::BootROM;
+use crate::memory::rom_info::RomInfo;
+use crate::memory::rom_loader::unpack_rom;
+use crate::memory::cartridge_header::{CartridgeHeader, RomError};
+use crate::memory::cram::CRAMImpl;
+use crate::memory::dma::DMAMemoryView;
+use crate::memory::mbc::{create_rom, has_battery, EmulationMode, RumbleMotor, MBC};
+use crate::memory::memory::{CGBMode, HardwareModel, Memory, ROMSize};
+use crate::memory::oam::{OAMCorruption, OAMImpl};
+use crate::memory::stack::Stack;
+use crate::memory::vram::VRAMImpl;
+use crate::memory::wram::WRAM;
+use crate::renderer::js_renderer::JSRenderer;
+use crate::renderer::renderer::Renderer;
+use crate::util::bit_util::BitUtil;
 use crate::MainMemory;
 
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Component {
+  CPU,
+  DMA,
+  Timer,
+  LCD,
+}
+
+/// The order in which the components are ticked during a single M-cycle.
+/// The CPU goes first, so that register writes it performs are visible to the other components in the same cycle.
+pub const TICK_ORDER: [Component; 4] = [Component::CPU, Component::DMA, Component::Timer, Component::LCD];
+
+/// Which components did observable work during a single M-cycle.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct StepResult {
+  pub cpu_executed_microop: bool,
+  pub ppu_mode: LCDMode,
+  pub timer_overflowed: bool,
+  pub dma_active: bool,
+}
+
+/// Why a memory write from outside the CPU was refused or cut short.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum WriteMemoryError {
+  /// Writes from outside the CPU are refused until set_debug_enabled(true) is called.
+  DebugDisabled,
+  /// A range that didn't fit below 0xFFFF was cut short after `bytes_written` bytes.
+  RangeTruncated { bytes_written: usize },
+  /// A ROM patch ran past the end of the `rom_size` byte image, so nothing was written.
+  PatchOutOfRange { rom_size: usize },
+}
+
+impl Display for WriteMemoryError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      WriteMemoryError::DebugDisabled => write!(f, "Debugging is disabled, call set_debug_enabled(true) first"),
+      WriteMemoryError::RangeTruncated { bytes_written } => write!(f, "Write was cut short at 0xFFFF after {} bytes", bytes_written),
+      WriteMemoryError::PatchOutOfRange { rom_size } => write!(f, "ROM patch runs past the end of the {} byte image", rom_size),
+    }
+  }
+}
+
+impl From<WriteMemoryError> for wasm_bindgen::JsValue {
+  fn from(error: WriteMemoryError) -> Self {
+    wasm_bindgen::JsError::new(&error.to_string()).into()
+  }
+}
+
+/// Why a save state was refused.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LoadStateError {
+  /// The state was saved by a different version of the emulator, whose format can't be read.
+  UnsupportedVersion(u32),
+  /// The state belongs to a game with different checksums in its cartridge header.
+  WrongGame,
+  /// The state is truncated or otherwise unreadable.
+  Corrupt,
+  /// Nothing was saved in the slot.
+  EmptySlot(u8),
+}
+
+impl Display for LoadStateError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      LoadStateError::UnsupportedVersion(version) => write!(f, "Save state version {} isn't supported", version),
+      LoadStateError::WrongGame => write!(f, "Save state belongs to a different game"),
+      LoadStateError::Corrupt => write!(f, "Save state is corrupt"),
+      LoadStateError::EmptySlot(slot) => write!(f, "Save slot {} is empty", slot),
+    }
+  }
+}
+
+impl From<LoadStateError> for wasm_bindgen::JsValue {
+  fn from(error: LoadStateError) -> Self {
+    wasm_bindgen::JsError::new(&error.to_string()).into()
+  }
+}
+
+/// Precedes the state of the components in a save state.
+#[derive(Serialize, Deserialize)]
+struct SaveStateHeader {
+  version: u32,
+  header_checksum: u8,
+  global_checksum: u16,
+}
+
+/// What export_replay writes. The header identifies the game like a save state's does, but carries the replay format's
+/// version.
+#[derive(Serialize, Deserialize)]
+struct ReplayBundle {
+  header: SaveStateHeader,
+  accuracy: Accuracy,
+  initial_state: Vec<u8>,
+  events: Vec<InputEvent>,
+  end: ReplayPosition,
+  /// The state_hash of the save state at the end.
+  final_hash: u64,
+}
+
+/// The components following the header of a save state, in the order save_state writes them. The cartridge state is
+/// serialized separately, since the cartridge is only known as a trait object.
+type SaveStateComponents = (
+  CPUImpl,
+  InterruptControllerImpl,
+  TimerControllerImpl,
+  ButtonControllerImpl,
+  SerialControllerImpl,
+  DMAControllerImpl,
+  LCDControllerImpl,
+  VRAMImpl,
+  WRAM,
+  OAMImpl,
+  CRAMImpl,
+  Stack,
+  SpeedControllerImpl,
+  u32,
+  u64,
+  Vec<u8>,
+);
+
+/// Why a call to run_frame returned.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum RunStatus {
+  FrameCompleted,
+  /// The CPU was about to execute the instruction at this address. Calling run_frame again continues the frame.
+  BreakpointHit(u16),
+  /// The CPU accessed a watched address. The M-cycle with the access has completed.
+  WatchpointHit(WatchpointHit),
+  /// A step_over or step_out finished, and the CPU is about to execute the instruction at this address.
+  StepCompleted(u16),
+}
+
+/// Why a call to run_until returned.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum RunResult {
+  /// The CPU is about to execute the instruction at the requested address.
+  AddressReached,
+  BreakpointHit(u16),
+  WatchpointHit(WatchpointHit),
+  /// The M-cycle budget ran out first.
+  CyclesExpired,
+}
+
+/// A temporary breakpoint set by step_over, step_out or run_until.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum StepTarget {
+  /// The instruction after a CALL or RST, once SP is back at the depth the call was made from.
+  Return { pc: u16, sp: u16 },
+  /// The first instruction after a return that pops SP above the given value.
+  /// returning is set while the CPU is about to execute a return instruction.
+  Out { sp: u16, returning: bool },
+  /// The instruction at the given address.
+  Address(u16),
+}
+
+#[wasm_bindgen]
 pub struct Emulator {
+  cpu: CPUImpl,
+  interrupt_controller: InterruptControllerRef,
+  timer: TimerControllerImpl,
+  buttons: ButtonControllerImpl,
+  serial: SerialControllerImpl,
+  dma: DMAControllerImpl,
+  lcd: LCDControllerImpl,
+  rom: Box<dyn MBC>,
+  boot_rom: BootROM,
+  battery: bool,
+  vram: VRAMImpl,
+  wram: WRAM,
+  oam: OAMImpl,
+  cram: CRAMImpl,
+  stack: Stack,
+  renderer: Box<dyn Renderer>,
+  speed: SpeedControllerImpl,
+  breakpoints: Vec<u16>,
+  stopped_at_breakpoint: bool,
+  watchpoints: Vec<Watchpoint>,
+  watchpoint_hit: Option<WatchpointHit>,
+  instruction_pc: u16,
+  frame_cycle: u32,
+  frames: u64,
+  save_slots: SaveSlots,
+  symbols: SymbolTable,
+  step_target: Option<StepTarget>,
+  cgb_mode: CGBMode,
+  header_checksum: u8,
+  global_checksum: u16,
+  rom_size: ROMSize,
+  /// Allows write_memory and patch_rom. On by default in the wasm build, where the debugger UI lives.
+  debug_enabled: bool,
+  accuracy: Accuracy,
+  rom_info: RomInfo,
+  replay_recording: Option<ReplayRecording>,
+  /// The components ticked during the last M-cycle, in the order they were ticked.
+  #[cfg(test)]
+  tick_log: Vec<Component>,
+}
+
+impl Emulator {
+  const M_CYCLES_PER_FRAME: u32 = 17556;
+  const SAVE_STATE_VERSION: u32 = 1;
+  const REPLAY_VERSION: u32 = 1;
+
+  /// Starts in the state the boot ROM leaves behind. The mode decides whether accesses to addresses the cartridge
+  /// doesn't decode panic or are tolerated. The ROM may be zipped. ROMs with a damaged header, or cartridge types that aren't emulated, are
+  /// refused.
+  pub fn new(rom: &[u8], renderer: Box<dyn Renderer>, mode: EmulationMode) -> Result<Emulator, RomError> {
+    let mut emulator = Emulator::create(rom, BootROM::none(), renderer, mode)?;
+    emulator.cpu.init(HardwareModel::from_cgb_mode(emulator.cgb_mode));
+    // The boot ROM leaves the LCD on, with the background enabled and the default palette
+    emulator.lcd.write(0xFF40, 0x91);
+    emulator.lcd.write(0xFF47, 0xFC);
+    Ok(emulator)
+  }
+
+  /// Runs the given DMG or CGB boot ROM from 0x0000 first, until it unmaps itself by writing to BANK.
+  pub fn new_with_boot_rom(rom: &[u8], boot_rom: &[u8], renderer: Box<dyn Renderer>, mode: EmulationMode) -> Result<Emulator, RomError> {
+    Emulator::create(rom, BootROM::new(boot_rom)?, renderer, mode)
+  }
+
+  /// Reads the cartridge header of a ROM, which may be zipped, without starting it.
+  pub fn parse_header(rom: &[u8]) -> Result<RomInfo, RomError> {
+    RomInfo::parse(&unpack_rom(rom)?)
+  }
+
+  pub fn rom_info(&self) -> RomInfo {
+    self.rom_info.clone()
+  }
+
+  fn create(rom: &[u8], boot_rom: BootROM, renderer: Box<dyn Renderer>, mode: EmulationMode) -> Result<Emulator, RomError> {
+    let rom = &*unpack_rom(rom)?;
+    let header = CartridgeHeader::parse(rom)?;
+    let cgb_mode = if header.cgb_flag.get_bit(7) { CGBMode::from_byte(header.cgb_flag) } else { CGBMode::Monochrome };
+    Ok(Emulator {
+      cpu: CPUImpl::new(),
+      interrupt_controller: Rc::new(RefCell::new(InterruptControllerImpl::new())),
+      timer: TimerControllerImpl::new(),
+      buttons: ButtonControllerImpl::new(),
+      serial: SerialControllerImpl::new(),
+      dma: DMAControllerImpl::new(),
+      lcd: LCDControllerImpl::new(cgb_mode),
+      rom: create_rom(rom, mode)?,
+      boot_rom,
+      battery: has_battery(rom),
+      vram: VRAMImpl::new(),
+      wram: WRAM::new(),
+      oam: OAMImpl::new(),
+      cram: CRAMImpl::new(),
+      stack: Stack::new(),
+      renderer,
+      speed: SpeedControllerImpl::new(cgb_mode),
+      breakpoints: vec![],
+      stopped_at_breakpoint: false,
+      watchpoints: vec![],
+      watchpoint_hit: None,
+      instruction_pc: 0,
+      frame_cycle: 0,
+      frames: 0,
+      save_slots: SaveSlots::new(),
+      symbols: SymbolTable::new(),
+      step_target: None,
+      cgb_mode,
+      header_checksum: header.header_checksum,
+      global_checksum: header.global_checksum,
+      rom_size: header.rom_size,
+      debug_enabled: cfg!(target_arch = "wasm32"),
+      accuracy: Accuracy::default(),
+      rom_info: RomInfo::parse(rom)?,
+      replay_recording: None,
+      #[cfg(test)]
+      tick_log: vec![],
+    })
+  }
+
+  /// Advances every component by exactly one M-cycle, in the order given by TICK_ORDER.
+  pub fn step_m_cycle(&mut self) -> StepResult {
+    self.stopped_at_breakpoint = false;
+    self.frame_cycle += 1;
+    let mut cpu_executed_microop = false;
+    let mut timer_overflowed = false;
+    let mut dma_active = false;
+    #[cfg(test)]
+    self.tick_log.clear();
+    for component in TICK_ORDER {
+      match component {
+        Component::CPU => {
+          let mut interrupt_controller = Rc::clone(&self.interrupt_controller);
+          let mut mapped_interrupt_controller = Rc::clone(&self.interrupt_controller);
+          let mut memory = MainMemory {
+            rom: &mut *self.rom,
+            boot_rom: &mut self.boot_rom,
+            vram: &mut self.vram,
+            wram: &mut self.wram,
+            oam: &mut self.oam,
+            cram: &mut self.cram,
+            lcd: &mut self.lcd,
+            timer: &mut self.timer,
+            buttons: &mut self.buttons,
+            serial: &mut self.serial,
+            dma: &mut self.dma,
+            speed: &mut self.speed,
+            stack: &mut self.stack,
+            interrupt_controller: &mut mapped_interrupt_controller,
+            cgb_mode: self.cgb_mode,
+            debug_peek: false,
+          };
+          if self.watchpoints.is_empty() {
+            cpu_executed_microop = self.cpu.tick(&mut memory, &mut interrupt_controller);
+          } else {
+            if self.cpu.at_instruction_boundary() {
+              self.instruction_pc = self.cpu.program_counter();
+            }
+            let mut watched_memory = WatchedMemory::new(&mut memory, &self.watchpoints, self.instruction_pc);
+            cpu_executed_microop = self.cpu.tick(&mut watched_memory, &mut interrupt_controller);
+            self.watchpoint_hit = watched_memory.hit();
+          }
+          #[cfg(test)]
+          self.tick_log.push(Component::CPU);
+          if let Some(corruption) = self.cpu.take_oam_corruption() {
+            self.apply_oam_corruption(corruption);
+          }
+          if self.cpu.take_stop() {
+            // The speed switch ends STOP by itself, otherwise the CPU waits for a button press
+            if self.speed.switch_armed() {
+              self.speed.switch_speed();
+              self.cpu.leave_stop();
+            }
+            self.timer.write(0xFF04, 0x00);
+          }
+        }
+        Component::DMA => {
+          dma_active = self.dma.active();
+          let mut memory = DMAMemoryView {
+            rom: &*self.rom,
+            vram: &mut self.vram,
+            wram: &self.wram,
+            oam: &mut self.oam,
+          };
+          self.dma.tick(&mut memory, &mut self.cpu, &self.lcd, self.speed.double_speed());
+          #[cfg(test)]
+          self.tick_log.push(Component::DMA);
+        }
+        Component::Timer => {
+          timer_overflowed = self.timer.tick(&mut Rc::clone(&self.interrupt_controller));
+          #[cfg(test)]
+          self.tick_log.push(Component::Timer);
+          // The serial clock is derived from the same system counter as the timer
+          self.serial.tick(&mut Rc::clone(&self.interrupt_controller));
+          self.rom.handle_tick(self.speed.double_speed());
+        }
+        Component::LCD => {
+          let mut interrupt_controller = Rc::clone(&self.interrupt_controller);
+          self.lcd.handle_tick(LCDDependencies {
+            renderer: &mut *self.renderer,
+            interrupt_controller: &mut interrupt_controller,
+            cram: &self.cram,
+            oam: &self.oam,
+            vram: &self.vram,
+          }, self.speed.double_speed());
+          #[cfg(test)]
+          self.tick_log.push(Component::LCD);
+        }
+      }
+    }
+    StepResult {
+      cpu_executed_microop,
+      ppu_mode: self.lcd.get_mode(),
+      timer_overflowed,
+      dma_active,
+    }
+  }
+
+  /// Steps until the CPU has finished its current instruction (or interrupt dispatch) and returns the number of M-cycles
+  /// that took. All other components are ticked along with the CPU.
+  pub fn step_instruction(&mut self) -> u32 {
+    let mut cycles = 0;
+    loop {
+      self.step_m_cycle();
+      cycles += 1;
+      if self.cpu.at_instruction_boundary() {
+        return cycles;
+      }
+    }
+  }
+
+  /// Serializes the CPU. The emulator first steps to the next instruction boundary, since the CPU can't be serialized
+  /// halfway through an instruction.
+  pub fn snapshot_cpu<S: Serializer>(&mut self, serializer: S) -> Result<S::Ok, S::Error> {
+    while !self.cpu.at_instruction_boundary() {
+      self.step_m_cycle();
+    }
+    self.cpu.serialize(serializer)
+  }
+
+  pub fn restore_cpu<'de, D: Deserializer<'de>>(&mut self, deserializer: D) -> Result<(), D::Error> {
+    self.cpu = CPUImpl::deserialize(deserializer)?;
+    Ok(())
+  }
+
+  /// Serializes the state of the whole machine, including the cartridge RAM and clock. The emulator first steps to the
+  /// next instruction boundary, like snapshot_cpu does. Breakpoints, watchpoints, symbols and the debugging aids aren't
+  /// part of the state.
+  pub fn save_state(&mut self) -> Vec<u8> {
+    self.finish_instruction();
+    let header = SaveStateHeader {
+      version: Emulator::SAVE_STATE_VERSION,
+      header_checksum: self.header_checksum,
+      global_checksum: self.global_checksum,
+    };
+    let mut state = bincode::serialize(&header).unwrap();
+    bincode::serialize_into(&mut state, &(
+      &self.cpu,
+      &*self.interrupt_controller.borrow(),
+      &self.timer,
+      &self.buttons,
+      &self.serial,
+      &self.dma,
+      &self.lcd,
+      &self.vram,
+      &self.wram,
+      &self.oam,
+      &self.cram,
+      &self.stack,
+      &self.speed,
+      self.frame_cycle,
+      self.frames,
+      self.rom.save_state(),
+    )).unwrap();
+    state
+  }
+
+  /// Restores a state from save_state. Nothing changes if the state is refused.
+  pub fn load_state(&mut self, state: &[u8]) -> Result<(), LoadStateError> {
+    let mut reader = state;
+    let header: SaveStateHeader = bincode::deserialize_from(&mut reader).map_err(|_| LoadStateError::Corrupt)?;
+    if header.version != Emulator::SAVE_STATE_VERSION {
+      return Err(LoadStateError::UnsupportedVersion(header.version));
+    }
+    if header.header_checksum != self.header_checksum || header.global_checksum != self.global_checksum {
+      return Err(LoadStateError::WrongGame);
+    }
+    let (cpu, interrupt_controller, timer, buttons, serial, dma, lcd, vram, wram, oam, cram, stack, speed, frame_cycle, frames, rom): SaveStateComponents = bincode::deserialize_from(&mut reader).map_err(|_| LoadStateError::Corrupt)?;
+    self.rom.load_state(&rom).map_err(|_| LoadStateError::Corrupt)?;
+    self.cpu = cpu;
+    *self.interrupt_controller.borrow_mut() = interrupt_controller;
+    self.timer = timer;
+    self.buttons = buttons;
+    self.serial.restore(serial);
+    self.dma = dma;
+    self.lcd = lcd;
+    self.vram = vram;
+    self.wram = wram;
+    self.oam = oam;
+    self.cram = cram;
+    self.stack = stack;
+    self.speed = speed;
+    self.frame_cycle = frame_cycle;
+    self.frames = frames;
+    // States don't record the boot ROM, so they're assumed to be saved after booting
+    self.boot_rom.unmap();
+    self.stopped_at_breakpoint = false;
+    self.watchpoint_hit = None;
+    self.step_target = None;
+    Ok(())
+  }
+
+  /// Saves the state into one of SaveSlots::COUNT slots kept in memory, replacing what it held.
+  pub fn save_slot(&mut self, slot: u8) {
+    let state = self.save_state();
+    self.save_slots.store(slot, self.frames, unix_time(), state);
+  }
+
+  pub fn load_slot(&mut self, slot: u8) -> Result<(), LoadStateError> {
+    let state = self.save_slots.state(slot).ok_or(LoadStateError::EmptySlot(slot))?.to_vec();
+    self.load_state(&state)
+  }
+
+  pub fn has_slot(&self, slot: u8) -> bool {
+    self.save_slots.state(slot).is_some()
+  }
+
+  pub fn clear_slot(&mut self, slot: u8) {
+    self.save_slots.clear(slot);
+  }
+
+  /// The state saved in a slot, for keeping it beyond the lifetime of the emulator.
+  pub fn slot_state(&self, slot: u8) -> Option<Vec<u8>> {
+    self.save_slots.state(slot).map(<[u8]>::to_vec)
+  }
+
+  /// The occupied slots, in order.
+  pub fn slot_info(&self) -> Vec<SlotInfo> {
+    self.save_slots.info()
+  }
+
+  pub fn set_accuracy(&mut self, accuracy: Accuracy) {
+    self.accuracy = accuracy;
+  }
+
+  /// Only the DMG has the OAM corruption bug.
+  fn apply_oam_corruption(&mut self, corruption: OAMCorruption) {
+    if !self.accuracy.oam_bug || self.cgb_mode != CGBMode::Monochrome {
+      return;
+    }
+    if let Some(row) = self.lcd.oam_scan_row() {
+      self.oam.corrupt(row, corruption);
+    }
+  }
+
+  /// The number of frames completed since the emulator started.
+  pub fn frames(&self) -> u64 {
+    self.frames
+  }
+
+  fn main_memory<'a>(&'a mut self, interrupt_controller: &'a mut InterruptControllerRef) -> MainMemory<'a> {
+    MainMemory {
+      rom: &mut *self.rom,
+      boot_rom: &mut self.boot_rom,
+      vram: &mut self.vram,
+      wram: &mut self.wram,
+      oam: &mut self.oam,
+      cram: &mut self.cram,
+      lcd: &mut self.lcd,
+      timer: &mut self.timer,
+      buttons: &mut self.buttons,
+      serial: &mut self.serial,
+      dma: &mut self.dma,
+      speed: &mut self.speed,
+      stack: &mut self.stack,
+      interrupt_controller,
+      cgb_mode: self.cgb_mode,
+      debug_peek: false,
+    }
+  }
+
+  /// Writes the bytes starting at the given address, exactly as if the CPU wrote them. Needs debugging enabled.
+  pub fn write_memory_range(&mut self, address: u16, bytes: &[u8]) -> Result<(), WriteMemoryError> {
+    self.check_debug_enabled()?;
+    let mut interrupt_controller = Rc::clone(&self.interrupt_controller);
+    let mut memory = self.main_memory(&mut interrupt_controller);
+    Emulator::write_range(address, bytes.iter().copied(), |address, value| memory.write(address, value))
+  }
+
+  /// Writes `len` copies of the value starting at the given address, exactly as if the CPU wrote them. Needs debugging
+  /// enabled.
+  pub fn fill_memory(&mut self, address: u16, len: u16, value: u8) -> Result<(), WriteMemoryError> {
+    self.check_debug_enabled()?;
+    let mut interrupt_controller = Rc::clone(&self.interrupt_controller);
+    let mut memory = self.main_memory(&mut interrupt_controller);
+    Emulator::write_range(address, (0..len).map(|_| value), |address, value| memory.write(address, value))
+  }
+
+  pub fn set_debug_enabled(&mut self, enabled: bool) {
+    self.debug_enabled = enabled;
+  }
+
+  /// Writes a byte as if the CPU wrote it, for debuggers and cheats. Needs debugging enabled.
+  pub fn write_memory(&mut self, address: u16, value: u8) -> Result<(), WriteMemoryError> {
+    self.check_debug_enabled()?;
+    let mut interrupt_controller = Rc::clone(&self.interrupt_controller);
+    self.main_memory(&mut interrupt_controller).write(address, value);
+    Ok(())
+  }
+
+  /// Overwrites bytes of the loaded ROM image, at an offset into the image rather than an address, so any bank can be
+  /// patched. Needs debugging enabled. A patch that runs past the end of the image is refused as a whole.
+  pub fn patch_rom(&mut self, offset: usize, bytes: &[u8]) -> Result<(), WriteMemoryError> {
+    self.check_debug_enabled()?;
+    let rom_size = self.rom_size.bytes();
+    if offset > rom_size || bytes.len() > rom_size - offset {
+      return Err(WriteMemoryError::PatchOutOfRange { rom_size });
+    }
+    self.rom.load_bytes(offset, bytes);
+    Ok(())
+  }
+
+  fn check_debug_enabled(&self) -> Result<(), WriteMemoryError> {
+    if self.debug_enabled { Ok(()) } else { Err(WriteMemoryError::DebugDisabled) }
+  }
+
+  /// Like write_memory_range, but bypasses access restrictions:
+  /// ROM addresses are written into the ROM image at that offset, and VRAM and OAM are written even while the LCD uses
+  /// them. Needs debugging enabled.
+  pub fn write_memory_range_raw(&mut self, address: u16, bytes: &[u8]) -> Result<(), WriteMemoryError> {
+    self.check_debug_enabled()?;
+    let mut interrupt_controller = Rc::clone(&self.interrupt_controller);
+    Emulator::write_range(address, bytes.iter().copied(), |address, value| match address {
+      0x0000..=0x7FFF => self.rom.load_byte(address as usize, value),
+      0x8000..=0x9FFF => self.vram.write(address, value),
+      0xFE00..=0xFE9F => self.oam.write(address, value),
+      _ => self.main_memory(&mut interrupt_controller).write(address, value)
+    })
+  }
 
+  fn write_range(address: u16, values: impl Iterator<Item=u8>, mut write: impl FnMut(u16, u8)) -> Result<(), WriteMemoryError> {
+    let available = 0x10000 - address as usize;
+    for (offset, value) in values.enumerate() {
+      if offset == available {
+        return Err(WriteMemoryError::RangeTruncated { bytes_written: offset });
+      }
+      write(address + offset as u16, value);
+    }
+    Ok(())
+  }
+
+  /// Runs the rest of the current frame's worth of M-cycles, unless a breakpoint is hit first.
+  /// In double speed mode, twice as many M-cycles fit in a frame.
+  pub fn run_frame(&mut self) -> RunStatus {
+    let m_cycles = self.frame_m_cycles().saturating_sub(self.frame_cycle);
+    match self.run(m_cycles as u64) {
+      Some(status) => status,
+      None => {
+        self.frame_cycle = 0;
+        self.frames += 1;
+        RunStatus::FrameCompleted
+      }
+    }
+  }
+
+  /// Runs until the CPU is about to execute the instruction at the given address, or the given number of M-cycles has
+  /// passed. Returns immediately if the CPU is already there. Breakpoints and watchpoints stop it like they stop
+  /// run_frame.
+  pub fn run_until(&mut self, address: u16, max_cycles: u64) -> RunResult {
+    self.step_target = Some(StepTarget::Address(address));
+    let status = self.run(max_cycles);
+    self.step_target = None;
+    self.frames += (self.frame_cycle / self.frame_m_cycles()) as u64;
+    self.frame_cycle %= self.frame_m_cycles();
+    match status {
+      Some(RunStatus::StepCompleted(_)) => RunResult::AddressReached,
+      Some(RunStatus::BreakpointHit(address)) => RunResult::BreakpointHit(address),
+      Some(RunStatus::WatchpointHit(hit)) => RunResult::WatchpointHit(hit),
+      Some(RunStatus::FrameCompleted) | None => RunResult::CyclesExpired,
+    }
+  }
+
+  /// Executes the current instruction. A CALL or RST runs until it has returned to the next instruction at the same
+  /// stack depth, which skips recursive calls and interrupts handled in between.
+  /// Like run_frame, this stops at the end of the frame, at breakpoints and at watchpoints. A breakpoint or watchpoint
+  /// cancels the step, while at the end of the frame it stays pending and a later run_frame reports StepCompleted.
+  pub fn step_over(&mut self) -> RunStatus {
+    self.finish_instruction();
+    let pc = self.cpu.program_counter();
+    let mut interrupt_controller = Rc::clone(&self.interrupt_controller);
+    let length = match self.main_memory(&mut interrupt_controller).read(pc) {
+      0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC => 3,
+      opcode if opcode & 0xC7 == 0xC7 => 1,
+      _ => {
+        self.step_instruction();
+        return RunStatus::StepCompleted(self.cpu.program_counter());
+      }
+    };
+    self.step_target = Some(StepTarget::Return { pc: pc.wrapping_add(length), sp: self.cpu.stack_pointer() });
+    self.stopped_at_breakpoint = true;
+    self.run_frame()
+  }
+
+  /// Runs until the current call frame returns, that is until a return instruction pops SP above its current value.
+  /// Stops like step_over does.
+  pub fn step_out(&mut self) -> RunStatus {
+    self.finish_instruction();
+    self.step_target = Some(StepTarget::Out { sp: self.cpu.stack_pointer(), returning: false });
+    self.stopped_at_breakpoint = true;
+    self.run_frame()
+  }
+
+  pub fn add_breakpoint(&mut self, address: u16) {
+    if let Err(index) = self.breakpoints.binary_search(&address) {
+      self.breakpoints.insert(index, address);
+    }
+  }
+
+  pub fn remove_breakpoint(&mut self, address: u16) {
+    if let Ok(index) = self.breakpoints.binary_search(&address) {
+      self.breakpoints.remove(index);
+    }
+  }
+
+  pub fn clear_breakpoints(&mut self) {
+    self.breakpoints.clear();
+  }
+
+  /// Stops run_frame when the CPU reads (on_read) or writes (on_write) any of the given addresses.
+  /// The reported PC is the address of the instruction that made the access.
+  pub fn add_watchpoint(&mut self, addresses: RangeInclusive<u16>, on_read: bool, on_write: bool) {
+    self.watchpoints.push(Watchpoint { addresses, on_read, on_write });
+  }
+
+  pub fn clear_watchpoints(&mut self) {
+    self.watchpoints.clear();
+  }
+
+  /// Pressing a button also wakes the CPU from STOP.
+  pub fn press_button(&mut self, button: Button) {
+    self.record_input(button, true);
+    self.buttons.press(button, &mut Rc::clone(&self.interrupt_controller));
+    self.cpu.leave_stop();
+  }
+
+  pub fn release_button(&mut self, button: Button) {
+    self.record_input(button, false);
+    self.buttons.release(button);
+  }
+
+  /// Starts recording the buttons pressed from the current state on, for export_replay. Restarts a recording in
+  /// progress.
+  pub fn start_replay_recording(&mut self) {
+    let initial_state = self.save_state();
+    self.replay_recording = Some(ReplayRecording { initial_state, events: vec![] });
+  }
+
+  /// Stops recording and bundles the recording with the accuracy settings and a hash of the current state, or returns
+  /// None if nothing was recorded. The session is expected to be played with run_frame, since verify_replay replays
+  /// the input at the same frame and M-cycle through run_frame.
+  pub fn export_replay(&mut self) -> Option<Vec<u8>> {
+    let recording = self.replay_recording.take()?;
+    let final_hash = state_hash(&self.save_state());
+    let bundle = ReplayBundle {
+      header: SaveStateHeader {
+        version: Emulator::REPLAY_VERSION,
+        header_checksum: self.header_checksum,
+        global_checksum: self.global_checksum,
+      },
+      accuracy: self.accuracy,
+      initial_state: recording.initial_state,
+      events: recording.events,
+      end: self.replay_position(),
+      final_hash,
+    };
+    Some(bincode::serialize(&bundle).unwrap())
+  }
+
+  /// Replays an exported replay of the ROM on a new emulator without a renderer, and checks that it ends in the
+  /// recorded state.
+  pub fn verify_replay(rom: &[u8], replay: &[u8]) -> Result<(), ReplayError> {
+    let bundle: ReplayBundle = bincode::deserialize(replay).map_err(|_| ReplayError::Corrupt)?;
+    if bundle.header.version != Emulator::REPLAY_VERSION {
+      return Err(ReplayError::UnsupportedVersion(bundle.header.version));
+    }
+    let mut emulator = Emulator::new(rom, Box::new(NullRenderer), EmulationMode::Lenient)
+      .map_err(ReplayError::InvalidRom)?;
+    let header = &bundle.header;
+    if header.header_checksum != emulator.header_checksum || header.global_checksum != emulator.global_checksum {
+      return Err(ReplayError::WrongGame);
+    }
+    emulator.load_state(&bundle.initial_state).map_err(|_| ReplayError::Corrupt)?;
+    emulator.set_accuracy(bundle.accuracy);
+    for event in bundle.events {
+      emulator.run_to(event.position);
+      if event.pressed {
+        emulator.press_button(event.button);
+      } else {
+        emulator.release_button(event.button);
+      }
+    }
+    emulator.run_to(bundle.end);
+    let actual = state_hash(&emulator.save_state());
+    if actual != bundle.final_hash {
+      return Err(ReplayError::HashMismatch { expected: bundle.final_hash, actual });
+    }
+    Ok(())
+  }
+
+  fn replay_position(&self) -> ReplayPosition {
+    ReplayPosition { frame: self.frames, frame_cycle: self.frame_cycle }
+  }
+
+  fn record_input(&mut self, button: Button, pressed: bool) {
+    let position = self.replay_position();
+    if let Some(recording) = self.replay_recording.as_mut() {
+      recording.events.push(InputEvent { position, button, pressed });
+    }
+  }
+
+  /// Runs whole frames up to the position's frame, then the M-cycles into it.
+  fn run_to(&mut self, position: ReplayPosition) {
+    while self.frames < position.frame {
+      self.run_frame();
+    }
+    self.run(position.frame_cycle.saturating_sub(self.frame_cycle) as u64);
+  }
+
+  /// The contents of the cartridge's .sav file, including the real time clock of MBC3 cartridges. Cartridges without a
+  /// battery lose their RAM when switched off, so their save is empty.
+  pub fn export_save(&self) -> Vec<u8> {
+    if !self.battery {
+      return vec![];
+    }
+    self.rom.export_save(unix_time())
+  }
+
+  /// Loads a .sav file. A saved real time clock is advanced by the time that has passed since it was saved.
+  /// Saves that don't match the cartridge RAM size are cut off or padded with zeros.
+  pub fn import_save(&mut self, save: &[u8]) {
+    if !self.battery {
+      return;
+    }
+    let now = unix_time();
+    if save.len() > self.rom.export_save(now).len() {
+      #[cfg(target_arch = "wasm32")]
+      web_sys::console::warn_1(&format!("Save of {} bytes is larger than the cartridge can hold", save.len()).into());
+    }
+    self.rom.import_save(save, now);
+  }
+
+  /// Whether the game finished writing its save since the last call, which is a good moment to export it.
+  pub fn take_save_dirty(&mut self) -> bool {
+    self.battery && self.rom.take_save_dirty()
+  }
+
+  /// Removes and returns the bytes the game sent over the serial port since the last call.
+  pub fn take_serial_output(&mut self) -> Vec<u8> {
+    self.serial.take_output()
+  }
+
+  /// Plugs a link cable partner into the serial port, replacing any connected before.
+  pub fn connect_serial(&mut self, link: Box<dyn SerialLink>) {
+    self.serial.connect(link);
+  }
+
+  pub fn disconnect_serial(&mut self) {
+    self.serial.disconnect();
+  }
+
+  /// Only MBC5 rumble cartridges drive the motor.
+  pub fn connect_rumble(&mut self, motor: Box<dyn RumbleMotor>) {
+    self.rom.connect_rumble(motor);
+  }
+
+  /// Tilts the cartridge. Only the MBC7 has an accelerometer.
+  pub fn set_accelerometer(&mut self, x: f32, y: f32) {
+    self.rom.set_acceleration(x, y);
+  }
+
+  pub fn cpu_info(&self) -> CPUInfo {
+    self.cpu.info(&self.interrupt_controller)
+  }
+
+  /// Writes a CPU register by name, like AF or A. Refused while an instruction is in progress, so step to the next
+  /// instruction first.
+  pub fn set_register(&mut self, name: &str, value: u16) -> Result<(), SetRegisterError> {
+    self.cpu.set_register(name, value)
+  }
+
+  /// Reads the given number of bytes through the memory map, like the CPU would, wrapping around at 0xFFFF.
+  pub fn read_memory_range(&mut self, address: u16, length: u16) -> Vec<u8> {
+    let mut interrupt_controller = Rc::clone(&self.interrupt_controller);
+    let memory = self.main_memory(&mut interrupt_controller);
+    (0..length).map(|offset| memory.read(address.wrapping_add(offset))).collect()
+  }
+
+  /// Disassembles the instructions in the given number of bytes, starting at the given address.
+  /// Call and jump targets are shown by their label if one was loaded for the currently mapped bank.
+  pub fn disassemble(&mut self, address: u16, length: u16) -> Vec<DisassembledInstruction> {
+    let bytes = self.read_memory_range(address, length);
+    disassemble_with_labels(&bytes, address, &|target| self.label(target))
+  }
+
+  /// Loads the labels from the contents of an RGBDS .sym file, replacing any loaded before.
+  pub fn load_symbols(&mut self, text: &str) {
+    self.symbols = SymbolTable::parse(text);
+  }
+
+  fn label(&self, address: u16) -> Option<String> {
+    let bank = match address {
+      0x0000..=0x7FFF => self.rom.rom_bank(address),
+      0xD000..=0xDFFF => self.wram.bank_index() as usize,
+      _ => 0
+    };
+    self.symbols.label(bank, address).map(String::from)
+  }
+
+  /// Starts recording executed instructions. DEFAULT_TRACE_CAPACITY is a sensible capacity for comparing against
+  /// reference logs.
+  pub fn enable_trace(&mut self, capacity: usize) {
+    self.cpu.enable_trace(capacity);
+  }
+
+  pub fn disable_trace(&mut self) {
+    self.cpu.disable_trace();
+  }
+
+  /// Returns the recorded instructions as gameboy-doctor log lines, oldest first.
+  pub fn dump_trace(&self) -> String {
+    self.cpu.trace()
+      .map(|trace| trace.entries().map(|entry| format!("{}\n", entry)).collect())
+      .unwrap_or_default()
+  }
+
+  /// Starts counting executed opcodes and the 4KB regions they are executed from.
+  pub fn enable_profiler(&mut self) {
+    self.cpu.enable_profiler();
+  }
+
+  pub fn disable_profiler(&mut self) {
+    self.cpu.disable_profiler();
+  }
+
+  /// Returns the opcode and region counts as tables sorted by count, or an empty string if the profiler is disabled.
+  pub fn profiler_report(&self) -> String {
+    self.cpu.profiler().map(|profiler| profiler.report()).unwrap_or_default()
+  }
+
+  /// Starts tracking CALL, RST and interrupt dispatches for call_stack.
+  pub fn enable_call_stack(&mut self) {
+    self.cpu.enable_call_stack();
+  }
+
+  pub fn disable_call_stack(&mut self) {
+    self.cpu.disable_call_stack();
+  }
+
+  /// The calls that haven't returned yet, outermost first. Empty unless call stack tracking is enabled.
+  pub fn call_stack(&self) -> Vec<CallFrame> {
+    self.cpu.call_stack().to_vec()
+  }
+
+  /// In double speed mode, twice as many M-cycles fit in a frame.
+  fn frame_m_cycles(&self) -> u32 {
+    if self.speed.double_speed() { 2 * Emulator::M_CYCLES_PER_FRAME } else { Emulator::M_CYCLES_PER_FRAME }
+  }
+
+  /// Runs the given number of M-cycles, unless the step target, a breakpoint or a watchpoint stops it first.
+  fn run(&mut self, m_cycles: u64) -> Option<RunStatus> {
+    for _ in 0..m_cycles {
+      if self.step_target_reached() {
+        self.step_target = None;
+        self.stopped_at_breakpoint = true;
+        return Some(RunStatus::StepCompleted(self.cpu.program_counter()));
+      }
+      if !self.stopped_at_breakpoint && self.breakpoint_reached() {
+        self.step_target = None;
+        self.stopped_at_breakpoint = true;
+        return Some(RunStatus::BreakpointHit(self.cpu.program_counter()));
+      }
+      self.step_m_cycle();
+      if let Some(hit) = self.watchpoint_hit.take() {
+        self.step_target = None;
+        return Some(RunStatus::WatchpointHit(hit));
+      }
+    }
+    None
+  }
+
+  fn finish_instruction(&mut self) {
+    while !self.cpu.at_instruction_boundary() {
+      self.step_m_cycle();
+    }
+  }
+
+  fn step_target_reached(&mut self) -> bool {
+    if !self.cpu.at_instruction_boundary() {
+      return false;
+    }
+    let pc = self.cpu.program_counter();
+    let sp = self.cpu.stack_pointer();
+    match self.step_target {
+      Some(StepTarget::Return { pc: return_pc, sp: call_sp }) => pc == return_pc && sp >= call_sp,
+      Some(StepTarget::Address(address)) => pc == address,
+      Some(StepTarget::Out { sp: frame_sp, returning }) => {
+        if returning && sp > frame_sp {
+          return true;
+        }
+        let mut interrupt_controller = Rc::clone(&self.interrupt_controller);
+        // RET, RETI and RET cc
+        let returning = matches!(self.main_memory(&mut interrupt_controller).read(pc), 0xC0 | 0xC8 | 0xC9 | 0xD0 | 0xD8 | 0xD9);
+        self.step_target = Some(StepTarget::Out { sp: frame_sp, returning });
+        false
+      }
+      None => false
+    }
+  }
+
+  fn breakpoint_reached(&self) -> bool {
+    !self.breakpoints.is_empty() &&
+      self.cpu.at_instruction_boundary() &&
+      self.breakpoints.binary_search(&self.cpu.program_counter()).is_ok()
+  }
 }
 
+/// The part of the API the frontend calls from JS.
+#[wasm_bindgen]
 impl Emulator {
+  /// Starts a game for the frontend, tolerating accesses to addresses the cartridge doesn't decode. Pixels are drawn
+  /// through the given function, see JSRenderer. A refused ROM throws an error with the reason instead of aborting the
+  /// module.
+  #[wasm_bindgen(constructor)]
+  pub fn from_js(rom: &[u8], draw_pixel: js_sys::Function) -> Result<Emulator, JsError> {
+    Ok(Emulator::new(rom, Box::new(JSRenderer::new(draw_pixel)), EmulationMode::Lenient)?)
+  }
+
+  /// Reads the given number of bytes through the memory map like read_memory_range, but as a debugger sees it: VRAM,
+  /// OAM and the palettes are readable even while the LCD is using them. JS receives the bytes as a Uint8Array.
+  pub fn read_memory(&mut self, address: u16, length: u16) -> Vec<u8> {
+    let mut interrupt_controller = Rc::clone(&self.interrupt_controller);
+    let mut memory = self.main_memory(&mut interrupt_controller);
+    memory.debug_peek = true;
+    (0..length).map(|offset| memory.read(address.wrapping_add(offset))).collect()
+  }
+
+  pub fn read_byte(&mut self, address: u16) -> u8 {
+    self.read_memory(address, 1)[0]
+  }
+}
+
+/// Lets two emulators be linked with a cable: wrap both in Rc<RefCell<…>> and connect each to a Weak of the other, so
+/// neither keeps the other alive.
+impl SerialLink for Emulator {
+  fn exchange(&mut self, out_byte: u8) -> Option<u8> {
+    self.serial.exchange(out_byte)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use assert_hex::assert_eq_hex;
+  use crate::cpu::interrupts::{Interrupt, InterruptController};
+  use crate::cpu::trace::DEFAULT_TRACE_CAPACITY;
+  use crate::memory::cartridge_header::header_checksum;
+  use crate::renderer::renderer::{Color, MockRenderer};
+  use std::collections::hash_map::DefaultHasher;
+  use std::collections::HashMap;
+  use std::hash::{Hash, Hasher};
+  use test_case::test_case;
+  use super::*;
+
+  /// Places the program at 0x0100, where execution starts after the boot ROM.
+  fn create_emulator(program: &[u8]) -> Emulator {
+    let mut rom = vec![0u8; 0x8000];
+    rom[0x0100..0x0100 + program.len()].copy_from_slice(program);
+    rom[0x014D] = header_checksum(&rom);
+    let mut renderer = MockRenderer::new();
+    renderer.expect_draw_pixel().return_const(());
+    Emulator::new(&rom, Box::new(renderer), EmulationMode::Strict).unwrap()
+  }
+
+  #[test]
+  fn new_refuses_damaged_rom() {
+    let mut rom = vec![0u8; 0x8000];
+    rom[0x014D] = header_checksum(&rom);
+    let create = |rom: &[u8]| Emulator::new(rom, Box::new(MockRenderer::new()), EmulationMode::Strict).err();
+    assert_eq!(create(&rom[..0x4000]), Some(RomError::ROMSizeMismatch { declared: 0x8000, actual: 0x4000 }));
+    rom[0x0147] = 0xFD;
+    rom[0x014D] = header_checksum(&rom);
+    assert_eq!(create(&rom), Some(RomError::UnsupportedCartridgeType(0xFD)));
+  }
+
+  #[test]
+  fn new_explains_why_a_rom_is_refused() {
+    let message = |rom: &[u8]| Emulator::new(rom, Box::new(MockRenderer::new()), EmulationMode::Strict).err().unwrap().to_string();
+    assert_eq!(message(&[]), "Invalid ROM: 0 bytes is too short for a cartridge header");
+    let mut rom = vec![0u8; 0x8000];
+    rom[0x0147] = 0xFD;
+    rom[0x014D] = header_checksum(&rom);
+    assert_eq!(message(&rom), "Unsupported cartridge type 0xfd");
+  }
+
+  #[test]
+  fn step_m_cycle_ticks_components_in_tick_order() {
+    // Changing the order has to be a conscious decision
+    assert_eq!(TICK_ORDER, [Component::CPU, Component::DMA, Component::Timer, Component::LCD]);
+    // LD A,0xC0; LDH (0x46),A starts an OAM DMA, so every component has work to do
+    let mut emulator = create_emulator(&[0x3E, 0xC0, 0xE0, 0x46, 0x18, 0xFE]);
+    let mut dma_seen = false;
+    for _ in 0..40 {
+      dma_seen |= emulator.step_m_cycle().dma_active;
+      assert_eq!(emulator.tick_log, TICK_ORDER);
+    }
+    assert!(dma_seen);
+  }
+
+  #[test]
+  fn new_starts_with_post_boot_state_for_the_cartridge() {
+    let info = create_emulator(&[]).cpu_info();
+    assert_eq!((info.af, info.bc, info.de, info.hl, info.sp, info.pc), (0x01B0, 0x0013, 0x00D8, 0x014D, 0xFFFE, 0x0100));
+    let info = create_color_emulator(&[]).cpu_info();
+    assert_eq!((info.af, info.bc, info.de, info.hl, info.sp, info.pc), (0x1180, 0x0000, 0xFF56, 0x000D, 0xFFFE, 0x0100));
+    let emulator = create_emulator(&[]);
+    assert_eq_hex!(emulator.lcd.read(0xFF40), 0x91);
+    assert_eq_hex!(emulator.lcd.read(0xFF47), 0xFC);
+  }
+
+  #[test]
+  fn boot_rom_runs_until_it_unmaps_itself() {
+    let mut boot_rom = vec![0u8; BootROM::DMG_SIZE];
+    // JP 0x00FC
+    boot_rom[0x0000..0x0003].copy_from_slice(&[0xC3, 0xFC, 0x00]);
+    // LD A,0x01; LDH (0x50),A
+    boot_rom[0x00FC..0x0100].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+    let mut rom = vec![0u8; 0x8000];
+    // LD A,0x42; JR -2
+    rom[0x0100..0x0104].copy_from_slice(&[0x3E, 0x42, 0x18, 0xFE]);
+    rom[0x014D] = header_checksum(&rom);
+    let mut renderer = MockRenderer::new();
+    renderer.expect_draw_pixel().return_const(());
+    let mut emulator = Emulator::new_with_boot_rom(&rom, &boot_rom, Box::new(renderer), EmulationMode::Strict).unwrap();
+    assert_eq_hex!(emulator.cpu_info().pc, 0x0000);
+    assert_eq_hex!(emulator.read_byte(0x0000), 0xC3);
+    (0..3).for_each(|_| { emulator.step_instruction(); });
+    assert_eq_hex!(emulator.cpu_info().pc, 0x0100);
+    assert_eq_hex!(emulator.read_byte(0x0000), 0x00);
+    emulator.step_instruction();
+    assert_eq_hex!(emulator.cpu_info().af >> 8, 0x42);
+  }
+
+  #[test]
+  #[cfg(feature = "zip")]
+  fn new_accepts_zipped_rom() {
+    let mut rom = vec![0u8; 0x8000];
+    rom[0x0147] = 0x01;
+    rom[0x014D] = header_checksum(&rom);
+    let zip = crate::memory::rom_loader::tests::create_zip(&[("game.gb", &rom)]);
+    let emulator = Emulator::new(&zip, Box::new(MockRenderer::new()), EmulationMode::Strict).unwrap();
+    assert_eq!(emulator.header_checksum, rom[0x014D]);
+  }
+
+  #[test]
+  fn rom_info_matches_parsed_header() {
+    let mut rom = vec![0u8; 0x8000];
+    rom[0x0134..0x013A].copy_from_slice(b"TETRIS");
+    rom[0x014D] = header_checksum(&rom);
+    let info = Emulator::parse_header(&rom).unwrap();
+    assert_eq!(info.title(), "TETRIS");
+    let emulator = Emulator::new(&rom, Box::new(MockRenderer::new()), EmulationMode::Strict).unwrap();
+    assert_eq!(emulator.rom_info(), info);
+  }
+
+  #[test]
+  fn new_with_boot_rom_rejects_wrong_size() {
+    let mut rom = vec![0u8; 0x8000];
+    rom[0x014D] = header_checksum(&rom);
+    let renderer = MockRenderer::new();
+    let result = Emulator::new_with_boot_rom(&rom, &[0u8; 0x200], Box::new(renderer), EmulationMode::Strict);
+    assert_eq!(result.err(), Some(RomError::InvalidBootROMSize(0x200)));
+  }
+
+  #[test]
+  fn cpu_does_work_every_cycle() {
+    let mut emulator = create_emulator(&[]);
+    (0..100).for_each(|_| assert!(emulator.step_m_cycle().cpu_executed_microop));
+  }
+
+  #[test]
+  fn timer_write_is_visible_to_timer_in_same_cycle() {
+    // LD A,0xFF; LDH (0x05),A; LD A,0x05; LDH (0x07),A
+    let mut emulator = create_emulator(&[0x3E, 0xFF, 0xE0, 0x05, 0x3E, 0x05, 0xE0, 0x07]);
+    let results: Vec<StepResult> = (0..30).map(|_| emulator.step_m_cycle()).collect();
+    let overflows: Vec<usize> = results.iter().enumerate()
+      .filter(|(_, result)| result.timer_overflowed)
+      .map(|(index, _)| index)
+      .collect();
+    // TAC is written in cycle 9, when the divider reaches 0x28, so bit 4 first toggles in cycle 11
+    assert_eq!(overflows, vec![11]);
+    assert_eq!(emulator.interrupt_controller.read(0xFF0F) & 0x04, 0x04);
+  }
+
+  #[test]
+  fn dma_is_active_during_transfer() {
+    // LD A,0xC0; LDH (0x46),A; JR -2
+    let mut emulator = create_emulator(&[0x3E, 0xC0, 0xE0, 0x46, 0x18, 0xFE]);
+    let active_cycles = (0..400).filter(|_| emulator.step_m_cycle().dma_active).count();
+    assert_eq!(active_cycles, 160);
+  }
+
+  #[test]
+  fn ppu_mode_follows_dots() {
+    let mut emulator = create_emulator(&[]);
+    let modes: Vec<LCDMode> = (0..114).map(|_| emulator.step_m_cycle().ppu_mode).collect();
+    assert_eq!(modes[18], LCDMode::Mode2);
+    assert_eq!(modes[19], LCDMode::Mode3);
+    assert_eq!(modes[60], LCDMode::Mode3);
+    assert_eq!(modes[61], LCDMode::HBlank);
+    assert_eq!(modes[113], LCDMode::Mode2);
+  }
+
+  #[test]
+  fn halt_with_interrupts_disabled_resumes_on_vertical_blank_without_dispatch() {
+    // DI; LD A,0x01; LDH (0xFF),A; HALT; INC A
+    let mut emulator = create_emulator(&[0xF3, 0x3E, 0x01, 0xE0, 0xFF, 0x76, 0x3C]);
+    while !emulator.cpu_info().halted {
+      emulator.step_m_cycle();
+    }
+    while emulator.cpu_info().halted {
+      emulator.step_m_cycle();
+    }
+    let info = emulator.cpu_info();
+    assert_eq!(emulator.lcd.read(0xFF44), 144);
+    assert_eq_hex!(info.pc, 0x0107);
+    assert_eq_hex!(info.af >> 8, 0x02);
+    assert!(!info.ime);
+    assert_eq_hex!(emulator.interrupt_controller.read(0xFF0F) & 0x01, 0x01);
+  }
+
+  #[test]
+  fn run_frame_steps_a_full_frame() {
+    let mut emulator = create_emulator(&[]);
+    assert_eq!(emulator.run_frame(), RunStatus::FrameCompleted);
+    assert_eq!(emulator.lcd.read(0xFF44), 0);
+    assert_eq!(emulator.step_m_cycle().ppu_mode, LCDMode::Mode2);
+  }
+
+  #[test]
+  fn write_memory_range_triggers_io_side_effects() {
+    let mut emulator = create_emulator(&[]);
+    emulator.set_debug_enabled(true);
+    // LYC, DMA and BGP
+    assert_eq!(emulator.write_memory_range(0xFF45, &[0x10, 0xC0, 0xE4]), Ok(()));
+    assert_eq!(emulator.lcd.read(0xFF45), 0x10);
+    assert_eq!(emulator.lcd.read(0xFF47), 0xE4);
+    assert!(emulator.dma.active());
+  }
+
+  #[test]
+  fn fill_memory_writes_every_byte() {
+    let mut emulator = create_emulator(&[]);
+    emulator.set_debug_enabled(true);
+    assert_eq!(emulator.fill_memory(0xC010, 0x20, 0xAB), Ok(()));
+    assert_eq!(emulator.wram.read(0xC00F), 0x00);
+    (0xC010..0xC030).for_each(|address| assert_eq!(emulator.wram.read(address), 0xAB));
+    assert_eq!(emulator.wram.read(0xC030), 0x00);
+  }
+
+  #[test]
+  fn vram_writes_are_restricted_during_mode_3_unless_raw() {
+    let mut emulator = create_emulator(&[]);
+    emulator.set_debug_enabled(true);
+    emulator.lcd.write(0xFF40, 0x80);
+    while emulator.step_m_cycle().ppu_mode != LCDMode::Mode3 {}
+    assert_eq!(emulator.write_memory_range(0x8000, &[0x12, 0x34]), Ok(()));
+    assert_eq!(emulator.vram.read(0x8000), 0x00);
+    assert_eq!(emulator.write_memory_range_raw(0x8000, &[0x12, 0x34]), Ok(()));
+    assert_eq!(emulator.vram.read(0x8000), 0x12);
+    assert_eq!(emulator.vram.read(0x8001), 0x34);
+  }
+
+  #[test]
+  fn vram_is_inaccessible_during_mode_3_only() {
+    let mut emulator = create_emulator(&[]);
+    emulator.set_debug_enabled(true);
+    emulator.vram.write(0x8000, 0x12);
+    while emulator.step_m_cycle().ppu_mode != LCDMode::Mode3 {}
+    assert_eq!(emulator.write_memory_range(0x8000, &[0x34]), Ok(()));
+    assert_eq!(emulator.read_memory_range(0x8000, 1), vec![0xFF]);
+    assert_eq!(emulator.vram.read(0x8000), 0x12);
+    while emulator.step_m_cycle().ppu_mode != LCDMode::HBlank {}
+    assert_eq!(emulator.read_memory_range(0x8000, 1), vec![0x12]);
+    assert_eq!(emulator.write_memory_range(0x8000, &[0x34]), Ok(()));
+    assert_eq!(emulator.read_memory_range(0x8000, 1), vec![0x34]);
+  }
+
+  #[test_case(LCDMode::HBlank, true)]
+  #[test_case(LCDMode::VBlank, true)]
+  #[test_case(LCDMode::Mode2, false)]
+  #[test_case(LCDMode::Mode3, false)]
+  fn oam_is_inaccessible_during_oam_scan_and_drawing(mode: LCDMode, accessible: bool) {
+    let mut emulator = create_emulator(&[]);
+    emulator.set_debug_enabled(true);
+    emulator.oam.write(0xFE10, 0x12);
+    while emulator.step_m_cycle().ppu_mode != mode {}
+    assert_eq!(emulator.write_memory_range(0xFE10, &[0x34]), Ok(()));
+    assert_eq!(emulator.oam.read(0xFE10), if accessible { 0x34 } else { 0x12 });
+    assert_eq!(emulator.read_memory_range(0xFE10, 1), vec![if accessible { 0x34 } else { 0xFF }]);
+    assert_eq!(emulator.write_memory_range_raw(0xFE10, &[0x56]), Ok(()));
+    assert_eq!(emulator.oam.read(0xFE10), 0x56);
+  }
+
+  #[test]
+  fn oam_is_accessible_while_the_lcd_is_off() {
+    let mut emulator = create_emulator(&[]);
+    emulator.set_debug_enabled(true);
+    emulator.lcd.write(0xFF40, 0x00);
+    assert_eq!(emulator.write_memory_range(0xFE10, &[0x34]), Ok(()));
+    assert_eq!(emulator.read_memory_range(0xFE10, 1), vec![0x34]);
+  }
+
+  #[test]
+  fn oam_dma_writes_during_oam_scan() {
+    // LD A,0xC0; LDH (0x46),A; JR -2
+    let mut emulator = create_emulator(&[0x3E, 0xC0, 0xE0, 0x46, 0x18, 0xFE]);
+    emulator.wram.write(0xC010, 0xAB);
+    while emulator.step_m_cycle().ppu_mode != LCDMode::Mode2 {}
+    (0..200).for_each(|_| { emulator.step_m_cycle(); });
+    assert_eq!(emulator.oam.read(0xFE10), 0xAB);
+  }
+
+  /// Records the red component of the first pixel of each line.
+  struct FirstColumnRecorder {
+    reds: RefCell<HashMap<u8, u8>>,
+  }
+
+  impl Renderer for Rc<FirstColumnRecorder> {
+    fn draw_pixel(&self, x: u8, y: u8, color: Color, _draw_in_back: bool) {
+      if x == 0 {
+        self.reds.borrow_mut().insert(y, color.red);
+      }
+    }
+  }
+
+  // LD HL,0xFE00; INC HL; DEC HL; JR -4
+  const OAM_BUG_PROGRAM: [u8; 7] = [0x21, 0x00, 0xFE, 0x23, 0x2B, 0x18, 0xFC];
+
+  fn oam_after_frame(mut emulator: Emulator, oam_bug: bool) -> Vec<u8> {
+    emulator.set_accuracy(Accuracy { oam_bug });
+    (0..160u16).for_each(|offset| emulator.oam.write(0xFE00 + offset, (offset as u8).wrapping_mul(0x35) ^ 0xA5));
+    (0..Emulator::M_CYCLES_PER_FRAME).for_each(|_| { emulator.step_m_cycle(); });
+    (0..160u16).map(|offset| emulator.oam.read(0xFE00 + offset)).collect()
+  }
+
+  #[test]
+  fn oam_bug_only_corrupts_oam_on_dmg_when_enabled() {
+    let original: Vec<u8> = (0..160u16).map(|offset| (offset as u8).wrapping_mul(0x35) ^ 0xA5).collect();
+    assert_eq!(oam_after_frame(create_emulator(&OAM_BUG_PROGRAM), false), original);
+    assert_eq!(oam_after_frame(create_color_emulator(&OAM_BUG_PROGRAM), true), original);
+    let corrupted = oam_after_frame(create_emulator(&OAM_BUG_PROGRAM), true);
+    assert_eq!(corrupted[..8], original[..8]);
+    assert_ne!(corrupted, original);
+  }
+
+  /// Records a few frames of OAM_BUG_PROGRAM with OAM filled in, holding Start for one of them.
+  fn record_oam_bug_replay(rom: &[u8]) -> Vec<u8> {
+    let mut emulator = Emulator::new(rom, Box::new(NullRenderer), EmulationMode::Strict).unwrap();
+    (0..160u16).for_each(|offset| emulator.oam.write(0xFE00 + offset, (offset as u8).wrapping_mul(0x35) ^ 0xA5));
+    emulator.start_replay_recording();
+    emulator.run_frame();
+    emulator.press_button(Button::Start);
+    emulator.run_frame();
+    emulator.release_button(Button::Start);
+    emulator.run_frame();
+    emulator.export_replay().unwrap()
+  }
+
+  fn create_oam_bug_rom() -> Vec<u8> {
+    let mut rom = vec![0u8; 0x8000];
+    rom[0x0100..0x0100 + OAM_BUG_PROGRAM.len()].copy_from_slice(&OAM_BUG_PROGRAM);
+    rom[0x014D] = header_checksum(&rom);
+    rom
+  }
+
+  #[test]
+  fn replay_verifies() {
+    let rom = create_oam_bug_rom();
+    let replay = record_oam_bug_replay(&rom);
+    assert_eq!(Emulator::verify_replay(&rom, &replay), Ok(()));
+    let mut bundle: ReplayBundle = bincode::deserialize(&replay).unwrap();
+    assert_eq!(bundle.end, ReplayPosition { frame: 3, frame_cycle: 0 });
+    bundle.events.truncate(1);
+    let result = Emulator::verify_replay(&rom, &bincode::serialize(&bundle).unwrap());
+    assert!(matches!(result, Err(ReplayError::HashMismatch { .. })));
+  }
+
+  #[test]
+  fn replay_with_another_accuracy_fails_verification() {
+    let rom = create_oam_bug_rom();
+    let mut bundle: ReplayBundle = bincode::deserialize(&record_oam_bug_replay(&rom)).unwrap();
+    bundle.accuracy.oam_bug = true;
+    let result = Emulator::verify_replay(&rom, &bincode::serialize(&bundle).unwrap());
+    assert!(matches!(result, Err(ReplayError::HashMismatch { expected, .. }) if expected == bundle.final_hash));
+  }
+
+  #[test]
+  fn replay_of_another_game_is_refused() {
+    let rom = create_oam_bug_rom();
+    let replay = record_oam_bug_replay(&rom);
+    let mut other_rom = rom.clone();
+    other_rom[0x0134] = b'X';
+    other_rom[0x014D] = header_checksum(&other_rom);
+    assert_eq!(Emulator::verify_replay(&other_rom, &replay), Err(ReplayError::WrongGame));
+    assert_eq!(Emulator::verify_replay(&rom, &replay[..16]), Err(ReplayError::Corrupt));
+  }
+
+  #[test]
+  fn export_replay_needs_a_recording() {
+    let mut emulator = create_emulator(&OAM_BUG_PROGRAM);
+    assert_eq!(emulator.export_replay(), None);
+    emulator.start_replay_recording();
+    assert!(emulator.export_replay().is_some());
+    assert_eq!(emulator.export_replay(), None);
+  }
+
+  #[test]
+  fn palette_data_is_inaccessible_during_mode_3() {
+    let mut rom = vec![0u8; 0x8000];
+    rom[0x0100..0x0102].copy_from_slice(&[0x18, 0xFE]); // JR -2
+    rom[0x0143] = 0x80;
+    rom[0x014D] = header_checksum(&rom);
+    let recorder = Rc::new(FirstColumnRecorder { reds: RefCell::new(HashMap::new()) });
+    let mut emulator = Emulator::new(&rom, Box::new(Rc::clone(&recorder)), EmulationMode::Strict).unwrap();
+    emulator.set_debug_enabled(true);
+    while emulator.step_m_cycle().ppu_mode != LCDMode::Mode3 {}
+    // The index register stays accessible, the data register doesn't
+    assert_eq!(emulator.write_memory_range(0xFF68, &[0x00]), Ok(()));
+    assert_eq!(emulator.write_memory_range(0xFF69, &[0x1F]), Ok(()));
+    assert_eq!(emulator.read_memory_range(0xFF68, 2), vec![0x00, 0xFF]);
+    while emulator.step_m_cycle().ppu_mode != LCDMode::HBlank {}
+    while emulator.step_m_cycle().ppu_mode != LCDMode::Mode3 {}
+    assert_eq!(recorder.reds.borrow()[&1], 0x00);
+    while emulator.step_m_cycle().ppu_mode != LCDMode::VBlank {}
+    assert_eq!(emulator.write_memory_range(0xFF69, &[0x1F]), Ok(()));
+    while emulator.step_m_cycle().ppu_mode != LCDMode::Mode3 {}
+    assert_eq!(recorder.reds.borrow()[&0], 0x1F);
+  }
 
-  pub fn run() {
-    // let interrupt_controller = Rc::new(RefCell::new(InterruptController::new()));
-    // let timer = Rc::new(RefCell::new(TimerController::new(Rc::clone(&interrupt_controller))));
-    // let oam = Rc::new(RefCell::new(OAM::new()));
-    // let dma: DMAControllerRef = Rc::new(RefCell::new(DMAController::new()));
+  #[test]
+  fn raw_rom_writes_go_through_the_rom_image() {
+    let mut emulator = create_emulator(&[]);
+    emulator.set_debug_enabled(true);
+    assert_eq!(emulator.write_memory_range(0x0100, &[0x12]), Ok(()));
+    assert_eq!(emulator.rom.read(0x0100), 0x00);
+    assert_eq!(emulator.write_memory_range_raw(0x0100, &[0x12]), Ok(()));
+    assert_eq!(emulator.rom.read(0x0100), 0x12);
+  }
+
+  #[test]
+  fn range_writes_past_0xffff_are_truncated() {
+    let mut emulator = create_emulator(&[]);
+    emulator.set_debug_enabled(true);
+    assert_eq!(emulator.write_memory_range(0xFFFF, &[0x11, 0x22]), Err(WriteMemoryError::RangeTruncated { bytes_written: 1 }));
+    assert_eq!(emulator.interrupt_controller.read(0xFFFF), 0x11);
+    assert_eq!(emulator.fill_memory(0xFFFF, 2, 0x00), Err(WriteMemoryError::RangeTruncated { bytes_written: 1 }));
+  }
+
+  fn create_color_emulator(program: &[u8]) -> Emulator {
+    let mut rom = vec![0u8; 0x8000];
+    rom[0x0100..0x0100 + program.len()].copy_from_slice(program);
+    rom[0x0143] = 0x80;
+    rom[0x014D] = header_checksum(&rom);
+    let mut renderer = MockRenderer::new();
+    renderer.expect_draw_pixel().return_const(());
+    Emulator::new(&rom, Box::new(renderer), EmulationMode::Strict).unwrap()
+  }
+
+  #[test]
+  fn vbk_write_switches_banks_in_cgb_mode() {
+    let mut emulator = create_color_emulator(&[]);
+    emulator.set_debug_enabled(true);
+    emulator.lcd.write(0xFF40, 0x00);
+    assert_eq!(emulator.write_memory_range(0x8000, &[0x12]), Ok(()));
+    assert_eq!(emulator.write_memory_range(0xFF4F, &[0x01]), Ok(()));
+    assert_eq!(emulator.read_memory_range(0x8000, 1), vec![0x00]);
+    assert_eq!(emulator.write_memory_range(0xFF4F, &[0x00]), Ok(()));
+    assert_eq!(emulator.read_memory_range(0x8000, 1), vec![0x12]);
+  }
+
+  #[test]
+  fn vbk_write_is_ignored_in_dmg_mode() {
+    let mut emulator = create_emulator(&[]);
+    emulator.set_debug_enabled(true);
+    emulator.lcd.write(0xFF40, 0x00);
+    assert_eq!(emulator.write_memory_range(0x8000, &[0x12]), Ok(()));
+    assert_eq!(emulator.write_memory_range(0xFF4F, &[0x01]), Ok(()));
+    assert_eq!(emulator.read_memory_range(0x8000, 1), vec![0x12]);
+  }
+
+  #[test_case(0xFF4D; "key1")]
+  #[test_case(0xFF4F; "vbk")]
+  #[test_case(0xFF51; "hdma1")]
+  #[test_case(0xFF55; "hdma5")]
+  #[test_case(0xFF68; "bcps")]
+  #[test_case(0xFF69; "bcpd")]
+  #[test_case(0xFF6A; "ocps")]
+  #[test_case(0xFF6B; "ocpd")]
+  #[test_case(0xFF70; "svbk")]
+  fn cgb_registers_read_ff_in_dmg_mode(address: u16) {
+    let mut emulator = create_emulator(&[]);
+    emulator.set_debug_enabled(true);
+    assert_eq!(emulator.write_memory_range(address, &[0x01]), Ok(()));
+    assert_eq!(emulator.read_memory_range(address, 1), vec![0xFF]);
+  }
+
+  #[test]
+  fn stop_switches_speed_when_armed() {
+    // LD A,0x01; LDH (0x4D),A; STOP; LD A,0x42; LD (0xC000),A
+    let mut emulator = create_color_emulator(&[0x3E, 0x01, 0xE0, 0x4D, 0x10, 0x00, 0x3E, 0x42, 0xEA, 0x00, 0xC0]);
+    (0..5).for_each(|_| { emulator.step_m_cycle(); });
+    assert_eq_hex!(emulator.speed.read(0xFF4D), 0x7F);
+    assert_eq_hex!(emulator.timer.read(0xFF04), 0x00);
+    emulator.step_m_cycle();
+    assert_eq_hex!(emulator.speed.read(0xFF4D), 0xFE);
+    assert_eq_hex!(emulator.timer.read(0xFF04), 0x00);
+    (0..6).for_each(|_| { emulator.step_m_cycle(); });
+    assert_eq_hex!(emulator.wram.read(0xC000), 0x42);
+  }
+
+  #[test]
+  fn stop_without_armed_switch_keeps_speed() {
+    // STOP
+    let mut emulator = create_color_emulator(&[0x10, 0x00]);
+    emulator.step_m_cycle();
+    assert_eq_hex!(emulator.speed.read(0xFF4D), 0x7E);
+    assert_eq_hex!(emulator.timer.read(0xFF04), 0x00);
+  }
+
+  #[test]
+  fn stop_resets_divider_and_skips_its_operand() {
+    // NOP x64; STOP; INC A
+    let mut program = vec![0x00; 0x40];
+    program.extend_from_slice(&[0x10, 0x3C, 0x3C]);
+    let mut emulator = create_emulator(&program);
+    (0..0x40).for_each(|_| { emulator.step_instruction(); });
+    assert_eq_hex!(emulator.timer.read(0xFF04), 0x01);
+    emulator.step_instruction();
+    assert_eq_hex!(emulator.timer.read(0xFF04), 0x00);
+    assert_eq_hex!(emulator.speed.read(0xFF4D), 0xFF);
+    assert_eq_hex!(emulator.cpu_info().pc, 0x0142);
+    assert!(emulator.cpu_info().stopped);
+    emulator.step_instruction();
+    assert_eq_hex!(emulator.cpu_info().pc, 0x0142);
+    emulator.press_button(Button::A);
+    emulator.step_instruction();
+    assert!(!emulator.cpu_info().stopped);
+    assert_eq_hex!(emulator.cpu_info().af >> 8, 0x02);
+    assert_eq_hex!(emulator.cpu_info().pc, 0x0143);
+  }
+
+  #[test]
+  fn double_speed_frame_takes_twice_as_many_cycles() {
+    // JR -2
+    let mut emulator = create_color_emulator(&[0x18, 0xFE]);
+    emulator.speed.write(0xFF4D, 0x01);
+    emulator.speed.switch_speed();
+    assert_eq!(emulator.run_frame(), RunStatus::FrameCompleted);
+    assert_eq!(emulator.lcd.read(0xFF44), 0);
+    assert_eq!(emulator.step_m_cycle().ppu_mode, LCDMode::Mode2);
+  }
+
+  #[test]
+  fn step_instruction_ticks_all_components() {
+    // LD BC,0x1234; JR -2
+    let mut emulator = create_emulator(&[0x01, 0x34, 0x12, 0x18, 0xFE]);
+    assert_eq!(emulator.step_instruction(), 3);
+    (0..63).for_each(|_| assert_eq!(emulator.step_instruction(), 3));
+    // 192 M-cycles advance the divider by 768
+    assert_eq_hex!(emulator.timer.read(0xFF04), 0x03);
+  }
+
+  #[test]
+  fn run_frame_stops_at_breakpoint() {
+    // INC A; JR -3
+    let mut emulator = create_emulator(&[0x3C, 0x18, 0xFD]);
+    emulator.add_breakpoint(0x0101);
+    assert_eq!(emulator.run_frame(), RunStatus::BreakpointHit(0x0101));
+    assert_eq!(emulator.frame_cycle, 1);
+    // Continuing executes the instruction at the breakpoint and stops when the loop reaches it again
+    assert_eq!(emulator.run_frame(), RunStatus::BreakpointHit(0x0101));
+    assert_eq!(emulator.frame_cycle, 5);
+    assert_eq!(emulator.step_instruction(), 3);
+    assert_eq!(emulator.run_frame(), RunStatus::BreakpointHit(0x0101));
+    assert_eq!(emulator.frame_cycle, 9);
+  }
+
+  #[test]
+  fn removed_breakpoints_are_ignored() {
+    // INC A; JR -3
+    let mut emulator = create_emulator(&[0x3C, 0x18, 0xFD]);
+    emulator.add_breakpoint(0x0100);
+    emulator.add_breakpoint(0x0101);
+    emulator.add_breakpoint(0x0101);
+    assert_eq!(emulator.breakpoints, vec![0x0100, 0x0101]);
+    emulator.remove_breakpoint(0x0100);
+    assert_eq!(emulator.run_frame(), RunStatus::BreakpointHit(0x0101));
+    emulator.clear_breakpoints();
+    assert_eq!(emulator.run_frame(), RunStatus::FrameCompleted);
+    assert_eq!(emulator.frame_cycle, 0);
+  }
+
+  #[test]
+  fn run_frame_stops_at_watched_write() {
+    // LD HL,0xC000; INC A; LD (HL),A; JR -4
+    let mut emulator = create_emulator(&[0x21, 0x00, 0xC0, 0x3C, 0x77, 0x18, 0xFC]);
+    emulator.add_watchpoint(0xC000..=0xC000, false, true);
+    let hit = WatchpointHit { pc: 0x0104, address: 0xC000, old_value: 0x00, new_value: 0x02 };
+    assert_eq!(emulator.run_frame(), RunStatus::WatchpointHit(hit));
+    assert_eq!(emulator.frame_cycle, 6);
+    let hit = WatchpointHit { pc: 0x0104, address: 0xC000, old_value: 0x02, new_value: 0x03 };
+    assert_eq!(emulator.run_frame(), RunStatus::WatchpointHit(hit));
+    emulator.clear_watchpoints();
+    assert_eq!(emulator.run_frame(), RunStatus::FrameCompleted);
+  }
+
+  #[test]
+  fn run_frame_stops_at_watched_read_in_range() {
+    // LD A,(0xFE10)
+    let mut emulator = create_emulator(&[0xFA, 0x10, 0xFE]);
+    // OAM can't be read while the LCD scans it
+    emulator.lcd.write(0xFF40, 0x00);
+    emulator.oam.write(0xFE10, 0xAB);
+    emulator.add_watchpoint(0xC000..=0xC000, true, false);
+    emulator.add_watchpoint(0xFE00..=0xFE9F, true, false);
+    let hit = WatchpointHit { pc: 0x0100, address: 0xFE10, old_value: 0xAB, new_value: 0xAB };
+    assert_eq!(emulator.run_frame(), RunStatus::WatchpointHit(hit));
+  }
+
+  #[test]
+  fn dump_trace_uses_gameboy_doctor_lines() {
+    // LD A,0x42; JR -4
+    let mut emulator = create_emulator(&[0x3E, 0x42, 0x18, 0xFC]);
+    assert_eq!(emulator.dump_trace(), "");
+    emulator.enable_trace(DEFAULT_TRACE_CAPACITY);
+    (0..5).for_each(|_| { emulator.step_m_cycle(); });
+    assert_eq!(emulator.dump_trace(), "\
+A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:3E,42,18,FC
+A:42 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0102 PCMEM:18,FC,00,00
+");
+    emulator.disable_trace();
+    assert_eq!(emulator.dump_trace(), "");
+  }
+
+  #[test]
+  fn profiler_report_is_dominated_by_loop_opcodes() {
+    // LD A,0x00; INC A; SWAP A; JR -5
+    let mut emulator = create_emulator(&[0x3E, 0x00, 0x3C, 0xCB, 0x37, 0x18, 0xFB]);
+    assert_eq!(emulator.profiler_report(), "");
+    emulator.enable_profiler();
+    (0..1000).for_each(|_| { emulator.step_instruction(); });
+    let report = emulator.profiler_report();
+    let opcodes: Vec<&str> = report.lines().skip(1).take(4).map(|line| &line[..5]).collect();
+    assert_eq!(opcodes, vec!["18   ", "3C   ", "CB   ", "CB 37"]);
+    assert!(report.contains("\n0000-0FFF  1000\n"));
+    emulator.disable_profiler();
+    assert_eq!(emulator.profiler_report(), "");
+  }
+
+  #[test]
+  fn call_stack_follows_calls_restarts_and_returns() {
+    let mut program = vec![0x00; 0x22];
+    // 0x0100: LD SP,0xD000; CALL 0x0110; JR -2
+    program[0x00..0x08].copy_from_slice(&[0x31, 0x00, 0xD0, 0xCD, 0x10, 0x01, 0x18, 0xFE]);
+    // 0x0110: CALL 0x0120; RET
+    program[0x10..0x14].copy_from_slice(&[0xCD, 0x20, 0x01, 0xC9]);
+    // 0x0120: RST 0x28; RET
+    program[0x20..0x22].copy_from_slice(&[0xEF, 0xC9]);
+    let mut emulator = create_emulator(&program);
+    emulator.set_debug_enabled(true);
+    // 0x0028: RET
+    assert_eq!(emulator.write_memory_range_raw(0x0028, &[0xC9]), Ok(()));
+    emulator.enable_call_stack();
+    let depths: Vec<usize> = (0..8).map(|_| {
+      emulator.step_instruction();
+      emulator.call_stack().len()
+    }).collect();
+    assert_eq!(depths, vec![0, 1, 2, 3, 2, 1, 0, 0]);
+    emulator.disable_call_stack();
+    assert_eq!(emulator.call_stack(), vec![]);
+  }
+
+  #[test]
+  fn call_stack_shows_nested_frames_and_interrupts() {
+    let mut program = vec![0x00; 0x12];
+    // 0x0100: LD SP,0xD000; EI; CALL 0x0110
+    program[0x00..0x07].copy_from_slice(&[0x31, 0x00, 0xD0, 0xFB, 0xCD, 0x10, 0x01]);
+    // 0x0110: JR -2
+    program[0x10..0x12].copy_from_slice(&[0x18, 0xFE]);
+    let mut emulator = create_emulator(&program);
+    emulator.enable_call_stack();
+    (0..3).for_each(|_| { emulator.step_instruction(); });
+    assert_eq!(emulator.call_stack(), vec![CallFrame { caller: 0x0104, target: 0x0110, sp: 0xCFFE }]);
+    emulator.interrupt_controller.write(0xFFFF, 0x04);
+    emulator.interrupt_controller.request_interrupt(Interrupt::TimerOverflow);
+    emulator.step_instruction();
+    assert_eq!(emulator.call_stack(), vec![
+      CallFrame { caller: 0x0104, target: 0x0110, sp: 0xCFFE },
+      CallFrame { caller: 0x0110, target: 0x0050, sp: 0xCFFC },
+    ]);
+  }
+
+  /// 0x0100: LD SP,0xD000; LD B,0x03; CALL 0x0110; JR -2
+  /// 0x0110: DEC B; JR Z,+3; CALL 0x0110; RET
+  fn create_recursive_emulator() -> Emulator {
+    let mut program = vec![0x00; 0x17];
+    program[0x00..0x0A].copy_from_slice(&[0x31, 0x00, 0xD0, 0x06, 0x03, 0xCD, 0x10, 0x01, 0x18, 0xFE]);
+    program[0x10..0x17].copy_from_slice(&[0x05, 0x28, 0x03, 0xCD, 0x10, 0x01, 0xC9]);
+    create_emulator(&program)
+  }
 
+  #[test]
+  fn step_over_runs_calls_until_they_return() {
+    let mut emulator = create_recursive_emulator();
+    assert_eq!(emulator.step_over(), RunStatus::StepCompleted(0x0103));
+    assert_eq!(emulator.step_over(), RunStatus::StepCompleted(0x0105));
+    assert_eq!(emulator.step_over(), RunStatus::StepCompleted(0x0108));
+    let info = emulator.cpu_info();
+    assert_eq_hex!(info.sp, 0xD000);
+    assert_eq_hex!(info.bc >> 8, 0x00);
+  }
+
+  #[test]
+  fn step_over_skips_recursive_calls_returning_to_the_same_address() {
+    let mut emulator = create_recursive_emulator();
+    (0..5).for_each(|_| { emulator.step_instruction(); });
+    assert_eq_hex!(emulator.cpu_info().pc, 0x0113);
+    assert_eq!(emulator.step_over(), RunStatus::StepCompleted(0x0116));
+    let info = emulator.cpu_info();
+    assert_eq_hex!(info.sp, 0xCFFE);
+    assert_eq_hex!(info.bc >> 8, 0x00);
+  }
+
+  #[test]
+  fn step_out_returns_from_the_current_frame() {
+    let mut emulator = create_recursive_emulator();
+    (0..6).for_each(|_| { emulator.step_instruction(); });
+    assert_eq_hex!(emulator.cpu_info().pc, 0x0110);
+    assert_eq_hex!(emulator.cpu_info().sp, 0xCFFC);
+    assert_eq!(emulator.step_out(), RunStatus::StepCompleted(0x0116));
+    assert_eq_hex!(emulator.cpu_info().sp, 0xCFFE);
+    assert_eq!(emulator.step_out(), RunStatus::StepCompleted(0x0108));
+    assert_eq_hex!(emulator.cpu_info().sp, 0xD000);
+  }
+
+  #[test]
+  fn step_over_runs_interrupts_dispatched_during_the_call() {
+    // LD SP,0xD000; EI; CALL 0x0110; JR -2
+    let mut program = vec![0x00; 0x11];
+    program[0x00..0x09].copy_from_slice(&[0x31, 0x00, 0xD0, 0xFB, 0xCD, 0x10, 0x01, 0x18, 0xFE]);
+    // 0x0110: RET
+    program[0x10] = 0xC9;
+    let mut emulator = create_emulator(&program);
+    emulator.set_debug_enabled(true);
+    // 0x0050: CALL 0x0110; RETI
+    assert_eq!(emulator.write_memory_range_raw(0x0050, &[0xCD, 0x10, 0x01, 0xD9]), Ok(()));
+    emulator.interrupt_controller.write(0xFFFF, 0x04);
+    emulator.interrupt_controller.request_interrupt(Interrupt::TimerOverflow);
+    assert_eq!(emulator.step_over(), RunStatus::StepCompleted(0x0103));
+    assert_eq!(emulator.step_over(), RunStatus::StepCompleted(0x0104));
+    assert_eq!(emulator.step_over(), RunStatus::StepCompleted(0x0107));
+    assert_eq_hex!(emulator.interrupt_controller.read(0xFF0F) & 0x04, 0x00);
+    assert_eq_hex!(emulator.cpu_info().sp, 0xD000);
+  }
+
+  #[test]
+  fn step_over_stops_at_breakpoints_in_the_call() {
+    let mut emulator = create_recursive_emulator();
+    (0..2).for_each(|_| { emulator.step_instruction(); });
+    emulator.add_breakpoint(0x0105);
+    emulator.add_breakpoint(0x0116);
+    assert_eq!(emulator.step_over(), RunStatus::BreakpointHit(0x0116));
+    assert_eq!(emulator.run_frame(), RunStatus::BreakpointHit(0x0116));
+    assert_eq!(emulator.run_frame(), RunStatus::BreakpointHit(0x0116));
+    assert_eq!(emulator.run_frame(), RunStatus::FrameCompleted);
+  }
+
+  #[test]
+  fn run_until_reaches_address() {
+    let mut emulator = create_recursive_emulator();
+    assert_eq!(emulator.run_until(0x0116, 1000), RunResult::AddressReached);
+    let info = emulator.cpu_info();
+    assert_eq_hex!(info.pc, 0x0116);
+    assert_eq_hex!(info.bc >> 8, 0x00);
+    assert_eq!(emulator.run_until(0x0116, 1000), RunResult::AddressReached);
+    assert_eq_hex!(emulator.cpu_info().sp, 0xCFFA);
+  }
+
+  #[test]
+  fn run_until_stops_when_cycles_expire() {
+    let mut emulator = create_recursive_emulator();
+    assert_eq!(emulator.run_until(0x0200, 2 * Emulator::M_CYCLES_PER_FRAME as u64 + 10), RunResult::CyclesExpired);
+    assert_eq!(emulator.frame_cycle, 10);
+    assert_eq!(emulator.step_target, None);
+  }
 
+  #[test]
+  fn run_until_stops_at_breakpoints_and_watchpoints() {
+    let mut emulator = create_recursive_emulator();
+    emulator.add_breakpoint(0x0113);
+    assert_eq!(emulator.run_until(0x0116, 1000), RunResult::BreakpointHit(0x0113));
+    emulator.clear_breakpoints();
+    emulator.add_watchpoint(0xCFFA..=0xCFFB, false, true);
+    assert!(matches!(emulator.run_until(0x0116, 1000), RunResult::WatchpointHit(_)));
   }
 
-}
\ No newline at end of file
+  #[test]
+  fn read_memory_range_reads_through_the_memory_map() {
+    let mut emulator = create_emulator(&[0x3E, 0x42]);
+    emulator.wram.write(0xC000, 0x99);
+    assert_eq!(emulator.read_memory_range(0x0100, 2), vec![0x3E, 0x42]);
+    assert_eq!(emulator.read_memory_range(0xC000, 1), vec![0x99]);
+    assert_eq!(emulator.read_memory_range(0xFF40, 1), vec![0x91]);
+  }
+
+  #[test]
+  fn read_memory_reads_across_the_bank_boundary() {
+    let mut emulator = create_mbc1_emulator(0x01);
+    emulator.set_debug_enabled(true);
+    emulator.rom.load_byte(0x3FFF, 0x12);
+    emulator.rom.load_byte(0xC000, 0x34);
+    assert_eq!(emulator.write_memory_range(0x2000, &[0x03]), Ok(()));
+    assert_eq!(emulator.read_memory(0x3FFF, 2), vec![0x12, 0x34]);
+    assert_eq!(emulator.write_memory_range(0x2000, &[0x01]), Ok(()));
+    assert_eq!(emulator.read_memory(0x3FFF, 2), vec![0x12, 0x00]);
+  }
+
+  #[test]
+  fn read_memory_sees_vram_and_oam_during_mode_3() {
+    let mut emulator = create_emulator(&[]);
+    emulator.vram.write(0x8000, 0x12);
+    emulator.oam.write(0xFE00, 0x34);
+    while emulator.step_m_cycle().ppu_mode != LCDMode::Mode3 {}
+    assert_eq!(emulator.read_memory_range(0x8000, 1), vec![0xFF]);
+    assert_eq!(emulator.read_byte(0x8000), 0x12);
+    assert_eq!(emulator.read_byte(0xFE00), 0x34);
+  }
+
+  #[test]
+  fn write_memory_changes_io_registers() {
+    let mut emulator = create_emulator(&[]);
+    emulator.set_debug_enabled(true);
+    assert_eq!(emulator.write_memory(0xFF40, 0x00), Ok(()));
+    assert_eq_hex!(emulator.lcd.read(0xFF40), 0x00);
+  }
+
+  #[test]
+  fn write_memory_respects_cartridge_ram_enable() {
+    let mut emulator = create_mbc1_emulator(0x03);
+    emulator.set_debug_enabled(true);
+    assert_eq!(emulator.write_memory(0xA000, 0x42), Ok(()));
+    assert_eq!(emulator.write_memory(0x0000, 0x0A), Ok(()));
+    assert_eq_hex!(emulator.read_byte(0xA000), 0x00);
+    assert_eq!(emulator.write_memory(0xA000, 0x42), Ok(()));
+    assert_eq_hex!(emulator.read_byte(0xA000), 0x42);
+  }
+
+  #[test]
+  fn patch_rom_is_visible_through_banked_reads() {
+    let mut emulator = create_mbc1_emulator(0x01);
+    emulator.set_debug_enabled(true);
+    assert_eq!(emulator.patch_rom(0xC000, &[0x12, 0x34]), Ok(()));
+    assert_eq!(emulator.write_memory(0x2000, 0x03), Ok(()));
+    assert_eq!(emulator.read_memory(0x4000, 2), vec![0x12, 0x34]);
+  }
+
+  #[test]
+  fn patch_rom_beyond_the_image_is_refused() {
+    let mut emulator = create_mbc1_emulator(0x01);
+    emulator.set_debug_enabled(true);
+    let rom_size = emulator.rom_size.bytes();
+    assert_eq!(emulator.patch_rom(rom_size - 1, &[0x12, 0x34]), Err(WriteMemoryError::PatchOutOfRange { rom_size }));
+    assert_eq!(emulator.patch_rom(usize::MAX, &[0x12]), Err(WriteMemoryError::PatchOutOfRange { rom_size }));
+    assert_eq!(emulator.write_memory(0x2000, 0x03), Ok(()));
+    assert_eq!(emulator.read_memory(0x7FFF, 1), vec![0x00]);
+    assert_eq!(emulator.patch_rom(rom_size - 1, &[0x12]), Ok(()));
+    assert_eq!(emulator.read_memory(0x7FFF, 1), vec![0x12]);
+  }
+
+  #[test]
+  fn writes_need_debugging_enabled() {
+    let mut emulator = create_mbc1_emulator(0x01);
+    assert_eq!(emulator.write_memory(0xFF40, 0x00), Err(WriteMemoryError::DebugDisabled));
+    assert_eq!(emulator.patch_rom(0x0000, &[0x12]), Err(WriteMemoryError::DebugDisabled));
+    assert_eq!(emulator.write_memory_range(0xC000, &[0x12]), Err(WriteMemoryError::DebugDisabled));
+    assert_eq!(emulator.fill_memory(0xC000, 1, 0x12), Err(WriteMemoryError::DebugDisabled));
+    assert_eq!(emulator.write_memory_range_raw(0x0000, &[0x12]), Err(WriteMemoryError::DebugDisabled));
+    assert_eq_hex!(emulator.lcd.read(0xFF40), 0x91);
+    assert_eq_hex!(emulator.read_byte(0x0000), 0x00);
+    assert_eq_hex!(emulator.read_byte(0xC000), 0x00);
+  }
+
+  #[test]
+  fn set_register_moves_execution_to_new_pc() {
+    // INC A; INC B; INC C
+    let mut emulator = create_emulator(&[0x3C, 0x04, 0x0C]);
+    assert_eq!(emulator.set_register("PC", 0x0102), Ok(()));
+    assert_eq!(emulator.set_register("A", 0x00), Ok(()));
+    emulator.step_instruction();
+    let info = emulator.cpu_info();
+    assert_eq_hex!(info.pc, 0x0103);
+    assert_eq_hex!(info.af >> 8, 0x00);
+    assert_eq_hex!(info.bc, 0x0014);
+  }
+
+  #[test]
+  fn set_register_is_refused_during_an_instruction() {
+    // LD BC,0x1234
+    let mut emulator = create_emulator(&[0x01, 0x34, 0x12]);
+    emulator.step_m_cycle();
+    assert_eq!(emulator.set_register("BC", 0x0000), Err(SetRegisterError::InstructionInProgress));
+    emulator.step_instruction();
+    assert_eq_hex!(emulator.cpu_info().bc, 0x1234);
+    assert_eq!(emulator.set_register("BC", 0x0000), Ok(()));
+  }
+
+  #[test]
+  fn every_io_register_is_mapped() {
+    let mut emulator = create_emulator(&[]);
+    emulator.set_debug_enabled(true);
+    assert_eq!(emulator.read_memory_range(0xFF00, 0x80).len(), 0x80);
+    assert_eq!(emulator.write_memory_range(0xFF10, &[0x00; 0x30]), Ok(()));
+  }
+
+  #[test]
+  fn sound_register_writes_are_ignored() {
+    // LD A,0x80; LDH (0x26),A
+    let mut emulator = create_emulator(&[0x3E, 0x80, 0xE0, 0x26]);
+    emulator.step_instruction();
+    emulator.step_instruction();
+    assert_eq_hex!(emulator.cpu_info().pc, 0x0104);
+  }
+
+  #[test_case(0xFF10, 0x80; "NR10")]
+  #[test_case(0xFF11, 0x3F; "NR11 reads only the duty")]
+  #[test_case(0xFF13, 0xFF; "NR13 is write-only")]
+  #[test_case(0xFF14, 0xBF; "NR14 reads only the length enable")]
+  #[test_case(0xFF15, 0xFF; "unused before NR21")]
+  #[test_case(0xFF1A, 0x7F; "NR30")]
+  #[test_case(0xFF1C, 0x9F; "NR32")]
+  #[test_case(0xFF1F, 0xFF; "unused before NR41")]
+  #[test_case(0xFF26, 0x70; "NR52 with the APU off")]
+  #[test_case(0xFF27, 0xFF; "first unused before wave RAM")]
+  #[test_case(0xFF2F, 0xFF; "last unused before wave RAM")]
+  #[test_case(0xFF30, 0x00; "wave RAM")]
+  fn sound_registers_read_their_unused_and_write_only_bits_as_set(address: u16, value: u8) {
+    let mut emulator = create_emulator(&[]);
+    emulator.set_debug_enabled(true);
+    assert_eq!(emulator.write_memory_range(address, &[0x00]), Ok(()));
+    assert_eq_hex!(emulator.read_memory_range(address, 1), vec![value]);
+  }
+
+  #[test_case(0xFF03)]
+  #[test_case(0xFF08)]
+  #[test_case(0xFF0E)]
+  #[test_case(0xFF4C)]
+  #[test_case(0xFF56)]
+  #[test_case(0xFF6C)]
+  #[test_case(0xFF7F)]
+  fn unused_io_address_reads_open_bus_and_ignores_writes(address: u16) {
+    let mut emulator = create_emulator(&[]);
+    emulator.set_debug_enabled(true);
+    assert_eq!(emulator.write_memory_range(address, &[0x12]), Ok(()));
+    assert_eq!(emulator.read_memory_range(address, 1), vec![0xFF]);
+  }
+
+  #[test]
+  fn echo_ram_mirrors_wram_banks() {
+    let mut emulator = create_color_emulator(&[]);
+    emulator.set_debug_enabled(true);
+    assert_eq!(emulator.write_memory_range(0xC123, &[0x42]), Ok(()));
+    assert_eq!(emulator.read_memory_range(0xE123, 1), vec![0x42]);
+    assert_eq!(emulator.write_memory_range(0xD123, &[0x11]), Ok(()));
+    assert_eq!(emulator.write_memory_range(0xFF70, &[0x02]), Ok(()));
+    assert_eq!(emulator.write_memory_range(0xF123, &[0x22]), Ok(()));
+    assert_eq!(emulator.read_memory_range(0xD123, 1), vec![0x22]);
+    assert_eq!(emulator.write_memory_range(0xFF70, &[0x01]), Ok(()));
+    assert_eq!(emulator.read_memory_range(0xF123, 1), vec![0x11]);
+  }
+
+  #[test]
+  fn hram_reads_back_what_was_written() {
+    let mut emulator = create_emulator(&[]);
+    emulator.set_debug_enabled(true);
+    let hram: Vec<u8> = (0..0x7F).collect();
+    assert_eq!(emulator.write_memory_range(0xFF80, &hram), Ok(()));
+    assert_eq!(emulator.read_memory_range(0xFF80, 0x7F), hram);
+  }
+
+  #[test_case(0xFEA0, 0xAA)]
+  #[test_case(0xFEC3, 0xCC)]
+  #[test_case(0xFEFF, 0xFF)]
+  fn unusable_area_ignores_writes(address: u16, color_value: u8) {
+    let mut emulator = create_emulator(&[]);
+    emulator.set_debug_enabled(true);
+    assert_eq!(emulator.write_memory_range(address, &[0x12]), Ok(()));
+    assert_eq!(emulator.read_memory_range(address, 1), vec![0x00]);
+    let mut emulator = create_color_emulator(&[]);
+    emulator.set_debug_enabled(true);
+    assert_eq!(emulator.write_memory_range(address, &[0x12]), Ok(()));
+    assert_eq!(emulator.read_memory_range(address, 1), vec![color_value]);
+  }
+
+  #[test]
+  fn joypad_register_reads_pressed_buttons() {
+    let mut emulator = create_emulator(&[]);
+    emulator.set_debug_enabled(true);
+    assert_eq!(emulator.write_memory_range(0xFF00, &[0x10]), Ok(()));
+    emulator.press_button(Button::A);
+    assert_eq!(emulator.read_memory_range(0xFF00, 1), vec![0xDE]);
+    assert_eq_hex!(emulator.interrupt_controller.read(0xFF0F) & 0x10, 0x10);
+    emulator.release_button(Button::A);
+    assert_eq!(emulator.read_memory_range(0xFF00, 1), vec![0xDF]);
+  }
+
+  #[test]
+  fn save_round_trip_keeps_the_rtc() {
+    let mut rom = vec![0u8; 0x40000];
+    // MBC3 with timer, 32KB RAM and battery
+    rom[0x0147] = 0x10;
+    rom[0x0148] = 0x03;
+    rom[0x0149] = 0x03;
+    rom[0x014D] = header_checksum(&rom);
+    let create = || {
+      let mut renderer = MockRenderer::new();
+      renderer.expect_draw_pixel().return_const(());
+      Emulator::new(&rom, Box::new(renderer), EmulationMode::Strict).unwrap()
+    };
+    let mut emulator = create();
+    emulator.set_debug_enabled(true);
+    // Enable RAM, write to RAM, then set the RTC hours
+    assert_eq!(emulator.write_memory_range(0x0000, &[0x0A]), Ok(()));
+    assert_eq!(emulator.write_memory_range(0xA000, &[0x42]), Ok(()));
+    assert_eq!(emulator.write_memory_range(0x4000, &[0x0A]), Ok(()));
+    assert_eq!(emulator.write_memory_range(0xA000, &[0x05]), Ok(()));
+    let save = emulator.export_save();
+    assert_eq!(save.len(), 0x8000 + 48);
+
+    let mut emulator = create();
+    emulator.set_debug_enabled(true);
+    emulator.import_save(&save);
+    assert_eq!(emulator.write_memory_range(0x0000, &[0x0A]), Ok(()));
+    assert_eq!(emulator.read_memory_range(0xA000, 1), vec![0x42]);
+    // Latch and read the RTC hours
+    assert_eq!(emulator.write_memory_range(0x6000, &[0x00, 0x01]), Ok(()));
+    assert_eq!(emulator.write_memory_range(0x4000, &[0x0A]), Ok(()));
+    assert_eq!(emulator.read_memory_range(0xA000, 1), vec![0x05]);
+  }
+
+  fn create_mbc1_emulator(cartridge_type: u8) -> Emulator {
+    let mut rom = vec![0u8; 0x10000];
+    rom[0x0147] = cartridge_type;
+    rom[0x0148] = 0x01;
+    rom[0x0149] = 0x02;
+    rom[0x014D] = header_checksum(&rom);
+    let mut renderer = MockRenderer::new();
+    renderer.expect_draw_pixel().return_const(());
+    Emulator::new(&rom, Box::new(renderer), EmulationMode::Strict).unwrap()
+  }
+
+  #[test]
+  fn only_battery_backed_ram_is_saved() {
+    let mut emulator = create_mbc1_emulator(0x02);
+    emulator.set_debug_enabled(true);
+    assert_eq!(emulator.write_memory_range(0x0000, &[0x0A]), Ok(()));
+    assert_eq!(emulator.write_memory_range(0xA000, &[0x42]), Ok(()));
+    assert!(emulator.export_save().is_empty());
+    emulator.import_save(&[0x24; 0x2000]);
+    assert_eq!(emulator.read_memory_range(0xA000, 1), vec![0x42]);
+    let mut emulator = create_mbc1_emulator(0x03);
+    emulator.set_debug_enabled(true);
+    assert_eq!(emulator.write_memory_range(0x0000, &[0x0A]), Ok(()));
+    assert_eq!(emulator.write_memory_range(0xA000, &[0x42]), Ok(()));
+    assert_eq!(emulator.export_save().len(), 0x2000);
+    assert_eq!(emulator.export_save()[0], 0x42);
+  }
+
+  #[test]
+  fn save_is_dirty_after_disabling_written_ram() {
+    let mut emulator = create_mbc1_emulator(0x03);
+    emulator.set_debug_enabled(true);
+    assert_eq!(emulator.write_memory_range(0x0000, &[0x0A]), Ok(()));
+    assert_eq!(emulator.write_memory_range(0xA000, &[0x42, 0x43]), Ok(()));
+    assert!(!emulator.take_save_dirty());
+    assert_eq!(emulator.write_memory_range(0x0000, &[0x00]), Ok(()));
+    assert!(emulator.take_save_dirty());
+    assert!(!emulator.take_save_dirty());
+    assert_eq!(emulator.write_memory_range(0x0000, &[0x0A, 0x00]), Ok(()));
+    assert!(!emulator.take_save_dirty());
+  }
+
+  #[test]
+  fn import_save_with_wrong_size_is_cut_off_or_padded() {
+    let mut emulator = create_mbc1_emulator(0x03);
+    emulator.import_save(&[0x42; 0x3000]);
+    assert_eq!(emulator.export_save(), vec![0x42; 0x2000]);
+    emulator.import_save(&[0x24; 0x1000]);
+    let save = emulator.export_save();
+    assert_eq!(save[..0x1000], [0x24; 0x1000]);
+    assert_eq!(save[0x1000..], [0x00; 0x1000]);
+  }
+
+  #[test]
+  fn serial_output_collects_sent_bytes() {
+    // LD A,0x4F; LDH (0x01),A; LD A,0x81; LDH (0x02),A; JR -2
+    let mut emulator = create_emulator(&[0x3E, 0x4F, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0x18, 0xFE]);
+    (0..4).for_each(|_| { emulator.step_instruction(); });
+    assert_eq!(emulator.take_serial_output(), vec![0x4F]);
+    (0..1024).for_each(|_| { emulator.step_m_cycle(); });
+    assert_eq_hex!(emulator.read_memory_range(0xFF01, 2), vec![0xFF, 0x7F]);
+    assert_eq_hex!(emulator.interrupt_controller.read(0xFF0F) & 0x08, 0x08);
+  }
+
+  // LD A,0x42; LDH (0x01),A; LD A,0x81; LDH (0x02),A; JR -2
+  const SERIAL_SEND_PROGRAM: [u8; 10] = [0x3E, 0x42, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0x18, 0xFE];
+
+  #[test]
+  fn linked_emulators_exchange_bytes() {
+    let clocking = Rc::new(RefCell::new(create_emulator(&SERIAL_SEND_PROGRAM)));
+    // LD A,0x99; LDH (0x01),A; LD A,0x80; LDH (0x02),A; JR -2
+    let clocked = Rc::new(RefCell::new(create_emulator(&[0x3E, 0x99, 0xE0, 0x01, 0x3E, 0x80, 0xE0, 0x02, 0x18, 0xFE])));
+    clocking.borrow_mut().connect_serial(Box::new(Rc::downgrade(&clocked)));
+    clocked.borrow_mut().connect_serial(Box::new(Rc::downgrade(&clocking)));
+    (0..4).for_each(|_| { clocked.borrow_mut().step_instruction(); });
+    (0..1100).for_each(|_| { clocking.borrow_mut().step_m_cycle(); });
+    clocked.borrow_mut().step_m_cycle();
+    assert_eq_hex!(clocking.borrow_mut().read_memory_range(0xFF01, 1), vec![0x99]);
+    assert_eq_hex!(clocked.borrow_mut().read_memory_range(0xFF01, 1), vec![0x42]);
+    assert_eq_hex!(clocked.borrow().interrupt_controller.read(0xFF0F) & 0x08, 0x08);
+    assert_eq!(Rc::strong_count(&clocking), 1);
+    assert_eq!(Rc::strong_count(&clocked), 1);
+  }
+
+  #[test]
+  fn transfer_to_a_dropped_partner_shifts_in_ones() {
+    let emulator = Rc::new(RefCell::new(create_emulator(&SERIAL_SEND_PROGRAM)));
+    let partner = Rc::new(RefCell::new(create_emulator(&[0x18, 0xFE])));
+    emulator.borrow_mut().connect_serial(Box::new(Rc::downgrade(&partner)));
+    drop(partner);
+    (0..1100).for_each(|_| { emulator.borrow_mut().step_m_cycle(); });
+    assert_eq_hex!(emulator.borrow_mut().read_memory_range(0xFF01, 1), vec![0xFF]);
+  }
+
+  #[test]
+  fn emulator_linked_to_itself_shifts_in_ones() {
+    let emulator = Rc::new(RefCell::new(create_emulator(&SERIAL_SEND_PROGRAM)));
+    emulator.borrow_mut().connect_serial(Box::new(Rc::downgrade(&emulator)));
+    (0..1100).for_each(|_| { emulator.borrow_mut().step_m_cycle(); });
+    assert_eq_hex!(emulator.borrow_mut().read_memory_range(0xFF01, 1), vec![0xFF]);
+  }
+
+  #[test]
+  fn disassemble_reads_through_the_memory_map() {
+    let mut emulator = create_emulator(&[0x3E, 0x42, 0xEA, 0x00, 0xC0]);
+    emulator.wram.write(0xC000, 0xC9);
+    let mnemonics: Vec<String> = emulator.disassemble(0x0100, 5).into_iter().map(|instruction| instruction.mnemonic).collect();
+    assert_eq!(mnemonics, vec!["LD A,$42", "LD ($C000),A"]);
+    assert_eq!(emulator.disassemble(0xC000, 1)[0].mnemonic, "RET");
+  }
+
+  #[test]
+  fn disassemble_shows_labels_of_the_mapped_bank() {
+    let mut rom = vec![0u8; 0x10000];
+    // MBC1 with 4 banks
+    rom[0x0147] = 0x01;
+    rom[0x0148] = 0x01;
+    // CALL $4123; JP $0150
+    rom[0x0100..0x0106].copy_from_slice(&[0xCD, 0x23, 0x41, 0xC3, 0x50, 0x01]);
+    rom[0x014D] = header_checksum(&rom);
+    let mut renderer = MockRenderer::new();
+    renderer.expect_draw_pixel().return_const(());
+    let mut emulator = Emulator::new(&rom, Box::new(renderer), EmulationMode::Strict).unwrap();
+    emulator.set_debug_enabled(true);
+    emulator.load_symbols("01:4123 MyFunction\n02:4123 OtherFunction\n00:0150 Main\n");
+    let mnemonics: Vec<String> = emulator.disassemble(0x0100, 6).into_iter().map(|instruction| instruction.mnemonic).collect();
+    assert_eq!(mnemonics, vec!["CALL MyFunction", "JP Main"]);
+    assert_eq!(emulator.write_memory_range(0x2000, &[0x02]), Ok(()));
+    assert_eq!(emulator.disassemble(0x0100, 3)[0].mnemonic, "CALL OtherFunction");
+    assert_eq!(emulator.write_memory_range(0x2000, &[0x03]), Ok(()));
+    assert_eq!(emulator.disassemble(0x0100, 3)[0].mnemonic, "CALL $4123");
+  }
+
+  #[test]
+  fn cpu_info_counts_cycles_in_double_speed() {
+    let mut emulator = create_color_emulator(&[]);
+    emulator.speed.write(0xFF4D, 0x01);
+    emulator.speed.switch_speed();
+    (0..100).for_each(|_| { emulator.step_m_cycle(); });
+    assert_eq!(emulator.cpu_info().cycles, 100);
+    assert_eq!(emulator.cpu_info().pc, 0x0100 + 100);
+  }
+
+  #[test]
+  fn snapshot_cpu_waits_for_instruction_boundary() {
+    // LD BC,0x1234; JR -2
+    let mut emulator = create_emulator(&[0x01, 0x34, 0x12, 0x18, 0xFE]);
+    emulator.step_m_cycle();
+    let mut snapshot = vec![];
+    emulator.snapshot_cpu(&mut serde_json::Serializer::new(&mut snapshot)).unwrap();
+    assert_eq!(emulator.cpu_info().pc, 0x0103);
+    (0..10).for_each(|_| { emulator.step_instruction(); });
+    emulator.restore_cpu(&mut serde_json::Deserializer::from_slice(&snapshot)).unwrap();
+    let info = emulator.cpu_info();
+    assert_eq!((info.pc, info.bc, info.cycles), (0x0103, 0x1234, 3));
+  }
+
+  /// Hashes the pixels drawn since the last call to take_hash.
+  struct HashingRenderer {
+    hasher: RefCell<DefaultHasher>,
+  }
+
+  impl Renderer for Rc<HashingRenderer> {
+    fn draw_pixel(&self, x: u8, y: u8, color: Color, draw_in_back: bool) {
+      (x, y, color.red, color.green, color.blue, draw_in_back).hash(&mut *self.hasher.borrow_mut());
+    }
+  }
+
+  impl HashingRenderer {
+    fn take_hash(&self) -> u64 {
+      self.hasher.replace(DefaultHasher::new()).finish()
+    }
+  }
+
+  fn create_hashing_emulator(rom: &[u8]) -> (Emulator, Rc<HashingRenderer>) {
+    let renderer = Rc::new(HashingRenderer { hasher: RefCell::new(DefaultHasher::new()) });
+    (Emulator::new(rom, Box::new(Rc::clone(&renderer)), EmulationMode::Strict).unwrap(), renderer)
+  }
+
+  /// A color game that keeps the timer running and changes the background palettes and SCX.
+  fn create_changing_rom() -> Vec<u8> {
+    let mut rom = vec![0u8; 0x8000];
+    // LD A,0x05; LDH (TAC),A; LD A,0x80; LDH (BCPS),A; INC A; LDH (BCPD),A; LDH (SCX),A; JR -7
+    let program = [0x3E, 0x05, 0xE0, 0x07, 0x3E, 0x80, 0xE0, 0x68, 0x3C, 0xE0, 0x69, 0xE0, 0x43, 0x18, 0xF9];
+    rom[0x0100..0x0100 + program.len()].copy_from_slice(&program);
+    rom[0x0143] = 0x80;
+    rom[0x014D] = header_checksum(&rom);
+    rom
+  }
+
+  fn frame_hashes(emulator: &mut Emulator, renderer: &HashingRenderer) -> Vec<u64> {
+    (0..10).map(|_| {
+      emulator.run_frame();
+      renderer.take_hash()
+    }).collect()
+  }
+
+  #[test]
+  fn restored_state_draws_the_same_frames() {
+    let rom = create_changing_rom();
+    let (mut emulator, renderer) = create_hashing_emulator(&rom);
+    frame_hashes(&mut emulator, &renderer);
+    let state = emulator.save_state();
+    let (mut restored, restored_renderer) = create_hashing_emulator(&rom);
+    assert_eq!(restored.load_state(&state), Ok(()));
+    let hashes = frame_hashes(&mut emulator, &renderer);
+    assert_eq!(frame_hashes(&mut restored, &restored_renderer), hashes);
+    assert_ne!(hashes[0], hashes[1]);
+    assert_eq!(restored.cpu_info().cycles, emulator.cpu_info().cycles);
+  }
+
+  #[test]
+  fn state_keeps_cartridge_ram_and_clock() {
+    let mut rom = vec![0u8; 0x8000];
+    rom[0x0147] = 0x10;
+    rom[0x0149] = 0x02;
+    rom[0x014D] = header_checksum(&rom);
+    let mut emulator = create_emulator_for_rom(&rom);
+    emulator.set_debug_enabled(true);
+    // Enable RAM and the clock, then write RAM and the seconds register
+    assert_eq!(emulator.write_memory_range(0x0000, &[0x0A]), Ok(()));
+    assert_eq!(emulator.write_memory_range(0xA123, &[0x42]), Ok(()));
+    assert_eq!(emulator.write_memory_range(0x4000, &[0x08]), Ok(()));
+    assert_eq!(emulator.write_memory_range(0xA000, &[0x17]), Ok(()));
+    let state = emulator.save_state();
+    let mut restored = create_emulator_for_rom(&rom);
+    restored.set_debug_enabled(true);
+    assert_eq!(restored.load_state(&state), Ok(()));
+    assert_eq!(restored.read_memory_range(0xA000, 1), vec![0x17]);
+    assert_eq!(restored.write_memory_range(0x4000, &[0x00]), Ok(()));
+    assert_eq!(restored.read_memory_range(0xA123, 1), vec![0x42]);
+  }
+
+  fn create_emulator_for_rom(rom: &[u8]) -> Emulator {
+    let mut renderer = MockRenderer::new();
+    renderer.expect_draw_pixel().return_const(());
+    Emulator::new(rom, Box::new(renderer), EmulationMode::Strict).unwrap()
+  }
+
+  struct RecordingMotor {
+    states: Vec<bool>,
+  }
+
+  impl RumbleMotor for RecordingMotor {
+    fn set_rumble(&mut self, on: bool) {
+      self.states.push(on);
+    }
+  }
+
+  #[test]
+  fn rumble_reaches_the_connected_motor() {
+    let mut rom = vec![0u8; 0x40000];
+    rom[0x0147] = 0x1C;
+    rom[0x0148] = 0x03;
+    rom[0x014D] = header_checksum(&rom);
+    let mut emulator = create_emulator_for_rom(&rom);
+    emulator.set_debug_enabled(true);
+    let motor = Rc::new(RefCell::new(RecordingMotor { states: vec![] }));
+    emulator.connect_rumble(Box::new(Rc::clone(&motor)));
+    for value in [0x08, 0x00] {
+      assert_eq!(emulator.write_memory(0x4000, value), Ok(()));
+    }
+    assert_eq!(motor.borrow().states, vec![true, false]);
+  }
+
+  #[test]
+  fn tilt_reaches_the_mbc7_accelerometer() {
+    let mut rom = vec![0u8; 0x40000];
+    rom[0x0147] = 0x22;
+    rom[0x0148] = 0x03;
+    rom[0x014D] = header_checksum(&rom);
+    let mut emulator = create_emulator_for_rom(&rom);
+    emulator.set_debug_enabled(true);
+    emulator.set_accelerometer(1.0, -1.0);
+    // Enable the registers, then erase and latch the acceleration
+    for (address, value) in [(0x0000, 0x0A), (0x4000, 0x40), (0xA000, 0x55), (0xA010, 0xAA)] {
+      assert_eq!(emulator.write_memory(address, value), Ok(()));
+    }
+    let read_word = |emulator: &mut Emulator, address: u16| u16::from_le_bytes([emulator.read_byte(address), emulator.read_byte(address + 0x10)]);
+    assert_eq_hex!(read_word(&mut emulator, 0xA020), 0x8240);
+    assert_eq_hex!(read_word(&mut emulator, 0xA040), 0x8160);
+  }
+
+  #[test]
+  fn state_of_another_game_is_refused() {
+    let rom = create_changing_rom();
+    let state = create_emulator_for_rom(&rom).save_state();
+    let mut other_rom = rom.clone();
+    other_rom[0x0134] = b'A';
+    other_rom[0x014D] = header_checksum(&other_rom);
+    let mut other = create_emulator_for_rom(&other_rom);
+    assert_eq!(other.load_state(&state), Err(LoadStateError::WrongGame));
+    let mut emulator = create_emulator_for_rom(&rom);
+    let mut future_state = state.clone();
+    future_state[0] = Emulator::SAVE_STATE_VERSION as u8 + 1;
+    assert_eq!(emulator.load_state(&future_state), Err(LoadStateError::UnsupportedVersion(Emulator::SAVE_STATE_VERSION + 1)));
+    assert_eq!(emulator.load_state(&state[..state.len() / 2]), Err(LoadStateError::Corrupt));
+  }
+
+  #[test]
+  fn save_slot_is_overwritten() {
+    let (mut emulator, renderer) = create_hashing_emulator(&create_changing_rom());
+    emulator.run_frame();
+    emulator.save_slot(2);
+    frame_hashes(&mut emulator, &renderer);
+    emulator.save_slot(2);
+    let hashes = frame_hashes(&mut emulator, &renderer);
+    assert_eq!(emulator.slot_info().iter().map(|info| (info.slot, info.frame)).collect::<Vec<_>>(), vec![(2, 11)]);
+    assert_eq!(emulator.load_slot(2), Ok(()));
+    assert_eq!(emulator.frames(), 11);
+    assert_eq!(frame_hashes(&mut emulator, &renderer), hashes);
+    emulator.clear_slot(2);
+    assert!(!emulator.has_slot(2));
+  }
+
+  #[test]
+  fn loading_an_empty_slot_changes_nothing() {
+    let mut emulator = create_emulator_for_rom(&create_changing_rom());
+    emulator.run_frame();
+    assert!(!emulator.has_slot(0));
+    assert_eq!(emulator.load_slot(0), Err(LoadStateError::EmptySlot(0)));
+    assert_eq!(emulator.frames(), 1);
+  }
+
+  #[test]
+  fn slot_of_another_game_is_refused() {
+    let rom = create_changing_rom();
+    let mut emulator = create_emulator_for_rom(&rom);
+    emulator.save_slot(0);
+    let mut other_rom = rom.clone();
+    other_rom[0x014E] = 0x12;
+    let mut other = create_emulator_for_rom(&other_rom);
+    assert_eq!(other.load_state(&emulator.slot_state(0).unwrap()), Err(LoadStateError::WrongGame));
+  }
+
+  const GBMICROTEST_FRAMES: usize = 10;
+
+  /// Runs the gbmicrotest ROMs from https://github.com/aappleby/gbmicrotest. Point GBMICROTEST_DIR at a directory with
+  /// the built .gb files and run `cargo test gbmicrotest -- --ignored`.
+  #[test]
+  #[ignore]
+  fn gbmicrotest_roms() {
+    let directory = std::env::var("GBMICROTEST_DIR").expect("GBMICROTEST_DIR should point at the gbmicrotest ROMs");
+    let mut paths: Vec<std::path::PathBuf> = std::fs::read_dir(&directory)
+      .unwrap_or_else(|error| panic!("Can't read gbmicrotest ROMs from {}: {}", directory, error))
+      .map(|entry| entry.unwrap().path())
+      .filter(|path| path.extension().is_some_and(|extension| extension == "gb"))
+      .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "No gbmicrotest ROMs found in {}", directory);
+    // A panic only counts as a failure for the ROM that caused it
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+    let failures: Vec<String> = paths.iter()
+      .filter_map(|path| {
+        let rom = std::fs::read(path).unwrap();
+        let name = path.file_stem().unwrap().to_string_lossy();
+        match std::panic::catch_unwind(|| run_gbmicrotest(&rom)) {
+          Ok(0x01) => None,
+          Ok(0xFF) => Some(format!("{}: failed", name)),
+          Ok(_) => Some(format!("{}: no result after {} frames", name, GBMICROTEST_FRAMES)),
+          Err(_) => Some(format!("{}: panicked", name)),
+        }
+      })
+      .collect();
+    std::panic::set_hook(hook);
+    println!("gbmicrotest: {} of {} passed", paths.len() - failures.len(), paths.len());
+    assert!(failures.is_empty(), "{} of {} ROMs failed:\n{}", failures.len(), paths.len(), failures.join("\n"));
+  }
+
+  /// Runs until the ROM writes 0x01 (pass) or 0xFF (fail) to 0xFF82, and returns that value.
+  fn run_gbmicrotest(rom: &[u8]) -> u8 {
+    let mut renderer = MockRenderer::new();
+    renderer.expect_draw_pixel().return_const(());
+    let mut emulator = Emulator::new(rom, Box::new(renderer), EmulationMode::Lenient).unwrap();
+    for _ in 0..GBMICROTEST_FRAMES {
+      emulator.run_frame();
+      let result = emulator.read_memory_range(0xFF82, 1)[0];
+      if result != 0x00 {
+        return result;
+      }
+    }
+    0x00
+  }
+}
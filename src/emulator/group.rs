@@ -0,0 +1,103 @@
+use crate::emulator::emulator::Emulator;
+
+struct GroupMember {
+  emulator: Emulator,
+  weight: u32,
+  paused: bool,
+}
+
+/// Runs several emulators side by side. Every round, each running emulator advances by as many frames as its weight,
+/// so the emulated speeds of the members keep the configured ratio.
+// TODO: Not done yet: sharing one AudioContext between members, capping the worklet nodes and pausing the audio of
+//  background members. There is no audio driver to hook these into.
+pub struct EmulatorGroup {
+  members: Vec<GroupMember>,
+}
+
+impl EmulatorGroup {
+  pub fn new() -> EmulatorGroup {
+    EmulatorGroup {
+      members: vec![],
+    }
+  }
+
+  /// Adds an emulator to the group and returns the index used to refer to it.
+  pub fn add(&mut self, emulator: Emulator, weight: u32) -> usize {
+    self.members.push(GroupMember {
+      emulator,
+      weight,
+      paused: false,
+    });
+    self.members.len() - 1
+  }
+
+  pub fn set_weight(&mut self, index: usize, weight: u32) {
+    self.members[index].weight = weight;
+  }
+
+  pub fn set_paused(&mut self, index: usize, paused: bool) {
+    self.members[index].paused = paused;
+  }
+
+  pub fn paused(&self, index: usize) -> bool {
+    self.members[index].paused
+  }
+
+  /// The number of frames the emulator at the given index has completed.
+  pub fn frames(&self, index: usize) -> u64 {
+    self.members[index].emulator.frames()
+  }
+
+  pub fn run_round(&mut self) {
+    for member in self.members.iter_mut().filter(|member| !member.paused) {
+      for _ in 0..member.weight {
+        member.emulator.run_frame();
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::memory::cartridge_header::header_checksum;
+  use crate::memory::mbc::EmulationMode;
+  use crate::renderer::renderer::MockRenderer;
+  use super::*;
+
+  fn create_emulator() -> Emulator {
+    let mut renderer = MockRenderer::new();
+    renderer.expect_draw_pixel().return_const(());
+    let mut rom = [0u8; 0x8000];
+    rom[0x0100..0x0102].copy_from_slice(&[0x18, 0xFE]); // JR -2
+    rom[0x014D] = header_checksum(&rom);
+    Emulator::new(&rom, Box::new(renderer), EmulationMode::Strict).unwrap()
+  }
+
+  #[test]
+  fn frames_advance_in_weight_ratio() {
+    let mut group = EmulatorGroup::new();
+    let fast = group.add(create_emulator(), 2);
+    let slow = group.add(create_emulator(), 1);
+    (0..3).for_each(|_| group.run_round());
+    assert_eq!(group.frames(fast), 6);
+    assert_eq!(group.frames(slow), 3);
+  }
+
+  #[test]
+  fn paused_members_do_not_advance() {
+    let mut group = EmulatorGroup::new();
+    let first = group.add(create_emulator(), 1);
+    let second = group.add(create_emulator(), 1);
+    group.run_round();
+    group.set_paused(second, true);
+    (0..2).for_each(|_| group.run_round());
+    assert!(group.paused(second));
+    assert_eq!(group.frames(first), 3);
+    assert_eq!(group.frames(second), 1);
+    group.set_paused(second, false);
+    group.set_weight(second, 2);
+    group.run_round();
+    assert_eq!(group.frames(first), 4);
+    assert_eq!(group.frames(second), 3);
+  }
+}
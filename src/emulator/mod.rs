@@ -1 +1,7 @@
-pub mod emulator;
\ No newline at end of file
+pub mod accuracy;
+pub mod emulator;
+pub mod group;
+pub mod replay;
+pub mod save_slots;
+pub mod symbols;
+pub mod watchpoint;
@@ -0,0 +1,79 @@
+use std::fmt::{Display, Formatter};
+use serde::{Deserialize, Serialize};
+use crate::controllers::buttons::Button;
+use crate::memory::cartridge_header::RomError;
+use crate::renderer::renderer::{Color, Renderer};
+
+/// An M-cycle in a session, counted in frames and M-cycles into the frame like run_frame counts them.
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Debug)]
+pub struct ReplayPosition {
+  pub frame: u64,
+  pub frame_cycle: u32,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Debug)]
+pub struct InputEvent {
+  pub position: ReplayPosition,
+  pub button: Button,
+  pub pressed: bool,
+}
+
+/// The input since a replay recording started, and the state it started from.
+pub struct ReplayRecording {
+  pub initial_state: Vec<u8>,
+  pub events: Vec<InputEvent>,
+}
+
+/// Why a replay failed verification.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ReplayError {
+  /// The replay was exported by a different version of the emulator, whose format can't be read.
+  UnsupportedVersion(u32),
+  /// The replay was recorded on a game with different checksums in its cartridge header.
+  WrongGame,
+  /// The replay is truncated or otherwise unreadable.
+  Corrupt,
+  InvalidRom(RomError),
+  /// Replaying the input didn't end in the recorded state, so the emulator or the replay isn't deterministic.
+  HashMismatch { expected: u64, actual: u64 },
+}
+
+impl Display for ReplayError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ReplayError::UnsupportedVersion(version) => write!(f, "Replay version {} isn't supported", version),
+      ReplayError::WrongGame => write!(f, "Replay belongs to a different game"),
+      ReplayError::Corrupt => write!(f, "Replay is corrupt"),
+      ReplayError::InvalidRom(error) => write!(f, "ROM was refused: {:?}", error),
+      ReplayError::HashMismatch { expected, actual } =>
+        write!(f, "Replay ended in state {:#018x} instead of {:#018x}", actual, expected),
+    }
+  }
+}
+
+/// Draws nothing, for replays that are only verified.
+pub struct NullRenderer;
+
+impl Renderer for NullRenderer {
+  fn draw_pixel(&self, _x: u8, _y: u8, _color: Color, _draw_in_back: bool) {}
+}
+
+/// FNV-1a of a save state. Unlike the standard library's hasher it's the same in every build, so a replay exported by
+/// one build can be verified by another.
+pub fn state_hash(state: &[u8]) -> u64 {
+  state.iter().fold(0xCBF29CE484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001B3))
+}
+
+#[cfg(test)]
+mod tests {
+  use assert_hex::assert_eq_hex;
+  use test_case::test_case;
+  use super::*;
+
+  #[test_case(b"", 0xCBF29CE484222325)]
+  #[test_case(b"a", 0xAF63DC4C8601EC8C)]
+  #[test_case(b"foobar", 0x85944171F73967E8)]
+  fn state_hash_is_fnv_1a(state: &[u8], hash: u64) {
+    assert_eq_hex!(state_hash(state), hash);
+  }
+}
@@ -0,0 +1,83 @@
+/// What a UI shows in its list of save slots.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SlotInfo {
+  pub slot: u8,
+  /// The number of frames the emulator had run when the state was saved.
+  pub frame: u64,
+  /// The unix time in seconds when the state was saved.
+  pub saved_at: u64,
+}
+
+struct SaveSlot {
+  info: SlotInfo,
+  state: Vec<u8>,
+}
+
+/// Save states kept in memory, in a fixed number of numbered slots. Slot numbers beyond COUNT panic.
+pub struct SaveSlots {
+  slots: Vec<Option<SaveSlot>>,
+}
+
+impl SaveSlots {
+  pub const COUNT: u8 = 10;
+
+  pub fn new() -> SaveSlots {
+    SaveSlots {
+      slots: (0..SaveSlots::COUNT).map(|_| None).collect(),
+    }
+  }
+
+  /// Replaces whatever state the slot held.
+  pub fn store(&mut self, slot: u8, frame: u64, saved_at: u64, state: Vec<u8>) {
+    self.slots[SaveSlots::index(slot)] = Some(SaveSlot {
+      info: SlotInfo { slot, frame, saved_at },
+      state,
+    });
+  }
+
+  pub fn state(&self, slot: u8) -> Option<&[u8]> {
+    self.slots[SaveSlots::index(slot)].as_ref().map(|save_slot| save_slot.state.as_slice())
+  }
+
+  pub fn clear(&mut self, slot: u8) {
+    self.slots[SaveSlots::index(slot)] = None;
+  }
+
+  /// The occupied slots, in order.
+  pub fn info(&self) -> Vec<SlotInfo> {
+    self.slots.iter().flatten().map(|save_slot| save_slot.info).collect()
+  }
+
+  fn index(slot: u8) -> usize {
+    if slot >= SaveSlots::COUNT {
+      panic!("Save slot {} doesn't exist, there are only {}", slot, SaveSlots::COUNT);
+    }
+    slot as usize
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn stored_state_replaces_the_previous_one() {
+    let mut slots = SaveSlots::new();
+    slots.store(3, 10, 1000, vec![0x01]);
+    slots.store(3, 20, 2000, vec![0x02]);
+    slots.store(1, 30, 3000, vec![0x03]);
+    assert_eq!(slots.state(3), Some([0x02].as_slice()));
+    assert_eq!(slots.info(), vec![
+      SlotInfo { slot: 1, frame: 30, saved_at: 3000 },
+      SlotInfo { slot: 3, frame: 20, saved_at: 2000 },
+    ]);
+    slots.clear(3);
+    assert_eq!(slots.state(3), None);
+  }
+
+  #[test]
+  #[should_panic(expected = "Save slot 10 doesn't exist")]
+  fn slots_beyond_count_panic() {
+    SaveSlots::new().clear(SaveSlots::COUNT);
+  }
+}
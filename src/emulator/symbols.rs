@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+
+/// Labels from an RGBDS .sym file, keyed by bank and address.
+pub struct SymbolTable {
+  labels: HashMap<(usize, u16), String>,
+}
+
+impl SymbolTable {
+  pub fn new() -> SymbolTable {
+    SymbolTable {
+      labels: HashMap::new(),
+    }
+  }
+
+  /// Parses lines of the form `bank:address label`, both in hex. Comments starting with `;` and lines that don't
+  /// follow the format are skipped.
+  pub fn parse(text: &str) -> SymbolTable {
+    let labels = text.lines()
+      .filter_map(|line| {
+        let mut parts = line.split(';').next()?.split_whitespace();
+        let (bank, address) = parts.next()?.split_once(':')?;
+        let label = parts.next()?;
+        Some(((usize::from_str_radix(bank, 16).ok()?, u16::from_str_radix(address, 16).ok()?), label.to_string()))
+      })
+      .collect();
+    SymbolTable { labels }
+  }
+
+  pub fn label(&self, bank: usize, address: u16) -> Option<&str> {
+    self.labels.get(&(bank, address)).map(String::as_str)
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.labels.is_empty()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_banked_labels() {
+    let symbols = SymbolTable::parse("\
+; File generated by rgblink
+00:0150 Main
+00:0158 Main.loop
+01:4123 MyFunction
+02:4123 OtherFunction ; same address in another bank
+00:c000 wCounter
+");
+    assert_eq!(symbols.label(0x00, 0x0150), Some("Main"));
+    assert_eq!(symbols.label(0x00, 0x0158), Some("Main.loop"));
+    assert_eq!(symbols.label(0x01, 0x4123), Some("MyFunction"));
+    assert_eq!(symbols.label(0x02, 0x4123), Some("OtherFunction"));
+    assert_eq!(symbols.label(0x00, 0xC000), Some("wCounter"));
+    assert_eq!(symbols.label(0x03, 0x4123), None);
+  }
+
+  #[test]
+  fn skips_malformed_lines() {
+    let symbols = SymbolTable::parse("\
+0150 NoBank
+zz:0150 BadBank
+00:0150
+01:4000 Valid
+");
+    assert_eq!(symbols.labels.len(), 1);
+    assert_eq!(symbols.label(0x01, 0x4000), Some("Valid"));
+    assert!(SymbolTable::parse("; only a comment").is_empty());
+  }
+}
@@ -0,0 +1,102 @@
+use std::cell::Cell;
+use std::ops::RangeInclusive;
+use crate::memory::memory::Memory;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Watchpoint {
+  pub addresses: RangeInclusive<u16>,
+  pub on_read: bool,
+  pub on_write: bool,
+}
+
+/// A watched memory access. For reads, the old and new value are both the value that was read.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct WatchpointHit {
+  pub pc: u16,
+  pub address: u16,
+  pub old_value: u8,
+  pub new_value: u8,
+}
+
+/// Wraps the memory bus of the CPU and records the first access that matches one of the watchpoints.
+pub struct WatchedMemory<'a> {
+  memory: &'a mut dyn Memory,
+  watchpoints: &'a [Watchpoint],
+  pc: u16,
+  hit: Cell<Option<WatchpointHit>>,
+}
+
+impl<'a> WatchedMemory<'a> {
+  pub fn new(memory: &'a mut dyn Memory, watchpoints: &'a [Watchpoint], pc: u16) -> WatchedMemory<'a> {
+    WatchedMemory {
+      memory,
+      watchpoints,
+      pc,
+      hit: Cell::new(None),
+    }
+  }
+
+  pub fn hit(&self) -> Option<WatchpointHit> {
+    self.hit.get()
+  }
+
+  fn record(&self, address: u16, old_value: u8, new_value: u8) {
+    if self.hit.get().is_none() {
+      self.hit.set(Some(WatchpointHit { pc: self.pc, address, old_value, new_value }));
+    }
+  }
+}
+
+impl<'a> Memory for WatchedMemory<'a> {
+  fn read(&self, address: u16) -> u8 {
+    let value = self.memory.read(address);
+    if self.watchpoints.iter().any(|watchpoint| watchpoint.on_read && watchpoint.addresses.contains(&address)) {
+      self.record(address, value, value);
+    }
+    value
+  }
+
+  fn write(&mut self, address: u16, value: u8) {
+    if self.watchpoints.iter().any(|watchpoint| watchpoint.on_write && watchpoint.addresses.contains(&address)) {
+      let old_value = self.memory.read(address);
+      self.memory.write(address, value);
+      self.record(address, old_value, value);
+    } else {
+      self.memory.write(address, value);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::memory::memory::test::MockMemory;
+  use super::*;
+
+  #[test]
+  fn records_first_matching_access() {
+    let mut memory = MockMemory::new(0x10000);
+    memory.write(0xFE00, 0x12);
+    let watchpoints = [
+      Watchpoint { addresses: 0xC000..=0xC000, on_read: false, on_write: true },
+      Watchpoint { addresses: 0xFE00..=0xFE9F, on_read: true, on_write: false },
+    ];
+    let mut watched_memory = WatchedMemory::new(&mut memory, &watchpoints, 0x0150);
+    watched_memory.read(0xC000);
+    watched_memory.write(0xFE00, 0x34);
+    assert_eq!(watched_memory.hit(), None);
+    assert_eq!(watched_memory.read(0xFE00), 0x34);
+    watched_memory.write(0xC000, 0x56);
+    assert_eq!(watched_memory.hit(), Some(WatchpointHit { pc: 0x0150, address: 0xFE00, old_value: 0x34, new_value: 0x34 }));
+  }
+
+  #[test]
+  fn write_reports_old_and_new_value() {
+    let mut memory = MockMemory::new(0x10000);
+    memory.write(0xC000, 0x12);
+    let watchpoints = [Watchpoint { addresses: 0xC000..=0xC000, on_read: false, on_write: true }];
+    let mut watched_memory = WatchedMemory::new(&mut memory, &watchpoints, 0x0150);
+    watched_memory.write(0xC000, 0x34);
+    assert_eq!(watched_memory.hit(), Some(WatchpointHit { pc: 0x0150, address: 0xC000, old_value: 0x12, new_value: 0x34 }));
+    assert_eq!(memory.read(0xC000), 0x34);
+  }
+}
@@ -0,0 +1,287 @@
+use std::ffi::{c_char, CStr};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::rc::Rc;
+use std::slice;
+use serde::Deserialize;
+use crate::controllers::buttons::Button;
+use crate::emulator::emulator::Emulator;
+use crate::ffi::frame_buffer::{FrameBuffer, FRAME_BUFFER_SIZE};
+use crate::memory::mbc::EmulationMode;
+
+/// The result of a call through the C ABI. Matches RustboyStatus in include/rustboy.h.
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Status {
+  Ok = 0,
+  NullPointer = 1,
+  InvalidButton = 2,
+  /// The buffer is too small. For rustboy_save_state, the size it needs is stored in written.
+  BufferTooSmall = 3,
+  InvalidState = 4,
+  /// The emulator panicked. Its state can't be trusted anymore, so every later call on the handle returns Panic too.
+  Panic = 5,
+}
+
+/// The JSON object passed to rustboy_create. Missing keys keep their defaults.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct Config {
+  /// Tolerate accesses to addresses the cartridge doesn't decode instead of panicking.
+  lenient: bool,
+}
+
+pub struct Handle {
+  emulator: Emulator,
+  frame_buffer: Rc<FrameBuffer>,
+  poisoned: bool,
+}
+
+impl Handle {
+  /// Runs the call, turning a panic into Status::Panic so it doesn't unwind into the host.
+  fn guard(&mut self, call: impl FnOnce(&mut Handle) -> Status) -> Status {
+    if self.poisoned {
+      return Status::Panic;
+    }
+    let status = catch_unwind(AssertUnwindSafe(|| call(self))).unwrap_or(Status::Panic);
+    self.poisoned = status == Status::Panic;
+    status
+  }
+}
+
+/// The buttons in the order of their numbers in the C ABI.
+fn button(index: u32) -> Option<Button> {
+  [Button::Right, Button::Left, Button::Up, Button::Down, Button::A, Button::B, Button::Select, Button::Start]
+    .get(index as usize)
+    .copied()
+}
+
+fn create(rom: &[u8], config: Option<&CStr>) -> Option<Handle> {
+  let mode = match config {
+    Some(config) => {
+      let config: Config = serde_json::from_slice(config.to_bytes()).ok()?;
+      if config.lenient { EmulationMode::Lenient } else { EmulationMode::Strict }
+    }
+    None => EmulationMode::default(),
+  };
+  let frame_buffer = Rc::new(FrameBuffer::new());
+  let emulator = Emulator::new(rom, Box::new(frame_buffer.clone()), mode).ok()?;
+  Some(Handle { emulator, frame_buffer, poisoned: false })
+}
+
+/// Starts a ROM, which may be zipped. config_json may be null for the defaults. Returns null if the ROM or the config
+/// is refused.
+///
+/// # Safety
+/// rom must point to rom_len readable bytes and config_json, unless null, to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rustboy_create(rom: *const u8, rom_len: usize, config_json: *const c_char) -> *mut Handle {
+  if rom.is_null() {
+    return std::ptr::null_mut();
+  }
+  let rom = slice::from_raw_parts(rom, rom_len);
+  let config = if config_json.is_null() { None } else { Some(CStr::from_ptr(config_json)) };
+  match catch_unwind(|| create(rom, config)) {
+    Ok(Some(handle)) => Box::into_raw(Box::new(handle)),
+    _ => std::ptr::null_mut(),
+  }
+}
+
+/// # Safety
+/// handle must be null or come from rustboy_create, and isn't valid afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn rustboy_destroy(handle: *mut Handle) {
+  if !handle.is_null() {
+    drop(Box::from_raw(handle));
+  }
+}
+
+/// Runs the emulator for a frame. Unless framebuffer is null, the screen is then copied to it as 160x144 RGBA8 pixels,
+/// row by row from the top left.
+///
+/// # Safety
+/// handle must come from rustboy_create, and framebuffer must be null or point to 92160 writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rustboy_run_frame(handle: *mut Handle, framebuffer: *mut u8) -> Status {
+  let Some(handle) = handle.as_mut() else {
+    return Status::NullPointer;
+  };
+  handle.guard(|handle| {
+    handle.emulator.run_frame();
+    if !framebuffer.is_null() {
+      handle.frame_buffer.copy_to(slice::from_raw_parts_mut(framebuffer, FRAME_BUFFER_SIZE));
+    }
+    Status::Ok
+  })
+}
+
+/// Presses or releases a button: 0 Right, 1 Left, 2 Up, 3 Down, 4 A, 5 B, 6 Select, 7 Start.
+///
+/// # Safety
+/// handle must come from rustboy_create.
+#[no_mangle]
+pub unsafe extern "C" fn rustboy_set_button(handle: *mut Handle, button_index: u32, pressed: bool) -> Status {
+  let Some(handle) = handle.as_mut() else {
+    return Status::NullPointer;
+  };
+  let Some(button) = button(button_index) else {
+    return Status::InvalidButton;
+  };
+  handle.guard(|handle| {
+    if pressed {
+      handle.emulator.press_button(button);
+    } else {
+      handle.emulator.release_button(button);
+    }
+    Status::Ok
+  })
+}
+
+/// Copies up to max_samples interleaved i16 stereo samples and returns how many were written. The APU isn't emulated
+/// yet, so this is always 0.
+///
+/// # Safety
+/// handle must come from rustboy_create, and out must be null or point to max_samples writable samples.
+#[no_mangle]
+pub unsafe extern "C" fn rustboy_get_audio(handle: *mut Handle, out: *mut i16, max_samples: usize) -> usize {
+  let _ = (handle, out, max_samples);
+  0
+}
+
+/// Writes a save state to out and its length to written. If capacity is too small, only the needed length is written.
+///
+/// # Safety
+/// handle must come from rustboy_create, out must point to capacity writable bytes and written to a writable size_t.
+#[no_mangle]
+pub unsafe extern "C" fn rustboy_save_state(
+  handle: *mut Handle,
+  out: *mut u8,
+  capacity: usize,
+  written: *mut usize,
+) -> Status {
+  let Some(handle) = handle.as_mut() else {
+    return Status::NullPointer;
+  };
+  if out.is_null() || written.is_null() {
+    return Status::NullPointer;
+  }
+  handle.guard(|handle| {
+    let state = handle.emulator.save_state();
+    *written = state.len();
+    if state.len() > capacity {
+      return Status::BufferTooSmall;
+    }
+    slice::from_raw_parts_mut(out, state.len()).copy_from_slice(&state);
+    Status::Ok
+  })
+}
+
+/// Restores a state from rustboy_save_state. Nothing changes if the state is refused.
+///
+/// # Safety
+/// handle must come from rustboy_create, and state must point to len readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rustboy_load_state(handle: *mut Handle, state: *const u8, len: usize) -> Status {
+  let Some(handle) = handle.as_mut() else {
+    return Status::NullPointer;
+  };
+  if state.is_null() {
+    return Status::NullPointer;
+  }
+  handle.guard(|handle| match handle.emulator.load_state(slice::from_raw_parts(state, len)) {
+    Ok(()) => Status::Ok,
+    Err(_) => Status::InvalidState,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use std::ffi::CString;
+  use test_case::test_case;
+  use super::*;
+
+  fn create_rom(program: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0u8; 0x8000];
+    rom[0x0100..0x0100 + program.len()].copy_from_slice(program);
+    rom[0x014D] = rom[0x0134..0x014D].iter().fold(0u8, |checksum, byte| checksum.wrapping_sub(*byte).wrapping_sub(1));
+    rom
+  }
+
+  fn create_handle(program: &[u8], config: Option<&CStr>) -> *mut Handle {
+    let rom = create_rom(program);
+    let handle = unsafe { rustboy_create(rom.as_ptr(), rom.len(), config.map_or(std::ptr::null(), CStr::as_ptr)) };
+    assert!(!handle.is_null());
+    handle
+  }
+
+  #[test]
+  fn create_refuses_a_damaged_rom() {
+    let rom = create_rom(&[]);
+    assert!(unsafe { rustboy_create(rom.as_ptr(), 0x4000, std::ptr::null()) }.is_null());
+    assert!(unsafe { rustboy_create(std::ptr::null(), 0, std::ptr::null()) }.is_null());
+  }
+
+  #[test_case("{\"lenient\": 1}")]
+  #[test_case("{\"fast\": true}")]
+  #[test_case("lenient")]
+  fn create_refuses_an_invalid_config(config: &str) {
+    let rom = create_rom(&[]);
+    let config = CString::new(config).unwrap();
+    assert!(unsafe { rustboy_create(rom.as_ptr(), rom.len(), config.as_ptr()) }.is_null());
+  }
+
+  #[test]
+  fn run_frame_fills_the_framebuffer_with_rgba8_pixels() {
+    // LD A,0xFF; LDH (0x47),A; JR -2 shades the whole background black
+    let handle = create_handle(&[0x3E, 0xFF, 0xE0, 0x47, 0x18, 0xFE], None);
+    let mut framebuffer = vec![0x12; FRAME_BUFFER_SIZE];
+    assert_eq!(unsafe { rustboy_run_frame(handle, framebuffer.as_mut_ptr()) }, Status::Ok);
+    assert_eq!(unsafe { rustboy_run_frame(handle, framebuffer.as_mut_ptr()) }, Status::Ok);
+    assert!(framebuffer.chunks(4).all(|pixel| pixel == [0x00, 0x00, 0x00, 0xFF]));
+    unsafe { rustboy_destroy(handle) };
+  }
+
+  #[test]
+  fn a_panic_is_reported_instead_of_unwinding_into_the_host() {
+    let handle = create_handle(&[0x18, 0xFE], Some(c"{\"lenient\": true}"));
+    assert_eq!(unsafe { &mut *handle }.guard(|_| panic!("Emulator bug")), Status::Panic);
+    assert_eq!(unsafe { rustboy_run_frame(handle, std::ptr::null_mut()) }, Status::Panic);
+    assert_eq!(unsafe { rustboy_set_button(handle, 0, true) }, Status::Panic);
+    unsafe { rustboy_destroy(handle) };
+  }
+
+  #[test]
+  fn buttons_are_numbered_like_the_header_says() {
+    let handle = create_handle(&[0x18, 0xFE], None);
+    assert_eq!(unsafe { rustboy_set_button(handle, 7, true) }, Status::Ok);
+    assert_eq!(unsafe { rustboy_set_button(handle, 7, false) }, Status::Ok);
+    assert_eq!(unsafe { rustboy_set_button(handle, 8, true) }, Status::InvalidButton);
+    assert_eq!(unsafe { rustboy_set_button(std::ptr::null_mut(), 0, true) }, Status::NullPointer);
+    unsafe { rustboy_destroy(handle) };
+  }
+
+  #[test]
+  fn save_state_reports_the_size_it_needs() {
+    let handle = create_handle(&[0x18, 0xFE], None);
+    let save_state = |state: &mut [u8]| {
+      let mut written = 0;
+      (unsafe { rustboy_save_state(handle, state.as_mut_ptr(), state.len(), &mut written) }, written)
+    };
+    let mut state = vec![0; 16];
+    let (status, needed) = save_state(&mut state);
+    assert_eq!(status, Status::BufferTooSmall);
+    state.resize(needed, 0);
+    assert_eq!(save_state(&mut state), (Status::Ok, needed));
+    assert_eq!(unsafe { rustboy_run_frame(handle, std::ptr::null_mut()) }, Status::Ok);
+    assert_eq!(unsafe { rustboy_load_state(handle, state.as_ptr(), state.len()) }, Status::Ok);
+    assert_eq!(unsafe { rustboy_load_state(handle, state.as_ptr(), 16) }, Status::InvalidState);
+    unsafe { rustboy_destroy(handle) };
+  }
+
+  #[test]
+  fn no_audio_is_produced() {
+    let handle = create_handle(&[0x18, 0xFE], None);
+    let mut samples = vec![0i16; 1024];
+    assert_eq!(unsafe { rustboy_get_audio(handle, samples.as_mut_ptr(), samples.len()) }, 0);
+    unsafe { rustboy_destroy(handle) };
+  }
+}
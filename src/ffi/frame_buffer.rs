@@ -0,0 +1,64 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use crate::renderer::renderer::{Color, Renderer};
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+pub const FRAME_BUFFER_SIZE: usize = SCREEN_WIDTH * SCREEN_HEIGHT * 4;
+
+/// Collects the drawn pixels as RGBA8, row by row from the top left. The renderer isn't told which background pixels
+/// have color 0, so objects drawn in back are drawn over the background like any other object.
+pub struct FrameBuffer {
+  pixels: RefCell<Vec<u8>>,
+}
+
+impl FrameBuffer {
+  pub fn new() -> FrameBuffer {
+    FrameBuffer {
+      pixels: RefCell::new(vec![0xFF; FRAME_BUFFER_SIZE]),
+    }
+  }
+
+  pub fn copy_to(&self, dst: &mut [u8]) {
+    dst.copy_from_slice(&self.pixels.borrow());
+  }
+}
+
+/// Scales a 5-bit color channel to 8 bits, so 0x1F becomes 0xFF.
+fn scale_channel(channel: u8) -> u8 {
+  (channel << 3) | (channel >> 2)
+}
+
+impl Renderer for Rc<FrameBuffer> {
+  fn draw_pixel(&self, x: u8, y: u8, color: Color, _draw_in_back: bool) {
+    let offset = (y as usize * SCREEN_WIDTH + x as usize) * 4;
+    let rgba = [scale_channel(color.red), scale_channel(color.green), scale_channel(color.blue), 0xFF];
+    self.pixels.borrow_mut()[offset..offset + 4].copy_from_slice(&rgba);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use assert_hex::assert_eq_hex;
+  use test_case::test_case;
+  use super::*;
+
+  #[test_case(0x00, 0x00)]
+  #[test_case(0x0A, 0x52)]
+  #[test_case(0x15, 0xAD)]
+  #[test_case(0x1F, 0xFF)]
+  fn channels_are_scaled_to_8_bits(channel: u8, scaled: u8) {
+    assert_eq_hex!(scale_channel(channel), scaled);
+  }
+
+  #[test]
+  fn pixels_are_stored_as_rgba8_row_by_row() {
+    let frame_buffer = Rc::new(FrameBuffer::new());
+    frame_buffer.draw_pixel(2, 1, Color { red: 0x1F, green: 0x00, blue: 0x15 }, false);
+    let mut pixels = vec![0; FRAME_BUFFER_SIZE];
+    frame_buffer.copy_to(&mut pixels);
+    let offset = (SCREEN_WIDTH + 2) * 4;
+    assert_eq_hex!(pixels[offset..offset + 4], [0xFF, 0x00, 0xAD, 0xFF]);
+    assert_eq_hex!(pixels[offset - 4..offset], [0xFF, 0xFF, 0xFF, 0xFF]);
+  }
+}
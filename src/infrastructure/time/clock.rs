@@ -1,3 +1,11 @@
+/// The current unix time in seconds.
+pub fn unix_time() -> u64 {
+  #[cfg(target_arch = "wasm32")]
+  return (js_sys::Date::now() / 1000.0) as u64;
+  #[cfg(not(target_arch = "wasm32"))]
+  std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |duration| duration.as_secs())
+}
+
 pub struct JSClock {
   previous_time: Option<f64>
 }
@@ -8,6 +8,13 @@ mod cpu;
 mod controllers;
 mod time;
 mod infrastructure;
+mod scorecard;
+#[cfg(feature = "ffi")]
+mod ffi;
+
+pub use emulator::emulator::Emulator;
+pub use memory::mbc::EmulationMode;
+pub use renderer::renderer::{Color, Renderer};
 
 use wasm_bindgen::JsCast;
 use wasm_bindgen::prelude::*;
@@ -17,6 +24,7 @@ use memory::main_memory::*;
 use web_sys::console;
 use crate::infrastructure::time::clock::JSClock;
 use crate::time::duration::Duration;
+use crate::scorecard::scorecard::Scorecard;
 
 #[cfg(feature = "wee_alloc")]
 #[global_allocator]
@@ -38,3 +46,8 @@ pub fn run_emulator() {
   // }
   // console::log_1(&"Emulator is now running".into());
 }
+
+#[wasm_bindgen]
+pub fn scorecard() -> String {
+  Scorecard::run().to_string()
+}
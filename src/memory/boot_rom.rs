@@ -0,0 +1,100 @@
+use crate::memory::cartridge_header::RomError;
+use crate::memory::memory::Memory;
+
+/// The boot ROM, mapped over the start of the cartridge until the program writes a non-zero value to BANK (0xFF50).
+/// The CGB boot ROM leaves 0x0100-0x01FF to the cartridge, so its header can be read.
+pub struct BootROM {
+  bytes: Vec<u8>,
+  mapped: bool,
+}
+
+impl BootROM {
+  pub const DMG_SIZE: usize = 0x100;
+  pub const CGB_SIZE: usize = 0x900;
+
+  pub fn new(bytes: &[u8]) -> Result<BootROM, RomError> {
+    if bytes.len() != BootROM::DMG_SIZE && bytes.len() != BootROM::CGB_SIZE {
+      return Err(RomError::InvalidBootROMSize(bytes.len()));
+    }
+    Ok(BootROM {
+      bytes: bytes.to_vec(),
+      mapped: true,
+    })
+  }
+
+  /// For emulators that start in the post-boot state.
+  pub fn none() -> BootROM {
+    BootROM {
+      bytes: vec![],
+      mapped: false,
+    }
+  }
+
+  /// Whether a read from this address goes to the boot ROM instead of the cartridge.
+  pub fn maps(&self, address: u16) -> bool {
+    self.mapped && (address as usize) < self.bytes.len() && !(0x0100..=0x01FF).contains(&address)
+  }
+
+  pub fn unmap(&mut self) {
+    self.mapped = false;
+  }
+}
+
+impl Memory for BootROM {
+  fn read(&self, address: u16) -> u8 {
+    match address {
+      0x0000..=0x08FF => self.bytes[address as usize],
+      // BANK only has its lowest bit, which can't be read back
+      0xFF50 => 0xFF,
+      _ => panic!("Can't read address {:#06x} from the boot ROM", address)
+    }
+  }
+
+  fn write(&mut self, address: u16, value: u8) {
+    match address {
+      0xFF50 => if value != 0 {
+        self.mapped = false;
+      },
+      _ => panic!("Can't write to address {:#06x} in the boot ROM", address)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use test_case::test_case;
+
+  #[test_case(0x0000, true)]
+  #[test_case(0x00FF, true)]
+  #[test_case(0x0100, false)]
+  #[test_case(0x0200, true)]
+  #[test_case(0x08FF, true)]
+  #[test_case(0x0900, false)]
+  fn cgb_boot_rom_leaves_the_header_to_the_cartridge(address: u16, mapped: bool) {
+    assert_eq!(BootROM::new(&[0; BootROM::CGB_SIZE]).unwrap().maps(address), mapped);
+  }
+
+  #[test]
+  fn dmg_boot_rom_only_maps_first_page() {
+    let boot_rom = BootROM::new(&[0; BootROM::DMG_SIZE]).unwrap();
+    assert!(boot_rom.maps(0x00FF));
+    assert!(!boot_rom.maps(0x0200));
+  }
+
+  #[test]
+  fn non_zero_bank_write_unmaps() {
+    let mut boot_rom = BootROM::new(&[0; BootROM::DMG_SIZE]).unwrap();
+    boot_rom.write(0xFF50, 0x00);
+    assert!(boot_rom.maps(0x0000));
+    boot_rom.write(0xFF50, 0x01);
+    assert!(!boot_rom.maps(0x0000));
+    boot_rom.write(0xFF50, 0x00);
+    assert!(!boot_rom.maps(0x0000));
+  }
+
+  #[test]
+  fn rejects_wrong_size() {
+    assert_eq!(BootROM::new(&[0; 0x200]).err(), Some(RomError::InvalidBootROMSize(0x200)));
+  }
+}
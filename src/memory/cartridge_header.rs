@@ -0,0 +1,162 @@
+use std::fmt::{Display, Formatter};
+use crate::memory::memory::{RAMSize, ROMSize};
+
+/// Why a ROM image was refused.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum RomError {
+  /// The image ends before the end of the cartridge header at 0x014F.
+  TooShort(usize),
+  /// The checksum at 0x014D doesn't match the header bytes 0x0134-0x014C.
+  HeaderChecksumMismatch { stored: u8, computed: u8 },
+  InvalidROMSize(u8),
+  /// The image isn't as large as the ROM size in the header says.
+  ROMSizeMismatch { declared: usize, actual: usize },
+  InvalidRAMSize(u8),
+  UnsupportedCartridgeType(u8),
+  /// Boot ROMs are 256 bytes for the DMG and 2304 bytes for the CGB.
+  InvalidBootROMSize(usize),
+  /// The ROM is a zip archive that can't be read.
+  DamagedArchive,
+  /// The ROM is a zip archive without a .gb or .gbc file.
+  NoROMInArchive,
+  /// The ROM in the zip archive is larger than the largest cartridge, 8MB.
+  ROMTooLarge,
+}
+
+impl Display for RomError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      RomError::TooShort(length) => write!(f, "Invalid ROM: {} bytes is too short for a cartridge header", length),
+      RomError::HeaderChecksumMismatch { stored, computed } =>
+        write!(f, "Invalid ROM: header checksum is {:#04x}, but the header adds up to {:#04x}", stored, computed),
+      RomError::InvalidROMSize(byte) => write!(f, "Invalid ROM: ROM size byte {:#04x}", byte),
+      RomError::ROMSizeMismatch { declared, actual } =>
+        write!(f, "Invalid ROM: the header declares {} bytes, but the file has {}", declared, actual),
+      RomError::InvalidRAMSize(byte) => write!(f, "Invalid ROM: RAM size byte {:#04x}", byte),
+      RomError::UnsupportedCartridgeType(cartridge_type) => write!(f, "Unsupported cartridge type {:#04x}", cartridge_type),
+      RomError::InvalidBootROMSize(length) =>
+        write!(f, "Invalid boot ROM: {} bytes, expected 256 for the DMG or 2304 for the CGB", length),
+      RomError::DamagedArchive => write!(f, "Invalid ROM: the zip archive is damaged"),
+      RomError::NoROMInArchive => write!(f, "Invalid ROM: the zip archive doesn't contain a .gb or .gbc file"),
+      RomError::ROMTooLarge => write!(f, "Invalid ROM: the file in the zip archive is larger than 8MB"),
+    }
+  }
+}
+
+/// Lets wasm_bindgen functions returning `Result<_, JsError>` use `?` on a RomError.
+impl std::error::Error for RomError {}
+
+impl From<RomError> for wasm_bindgen::JsValue {
+  fn from(error: RomError) -> Self {
+    wasm_bindgen::JsError::new(&error.to_string()).into()
+  }
+}
+
+/// The cartridge header at 0x0100-0x014F.
+pub struct CartridgeHeader {
+  pub cgb_flag: u8,
+  pub cartridge_type: u8,
+  pub rom_size: ROMSize,
+  pub ram_size: RAMSize,
+  pub header_checksum: u8,
+  pub global_checksum: u16,
+}
+
+impl CartridgeHeader {
+  const END_ADDRESS: usize = 0x014F;
+
+  /// Checks that the header is intact and describes the image it's in. The cartridge type isn't checked, since
+  /// that's up to create_rom.
+  pub fn parse(rom: &[u8]) -> Result<CartridgeHeader, RomError> {
+    if rom.len() <= CartridgeHeader::END_ADDRESS {
+      return Err(RomError::TooShort(rom.len()));
+    }
+    let computed = header_checksum(rom);
+    if rom[0x014D] != computed {
+      return Err(RomError::HeaderChecksumMismatch { stored: rom[0x014D], computed });
+    }
+    let rom_size = ROMSize::from_byte(rom[0x0148]).ok_or(RomError::InvalidROMSize(rom[0x0148]))?;
+    if rom.len() != rom_size.bytes() {
+      return Err(RomError::ROMSizeMismatch { declared: rom_size.bytes(), actual: rom.len() });
+    }
+    let ram_size = RAMSize::from_byte(rom[0x0149]).ok_or(RomError::InvalidRAMSize(rom[0x0149]))?;
+    Ok(CartridgeHeader {
+      cgb_flag: rom[0x0143],
+      cartridge_type: rom[0x0147],
+      rom_size,
+      ram_size,
+      header_checksum: rom[0x014D],
+      global_checksum: u16::from_be_bytes([rom[0x014E], rom[0x014F]]),
+    })
+  }
+}
+
+/// The checksum over 0x0134-0x014C that the boot ROM compares with the byte at 0x014D.
+pub fn header_checksum(rom: &[u8]) -> u8 {
+  rom[0x0134..=0x014C].iter().fold(0u8, |checksum, byte| checksum.wrapping_sub(*byte).wrapping_sub(1))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use test_case::test_case;
+
+  fn create_rom_bytes(length: usize, rom_size: u8, ram_size: u8) -> Vec<u8> {
+    let mut rom = vec![0u8; length];
+    rom[0x0148] = rom_size;
+    rom[0x0149] = ram_size;
+    rom[0x014D] = header_checksum(&rom);
+    rom
+  }
+
+  #[test]
+  fn parses_valid_header() {
+    let mut rom = create_rom_bytes(0x10000, 0x01, 0x03);
+    rom[0x0147] = 0x13;
+    rom[0x014E..=0x014F].copy_from_slice(&[0x12, 0x34]);
+    rom[0x014D] = header_checksum(&rom);
+    let header = CartridgeHeader::parse(&rom).unwrap();
+    assert_eq!(header.cartridge_type, 0x13);
+    assert_eq!(header.rom_size, ROMSize::KB64);
+    assert_eq!(header.ram_size, RAMSize::KB32);
+    assert_eq!(header.global_checksum, 0x1234);
+  }
+
+  #[test_case(0x52, ROMSize::MB1_1)]
+  #[test_case(0x53, ROMSize::MB1_2)]
+  #[test_case(0x54, ROMSize::MB1_5)]
+  fn parses_rom_sizes_that_are_not_a_power_of_two(byte: u8, rom_size: ROMSize) {
+    let header = CartridgeHeader::parse(&create_rom_bytes(rom_size.bytes(), byte, 0x00)).unwrap();
+    assert_eq!(header.rom_size, rom_size);
+  }
+
+  #[test]
+  fn rejects_truncated_image() {
+    assert_eq!(CartridgeHeader::parse(&[0u8; 0x0100]).err(), Some(RomError::TooShort(0x0100)));
+  }
+
+  #[test]
+  fn rejects_wrong_header_checksum() {
+    let mut rom = create_rom_bytes(0x8000, 0x00, 0x00);
+    rom[0x0134] = b'A';
+    assert_eq!(CartridgeHeader::parse(&rom).err(), Some(RomError::HeaderChecksumMismatch { stored: 0xE7, computed: 0xA6 }));
+  }
+
+  #[test]
+  fn rejects_invalid_rom_size() {
+    assert_eq!(CartridgeHeader::parse(&create_rom_bytes(0x8000, 0x09, 0x00)).err(), Some(RomError::InvalidROMSize(0x09)));
+  }
+
+  #[test]
+  fn rejects_image_of_the_wrong_size() {
+    assert_eq!(
+      CartridgeHeader::parse(&create_rom_bytes(0x8000, 0x01, 0x00)).err(),
+      Some(RomError::ROMSizeMismatch { declared: 0x10000, actual: 0x8000 })
+    );
+  }
+
+  #[test]
+  fn rejects_invalid_ram_size() {
+    assert_eq!(CartridgeHeader::parse(&create_rom_bytes(0x8000, 0x00, 0x06)).err(), Some(RomError::InvalidRAMSize(0x06)));
+  }
+}
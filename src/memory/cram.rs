@@ -2,6 +2,7 @@ use std::cell::RefCell;
 use std::ops::Index;
 use std::rc::Rc;
 use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
+use serde::{Deserialize, Serialize};
 use crate::memory::memory::Memory;
 use crate::renderer::renderer::{Color, ColorIndex, PaletteIndex};
 use crate::util::bit_util::BitUtil;
@@ -14,10 +15,13 @@ pub trait CRAM {
   fn get_object_color(&self, palette_index: PaletteIndex, color_index: ColorIndex) -> Color;
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct CRAMImpl {
   background_palette_index: u8,
+  #[serde(with = "crate::util::serde_util::array")]
   background_palettes: [u8; 2 * COLORS_PER_PALETTE * NUMBER_OF_PALETTES],
   object_palette_index: u8,
+  #[serde(with = "crate::util::serde_util::array")]
   object_palettes: [u8; 2 * COLORS_PER_PALETTE * NUMBER_OF_PALETTES],
 }
 
@@ -82,6 +86,22 @@ impl Memory for CRAMImpl {
   }
 }
 
+pub mod checks {
+  use crate::memory::memory::Memory;
+  use super::CRAMImpl;
+
+  pub fn writes_color_with_auto_increment(index_address: u16, data_address: u16) -> bool {
+    let mut cram = CRAMImpl::new();
+    cram.write(index_address, 0xB4);
+    cram.write(data_address, 0xD5);
+    cram.write(data_address, 0x2B);
+    cram.write(index_address, 0x34);
+    let first = cram.read(data_address);
+    cram.write(index_address, 0x35);
+    first == 0xD5 && cram.read(data_address) == 0x2B
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -104,14 +124,7 @@ mod tests {
   #[test_case(0x0FF68, 0xFF69; "background color")]
   #[test_case(0x0FF68, 0xFF69; "object color")]
   fn writes_color_with_auto_increment(index_address: u16, data_address: u16) {
-    let mut cram = CRAMImpl::new();
-    cram.write(index_address, 0xB4);
-    cram.write(data_address, 0xD5);
-    cram.write(data_address, 0x2B);
-    cram.write(index_address, 0x34);
-    assert_eq!(cram.read(data_address), 0xD5);
-    cram.write(index_address, 0x35);
-    assert_eq!(cram.read(data_address), 0x2B);
+    assert!(checks::writes_color_with_auto_increment(index_address, data_address));
   }
 
   #[test]
@@ -1,13 +1,13 @@
-use crate::memory::memory::Memory;
+use crate::memory::memory::{read_bytes, Memory};
 use crate::memory::oam::OAMImpl;
 use crate::memory::vram::VRAMImpl;
 use crate::memory::wram::WRAM;
 
 pub struct DMAMemoryView<'a> {
-  rom: &'a dyn Memory,
-  vram: &'a mut VRAMImpl,
-  wram: &'a WRAM,
-  oam: &'a mut OAMImpl
+  pub rom: &'a dyn Memory,
+  pub vram: &'a mut VRAMImpl,
+  pub wram: &'a WRAM,
+  pub oam: &'a mut OAMImpl
 }
 
 impl<'a> Memory for DMAMemoryView<'a> {
@@ -17,11 +17,23 @@ impl<'a> Memory for DMAMemoryView<'a> {
       0x8000..=0x9FFF => self.vram.read(address),
       0xA000..=0xBFFF => self.rom.read(address),
       0xC000..=0xDFFF => self.wram.read(address),
+      0xE000..=0xFDFF => self.wram.read(address - 0x2000),
       0xFE00..=0xFE9F => self.oam.read(address),
       _ => panic!("DMA does not have read access to memory at address {:#06x}", address)
     }
   }
 
+  /// Transfers read their source in blocks, which never cross from one region into the next.
+  fn read_block(&self, address: u16, dst: &mut [u8]) {
+    match address {
+      0x0000..=0x7FFF | 0xA000..=0xBFFF => self.rom.read_block(address, dst),
+      0x8000..=0x9FFF => self.vram.read_block(address, dst),
+      0xC000..=0xDFFF => self.wram.read_block(address, dst),
+      0xE000..=0xFDFF => self.wram.read_block(address - 0x2000, dst),
+      _ => read_bytes(self, address, dst),
+    }
+  }
+
   fn write(&mut self, address: u16, value: u8) {
     match address {
       0x8000..=0x9FFF => self.vram.write(address, value),
@@ -29,4 +41,11 @@ impl<'a> Memory for DMAMemoryView<'a> {
       _ => panic!("DMA does not have write access to memory at address {:#06x}", address)
     }
   }
+
+  fn write_block(&mut self, address: u16, src: &[u8]) {
+    match address {
+      0x8000..=0x9FFF => self.vram.write_block(address, src),
+      _ => src.iter().enumerate().for_each(|(offset, byte)| self.write(address + offset as u16, *byte)),
+    }
+  }
 }
\ No newline at end of file
@@ -0,0 +1,169 @@
+use serde::{Deserialize, Serialize};
+use crate::memory::mbc::{import_ram, mask_ram_address, mask_rom_bank, read_cartridge_block, EmulationMode, Loadable, MBC, UnmappedAccess};
+use crate::memory::memory::{Memory, RAMSize, ROMSize};
+
+/// Hudson's HuC1 banks like a simple MBC1. Instead of enabling RAM, 0x0000-0x1FFF switches 0xA000-0xBFFF between the RAM
+/// and the infrared port. The port is stubbed: its LED can be toggled, but it never sees any light.
+#[derive(Serialize, Deserialize)]
+pub struct HuC1 {
+  ir_selected: bool,
+  rom_bank_address: usize,
+  ram_bank_address: usize,
+  #[serde(skip)]
+  rom: Vec<u8>,
+  #[serde(skip)]
+  unmapped: UnmappedAccess,
+  ram: Vec<u8>,
+}
+
+impl HuC1 {
+  const IR_NO_LIGHT: u8 = 0xC0;
+
+  pub fn new(rom_size: ROMSize, ram_size: RAMSize) -> HuC1 {
+    HuC1 {
+      ir_selected: false,
+      rom_bank_address: 0x01,
+      ram_bank_address: 0x00,
+      ram: vec![0; ram_size.bytes()],
+      rom: vec![0; rom_size.bytes()],
+      unmapped: UnmappedAccess::new(EmulationMode::Strict),
+    }
+  }
+
+  fn ram_address(&self, address: u16) -> Option<usize> {
+    mask_ram_address(&self.ram, self.ram_bank_address, address)
+  }
+}
+
+impl Memory for HuC1 {
+  fn read(&self, address: u16) -> u8 {
+    match address {
+      0x0000..=0x7FFF => self.rom[(self.rom_bank(address) << 14) | (address as usize & 0x3FFF)],
+      0xA000..=0xBFFF if self.ir_selected => HuC1::IR_NO_LIGHT,
+      0xA000..=0xBFFF => self.ram_address(address).map_or(0xFF, |address_in_ram| self.ram[address_in_ram]),
+      _ => self.unmapped.read("HuC1", address)
+    }
+  }
+
+  fn read_block(&self, address: u16, dst: &mut [u8]) {
+    read_cartridge_block(self, &self.rom, address, dst);
+  }
+
+  fn write(&mut self, address: u16, value: u8) {
+    match address {
+      0x0000..=0x1FFF => self.ir_selected = (value & 0x0F) == 0x0E,
+      0x2000..=0x3FFF => {
+        self.rom_bank_address = (value & 0x3F) as usize;
+        if self.rom_bank_address == 0 {
+          self.rom_bank_address = 1;
+        }
+      }
+      0x4000..=0x5FFF => self.ram_bank_address = (value & 0x03) as usize,
+      0x6000..=0x7FFF => {}
+      // Writes to the IR port toggle its LED
+      0xA000..=0xBFFF if self.ir_selected => {}
+      0xA000..=0xBFFF => if let Some(address_in_ram) = self.ram_address(address) {
+        self.ram[address_in_ram] = value;
+      },
+      _ => self.unmapped.write("HuC1", address)
+    }
+  }
+}
+
+impl MBC for HuC1 {
+  fn rom_bank(&self, address: u16) -> usize {
+    if address < 0x4000 { 0 } else { mask_rom_bank(self.rom_bank_address, &self.rom) }
+  }
+
+  fn export_save(&self, _now: u64) -> Vec<u8> {
+    self.ram.clone()
+  }
+
+  fn import_save(&mut self, save: &[u8], _now: u64) {
+    import_ram(&mut self.ram, save);
+  }
+
+  fn set_mode(&mut self, mode: EmulationMode) {
+    self.unmapped = UnmappedAccess::new(mode);
+  }
+
+  fn save_state(&self) -> Vec<u8> {
+    bincode::serialize(self).unwrap()
+  }
+
+  fn load_state(&mut self, state: &[u8]) -> bincode::Result<()> {
+    let loaded: HuC1 = bincode::deserialize(state)?;
+    *self = HuC1 {
+      rom: std::mem::take(&mut self.rom),
+      unmapped: std::mem::take(&mut self.unmapped),
+      ..loaded
+    };
+    Ok(())
+  }
+}
+
+impl Loadable for HuC1 {
+  fn load_byte(&mut self, address: usize, value: u8) {
+    self.rom[address] = value;
+  }
+
+  fn load_bytes(&mut self, address: usize, values: &[u8]) {
+    self.rom.as_mut_slice()[address..(address + values.len())].copy_from_slice(values);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use assert_hex::assert_eq_hex;
+
+  #[test]
+  fn switches_rom_banks() {
+    let mut memory = HuC1::new(ROMSize::MB1, RAMSize::KB32);
+    memory.load_byte(0x4123, 0x12);
+    memory.load_byte(0xFC123, 0x34);
+    assert_eq_hex!(memory.read(0x4123), 0x12);
+    memory.write(0x2000, 0x3F);
+    assert_eq_hex!(memory.read(0x4123), 0x34);
+    memory.write(0x2000, 0x00);
+    assert_eq_hex!(memory.read(0x4123), 0x12);
+  }
+
+  #[test]
+  fn switches_ram_banks() {
+    let mut memory = HuC1::new(ROMSize::MB1, RAMSize::KB32);
+    (0u8..=3u8).for_each(|bank| {
+      memory.write(0x4000, bank);
+      memory.write(0xA123, 0x10 | bank);
+    });
+    (0u8..=3u8).for_each(|bank| {
+      memory.write(0x4000, bank);
+      assert_eq_hex!(memory.read(0xA123), 0x10 | bank);
+    });
+  }
+
+  #[test]
+  fn save_round_trip() {
+    let mut memory = HuC1::new(ROMSize::MB1, RAMSize::KB32);
+    memory.write(0x4000, 0x03);
+    memory.write(0xA123, 0x42);
+    let save = memory.export_save(0);
+    assert_eq!(save.len(), RAMSize::KB32.bytes());
+    let mut imported = HuC1::new(ROMSize::MB1, RAMSize::KB32);
+    imported.import_save(&save, 0);
+    imported.write(0x4000, 0x03);
+    assert_eq_hex!(imported.read(0xA123), 0x42);
+  }
+
+  #[test]
+  fn ir_mode_does_not_touch_ram() {
+    let mut memory = HuC1::new(ROMSize::MB1, RAMSize::KB32);
+    memory.write(0xA123, 0x42);
+    memory.write(0x0000, 0x0E);
+    assert_eq_hex!(memory.read(0xA123), 0xC0);
+    memory.write(0xA123, 0x01);
+    assert_eq_hex!(memory.read(0xA123), 0xC0);
+    memory.write(0x0000, 0x0A);
+    assert_eq_hex!(memory.read(0xA123), 0x42);
+  }
+}
@@ -0,0 +1,328 @@
+use serde::{Deserialize, Serialize};
+use crate::memory::mbc::{import_ram, mask_ram_address, mask_rom_bank, read_cartridge_block, EmulationMode, Loadable, MBC, UnmappedAccess};
+use crate::memory::memory::{Memory, RAMSize, ROMSize};
+use crate::time::cycle_time::CycleTime;
+
+/// Hudson's HuC3. Writing to 0x0000-0x1FFF selects what 0xA000-0xBFFF maps to: RAM, or one of the registers used to
+/// talk to the chip that contains the clock. A command is written to the command register, executed by clearing the
+/// semaphore, and its result read from the response register.
+/// The clock counts minutes of the day and days. Alarms, the tone generator and the IR port aren't emulated.
+#[derive(Serialize, Deserialize)]
+pub struct HuC3 {
+  mode: u8,
+  rom_bank_address: usize,
+  ram_bank_address: usize,
+  command: u8,
+  response: u8,
+  access_address: u8,
+  /// The chip's memory. Locations 0x00-0x06 hold the time copied from or to the clock.
+  #[serde(with = "crate::util::serde_util::array")]
+  nibbles: [u8; 256],
+  minutes: u16,
+  days: u16,
+  nanoseconds: u64,
+  cycle_time: CycleTime,
+  #[serde(skip)]
+  rom: Vec<u8>,
+  #[serde(skip)]
+  unmapped: UnmappedAccess,
+  ram: Vec<u8>,
+}
+
+impl HuC3 {
+  const MINUTE_NANOSECONDS: u64 = 60 * 1_000_000_000;
+  const MINUTES_PER_DAY: u64 = 24 * 60;
+  const RTC_FOOTER_SIZE: usize = 17;
+
+  pub fn new(rom_size: ROMSize, ram_size: RAMSize) -> HuC3 {
+    HuC3 {
+      mode: 0x00,
+      rom_bank_address: 0x01,
+      ram_bank_address: 0x00,
+      command: 0x00,
+      response: 0x00,
+      access_address: 0x00,
+      nibbles: [0; 256],
+      minutes: 0,
+      days: 0,
+      nanoseconds: 0,
+      cycle_time: CycleTime::new(),
+      ram: vec![0; ram_size.bytes()],
+      rom: vec![0; rom_size.bytes()],
+      unmapped: UnmappedAccess::new(EmulationMode::Strict),
+    }
+  }
+
+  fn ram_address(&self, address: u16) -> Option<usize> {
+    mask_ram_address(&self.ram, self.ram_bank_address, address)
+  }
+
+  fn advance_clock(&mut self, nanoseconds: u64) {
+    let total_nanoseconds = self.nanoseconds + nanoseconds;
+    let total_minutes = self.minutes as u64 + total_nanoseconds / HuC3::MINUTE_NANOSECONDS;
+    self.nanoseconds = total_nanoseconds % HuC3::MINUTE_NANOSECONDS;
+    self.minutes = (total_minutes % HuC3::MINUTES_PER_DAY) as u16;
+    self.days = self.days.wrapping_add((total_minutes / HuC3::MINUTES_PER_DAY) as u16);
+  }
+
+  /// The upper nibble of the command selects what it does, the lower nibble is its argument.
+  fn execute_command(&mut self) {
+    let argument = self.command & 0x0F;
+    let result = match self.command >> 4 {
+      // Read and increment the access address
+      0x1 => {
+        let value = self.nibbles[self.access_address as usize];
+        self.access_address = self.access_address.wrapping_add(1);
+        value
+      }
+      // Write and increment the access address
+      0x3 => {
+        self.nibbles[self.access_address as usize] = argument;
+        self.access_address = self.access_address.wrapping_add(1);
+        argument
+      }
+      0x4 => {
+        self.access_address = (self.access_address & 0xF0) | argument;
+        argument
+      }
+      0x5 => {
+        self.access_address = (self.access_address & 0x0F) | (argument << 4);
+        argument
+      }
+      0x6 => self.execute_extended_command(argument),
+      _ => argument
+    };
+    self.response = (self.command & 0x70) | result;
+  }
+
+  fn execute_extended_command(&mut self, argument: u8) -> u8 {
+    match argument {
+      // Copy the time to memory, minutes in 0x00-0x02 and days in 0x03-0x06, least significant nibble first
+      0x0 => {
+        let time = self.minutes as u32 | ((self.days as u32) << 12);
+        for (index, nibble) in self.nibbles[..7].iter_mut().enumerate() {
+          *nibble = ((time >> (4 * index)) & 0x0F) as u8;
+        }
+        argument
+      }
+      // Set the time from memory
+      0x1 => {
+        let time = self.nibbles[..7].iter().rev().fold(0u32, |time, nibble| (time << 4) | *nibble as u32);
+        self.minutes = ((time & 0xFFF) as u64 % HuC3::MINUTES_PER_DAY) as u16;
+        self.days = (time >> 12) as u16;
+        self.nanoseconds = 0;
+        argument
+      }
+      // Reports that the chip is ready
+      0x2 => 0x1,
+      _ => argument
+    }
+  }
+}
+
+impl Memory for HuC3 {
+  fn read(&self, address: u16) -> u8 {
+    match address {
+      0x0000..=0x7FFF => self.rom[(self.rom_bank(address) << 14) | (address as usize & 0x3FFF)],
+      0xA000..=0xBFFF => match self.mode {
+        0x0 | 0xA => self.ram_address(address).map_or(0xFF, |address_in_ram| self.ram[address_in_ram]),
+        0xC => 0x80 | self.response,
+        // Commands are executed immediately, so the semaphore always reads as ready
+        0xD => 0xFF,
+        // The IR port never sees any light
+        0xE => 0xC0,
+        _ => 0xFF
+      },
+      _ => self.unmapped.read("HuC3", address)
+    }
+  }
+
+  fn read_block(&self, address: u16, dst: &mut [u8]) {
+    read_cartridge_block(self, &self.rom, address, dst);
+  }
+
+  fn write(&mut self, address: u16, value: u8) {
+    match address {
+      0x0000..=0x1FFF => self.mode = value & 0x0F,
+      0x2000..=0x3FFF => {
+        self.rom_bank_address = (value & 0x7F) as usize;
+        if self.rom_bank_address == 0 {
+          self.rom_bank_address = 1;
+        }
+      }
+      0x4000..=0x5FFF => self.ram_bank_address = (value & 0x03) as usize,
+      0x6000..=0x7FFF => {}
+      0xA000..=0xBFFF => match self.mode {
+        0xA => if let Some(address_in_ram) = self.ram_address(address) {
+          self.ram[address_in_ram] = value;
+        },
+        0xB => self.command = value & 0x7F,
+        // Clearing bit 0 of the semaphore executes the command
+        0xD if value & 0x01 == 0 => self.execute_command(),
+        _ => {}
+      },
+      _ => self.unmapped.write("HuC3", address)
+    }
+  }
+}
+
+impl MBC for HuC3 {
+  fn rom_bank(&self, address: u16) -> usize {
+    if address < 0x4000 { 0 } else { mask_rom_bank(self.rom_bank_address, &self.rom) }
+  }
+
+  fn handle_tick(&mut self, double_speed: bool) {
+    let nanoseconds = self.cycle_time.elapse(double_speed);
+    self.advance_clock(nanoseconds);
+  }
+
+  /// Appends the clock in the footer format used by other emulators: the 64-bit unix time of the save, followed by the
+  /// minutes, the days, the alarm minutes and days as 16-bit values and the alarm enable flag, all little endian.
+  fn export_save(&self, now: u64) -> Vec<u8> {
+    let mut save = self.ram.clone();
+    save.extend_from_slice(&now.to_le_bytes());
+    save.extend_from_slice(&self.minutes.to_le_bytes());
+    save.extend_from_slice(&self.days.to_le_bytes());
+    save.extend_from_slice(&[0; 5]);
+    save
+  }
+
+  /// The clock is advanced by the time that has passed since the save was written.
+  fn import_save(&mut self, save: &[u8], now: u64) {
+    import_ram(&mut self.ram, save);
+    let footer = &save[self.ram.len().min(save.len())..];
+    if footer.len() != HuC3::RTC_FOOTER_SIZE {
+      return;
+    }
+    let timestamp = u64::from_le_bytes(footer[0..8].try_into().unwrap());
+    self.minutes = (u16::from_le_bytes([footer[8], footer[9]]) as u64 % HuC3::MINUTES_PER_DAY) as u16;
+    self.days = u16::from_le_bytes([footer[10], footer[11]]);
+    self.nanoseconds = 0;
+    self.cycle_time.reset();
+    self.advance_clock(now.saturating_sub(timestamp) * 1_000_000_000);
+  }
+
+  fn set_mode(&mut self, mode: EmulationMode) {
+    self.unmapped = UnmappedAccess::new(mode);
+  }
+
+  fn save_state(&self) -> Vec<u8> {
+    bincode::serialize(self).unwrap()
+  }
+
+  fn load_state(&mut self, state: &[u8]) -> bincode::Result<()> {
+    let loaded: HuC3 = bincode::deserialize(state)?;
+    *self = HuC3 {
+      rom: std::mem::take(&mut self.rom),
+      unmapped: std::mem::take(&mut self.unmapped),
+      ..loaded
+    };
+    Ok(())
+  }
+}
+
+impl Loadable for HuC3 {
+  fn load_byte(&mut self, address: usize, value: u8) {
+    self.rom[address] = value;
+  }
+
+  fn load_bytes(&mut self, address: usize, values: &[u8]) {
+    self.rom.as_mut_slice()[address..(address + values.len())].copy_from_slice(values);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use assert_hex::assert_eq_hex;
+
+  /// Writes and executes the command, and returns the response.
+  fn run_command(memory: &mut HuC3, command: u8) -> u8 {
+    memory.write(0x0000, 0x0B);
+    memory.write(0xA000, command);
+    memory.write(0x0000, 0x0D);
+    memory.write(0xA000, 0xFE);
+    memory.write(0x0000, 0x0C);
+    memory.read(0xA000)
+  }
+
+  fn read_time(memory: &mut HuC3) -> (u16, u16) {
+    run_command(memory, 0x60);
+    run_command(memory, 0x40);
+    run_command(memory, 0x50);
+    let nibbles: Vec<u16> = (0..7).map(|_| (run_command(memory, 0x10) & 0x0F) as u16).collect();
+    (nibbles[0] | (nibbles[1] << 4) | (nibbles[2] << 8), nibbles[3] | (nibbles[4] << 4) | (nibbles[5] << 8) | (nibbles[6] << 12))
+  }
+
+  #[test]
+  fn command_handshake() {
+    let mut memory = HuC3::new(ROMSize::KB256, RAMSize::KB32);
+    memory.write(0x0000, 0x0D);
+    assert_eq_hex!(memory.read(0xA000), 0xFF);
+    assert_eq_hex!(run_command(&mut memory, 0x62), 0xE1);
+    assert_eq_hex!(run_command(&mut memory, 0x47), 0xC7);
+    assert_eq_hex!(run_command(&mut memory, 0x35), 0xB5);
+    assert_eq_hex!(memory.nibbles[0x07], 0x05);
+    run_command(&mut memory, 0x47);
+    assert_eq_hex!(run_command(&mut memory, 0x10), 0x95);
+    assert_eq_hex!(memory.access_address, 0x08);
+  }
+
+  #[test]
+  fn command_is_only_executed_when_semaphore_is_cleared() {
+    let mut memory = HuC3::new(ROMSize::KB256, RAMSize::KB32);
+    memory.write(0x0000, 0x0B);
+    memory.write(0xA000, 0x35);
+    memory.write(0x0000, 0x0D);
+    memory.write(0xA000, 0xFF);
+    assert_eq_hex!(memory.nibbles[0x00], 0x00);
+    memory.write(0xA000, 0xFE);
+    assert_eq_hex!(memory.nibbles[0x00], 0x05);
+  }
+
+  #[test]
+  fn clock_read_round_trip() {
+    let mut memory = HuC3::new(ROMSize::KB256, RAMSize::KB32);
+    // 1439 minutes (0x59F) of day 0x123
+    run_command(&mut memory, 0x40);
+    run_command(&mut memory, 0x50);
+    for nibble in [0xF, 0x9, 0x5, 0x3, 0x2, 0x1, 0x0] {
+      run_command(&mut memory, 0x30 | nibble);
+    }
+    run_command(&mut memory, 0x61);
+    assert_eq!(read_time(&mut memory), (1439, 0x123));
+    memory.advance_clock(59 * 1_000_000_000);
+    assert_eq!(read_time(&mut memory), (1439, 0x123));
+    memory.advance_clock(1_000_000_000);
+    assert_eq!(read_time(&mut memory), (0, 0x124));
+  }
+
+  #[test]
+  fn ram_is_only_writable_in_ram_mode() {
+    let mut memory = HuC3::new(ROMSize::KB256, RAMSize::KB32);
+    memory.write(0x0000, 0x0A);
+    memory.write(0x4000, 0x02);
+    memory.write(0xA123, 0x42);
+    memory.write(0x0000, 0x00);
+    memory.write(0xA123, 0x24);
+    assert_eq_hex!(memory.read(0xA123), 0x42);
+    memory.write(0x4000, 0x01);
+    assert_eq_hex!(memory.read(0xA123), 0x00);
+  }
+
+  #[test]
+  fn save_round_trip_fast_forwards_the_clock() {
+    let mut memory = HuC3::new(ROMSize::KB256, RAMSize::KB32);
+    memory.write(0x0000, 0x0A);
+    memory.write(0xA000, 0x42);
+    memory.advance_clock(2 * HuC3::MINUTE_NANOSECONDS);
+    let save = memory.export_save(1_700_000_000);
+    assert_eq!(save.len(), RAMSize::KB32.bytes() + 17);
+
+    let mut imported = HuC3::new(ROMSize::KB256, RAMSize::KB32);
+    imported.import_save(&save, 1_700_000_000 + 86_400 + 60);
+    assert_eq!(read_time(&mut imported), (3, 1));
+    imported.write(0x0000, 0x0A);
+    assert_eq_hex!(imported.read(0xA000), 0x42);
+  }
+}
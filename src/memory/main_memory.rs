@@ -4,68 +4,140 @@ use crate::controllers::lcd::LCDController;
 use crate::memory::oam::OAM;
 use crate::controllers::timer::TimerController;
 use crate::memory::bank_memory::BankMemory;
+use crate::memory::boot_rom::BootROM;
 use crate::memory::linear_memory::LinearMemory;
 use crate::memory::memory::{CGBMode, Memory};
 use crate::memory::stack::Stack;
 use crate::memory::vram::VRAMImpl;
 use crate::memory::wram::WRAM;
 
+/// The bits of NR10-NR52 (0xFF10-0xFF26) that read as 1 whatever was written: unused and write-only bits, and the
+/// addresses without a register. Sound isn't emulated, so the APU stays off and the other bits read 0.
+const SOUND_READ_MASKS: [u8; 0x17] = [
+  0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+  0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR21-NR24
+  0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+  0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR41-NR44
+  0x00, 0x00, 0x70, // NR50-NR52
+];
+
 pub struct MainMemory<'a> {
-  rom: &'a mut dyn Memory,
-  vram: &'a mut dyn Memory,
-  wram: &'a mut dyn Memory,
-  oam: &'a mut dyn Memory,
-  lcd: &'a mut dyn Memory,
-  timer: &'a mut dyn Memory,
-  dma: &'a mut dyn Memory,
-  stack: &'a mut dyn Memory,
-  reserved_area_1: &'a mut dyn Memory,
-  reserved_area_2: &'a mut dyn Memory,
-  interrupt_controller: &'a mut dyn Memory
+  pub rom: &'a mut dyn Memory,
+  pub boot_rom: &'a mut BootROM,
+  pub vram: &'a mut dyn Memory,
+  pub wram: &'a mut dyn Memory,
+  pub oam: &'a mut dyn Memory,
+  pub cram: &'a mut dyn Memory,
+  pub lcd: &'a mut dyn Memory,
+  pub timer: &'a mut dyn Memory,
+  pub buttons: &'a mut dyn Memory,
+  pub serial: &'a mut dyn Memory,
+  pub dma: &'a mut dyn Memory,
+  pub speed: &'a mut dyn Memory,
+  pub stack: &'a mut dyn Memory,
+  pub interrupt_controller: &'a mut dyn Memory,
+  pub cgb_mode: CGBMode,
+  /// Lets a debugger see VRAM, OAM and the palettes while the LCD is using them.
+  pub debug_peek: bool,
+}
+
+impl<'a> MainMemory<'a> {
+  /// The CPU can't access VRAM or the CGB palette data while the LCD is enabled and drawing a line (mode 3).
+  fn lcd_drawing(&self) -> bool {
+    !self.debug_peek && self.lcd.read(0xFF40) & 0x80 != 0 && self.lcd.read(0xFF41) & 0x03 == 0x03
+  }
+
+  /// The CPU can't access OAM while the LCD is enabled and scanning OAM (mode 2) or drawing a line (mode 3).
+  /// OAM DMA doesn't go through this bus, so it isn't affected.
+  fn oam_accessible(&self) -> bool {
+    self.debug_peek || self.lcd.read(0xFF40) & 0x80 == 0 || self.lcd.read(0xFF41) & 0x02 == 0
+  }
+
+  /// DMG games run in compatibility mode, where the CGB-only registers (KEY1, VBK, HDMA, the palette RAM and SVBK)
+  /// read 0xFF and ignore writes.
+  fn cgb_registers_disabled(&self) -> bool {
+    self.cgb_mode == CGBMode::Monochrome
+  }
+
+  /// The area between OAM and the I/O registers ignores writes. The CGB reads the upper nibble of the address' low byte
+  /// in both nibbles, the DMG reads 0.
+  fn read_unusable(&self, address: u16) -> u8 {
+    match self.cgb_mode {
+      CGBMode::Monochrome => 0x00,
+      _ => {
+        let nibble = (address as u8) >> 4;
+        nibble << 4 | nibble
+      }
+    }
+  }
 }
 
 impl<'a> Memory for MainMemory<'a> {
   fn read(&self, address: u16) -> u8 {
     match address {
+      0x0000..=0x08FF if self.boot_rom.maps(address) => self.boot_rom.read(address),
       0x0000..=0x7FFF => self.rom.read(address),
-      0x8000..=0x9FFF => self.vram.read(address),
+      0x8000..=0x9FFF => if self.lcd_drawing() { 0xFF } else { self.vram.read(address) },
       0xA000..=0xBFFF => self.rom.read(address),
       0xC000..=0xDFFF => self.wram.read(address),
-      0xE000..=0xFDFF => self.reserved_area_1.read(address),
-      0xFE00..=0xFE9F => self.oam.read(address),
-      0xFEA0..=0xFEFF => self.reserved_area_2.read(address),
-      0xFF00..=0xFF03 => 0,
+      // Echo RAM mirrors 0xC000-0xDDFF
+      0xE000..=0xFDFF => self.wram.read(address - 0x2000),
+      0xFE00..=0xFE9F => if self.oam_accessible() { self.oam.read(address) } else { 0xFF },
+      0xFEA0..=0xFEFF => self.read_unusable(address),
+      0xFF00 => self.buttons.read(address),
+      0xFF01..=0xFF02 => self.serial.read(address),
       0xFF04..=0xFF07 => self.timer.read(address),
-      0xFF08..=0xFF0E => 0,
       0xFF0F => self.interrupt_controller.read(address),
-      0xFF10..=0xFF3F => 0,
+      0xFF10..=0xFF26 => SOUND_READ_MASKS[address as usize - 0xFF10],
+      0xFF27..=0xFF2F => 0xFF,
+      0xFF30..=0xFF3F => 0,
       0xFF40..=0xFF45 => self.lcd.read(address),
       0xFF46 => self.dma.read(address),
+      0xFF47..=0xFF4B => self.lcd.read(address),
+      0xFF4D | 0xFF4F | 0xFF51..=0xFF55 | 0xFF68..=0xFF6B | 0xFF70 if self.cgb_registers_disabled() => 0xFF,
+      0xFF4D => self.speed.read(address),
       0xFF4F => self.vram.read(address),
+      0xFF50 => self.boot_rom.read(address),
       0xFF51..=0xFF55 => self.dma.read(address),
+      0xFF69 | 0xFF6B if self.lcd_drawing() => 0xFF,
+      0xFF68..=0xFF6B => self.cram.read(address),
       0xFF70 => self.wram.read(address),
+      // Unused I/O addresses read like an open bus, and so do the infrared port and object priority registers
+      0xFF03 | 0xFF08..=0xFF0E | 0xFF4C | 0xFF4E | 0xFF56..=0xFF67 | 0xFF6C..=0xFF6F | 0xFF71..=0xFF7F => 0xFF,
       0xFF80..=0xFFFE => self.stack.read(address),
-      0xFFFF => self.interrupt_controller.read(0xFFFF),
-      _ => panic!("Trying to read value from main memory at unmapped address {:#06x}", address)
+      0xFFFF => self.interrupt_controller.read(0xFFFF)
     }
   }
 
   fn write(&mut self, address: u16, value: u8) {
     match address {
       0x0000..=0x7FFF => self.rom.write(address, value),
-      0x8000..=0x9FFF => self.vram.write(address, value),
+      0x8000..=0x9FFF => if !self.lcd_drawing() { self.vram.write(address, value) },
       0xA000..=0xBFFF => self.rom.write(address, value),
       0xC000..=0xDFFF => self.wram.write(address, value),
-      0xE000..=0xFDFF => self.reserved_area_1.write(address - 0xE000, value),
-      0xFE00..=0xFEBF => self.oam.write(address, value),
-      0xFEA0..=0xFEFF => self.reserved_area_2.write(address - 0xFEA0, value),
+      0xE000..=0xFDFF => self.wram.write(address - 0x2000, value),
+      0xFE00..=0xFE9F => if self.oam_accessible() { self.oam.write(address, value) },
+      0xFEA0..=0xFEFF => {}
+      0xFF00 => self.buttons.write(address, value),
+      0xFF01..=0xFF02 => self.serial.write(address, value),
       0xFF04..=0xFF07 => self.timer.write(address, value),
+      0xFF0F => self.interrupt_controller.write(address, value),
+      // Sound isn't emulated, so its registers ignore writes
+      0xFF10..=0xFF3F => {}
+      0xFF40..=0xFF45 => self.lcd.write(address, value),
       0xFF46 => self.dma.write(address, value),
+      0xFF47..=0xFF4B => self.lcd.write(address, value),
+      0xFF4D | 0xFF4F | 0xFF51..=0xFF55 | 0xFF68..=0xFF6B | 0xFF70 if self.cgb_registers_disabled() => {}
+      0xFF4D => self.speed.write(address, value),
       0xFF4F => self.vram.write(address, value),
+      0xFF50 => self.boot_rom.write(address, value),
       0xFF51..=0xFF55 => self.dma.write(address, value),
+      0xFF69 | 0xFF6B if self.lcd_drawing() => {}
+      0xFF68..=0xFF6B => self.cram.write(address, value),
       0xFF70 => self.wram.write(address, value),
-      0xFF80..=0xFFFE => self.stack.write(address - 0xFF80, value),
-      _ => panic!("Trying to write value to main memory at unmapped address {:#06x}", address)
+      0xFF03 | 0xFF08..=0xFF0E | 0xFF4C | 0xFF4E | 0xFF56..=0xFF67 | 0xFF6C..=0xFF6F | 0xFF71..=0xFF7F => {}
+      0xFF80..=0xFFFE => self.stack.write(address, value),
+      0xFFFF => self.interrupt_controller.write(address, value)
     }
   }
 }
\ No newline at end of file
@@ -1,6 +1,418 @@
-use crate::memory::memory::Memory;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+use serde::{Deserialize, Serialize};
+use crate::memory::cartridge_header::{CartridgeHeader, RomError};
+use crate::memory::huc1::HuC1;
+use crate::memory::huc3::HuC3;
+use crate::memory::mbc0::MBC0;
+use crate::memory::mbc1::MBC1;
+use crate::memory::mbc2::MBC2;
+use crate::memory::mbc3::MBC3;
+use crate::memory::mbc5::MBC5;
+use crate::memory::mbc7::MBC7;
+use crate::memory::memory::{read_bytes, Memory, RAMSize};
 
 pub trait Loadable {
   fn load_byte(&mut self, address: usize, value: u8);
   fn load_bytes(&mut self, address: usize, values: &[u8]);
-}
\ No newline at end of file
+}
+
+pub trait MBC: Memory + Loadable {
+  /// The ROM bank that is currently mapped at the given address in 0x0000-0x7FFF. Banks beyond the ROM size wrap
+  /// around.
+  fn rom_bank(&self, address: u16) -> usize;
+
+  /// Advances the cartridge by one M-cycle. Only cartridges with a real time clock keep track of time.
+  fn handle_tick(&mut self, _double_speed: bool) {}
+
+  /// The contents of a .sav file: the cartridge RAM, followed by any other state the cartridge keeps. `now` is the unix
+  /// time in seconds.
+  fn export_save(&self, _now: u64) -> Vec<u8> {
+    vec![]
+  }
+
+  fn import_save(&mut self, _save: &[u8], _now: u64) {}
+
+  /// Cartridges without a rumble motor ignore it.
+  fn connect_rumble(&mut self, _motor: Box<dyn RumbleMotor>) {}
+
+  /// The acceleration along the x and y axis in g. Cartridges without an accelerometer ignore it.
+  fn set_acceleration(&mut self, _x: f32, _y: f32) {}
+
+  /// How accesses to addresses the cartridge doesn't decode are handled. Cartridges start out strict.
+  fn set_mode(&mut self, mode: EmulationMode);
+
+  /// Whether the game finished writing its save since the last call. MBC0, HuC1 and HuC3 cartridges never report it.
+  fn take_save_dirty(&mut self) -> bool {
+    false
+  }
+
+  /// Everything but the ROM, for save states.
+  fn save_state(&self) -> Vec<u8>;
+
+  /// Restores a state from save_state of a cartridge of the same type and size.
+  fn load_state(&mut self, state: &[u8]) -> bincode::Result<()>;
+}
+
+/// Games disable the cartridge RAM once they're done writing it, so that's when the save is complete. Disabling RAM
+/// without having written it doesn't count.
+#[derive(Serialize, Deserialize)]
+pub struct SaveTracker {
+  written: bool,
+  dirty: bool,
+}
+
+impl SaveTracker {
+  pub fn new() -> SaveTracker {
+    SaveTracker {
+      written: false,
+      dirty: false,
+    }
+  }
+
+  pub fn record_write(&mut self) {
+    self.written = true;
+  }
+
+  pub fn record_ram_enable(&mut self, enabled: bool) {
+    if !enabled && self.written {
+      self.written = false;
+      self.dirty = true;
+    }
+  }
+
+  pub fn take_dirty(&mut self) -> bool {
+    std::mem::take(&mut self.dirty)
+  }
+}
+
+/// Receives the state of the rumble motor in a cartridge, whenever it turns on or off.
+pub trait RumbleMotor {
+  fn set_rumble(&mut self, on: bool);
+}
+
+impl<T: RumbleMotor> RumbleMotor for Rc<RefCell<T>> {
+  fn set_rumble(&mut self, on: bool) {
+    self.borrow_mut().set_rumble(on)
+  }
+}
+
+/// Wraps a bank number around to the banks in the ROM. Bank numbers are masked to the next power of two first, like the
+/// unused upper address lines of the cartridge, which leaves only the 72, 80 and 96 bank carts to wrap around further.
+pub fn mask_rom_bank(bank: usize, rom: &[u8]) -> usize {
+  let banks = rom.len() / 0x4000;
+  (bank & (banks.next_power_of_two() - 1)) % banks
+}
+
+/// Where an address in 0xA000-0xBFFF is in the given RAM bank, with banks beyond the RAM size wrapping around. None if
+/// the cartridge has no RAM.
+pub fn mask_ram_address(ram: &[u8], bank: usize, address: u16) -> Option<usize> {
+  if ram.is_empty() {
+    return None;
+  }
+  Some(((bank << 13) | (address as usize & 0x1FFF)) & (ram.len() - 1))
+}
+
+/// Reads a block from a cartridge. Blocks within one ROM bank are copied out of the ROM at once, anything else is read a
+/// byte at a time.
+pub fn read_cartridge_block<C: MBC + ?Sized>(cartridge: &C, rom: &[u8], address: u16, dst: &mut [u8]) {
+  let offset = address as usize & 0x3FFF;
+  if address <= 0x7FFF && offset + dst.len() <= 0x4000 {
+    let start = (cartridge.rom_bank(address) << 14) | offset;
+    dst.copy_from_slice(&rom[start..start + dst.len()]);
+  } else {
+    read_bytes(cartridge, address, dst);
+  }
+}
+
+/// How cartridges handle accesses to addresses they don't decode.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum EmulationMode {
+  /// Panics, to catch emulator bugs early.
+  Strict,
+  /// Logs a warning, reads 0xFF like an open bus and ignores writes.
+  Lenient,
+}
+
+/// Lenient in the wasm build, where a panic takes the whole page down, and strict everywhere else, tests included.
+impl Default for EmulationMode {
+  fn default() -> Self {
+    if cfg!(target_arch = "wasm32") { EmulationMode::Lenient } else { EmulationMode::Strict }
+  }
+}
+
+/// Applies the emulation mode to accesses a cartridge doesn't decode. Games tend to repeat the same access every frame,
+/// so lenient mode only warns about each address once.
+#[derive(Default)]
+pub struct UnmappedAccess {
+  mode: EmulationMode,
+  warned: RefCell<HashSet<u16>>,
+}
+
+impl UnmappedAccess {
+  pub fn new(mode: EmulationMode) -> UnmappedAccess {
+    UnmappedAccess {
+      mode,
+      warned: RefCell::new(HashSet::new()),
+    }
+  }
+
+  pub fn read(&self, cartridge: &str, address: u16) -> u8 {
+    if self.mode == EmulationMode::Strict {
+      panic!("Can't read from address {:#06x} on {}", address, cartridge);
+    }
+    self.warn_once("Read from", cartridge, address);
+    0xFF
+  }
+
+  pub fn write(&self, cartridge: &str, address: u16) {
+    if self.mode == EmulationMode::Strict {
+      panic!("Can't write to address {:#06x} on {}", address, cartridge);
+    }
+    self.warn_once("Write to", cartridge, address);
+  }
+
+  #[cfg_attr(not(target_arch = "wasm32"), allow(unused_variables))]
+  fn warn_once(&self, access: &str, cartridge: &str, address: u16) {
+    if self.warned.borrow_mut().insert(address) {
+      #[cfg(target_arch = "wasm32")]
+      web_sys::console::warn_1(&format!("{} unmapped address {:#06x} on {}", access, address, cartridge).into());
+    }
+  }
+}
+
+/// Copies the start of a save into the cartridge RAM. If the save is shorter than the RAM, the rest is cleared.
+pub fn import_ram(ram: &mut [u8], save: &[u8]) {
+  let length = ram.len().min(save.len());
+  if length < ram.len() {
+    #[cfg(target_arch = "wasm32")]
+    web_sys::console::warn_1(&format!("Save of {} bytes is smaller than the cartridge RAM of {} bytes", save.len(), ram.len()).into());
+  }
+  ram[..length].copy_from_slice(&save[..length]);
+  ram[length..].fill(0);
+}
+
+/// Whether the cartridge type in the header has a battery, which keeps the RAM and clock contents.
+pub fn has_battery(rom: &[u8]) -> bool {
+  matches!(rom[0x0147], 0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFC..=0xFF)
+}
+
+const NINTENDO_LOGO: [u8; 48] = [
+  0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+  0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+  0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+/// MBC1M multicarts are 1MB and contain a game with its own header every 256KB. Like other emulators, this looks for
+/// the Nintendo logo in the header of the second game.
+fn is_mbc1_multicart(rom: &[u8]) -> bool {
+  rom.len() == 0x100000 && rom[0x40104..0x40134] == NINTENDO_LOGO
+}
+
+/// Creates the memory bank controller described by the cartridge header and loads the ROM into it.
+pub fn create_rom(rom: &[u8], mode: EmulationMode) -> Result<Box<dyn MBC>, RomError> {
+  let CartridgeHeader { cartridge_type, rom_size, ram_size, .. } = CartridgeHeader::parse(rom)?;
+  let mut mbc: Box<dyn MBC> = match cartridge_type {
+    0x00 => Box::new(MBC0::new()),
+    0x08..=0x09 if matches!(ram_size, RAMSize::NotAvailable) => Box::new(MBC0::new_with_ram(RAMSize::KB8)),
+    0x08..=0x09 => Box::new(MBC0::new_with_ram(ram_size)),
+    0x01..=0x03 if is_mbc1_multicart(rom) => Box::new(MBC1::new_multicart(rom_size, ram_size)),
+    0x01..=0x03 => Box::new(MBC1::new(rom_size, ram_size)),
+    0x05..=0x06 => Box::new(MBC2::new(rom_size)),
+    0x0F..=0x13 if matches!(ram_size, RAMSize::KB64) => Box::new(MBC3::new_mbc30(rom_size)),
+    0x0F..=0x13 => Box::new(MBC3::new(rom_size, ram_size)),
+    0x19..=0x1B => Box::new(MBC5::new(rom_size, ram_size)),
+    0x1C..=0x1E => Box::new(MBC5::new_rumble(rom_size, ram_size)),
+    0x22 => Box::new(MBC7::new(rom_size)),
+    0xFE => Box::new(HuC3::new(rom_size, ram_size)),
+    0xFF => Box::new(HuC1::new(rom_size, ram_size)),
+    _ => return Err(RomError::UnsupportedCartridgeType(cartridge_type))
+  };
+  mbc.load_bytes(0, rom);
+  mbc.set_mode(mode);
+  Ok(mbc)
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::memory::cartridge_header::header_checksum;
+  use crate::memory::memory::ROMSize;
+  use super::*;
+
+  fn create_rom_bytes(cartridge_type: u8, rom_size: u8, ram_size: u8) -> Vec<u8> {
+    let mut rom = vec![0u8; ROMSize::from_byte(rom_size).unwrap().bytes()];
+    rom[0x0147] = cartridge_type;
+    rom[0x0148] = rom_size;
+    rom[0x0149] = ram_size;
+    rom[0x014D] = header_checksum(&rom);
+    rom
+  }
+
+  #[test]
+  fn emulation_mode_is_strict_outside_wasm() {
+    assert_eq!(EmulationMode::default(), EmulationMode::Strict);
+  }
+
+  #[test]
+  fn blocks_are_read_from_the_mapped_rom_banks() {
+    let mut rom = create_rom_bytes(0x19, 0x02, 0x00);
+    rom[0x3FFE..0x4000].copy_from_slice(&[0x01, 0x02]);
+    rom[0xC000..0xC002].copy_from_slice(&[0x03, 0x04]);
+    let mut mbc = create_rom(&rom, EmulationMode::Strict).unwrap();
+    mbc.write(0x2000, 0x03);
+    let mut block = [0; 2];
+    mbc.read_block(0x4000, &mut block);
+    assert_eq!(block, [0x03, 0x04]);
+    // A block running into the next bank is read a byte at a time
+    let mut block = [0; 4];
+    mbc.read_block(0x3FFE, &mut block);
+    assert_eq!(block, [0x01, 0x02, 0x03, 0x04]);
+  }
+
+  #[test]
+  fn create_rom_loads_rom_bytes() {
+    let mut rom = create_rom_bytes(0x00, 0x00, 0x00);
+    rom[0x0100] = 0xAB;
+    rom[0x7FFF] = 0xCD;
+    let mbc = create_rom(&rom, EmulationMode::Strict).unwrap();
+    assert_eq!(mbc.read(0x0100), 0xAB);
+    assert_eq!(mbc.read(0x7FFF), 0xCD);
+  }
+
+  #[test]
+  fn create_rom_selects_mbc_from_header() {
+    let mut rom = create_rom_bytes(0x01, 0x02, 0x00);
+    rom[0x14000] = 0xAB;
+    let mut mbc = create_rom(&rom, EmulationMode::Strict).unwrap();
+    mbc.write(0x2000, 0x05);
+    assert_eq!(mbc.read(0x4000), 0xAB);
+  }
+
+  #[test]
+  fn create_rom_adds_ram_to_rom_only_carts() {
+    let mut mbc = create_rom(&create_rom_bytes(0x09, 0x00, 0x02), EmulationMode::Strict).unwrap();
+    mbc.write(0xA123, 0x42);
+    assert_eq!(mbc.read(0xA123), 0x42);
+    assert_eq!(mbc.export_save(0).len(), 0x2000);
+    let mut mbc = create_rom(&create_rom_bytes(0x00, 0x00, 0x00), EmulationMode::Strict).unwrap();
+    mbc.write(0xA123, 0x42);
+    assert_eq!(mbc.read(0xA123), 0xFF);
+  }
+
+  #[test]
+  fn rom_bank_follows_bank_switches() {
+    let mut mbc = create_rom(&create_rom_bytes(0x01, 0x02, 0x00), EmulationMode::Strict).unwrap();
+    assert_eq!(mbc.rom_bank(0x0150), 0);
+    assert_eq!(mbc.rom_bank(0x4000), 1);
+    mbc.write(0x2000, 0x05);
+    assert_eq!(mbc.rom_bank(0x0150), 0);
+    assert_eq!(mbc.rom_bank(0x7FFF), 5);
+  }
+
+  #[test]
+  fn create_rom_detects_mbc1_multicart() {
+    let mut rom = create_rom_bytes(0x01, 0x05, 0x00);
+    for game in 0..4 {
+      rom[game * 0x40000 + 0x0104..game * 0x40000 + 0x0134].copy_from_slice(&NINTENDO_LOGO);
+    }
+    rom[0x44000] = 0xAB;
+    let mut mbc = create_rom(&rom, EmulationMode::Strict).unwrap();
+    mbc.write(0x4000, 0x01);
+    mbc.write(0x2000, 0x01);
+    assert_eq!(mbc.rom_bank(0x4000), 0x11);
+    assert_eq!(mbc.read(0x4000), 0xAB);
+    rom[0x40104] = 0x00;
+    let mut mbc = create_rom(&rom, EmulationMode::Strict).unwrap();
+    mbc.write(0x4000, 0x01);
+    mbc.write(0x2000, 0x01);
+    assert_eq!(mbc.rom_bank(0x4000), 0x21);
+  }
+
+  #[test]
+  fn create_rom_detects_mbc30_by_ram_size() {
+    let mut rom = create_rom_bytes(0x10, 0x07, 0x05);
+    rom[0x3FC000] = 0xAB;
+    let mut mbc = create_rom(&rom, EmulationMode::Strict).unwrap();
+    mbc.write(0x2000, 0xFF);
+    assert_eq!(mbc.read(0x4000), 0xAB);
+    mbc.write(0x0000, 0x0A);
+    mbc.write(0x4000, 0x07);
+    mbc.write(0xA000, 0xCD);
+    assert_eq!(mbc.read(0xA000), 0xCD);
+  }
+
+  #[test]
+  #[should_panic(expected = "Can't write to address 0xc000 on MBC1")]
+  fn strict_cartridge_panics_on_undecoded_address() {
+    let mut mbc = create_rom(&create_rom_bytes(0x01, 0x02, 0x00), EmulationMode::Strict).unwrap();
+    mbc.write(0xC000, 0x12);
+  }
+
+  #[test]
+  fn lenient_cartridge_tolerates_undecoded_address() {
+    let mut mbc = create_rom(&create_rom_bytes(0x01, 0x02, 0x00), EmulationMode::Lenient).unwrap();
+    mbc.write(0xC000, 0x12);
+    assert_eq!(mbc.read(0xC000), 0xFF);
+  }
+
+  #[test]
+  fn lenient_access_warns_once_per_address() {
+    let unmapped = UnmappedAccess::new(EmulationMode::Lenient);
+    unmapped.read("MBC1", 0xC000);
+    unmapped.write("MBC1", 0xC000);
+    unmapped.write("MBC1", 0xD000);
+    assert_eq!(unmapped.warned.borrow().len(), 2);
+  }
+
+  #[test]
+  fn load_state_keeps_the_mode() {
+    let mut mbc = create_rom(&create_rom_bytes(0x01, 0x02, 0x00), EmulationMode::Lenient).unwrap();
+    let state = mbc.save_state();
+    mbc.load_state(&state).unwrap();
+    assert_eq!(mbc.read(0xC000), 0xFF);
+  }
+
+  #[test]
+  fn battery_follows_cartridge_type() {
+    assert!(has_battery(&create_rom_bytes(0x03, 0x00, 0x00)));
+    assert!(has_battery(&create_rom_bytes(0x10, 0x00, 0x00)));
+    assert!(!has_battery(&create_rom_bytes(0x01, 0x00, 0x00)));
+    assert!(!has_battery(&create_rom_bytes(0x19, 0x00, 0x00)));
+  }
+
+  #[test]
+  fn save_is_dirty_when_ram_is_disabled_after_writes() {
+    let mut tracker = SaveTracker::new();
+    tracker.record_ram_enable(true);
+    tracker.record_write();
+    tracker.record_write();
+    assert!(!tracker.take_dirty());
+    tracker.record_ram_enable(false);
+    assert!(tracker.take_dirty());
+    assert!(!tracker.take_dirty());
+    tracker.record_ram_enable(true);
+    tracker.record_ram_enable(false);
+    assert!(!tracker.take_dirty());
+  }
+
+  #[test]
+  fn import_ram_pads_short_saves() {
+    let mut ram = [0xFF; 4];
+    import_ram(&mut ram, &[0x12, 0x34]);
+    assert_eq!(ram, [0x12, 0x34, 0x00, 0x00]);
+    import_ram(&mut ram, &[0x56, 0x78, 0x9A, 0xBC, 0xDE]);
+    assert_eq!(ram, [0x56, 0x78, 0x9A, 0xBC]);
+  }
+
+  #[test]
+  fn create_rom_rejects_unknown_cartridge_type() {
+    assert_eq!(create_rom(&create_rom_bytes(0xFD, 0x00, 0x00), EmulationMode::Strict).err(), Some(RomError::UnsupportedCartridgeType(0xFD)));
+  }
+
+  #[test]
+  fn create_rom_rejects_truncated_image() {
+    let rom = create_rom_bytes(0x01, 0x02, 0x00);
+    assert_eq!(create_rom(&rom[..0x10000], EmulationMode::Strict).err(), Some(RomError::ROMSizeMismatch { declared: 0x20000, actual: 0x10000 }));
+  }
+}
@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+use crate::memory::mbc::{import_ram, read_cartridge_block, EmulationMode, Loadable, MBC, UnmappedAccess};
+use crate::memory::memory::{Memory, RAMSize, ROMSize};
+
+/// Cartridge without a memory bank controller, containing a single 32 KiB ROM and optionally up to 8 KiB of RAM.
+#[derive(Serialize, Deserialize)]
+pub struct MBC0 {
+  #[serde(skip)]
+  rom: Vec<u8>,
+  #[serde(skip)]
+  unmapped: UnmappedAccess,
+  ram: Vec<u8>,
+}
+
+impl MBC0 {
+  pub fn new() -> MBC0 {
+    MBC0 {
+      rom: vec![0; ROMSize::KB32.bytes()],
+      unmapped: UnmappedAccess::new(EmulationMode::Strict),
+      ram: vec![],
+    }
+  }
+
+  /// The RAM is always accessible, since there's no register to enable it.
+  pub fn new_with_ram(ram_size: RAMSize) -> MBC0 {
+    MBC0 {
+      ram: vec![0; ram_size.bytes().min(0x2000)],
+      ..MBC0::new()
+    }
+  }
+
+  /// Smaller RAM is mirrored throughout 0xA000-0xBFFF.
+  fn ram_address(&self, address: u16) -> Option<usize> {
+    if self.ram.is_empty() {
+      return None;
+    }
+    Some((address as usize & 0x1FFF) % self.ram.len())
+  }
+}
+
+impl Memory for MBC0 {
+  fn read(&self, address: u16) -> u8 {
+    match address {
+      0x0000..=0x7FFF => self.rom[address as usize],
+      0xA000..=0xBFFF => self.ram_address(address).map_or(0xFF, |address_in_ram| self.ram[address_in_ram]),
+      _ => self.unmapped.read("MBC0", address)
+    }
+  }
+
+  fn read_block(&self, address: u16, dst: &mut [u8]) {
+    read_cartridge_block(self, &self.rom, address, dst);
+  }
+
+  fn write(&mut self, address: u16, value: u8) {
+    match address {
+      0x0000..=0x7FFF => {}
+      0xA000..=0xBFFF => if let Some(address_in_ram) = self.ram_address(address) {
+        self.ram[address_in_ram] = value;
+      },
+      _ => self.unmapped.write("MBC0", address)
+    }
+  }
+}
+
+impl MBC for MBC0 {
+  fn rom_bank(&self, address: u16) -> usize {
+    (address >> 14) as usize
+  }
+
+  fn export_save(&self, _now: u64) -> Vec<u8> {
+    self.ram.clone()
+  }
+
+  fn import_save(&mut self, save: &[u8], _now: u64) {
+    import_ram(&mut self.ram, save);
+  }
+
+  fn set_mode(&mut self, mode: EmulationMode) {
+    self.unmapped = UnmappedAccess::new(mode);
+  }
+
+  fn save_state(&self) -> Vec<u8> {
+    bincode::serialize(self).unwrap()
+  }
+
+  fn load_state(&mut self, state: &[u8]) -> bincode::Result<()> {
+    let loaded: MBC0 = bincode::deserialize(state)?;
+    *self = MBC0 {
+      rom: std::mem::take(&mut self.rom),
+      unmapped: std::mem::take(&mut self.unmapped),
+      ..loaded
+    };
+    Ok(())
+  }
+}
+
+impl Loadable for MBC0 {
+  fn load_byte(&mut self, address: usize, value: u8) {
+    self.rom[address] = value;
+  }
+
+  fn load_bytes(&mut self, address: usize, values: &[u8]) {
+    self.rom.as_mut_slice()[address..(address + values.len())].copy_from_slice(values);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn read_rom() {
+    let mut memory = MBC0::new();
+    memory.load_byte(0x1234, 0xAB);
+    memory.load_byte(0x7FFF, 0xCD);
+    assert_eq!(memory.read(0x1234), 0xAB);
+    assert_eq!(memory.read(0x7FFF), 0xCD);
+  }
+
+  #[test]
+  fn writes_are_ignored() {
+    let mut memory = MBC0::new();
+    memory.load_byte(0x2000, 0xAB);
+    memory.write(0x2000, 0x00);
+    memory.write(0xA000, 0x12);
+    assert_eq!(memory.read(0x2000), 0xAB);
+    assert_eq!(memory.read(0xA000), 0xFF);
+  }
+
+  #[test]
+  fn read_write_ram() {
+    let mut memory = MBC0::new_with_ram(RAMSize::KB8);
+    memory.write(0xA000, 0x12);
+    memory.write(0xBFFF, 0x34);
+    assert_eq!(memory.read(0xA000), 0x12);
+    assert_eq!(memory.read(0xBFFF), 0x34);
+  }
+
+  #[test]
+  fn save_round_trip() {
+    let mut memory = MBC0::new_with_ram(RAMSize::KB8);
+    memory.write(0xA123, 0x42);
+    let save = memory.export_save(0);
+    assert_eq!(save.len(), 0x2000);
+    let mut imported = MBC0::new_with_ram(RAMSize::KB8);
+    imported.import_save(&save, 0);
+    assert_eq!(imported.read(0xA123), 0x42);
+    assert!(MBC0::new().export_save(0).is_empty());
+  }
+}
@@ -1,26 +1,102 @@
+use serde::{Deserialize, Serialize};
 use crate::memory::memory::{Memory, ROMSize, RAMSize};
-use crate::memory::mbc::Loadable;
+use crate::memory::mbc::{import_ram, mask_ram_address, mask_rom_bank, read_cartridge_block, EmulationMode, Loadable, MBC, SaveTracker, UnmappedAccess};
 
+#[derive(Serialize, Deserialize)]
 pub struct MBC1 {
+  multicart: bool,
   ram_enabled: bool,
+  save_tracker: SaveTracker,
   upper_bank_address_enabled: bool,
   lower_bank_address: usize,
   upper_bank_address: usize,
+  #[serde(skip)]
   rom: Vec<u8>,
+  #[serde(skip)]
+  unmapped: UnmappedAccess,
   ram: Vec<u8>,
 }
 
 impl MBC1 {
   pub fn new(rom_size: ROMSize, ram_size: RAMSize) -> MBC1 {
     MBC1 {
+      multicart: false,
       ram_enabled: false,
+      save_tracker: SaveTracker::new(),
       upper_bank_address_enabled: false,
       lower_bank_address: 0x01,
       upper_bank_address: 0x00,
       ram: vec![0; ram_size.bytes()],
       rom: vec![0; rom_size.bytes()],
+      unmapped: UnmappedAccess::new(EmulationMode::Strict),
     }
   }
+
+  /// An MBC1M multicart wires the upper bank address to bit 4 of the bank number instead of bit 5, and ignores bit 4
+  /// of the lower bank address. Each game then sees 16 banks.
+  pub fn new_multicart(rom_size: ROMSize, ram_size: RAMSize) -> MBC1 {
+    MBC1 {
+      multicart: true,
+      ..MBC1::new(rom_size, ram_size)
+    }
+  }
+
+  /// In mode 1, the upper bank address also selects the RAM bank.
+  fn ram_address(&self, address: u16) -> Option<usize> {
+    if !self.ram_enabled {
+      return None;
+    }
+    let bank = if self.upper_bank_address_enabled { self.upper_bank_address } else { 0 };
+    mask_ram_address(&self.ram, bank, address)
+  }
+}
+
+impl MBC for MBC1 {
+  /// In mode 1, the upper bank address also selects the bank at 0x0000-0x3FFF. Banks beyond the ROM size wrap around,
+  /// so on carts smaller than 1MB the upper bank address only selects the RAM bank.
+  fn rom_bank(&self, address: u16) -> usize {
+    let (lower_bank_address, upper_bank_shift) = if self.multicart {
+      (self.lower_bank_address & 0x0F, 4)
+    } else {
+      (self.lower_bank_address, 5)
+    };
+    let bank = match address {
+      0x0000..=0x3FFF if self.upper_bank_address_enabled => self.upper_bank_address << upper_bank_shift,
+      0x0000..=0x3FFF => 0,
+      _ => lower_bank_address | (self.upper_bank_address << upper_bank_shift)
+    };
+    mask_rom_bank(bank, &self.rom)
+  }
+
+  fn export_save(&self, _now: u64) -> Vec<u8> {
+    self.ram.clone()
+  }
+
+  fn import_save(&mut self, save: &[u8], _now: u64) {
+    import_ram(&mut self.ram, save);
+  }
+
+  fn take_save_dirty(&mut self) -> bool {
+    self.save_tracker.take_dirty()
+  }
+
+  fn set_mode(&mut self, mode: EmulationMode) {
+    self.unmapped = UnmappedAccess::new(mode);
+  }
+
+  fn save_state(&self) -> Vec<u8> {
+    bincode::serialize(self).unwrap()
+  }
+
+  fn load_state(&mut self, state: &[u8]) -> bincode::Result<()> {
+    let loaded: MBC1 = bincode::deserialize(state)?;
+    *self = MBC1 {
+      rom: std::mem::take(&mut self.rom),
+      unmapped: std::mem::take(&mut self.unmapped),
+      ..loaded
+    };
+    Ok(())
+  }
 }
 
 impl Loadable for MBC1 {
@@ -36,26 +112,21 @@ impl Loadable for MBC1 {
 impl Memory for MBC1 {
   fn read(&self, address: u16) -> u8 {
     match address {
-      0x0000..=0x3FFF => {
-        let address_in_rom = ((address as usize) & 0x3FFF) | (if self.upper_bank_address_enabled { self.upper_bank_address << 19 } else { 0 });
-        self.rom[address_in_rom]
-      }
-      0x4000..=0x7FFF => {
-        let address_in_rom = ((address as usize) & 0x3FFF) | (self.lower_bank_address << 14) | (self.upper_bank_address << 19);
-        self.rom[address_in_rom]
-      }
-      0xA000..=0xBFFF => {
-        let address_in_ram = ((address as usize) & 0x1FFF) | (if self.upper_bank_address_enabled { self.upper_bank_address << 13 } else { 0 });
-        self.ram[address_in_ram]
-      }
-      _ => panic!("Can't read from address {:#06x} on MBC1", address)
+      0x0000..=0x7FFF => self.rom[(self.rom_bank(address) << 14) | (address as usize & 0x3FFF)],
+      0xA000..=0xBFFF => self.ram_address(address).map_or(0xFF, |address_in_ram| self.ram[address_in_ram]),
+      _ => self.unmapped.read("MBC1", address)
     }
   }
 
+  fn read_block(&self, address: u16, dst: &mut [u8]) {
+    read_cartridge_block(self, &self.rom, address, dst);
+  }
+
   fn write(&mut self, address: u16, value: u8) {
     match address {
       0x0000..=0x1FFF => {
         self.ram_enabled = (value & 0x0F) == 0x0A;
+        self.save_tracker.record_ram_enable(self.ram_enabled);
       }
       0x2000..=0x3FFF => {
         self.lower_bank_address = (value & 0x1F) as usize;
@@ -70,22 +141,22 @@ impl Memory for MBC1 {
         self.upper_bank_address_enabled = (value & 0x01) == 0x01;
       }
       0xA000..=0xBFFF => {
-        if self.ram_enabled {
-          let address_in_ram = ((address as usize) & 0x1FFF) | (if self.upper_bank_address_enabled { self.upper_bank_address << 13 } else { 0 });
+        if let Some(address_in_ram) = self.ram_address(address) {
           self.ram[address_in_ram] = value;
+          self.save_tracker.record_write();
         }
       }
-      _ => panic!("Can't write to address {:#06x} on MBC1", address)
+      _ => self.unmapped.write("MBC1", address)
     };
   }
 }
 
-#[cfg(test)]
-mod tests {
-  use super::*;
+pub mod checks {
+  use crate::memory::mbc::Loadable;
+  use crate::memory::memory::{Memory, RAMSize, ROMSize};
+  use super::MBC1;
 
-  #[test]
-  fn read_write_ram() {
+  pub fn read_write_ram() -> bool {
     let mut memory = MBC1::new(ROMSize::MB8, RAMSize::KB32);
     memory.write(0x0000, 0x0A); // Enable RAM
     memory.write(0x6000, 0x01); // Enable upper bank address
@@ -94,11 +165,75 @@ mod tests {
       memory.write(0xA123, 0x0A | (bank << 4));
       memory.write(0xB456, 0x0B | (bank << 4));
     });
-    (0u8..=3u8).for_each(|bank| {
+    (0u8..=3u8).all(|bank| {
       memory.write(0x4000, bank); //Switch to bank
-      assert_eq!(memory.read(0xA123), 0x0A | (bank << 4));
-      assert_eq!(memory.read(0xB456), 0x0B | (bank << 4));
-    });
+      memory.read(0xA123) == 0x0A | (bank << 4) && memory.read(0xB456) == 0x0B | (bank << 4)
+    })
+  }
+
+  /// Bank 0x20 can't be selected in the switchable area, since the lower bank address maps 0 to 1, but mode 1 maps it
+  /// at 0x0000-0x3FFF.
+  pub fn mode_1_remaps_lower_rom_on_large_carts() -> bool {
+    let mut memory = MBC1::new(ROMSize::MB1, RAMSize::KB8);
+    memory.load_byte(0x00000, 0x00);
+    memory.load_byte(0x80000, 0x20);
+    memory.load_byte(0x84000, 0x21);
+    memory.write(0x4000, 0x01); // Set upper bank address to 1
+    let mode_0 = memory.read(0x0000) == 0x00 && memory.read(0x4000) == 0x21;
+    memory.write(0x6000, 0x01); // Switch to mode 1
+    let mode_1 = memory.read(0x0000) == 0x20 && memory.read(0x4000) == 0x21;
+    memory.write(0x6000, 0x00); // Switch back to mode 0
+    mode_0 && mode_1 && memory.read(0x0000) == 0x00
+  }
+
+  pub fn lower_bank_address_is_never_zero() -> bool {
+    let mut memory = MBC1::new(ROMSize::MB8, RAMSize::KB32);
+    memory.load_byte(0x1072A7, 0xAB);
+    memory.write(0x2000, 0); // Set lower bank address to 0
+    memory.write(0x4000, 0x2); // Set upper bank address to 2
+    memory.read(0x72A7) == 0xAB
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn read_write_ram() {
+    assert!(checks::read_write_ram());
+  }
+
+  #[test]
+  fn save_round_trip() {
+    let mut memory = MBC1::new(ROMSize::MB8, RAMSize::KB32);
+    memory.write(0x0000, 0x0A); // Enable RAM
+    memory.write(0x6000, 0x01); // Enable upper bank address
+    memory.write(0x4000, 0x02);
+    memory.write(0xA123, 0x42);
+    let save = memory.export_save(0);
+    assert_eq!(save.len(), RAMSize::KB32.bytes());
+    let mut imported = MBC1::new(ROMSize::MB8, RAMSize::KB32);
+    imported.import_save(&save, 0);
+    imported.write(0x0000, 0x0A);
+    imported.write(0x6000, 0x01);
+    imported.write(0x4000, 0x02);
+    assert_eq!(imported.read(0xA123), 0x42);
+  }
+
+  #[test]
+  fn disabling_written_ram_marks_save_dirty() {
+    let mut memory = MBC1::new(ROMSize::MB1, RAMSize::KB32);
+    memory.write(0x0000, 0x0A); // Enable RAM
+    memory.write(0xA000, 0x42);
+    memory.write(0x0000, 0x00); // Disable RAM
+    assert!(memory.take_save_dirty());
+    memory.write(0x0000, 0x00);
+    assert!(!memory.take_save_dirty());
+    // Writes to disabled RAM don't count
+    memory.write(0xA000, 0x42);
+    memory.write(0x0000, 0x00);
+    assert!(!memory.take_save_dirty());
   }
 
   #[test]
@@ -152,12 +287,70 @@ mod tests {
     assert_eq!(memory.read(0x72A7), 0xAB);
   }
 
+  #[test]
+  fn mode_1_remaps_lower_rom_on_large_carts() {
+    assert!(checks::mode_1_remaps_lower_rom_on_large_carts());
+  }
+
+  #[test]
+  fn mode_1_selects_ram_bank_on_small_carts() {
+    let mut memory = MBC1::new(ROMSize::KB512, RAMSize::KB32);
+    memory.load_byte(0x4000, 0x01);
+    memory.write(0x0000, 0x0A); // Enable RAM
+    memory.write(0x4000, 0x02); // Set upper bank address to 2
+    memory.write(0xA000, 0xAB); // Mode 0 writes to RAM bank 0
+    memory.write(0x6000, 0x01); // Switch to mode 1
+    memory.write(0xA000, 0xCD);
+    // The upper bank address doesn't reach beyond 512KB of ROM
+    assert_eq!(memory.read(0x0000), 0x00);
+    assert_eq!(memory.read(0x4000), 0x01);
+    assert_eq!(memory.rom_bank(0x4000), 1);
+    assert_eq!(memory.read(0xA000), 0xCD);
+    memory.write(0x6000, 0x00);
+    assert_eq!(memory.read(0xA000), 0xAB);
+  }
+
+  #[test]
+  fn ram_reads_open_bus_when_disabled() {
+    let mut memory = MBC1::new(ROMSize::KB512, RAMSize::KB8);
+    memory.write(0x0000, 0x0A);
+    memory.write(0xA000, 0x12);
+    memory.write(0x0000, 0x00);
+    assert_eq!(memory.read(0xA000), 0xFF);
+    memory.write(0xA000, 0x34);
+    memory.write(0x0000, 0x0A);
+    assert_eq!(memory.read(0xA000), 0x12);
+  }
+
+  #[test]
+  fn multicart_selects_game_with_upper_bank_address() {
+    let mut memory = MBC1::new_multicart(ROMSize::MB1, RAMSize::NotAvailable);
+    memory.load_byte(0x40000, 0x10);
+    memory.load_byte(0x44000, 0x11);
+    memory.load_byte(0x7C000, 0x1F);
+    memory.write(0x4000, 0x01); // Select the second game
+    memory.write(0x2000, 0x01);
+    assert_eq!(memory.read(0x0000), 0x00);
+    assert_eq!(memory.read(0x4000), 0x11);
+    memory.write(0x6000, 0x01); // Switch to mode 1
+    assert_eq!(memory.read(0x0000), 0x10);
+    memory.write(0x2000, 0x1F);
+    assert_eq!(memory.read(0x4000), 0x1F);
+    // Bit 4 of the lower bank address is ignored, but still prevents the bank from being bumped to 1
+    memory.write(0x2000, 0x10);
+    assert_eq!(memory.rom_bank(0x4000), 0x10);
+  }
+
+  #[test]
+  fn bank_number_wraps_to_rom_size() {
+    let mut memory = MBC1::new(ROMSize::KB256, RAMSize::KB8);
+    memory.load_byte(0x8123, 0x42);
+    memory.write(0x2000, 0x42);
+    assert_eq!(memory.read(0x4123), 0x42);
+  }
+
   #[test]
   fn lower_bank_address_is_never_zero() {
-    let mut memory = MBC1::new(ROMSize::MB8, RAMSize::KB32);
-    memory.load_byte(0x1072A7, 0xAB);
-    memory.write(0x2000, 0); // Set lower bank address to 0
-    memory.write(0x4000, 0x2); // Set upper bank address to 2
-    assert_eq!(memory.read(0x72A7), 0xAB);
+    assert!(checks::lower_bank_address_is_never_zero());
   }
 }
\ No newline at end of file
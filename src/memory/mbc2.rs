@@ -1,21 +1,29 @@
-use crate::memory::mbc::Loadable;
+use serde::{Deserialize, Serialize};
+use crate::memory::mbc::{import_ram, mask_rom_bank, read_cartridge_block, EmulationMode, Loadable, MBC, SaveTracker, UnmappedAccess};
 use crate::memory::memory::{Memory, ROMSize};
 use crate::util::bit_util::BitUtil;
 
-struct MBC2 {
+#[derive(Serialize, Deserialize)]
+pub struct MBC2 {
   ram_enabled: bool,
+  save_tracker: SaveTracker,
   bank_address: usize,
+  #[serde(skip)]
   rom: Vec<u8>,
+  #[serde(skip)]
+  unmapped: UnmappedAccess,
   ram: Vec<u8>,
 }
 
 impl MBC2 {
-  fn new(rom_size: ROMSize) -> MBC2 {
+  pub fn new(rom_size: ROMSize) -> MBC2 {
     MBC2 {
       ram_enabled: false,
+      save_tracker: SaveTracker::new(),
       bank_address: 0x01,
       ram: vec![0; 0x200],
       rom: vec![0; rom_size.bytes()],
+      unmapped: UnmappedAccess::new(EmulationMode::Strict),
     }
   }
 }
@@ -23,21 +31,18 @@ impl MBC2 {
 impl Memory for MBC2 {
   fn read(&self, address: u16) -> u8 {
     match address {
-      0x0000..=0x3FFF => {
-        self.rom[address as usize]
-      },
-      0x4000..=0x7FFF => {
-        let address_in_rom = ((address as usize) & 0x3FFF) | (self.bank_address << 14);
-        self.rom[address_in_rom]
-      },
-      0xA000..=0xBFFF => {
-        let address_in_ram = (address as usize) & 0x1FF;
-        self.ram[address_in_ram]
-      },
-      _ => panic!("Can't read from address {:#06x} on MBC2", address)
+      0x0000..=0x7FFF => self.rom[(self.rom_bank(address) << 14) | (address as usize & 0x3FFF)],
+      // The built-in RAM stores 512 half bytes, mirrored throughout 0xA000-0xBFFF
+      0xA000..=0xBFFF if self.ram_enabled => 0xF0 | self.ram[(address as usize) & 0x1FF],
+      0xA000..=0xBFFF => 0xFF,
+      _ => self.unmapped.read("MBC2", address)
     }
   }
 
+  fn read_block(&self, address: u16, dst: &mut [u8]) {
+    read_cartridge_block(self, &self.rom, address, dst);
+  }
+
   fn write(&mut self, address: u16, value: u8) {
     match address {
       0x0000..=0x3FFF => {
@@ -48,14 +53,54 @@ impl Memory for MBC2 {
           }
         } else {
           self.ram_enabled = (value & 0x0F) == 0x0A;
+          self.save_tracker.record_ram_enable(self.ram_enabled);
         }
       },
-      0xA000..=0xBFFF => {
-        let address_in_ram = (address as usize) & 0x1FF;
-        self.ram[address_in_ram] = value;
+      0xA000..=0xBFFF => if self.ram_enabled {
+        self.ram[(address as usize) & 0x1FF] = value & 0x0F;
+        self.save_tracker.record_write();
       },
-      _ => panic!("Can't write to address {:#06x} on MBC2", address)
+      0x4000..=0x7FFF => {}
+      _ => self.unmapped.write("MBC2", address)
+    };
+  }
+}
+
+impl MBC for MBC2 {
+  fn rom_bank(&self, address: u16) -> usize {
+    if address < 0x4000 { 0 } else { mask_rom_bank(self.bank_address, &self.rom) }
+  }
+
+  fn export_save(&self, _now: u64) -> Vec<u8> {
+    self.ram.clone()
+  }
+
+  fn import_save(&mut self, save: &[u8], _now: u64) {
+    import_ram(&mut self.ram, save);
+    // Other emulators store the unused upper nibble as well
+    self.ram.iter_mut().for_each(|value| *value &= 0x0F);
+  }
+
+  fn take_save_dirty(&mut self) -> bool {
+    self.save_tracker.take_dirty()
+  }
+
+  fn set_mode(&mut self, mode: EmulationMode) {
+    self.unmapped = UnmappedAccess::new(mode);
+  }
+
+  fn save_state(&self) -> Vec<u8> {
+    bincode::serialize(self).unwrap()
+  }
+
+  fn load_state(&mut self, state: &[u8]) -> bincode::Result<()> {
+    let loaded: MBC2 = bincode::deserialize(state)?;
+    *self = MBC2 {
+      rom: std::mem::take(&mut self.rom),
+      unmapped: std::mem::take(&mut self.unmapped),
+      ..loaded
     };
+    Ok(())
   }
 }
 
@@ -81,9 +126,61 @@ mod tests {
     memory.write(0xA000, 0xAB);
     memory.write(0xA080, 0xCD);
     memory.write(0xA1FF, 0xEF);
-    assert_eq_hex!(memory.read(0xA000), 0xAB);
-    assert_eq_hex!(memory.read(0xA080), 0xCD);
-    assert_eq_hex!(memory.read(0xA1FF), 0xEF);
+    assert_eq_hex!(memory.read(0xA000), 0xFB);
+    assert_eq_hex!(memory.read(0xA080), 0xFD);
+    assert_eq_hex!(memory.read(0xA1FF), 0xFF);
+  }
+
+  #[test]
+  fn ignores_writes_to_upper_rom() {
+    let mut memory = MBC2::new(ROMSize::KB256);
+    memory.load_byte(0x8000, 0xAB);
+    memory.write(0x2100, 0x02);
+    memory.write(0x4000, 0x05);
+    memory.write(0x7FFF, 0x05);
+    assert_eq_hex!(memory.read(0x4000), 0xAB);
+  }
+
+  #[test]
+  fn ram_is_only_accessible_when_enabled() {
+    let mut memory = MBC2::new(ROMSize::KB256);
+    memory.write(0xA000, 0x01);
+    memory.write(0x0000, 0x0A); // Enable RAM
+    assert_eq_hex!(memory.read(0xA000), 0xF0);
+    memory.write(0xA000, 0x02);
+    memory.write(0x0000, 0x00); // Disable RAM
+    assert_eq_hex!(memory.read(0xA000), 0xFF);
+    memory.write(0x0000, 0x0A);
+    assert_eq_hex!(memory.read(0xA000), 0xF2);
+  }
+
+  #[test]
+  fn address_bit_8_selects_register() {
+    let mut memory = MBC2::new(ROMSize::KB256);
+    memory.load_byte(0x0C000, 0x03);
+    memory.write(0x2000, 0x03); // Bit 8 clear, so this disables RAM instead of switching banks
+    assert_eq_hex!(memory.rom_bank(0x4000), 0x01);
+    memory.write(0x2100, 0x03);
+    assert_eq_hex!(memory.read(0x4000), 0x03);
+    memory.write(0x2100, 0x0A); // Bit 8 set, so this switches banks instead of enabling RAM
+    assert_eq_hex!(memory.read(0xA000), 0xFF);
+  }
+
+  #[test]
+  fn save_round_trip() {
+    let mut memory = MBC2::new(ROMSize::KB256);
+    memory.write(0x0000, 0x0A); // Enable RAM
+    memory.write(0xA123, 0x0C);
+    let save = memory.export_save(0);
+    assert_eq!(save.len(), 512);
+    let mut imported = MBC2::new(ROMSize::KB256);
+    imported.import_save(&save, 0);
+    imported.write(0x0000, 0x0A);
+    assert_eq_hex!(imported.read(0xA123), 0xFC);
+    // Saves that store the upper nibble load the same way
+    imported.import_save(&[0xF7; 512], 0);
+    assert_eq_hex!(imported.read(0xA123), 0xF7);
+    assert_eq!(imported.export_save(0), vec![0x07; 512]);
   }
 
   #[test]
@@ -93,9 +190,9 @@ mod tests {
     memory.write(0xA000, 0xAB);
     memory.write(0xAC80, 0xCD);
     memory.write(0xB3FF, 0xEF);
-    assert_eq_hex!(memory.read(0xA000), 0xAB);
-    assert_eq_hex!(memory.read(0xA080), 0xCD);
-    assert_eq_hex!(memory.read(0xA1FF), 0xEF);
+    assert_eq_hex!(memory.read(0xA000), 0xFB);
+    assert_eq_hex!(memory.read(0xA080), 0xFD);
+    assert_eq_hex!(memory.read(0xA1FF), 0xFF);
   }
 
   #[test]
@@ -139,4 +236,12 @@ mod tests {
     assert_eq_hex!(memory.read(0x7FFF), 0x56);
 
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn bank_number_wraps_to_rom_size() {
+    let mut memory = MBC2::new(ROMSize::KB256);
+    memory.load_byte(0x8123, 0x42);
+    memory.write(0x2100, 0x42);
+    assert_eq_hex!(memory.read(0x4123), 0x42);
+  }
+}
@@ -1,6 +1,8 @@
 use std::cell::{RefCell, RefMut};
+use serde::{Deserialize, Serialize};
+use crate::time::cycle_time::CycleTime;
 use crate::time::duration::{Duration, RTCDuration};
-use crate::memory::mbc::Loadable;
+use crate::memory::mbc::{import_ram, mask_ram_address, mask_rom_bank, read_cartridge_block, EmulationMode, Loadable, MBC, SaveTracker, UnmappedAccess};
 use crate::memory::memory::{Memory, RAMSize, ROMSize};
 use crate::time::time::ClockAware;
 use crate::util::bit_util::{BitUtil, WordUtil};
@@ -40,12 +42,30 @@ impl FormattedRTC {
       days_high: (days.get_high_byte() & 0x01) | (if rtc.halted { 0x40 } else { 0x00 }) | (if rtc.days_carry { 0x80 } else { 0x00 }),
     }
   }
+
+  /// Takes the seconds, minutes, hours, days low and days high registers, in that order.
+  pub fn from_registers(registers: &[u8]) -> FormattedRTC {
+    FormattedRTC {
+      nanoseconds: 0,
+      seconds: registers[0],
+      minutes: registers[1],
+      hours: registers[2],
+      days_low: registers[3],
+      days_high: registers[4],
+    }
+  }
+
+  pub fn registers(&self) -> [u8; 5] {
+    [self.seconds, self.minutes, self.hours, self.days_low, self.days_high]
+  }
 }
 
+#[derive(Serialize, Deserialize)]
 struct RTC {
   nanoseconds: u64,
   days_carry: bool,
   halted: bool,
+  #[serde(skip)]
   formatted_rtc: RefCell<Option<FormattedRTC>>,
 }
 
@@ -72,7 +92,12 @@ impl RTC {
     }
   }
 
-  pub fn update_from_formatted_rtc(&mut self, formatted_rtc: FormattedRTC) {
+  /// Unused register bits aren't stored, so they read back as 0.
+  pub fn update_from_formatted_rtc(&mut self, mut formatted_rtc: FormattedRTC) {
+    formatted_rtc.seconds &= 0x3F;
+    formatted_rtc.minutes &= 0x3F;
+    formatted_rtc.hours &= 0x1F;
+    formatted_rtc.days_high &= 0xC1;
     self.nanoseconds = formatted_rtc.nanoseconds as u64 +
       formatted_rtc.seconds as u64 * FormattedRTC::SECONDS_NANOSECONDS +
       formatted_rtc.minutes as u64 * FormattedRTC::MINUTES_NANOSECONDS +
@@ -86,6 +111,8 @@ impl RTC {
   pub fn set_seconds(&mut self, seconds: u8) {
     let mut formatted_rtc = *self.get_formatted_rtc();
     formatted_rtc.seconds = seconds;
+    // Writing the seconds resets the sub-second divider
+    formatted_rtc.nanoseconds = 0;
     self.update_from_formatted_rtc(formatted_rtc);
   }
 
@@ -113,6 +140,8 @@ impl RTC {
     self.update_from_formatted_rtc(formatted_rtc);
   }
 
+  /// A halted clock keeps its value until it's resumed. The day carry stays set until it's cleared through the days
+  /// high register.
   pub fn tick(&mut self, nanoseconds: u64) {
     if self.halted {
       return;
@@ -132,31 +161,57 @@ impl RTC {
   }
 }
 
-struct MBC3 {
+#[derive(Serialize, Deserialize)]
+pub struct MBC3 {
+  mbc30: bool,
   rtc: RTC,
+  cycle_time: CycleTime,
   rtc_registers: RTC,
   clock_counter_data_latch: bool,
   ram_enabled: bool,
+  save_tracker: SaveTracker,
   rom_bank_address: usize,
   ram_bank_address: usize,
+  #[serde(skip)]
   rom: Vec<u8>,
+  #[serde(skip)]
+  unmapped: UnmappedAccess,
   ram: Vec<u8>,
 }
 
 impl MBC3 {
-  fn new(rom_size: ROMSize, ram_size: RAMSize) -> MBC3 {
+  const RTC_FOOTER_REGISTERS_SIZE: usize = 40;
+
+  pub fn new(rom_size: ROMSize, ram_size: RAMSize) -> MBC3 {
     MBC3 {
+      mbc30: false,
       rtc: RTC::new(),
+      cycle_time: CycleTime::new(),
       rtc_registers: RTC::new(),
       clock_counter_data_latch: false,
       ram_enabled: false,
+      save_tracker: SaveTracker::new(),
       rom_bank_address: 0x01,
       ram_bank_address: 0x00,
       ram: vec![0; ram_size.bytes()],
       rom: vec![0; rom_size.bytes()],
+      unmapped: UnmappedAccess::new(EmulationMode::Strict),
     }
   }
 
+  /// The MBC30 in Pokémon Crystal (JP) uses all 8 bits of the ROM bank number and 3 bits of the RAM bank number, for
+  /// 4MB of ROM and 64KB of RAM.
+  pub fn new_mbc30(rom_size: ROMSize) -> MBC3 {
+    MBC3 {
+      mbc30: true,
+      ..MBC3::new(rom_size, RAMSize::KB64)
+    }
+  }
+
+  fn ram_address(&self, address: u16) -> Option<usize> {
+    mask_ram_address(&self.ram, self.ram_bank_address, address)
+  }
+
   fn latch_counter_data(&mut self) {
     self.rtc_registers = self.rtc.clone();
   }
@@ -169,54 +224,56 @@ impl MBC3 {
     self.handle_tick(true);
   }
 
-  fn handle_tick(&mut self, double_speed: bool) {
-    let passed_nanoseconds = if double_speed { 500 } else { 1000 };
-    self.rtc.tick(passed_nanoseconds);
+  /// Advances the clock by the given number of half M-cycles.
+  fn elapse_half_cycles(&mut self, half_cycles: u64) {
+    let nanoseconds = self.cycle_time.elapse_half_cycles(half_cycles);
+    self.rtc.tick(nanoseconds);
   }
 }
 
 impl Memory for MBC3 {
   fn read(&self, address: u16) -> u8 {
     match address {
-      0x0000..=0x3FFF => {
-        self.rom[address as usize]
-      }
-      0x4000..=0x7FFF => {
-        let address_in_rom = ((address as usize) & 0x3FFF) | (self.rom_bank_address << 14);
-        self.rom[address_in_rom]
-      }
+      0x0000..=0x7FFF => self.rom[(self.rom_bank(address) << 14) | (address as usize & 0x3FFF)],
       0xA000..=0xBFFF => {
         match self.ram_bank_address {
-          0x0..=0x7 => {
-            let address_in_ram = ((address as usize) & 0x1FFF) | (self.ram_bank_address << 13);
-            self.ram[address_in_ram]
-          }
+          0x0..=0x7 => self.ram_address(address).map_or(0xFF, |address_in_ram| self.ram[address_in_ram]),
           0x8 => self.rtc_registers.get_formatted_rtc().seconds,
           0x9 => self.rtc_registers.get_formatted_rtc().minutes,
           0xA => self.rtc_registers.get_formatted_rtc().hours,
           0xB => self.rtc_registers.get_formatted_rtc().days_low,
           0xC => self.rtc_registers.get_formatted_rtc().days_high,
-          _ => panic!("{:#06x} is not a valid RAM bank address", self.ram_bank_address)
+          _ => self.unmapped.read("MBC3", address)
         }
       }
-      _ => panic!("Can't read from address {:#06x} on MBC3", address)
+      _ => self.unmapped.read("MBC3", address)
     }
   }
 
+  fn read_block(&self, address: u16, dst: &mut [u8]) {
+    read_cartridge_block(self, &self.rom, address, dst);
+  }
+
   fn write(&mut self, address: u16, value: u8) {
     match address {
       0x0000..=0x1FFF => {
         self.ram_enabled = (value & 0x0F) == 0x0A;
+        self.save_tracker.record_ram_enable(self.ram_enabled);
       }
       0x2000..=0x3FFF => {
-        self.rom_bank_address = value as usize;
+        self.rom_bank_address = (value & if self.mbc30 { 0xFF } else { 0x7F }) as usize;
         if self.rom_bank_address == 0 {
           self.rom_bank_address = 1;
         }
       }
       0x4000..=0x5FFF if value <= 0x0C => {
-        self.ram_bank_address = (value & 0x0F) as usize;
+        self.ram_bank_address = match value {
+          0x0..=0x7 if !self.mbc30 => (value & 0x03) as usize,
+          _ => value as usize
+        };
       }
+      // Values beyond the RTC registers don't select anything
+      0x4000..=0x5FFF => {}
       0x6000..=0x7FFF => {
         let new_value = (value & 1u8) == 1;
         if new_value & !self.clock_counter_data_latch {
@@ -226,11 +283,11 @@ impl Memory for MBC3 {
       }
       0xA000..=0xBFFF => {
         if self.ram_enabled {
+          self.save_tracker.record_write();
           match self.ram_bank_address {
-            0x0..=0x7 => {
-              let address_in_ram = ((address as usize) & 0x1FFF) | (self.ram_bank_address << 13);
+            0x0..=0x7 => if let Some(address_in_ram) = self.ram_address(address) {
               self.ram[address_in_ram] = value;
-            }
+            },
             0x8 => {
               self.rtc_registers.set_seconds(value);
               self.rtc.set_seconds(value);
@@ -251,12 +308,74 @@ impl Memory for MBC3 {
               self.rtc_registers.set_days_high(value);
               self.rtc.set_days_high(value);
             }
-            _ => panic!("{:#06x} is not a valid RAM bank address", self.ram_bank_address)
+            _ => self.unmapped.write("MBC3", address)
           };
         }
       }
-      _ => panic!("Can't write to address {:#06x} on MBC3", address)
+      _ => self.unmapped.write("MBC3", address)
+    };
+  }
+}
+
+impl MBC for MBC3 {
+  fn rom_bank(&self, address: u16) -> usize {
+    if address < 0x4000 { 0 } else { mask_rom_bank(self.rom_bank_address, &self.rom) }
+  }
+
+  fn handle_tick(&mut self, double_speed: bool) {
+    self.elapse_half_cycles(if double_speed { 1 } else { 2 });
+  }
+
+  /// Appends the RTC in the footer format shared by most emulators: the current and the latched registers as 32-bit
+  /// little endian values, followed by the 64-bit unix time of the save.
+  fn export_save(&self, now: u64) -> Vec<u8> {
+    let mut save = self.ram.clone();
+    for rtc in [&self.rtc, &self.rtc_registers] {
+      for register in rtc.get_formatted_rtc().registers() {
+        save.extend_from_slice(&(register as u32).to_le_bytes());
+      }
+    }
+    save.extend_from_slice(&now.to_le_bytes());
+    save
+  }
+
+  /// Also accepts the older footer with a 32-bit unix time. The clock is advanced by the time that has passed since the
+  /// save was written, unless it was halted.
+  fn import_save(&mut self, save: &[u8], now: u64) {
+    import_ram(&mut self.ram, save);
+    let footer = &save[self.ram.len().min(save.len())..];
+    let timestamp = match footer.len() {
+      44 => u32::from_le_bytes(footer[40..44].try_into().unwrap()) as u64,
+      48 => u64::from_le_bytes(footer[40..48].try_into().unwrap()),
+      _ => return
+    };
+    let registers: Vec<u8> = footer[..MBC3::RTC_FOOTER_REGISTERS_SIZE].chunks(4).map(|register| register[0]).collect();
+    self.rtc.update_from_formatted_rtc(FormattedRTC::from_registers(&registers[0..5]));
+    self.rtc_registers.update_from_formatted_rtc(FormattedRTC::from_registers(&registers[5..10]));
+    self.cycle_time.reset();
+    self.rtc.tick(now.saturating_sub(timestamp) * FormattedRTC::SECONDS_NANOSECONDS);
+  }
+
+  fn take_save_dirty(&mut self) -> bool {
+    self.save_tracker.take_dirty()
+  }
+
+  fn set_mode(&mut self, mode: EmulationMode) {
+    self.unmapped = UnmappedAccess::new(mode);
+  }
+
+  fn save_state(&self) -> Vec<u8> {
+    bincode::serialize(self).unwrap()
+  }
+
+  fn load_state(&mut self, state: &[u8]) -> bincode::Result<()> {
+    let loaded: MBC3 = bincode::deserialize(state)?;
+    *self = MBC3 {
+      rom: std::mem::take(&mut self.rom),
+      unmapped: std::mem::take(&mut self.unmapped),
+      ..loaded
     };
+    Ok(())
   }
 }
 
@@ -288,6 +407,16 @@ mod tests {
     assert_eq_hex!(memory.read(0xA1FF), 0xEF);
   }
 
+  #[test]
+  fn ignores_ram_bank_values_beyond_rtc_registers() {
+    let mut memory = MBC3::new(ROMSize::KB256, RAMSize::KB32);
+    memory.write(0x0000, 0xA); // Enable RAM
+    memory.write(0x4000, 0x01);
+    memory.write(0xA000, 0xAB);
+    memory.write(0x4000, 0x0D);
+    assert_eq_hex!(memory.read(0xA000), 0xAB);
+  }
+
   #[test]
   fn ram_enabled_register_blocks_writes() {
     let mut memory = MBC3::new(ROMSize::KB256, RAMSize::KB32);
@@ -384,8 +513,8 @@ mod tests {
     memory.write(0x4000, 0x0C); // Set RAM bank to RTC days high
     memory.write(0xA000, 0x01); // Write 512 days (non-halted, no carry)
     memory.write(0x0000, 0xB); // Disable RAM
-    // Tick a full second (1 tick = 1 microsecond)
-    for _ in (0..1_000_000usize) {
+    // Tick a full second (1 tick = 1 M-cycle)
+    for _ in (0..1_048_576usize) {
       memory.handle_tick(false);
     }
     memory.handle_tick(false);
@@ -412,5 +541,198 @@ mod tests {
     assert_eq!(memory.read(0xA000), 0); // Read days low
     memory.write(0x4000, 0x0C); // Set RAM bank to RTC days high
     assert_eq_hex!(memory.read(0xA000), 0x80); // Read days high (non-halted, carry enabled)
+
+    // The carry persists while the clock keeps counting
+    tick_seconds(&mut memory, 1);
+    latch(&mut memory);
+    memory.write(0x4000, 0x08); // Set RAM bank to RTC seconds
+    assert_eq!(memory.read(0xA000), 1); // Read seconds
+    memory.write(0x4000, 0x0C); // Set RAM bank to RTC days high
+    assert_eq_hex!(memory.read(0xA000), 0x80); // Read days high (non-halted, carry enabled)
+
+    // Halting freezes the clock, resuming continues from the frozen value
+    memory.write(0x0000, 0xA); // Enable RAM
+    memory.write(0xA000, 0xC0); // Halt, keeping the carry
+    tick_seconds(&mut memory, 2);
+    latch(&mut memory);
+    memory.write(0x4000, 0x08); // Set RAM bank to RTC seconds
+    assert_eq!(memory.read(0xA000), 1); // Read seconds
+    memory.write(0x4000, 0x0C); // Set RAM bank to RTC days high
+    assert_eq_hex!(memory.read(0xA000), 0xC0); // Read days high (halted, carry enabled)
+    memory.write(0xA000, 0x00); // Resume and clear the carry
+    tick_seconds(&mut memory, 1);
+    latch(&mut memory);
+    memory.write(0x4000, 0x08); // Set RAM bank to RTC seconds
+    assert_eq!(memory.read(0xA000), 2); // Read seconds
+    memory.write(0x4000, 0x0C); // Set RAM bank to RTC days high
+    assert_eq_hex!(memory.read(0xA000), 0x00); // Read days high (non-halted, no carry)
+  }
+
+  #[test]
+  fn rtc_counts_elapsed_time_at_double_speed() {
+    let mut memory = MBC3::new(ROMSize::KB256, RAMSize::KB32);
+    for _ in 0..2 * 1_048_576 {
+      memory.handle_tick(true);
+    }
+    latch(&mut memory);
+    memory.write(0x4000, 0x08); // Set RAM bank to RTC seconds
+    assert_eq!(memory.read(0xA000), 1); // Read seconds
+    assert_eq!(memory.rtc.nanoseconds, 1_000_000_000);
+    // The equivalent of ticking an hour at double speed
+    memory.elapse_half_cycles(3600 * 2 * 1_048_576 - 1);
+    memory.handle_tick(true);
+    latch(&mut memory);
+    assert_eq!(memory.rtc.nanoseconds, 3601 * 1_000_000_000);
+    memory.write(0x4000, 0x0A); // Set RAM bank to RTC hours
+    assert_eq!(memory.read(0xA000), 1); // Read hours
+  }
+
+  #[test]
+  fn rtc_overflows_after_512_days() {
+    let mut memory = MBC3::new(ROMSize::KB256, RAMSize::KB32);
+    memory.rtc.tick(RTC::MAX_DAYS_IN_NANOSECONDS + 3 * FormattedRTC::DAYS_NANOSECONDS);
+    latch(&mut memory);
+    memory.write(0x4000, 0x0B); // Set RAM bank to RTC days low
+    assert_eq!(memory.read(0xA000), 3); // Read days low
+    memory.write(0x4000, 0x0C); // Set RAM bank to RTC days high
+    assert_eq_hex!(memory.read(0xA000), 0x80); // Read days high (non-halted, carry enabled)
+  }
+
+  #[test]
+  fn rtc_registers_mask_unused_bits() {
+    let mut memory = MBC3::new(ROMSize::KB256, RAMSize::KB32);
+    memory.write(0x0000, 0xA); // Enable RAM
+    memory.write(0x4000, 0x0A); // Set RAM bank to RTC hours
+    memory.write(0xA000, 0xE5);
+    assert_eq_hex!(memory.read(0xA000), 0x05);
+    memory.write(0x4000, 0x0C); // Set RAM bank to RTC days high
+    memory.write(0xA000, 0xFF);
+    assert_eq_hex!(memory.read(0xA000), 0xC1);
+  }
+
+  fn write_rtc_register(memory: &mut MBC3, register: u8, value: u8) {
+    memory.write(0x4000, register);
+    memory.write(0xA000, value);
+  }
+
+  fn read_rtc_register(memory: &mut MBC3, register: u8) -> u8 {
+    memory.write(0x4000, register);
+    memory.read(0xA000)
+  }
+
+  #[test]
+  fn save_round_trip() {
+    let mut memory = MBC3::new(ROMSize::KB256, RAMSize::KB32);
+    memory.write(0x0000, 0xA); // Enable RAM
+    memory.write(0x4000, 0x03);
+    memory.write(0xA123, 0x42);
+    write_rtc_register(&mut memory, 0x08, 12);
+    write_rtc_register(&mut memory, 0x0A, 5);
+    write_rtc_register(&mut memory, 0x0C, 0x81);
+    latch(&mut memory);
+    tick_seconds(&mut memory, 2); // Only the current seconds change
+    let save = memory.export_save(1_700_000_000);
+    assert_eq!(save.len(), RAMSize::KB32.bytes() + 48);
+    assert_eq!(save[RAMSize::KB32.bytes()..RAMSize::KB32.bytes() + 20],
+               [14, 0, 0, 0, 0, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 0x81, 0, 0, 0]);
+
+    let mut imported = MBC3::new(ROMSize::KB256, RAMSize::KB32);
+    imported.import_save(&save, 1_700_000_000);
+    assert_eq!(imported.export_save(1_700_000_000), save);
+    imported.write(0x0000, 0xA); // Enable RAM
+    imported.write(0x4000, 0x03);
+    assert_eq_hex!(imported.read(0xA123), 0x42);
+    assert_eq!(read_rtc_register(&mut imported, 0x08), 12); // Latched seconds
+    latch(&mut imported);
+    assert_eq!(read_rtc_register(&mut imported, 0x08), 14);
+    assert_eq_hex!(read_rtc_register(&mut imported, 0x0C), 0x81);
+  }
+
+  #[test]
+  fn import_save_fast_forwards_the_clock() {
+    let mut memory = MBC3::new(ROMSize::KB256, RAMSize::KB32);
+    memory.write(0x0000, 0xA); // Enable RAM
+    write_rtc_register(&mut memory, 0x0B, 41);
+    let mut save = memory.export_save(1_700_000_000);
+    // The older footer with a 32-bit timestamp
+    save.truncate(save.len() - 4);
+
+    let mut imported = MBC3::new(ROMSize::KB256, RAMSize::KB32);
+    imported.import_save(&save, 1_700_000_000 + 86_400 + 5);
+    latch(&mut imported);
+    assert_eq!(read_rtc_register(&mut imported, 0x0B), 42);
+    assert_eq!(read_rtc_register(&mut imported, 0x08), 5);
+  }
+
+  #[test]
+  fn import_save_keeps_a_halted_clock() {
+    let mut memory = MBC3::new(ROMSize::KB256, RAMSize::KB32);
+    memory.write(0x0000, 0xA); // Enable RAM
+    write_rtc_register(&mut memory, 0x0C, 0x40);
+    let save = memory.export_save(1_700_000_000);
+
+    let mut imported = MBC3::new(ROMSize::KB256, RAMSize::KB32);
+    imported.import_save(&save, 1_700_000_000 + 86_400);
+    latch(&mut imported);
+    assert_eq!(read_rtc_register(&mut imported, 0x0B), 0);
+    assert_eq_hex!(read_rtc_register(&mut imported, 0x0C), 0x40);
+  }
+
+  fn tick_seconds(memory: &mut MBC3, seconds: usize) {
+    for _ in 0..seconds * 1_048_576 {
+      memory.handle_tick(false);
+    }
+  }
+
+  fn latch(memory: &mut MBC3) {
+    memory.write(0x6000, 0x00);
+    memory.write(0x6000, 0x01);
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn mbc30_has_8_ram_banks() {
+    let mut memory = MBC3::new_mbc30(ROMSize::MB4);
+    memory.write(0x0000, 0xA); // Enable RAM
+    memory.write(0x4000, 0x00);
+    memory.write(0xA123, 0x12);
+    memory.write(0x4000, 0x07);
+    memory.write(0xA123, 0x34);
+    write_rtc_register(&mut memory, 0x08, 56);
+    memory.write(0x4000, 0x00);
+    assert_eq_hex!(memory.read(0xA123), 0x12);
+    memory.write(0x4000, 0x07);
+    assert_eq_hex!(memory.read(0xA123), 0x34);
+    memory.write(0x4000, 0x08);
+    assert_eq!(memory.read(0xA000), 56);
+  }
+
+  #[test]
+  fn mbc30_uses_8_bit_rom_bank_numbers() {
+    let mut memory = MBC3::new_mbc30(ROMSize::MB4);
+    memory.load_byte(0x3FC123, 0x42);
+    memory.write(0x2000, 0xFF);
+    assert_eq_hex!(memory.read(0x4123), 0x42);
+    let mut memory = MBC3::new(ROMSize::MB4, RAMSize::KB32);
+    memory.load_byte(0x1FC123, 0x42);
+    memory.write(0x2000, 0xFF);
+    assert_eq_hex!(memory.read(0x4123), 0x42);
+  }
+
+  #[test]
+  fn mbc3_ram_bank_number_has_2_bits() {
+    let mut memory = MBC3::new(ROMSize::KB256, RAMSize::KB32);
+    memory.write(0x0000, 0xA); // Enable RAM
+    memory.write(0x4000, 0x01);
+    memory.write(0xA123, 0x12);
+    memory.write(0x4000, 0x05);
+    assert_eq_hex!(memory.read(0xA123), 0x12);
+  }
+
+  #[test]
+  fn bank_number_wraps_to_rom_size() {
+    let mut memory = MBC3::new(ROMSize::KB256, RAMSize::KB32);
+    memory.load_byte(0x8123, 0x42);
+    memory.write(0x2000, 0x42);
+    assert_eq_hex!(memory.read(0x4123), 0x42);
+  }
+}
@@ -1,22 +1,59 @@
-use crate::memory::mbc::Loadable;
+use serde::{Deserialize, Serialize};
+use crate::memory::mbc::{import_ram, mask_ram_address, mask_rom_bank, read_cartridge_block, EmulationMode, Loadable, MBC, RumbleMotor, SaveTracker, UnmappedAccess};
 use crate::memory::memory::{Memory, RAMSize, ROMSize};
 
-struct MBC5 {
+#[derive(Serialize, Deserialize)]
+pub struct MBC5 {
+  rumble: bool,
+  rumbling: bool,
+  #[serde(skip)]
+  motor: Option<Box<dyn RumbleMotor>>,
   ram_enabled: bool,
+  save_tracker: SaveTracker,
   ram_bank_address: usize,
   rom_bank_address: usize,
+  #[serde(skip)]
   rom: Vec<u8>,
+  #[serde(skip)]
+  unmapped: UnmappedAccess,
   ram: Vec<u8>,
 }
 
 impl MBC5 {
-  fn new(rom_size: ROMSize, ram_size: RAMSize) -> MBC5 {
+  pub fn new(rom_size: ROMSize, ram_size: RAMSize) -> MBC5 {
     MBC5 {
+      rumble: false,
+      rumbling: false,
+      motor: None,
       ram_enabled: false,
+      save_tracker: SaveTracker::new(),
       ram_bank_address: 0x00,
-      rom_bank_address: 0x00,
+      rom_bank_address: 0x01,
       ram: vec![0; ram_size.bytes()],
       rom: vec![0; rom_size.bytes()],
+      unmapped: UnmappedAccess::new(EmulationMode::Strict),
+    }
+  }
+
+  /// On rumble cartridges, bit 3 of the RAM bank number drives the motor instead of selecting a RAM bank.
+  pub fn new_rumble(rom_size: ROMSize, ram_size: RAMSize) -> MBC5 {
+    MBC5 {
+      rumble: true,
+      ..MBC5::new(rom_size, ram_size)
+    }
+  }
+
+  fn ram_address(&self, address: u16) -> Option<usize> {
+    mask_ram_address(&self.ram, self.ram_bank_address, address)
+  }
+
+  /// Only tells the motor about changes, since games write the RAM bank number far more often than they toggle it.
+  fn set_rumbling(&mut self, rumbling: bool) {
+    if rumbling != self.rumbling {
+      self.rumbling = rumbling;
+      if let Some(motor) = self.motor.as_mut() {
+        motor.set_rumble(rumbling);
+      }
     }
   }
 }
@@ -24,46 +61,97 @@ impl MBC5 {
 impl Memory for MBC5 {
   fn read(&self, address: u16) -> u8 {
     match address {
-      0x0000..=0x3FFF => {
-        self.rom[address as usize]
-      }
-      0x4000..=0x7FFF => {
-        let address_in_rom = ((address as usize) & 0x3FFF) | (self.rom_bank_address << 14);
-        self.rom[address_in_rom]
-      }
-      0xA000..=0xBFFF => {
-        let address_in_ram = ((address as usize) & 0x1FFF) | (self.ram_bank_address << 13);
-        self.ram[address_in_ram]
-      }
-      _ => panic!("Can't read from address {:#06x} on MBC5", address)
+      0x0000..=0x7FFF => self.rom[(self.rom_bank(address) << 14) | (address as usize & 0x3FFF)],
+      0xA000..=0xBFFF => self.ram_address(address).map_or(0xFF, |address_in_ram| self.ram[address_in_ram]),
+      _ => self.unmapped.read("MBC5", address)
     }
   }
 
+  fn read_block(&self, address: u16, dst: &mut [u8]) {
+    read_cartridge_block(self, &self.rom, address, dst);
+  }
+
   fn write(&mut self, address: u16, value: u8) {
     match address {
       0x0000..=0x1FFF => {
         self.ram_enabled = (value & 0x0F) == 0x0A;
+        self.save_tracker.record_ram_enable(self.ram_enabled);
       }
       0x2000..=0x2FFF => {
         self.rom_bank_address = (self.rom_bank_address & 0x100) | (value as usize);
       }
       0x3000..=0x3FFF => {
-        self.rom_bank_address = ((value as usize) << 8) | (self.rom_bank_address & 0xFF);
+        self.rom_bank_address = (((value & 0x01) as usize) << 8) | (self.rom_bank_address & 0xFF);
+      }
+      0x4000..=0x5FFF if self.rumble => {
+        self.ram_bank_address = (value & 0x07) as usize;
+        self.set_rumbling(value & 0x08 != 0);
       }
       0x4000..=0x5FFF => {
-        self.ram_bank_address = value as usize;
+        self.ram_bank_address = (value & 0x0F) as usize;
       }
+      0x6000..=0x7FFF => {}
       0xA000..=0xBFFF => {
-        if self.ram_enabled {
-          let address_in_ram = ((address as usize) & 0x1FFF) | (self.ram_bank_address << 13);
-          self.ram[address_in_ram] = value
+        if let Some(address_in_ram) = self.ram_address(address).filter(|_| self.ram_enabled) {
+          self.ram[address_in_ram] = value;
+          self.save_tracker.record_write();
         }
       }
-      _ => panic!("Can't write to address {:#06x} on MBC5", address)
+      _ => self.unmapped.write("MBC5", address)
     };
   }
 }
 
+impl MBC for MBC5 {
+  /// Unlike on the other MBCs, bank 0 can be mapped at 0x4000-0x7FFF.
+  fn rom_bank(&self, address: u16) -> usize {
+    if address < 0x4000 { 0 } else { mask_rom_bank(self.rom_bank_address, &self.rom) }
+  }
+
+  fn export_save(&self, _now: u64) -> Vec<u8> {
+    self.ram.clone()
+  }
+
+  fn import_save(&mut self, save: &[u8], _now: u64) {
+    import_ram(&mut self.ram, save);
+  }
+
+  fn connect_rumble(&mut self, motor: Box<dyn RumbleMotor>) {
+    self.motor = Some(motor);
+  }
+
+  fn take_save_dirty(&mut self) -> bool {
+    self.save_tracker.take_dirty()
+  }
+
+  fn set_mode(&mut self, mode: EmulationMode) {
+    self.unmapped = UnmappedAccess::new(mode);
+  }
+
+  fn save_state(&self) -> Vec<u8> {
+    bincode::serialize(self).unwrap()
+  }
+
+  /// The motor stays connected, and hears about the rumble state of the loaded state.
+  fn load_state(&mut self, state: &[u8]) -> bincode::Result<()> {
+    let loaded: MBC5 = bincode::deserialize(state)?;
+    let rumbling = loaded.rumbling;
+    let mut motor = self.motor.take();
+    if let Some(motor) = motor.as_mut() {
+      if rumbling != self.rumbling {
+        motor.set_rumble(rumbling);
+      }
+    }
+    *self = MBC5 {
+      motor,
+      rom: std::mem::take(&mut self.rom),
+      unmapped: std::mem::take(&mut self.unmapped),
+      ..loaded
+    };
+    Ok(())
+  }
+}
+
 impl Loadable for MBC5 {
   fn load_byte(&mut self, address: usize, value: u8) {
     self.rom[address] = value;
@@ -76,8 +164,55 @@ impl Loadable for MBC5 {
 
 #[cfg(test)]
 mod tests {
+  use std::cell::RefCell;
+  use std::rc::Rc;
   use super::*;
   use assert_hex::assert_eq_hex;
+  use test_case::test_case;
+
+  struct RecordingMotor {
+    states: Vec<bool>,
+  }
+
+  impl RumbleMotor for RecordingMotor {
+    fn set_rumble(&mut self, on: bool) {
+      self.states.push(on);
+    }
+  }
+
+  #[test]
+  fn rumble_motor_follows_bit_3_transitions() {
+    let mut memory = MBC5::new_rumble(ROMSize::KB256, RAMSize::KB32);
+    let motor = Rc::new(RefCell::new(RecordingMotor { states: vec![] }));
+    memory.connect_rumble(Box::new(Rc::clone(&motor)));
+    for value in [0x00, 0x08, 0x09, 0x0B, 0x03, 0x00, 0x08] {
+      memory.write(0x4000, value);
+    }
+    assert_eq!(motor.borrow().states, vec![true, false, true]);
+  }
+
+  #[test]
+  fn rumble_cartridge_banks_ram_with_bits_0_to_2() {
+    let mut memory = MBC5::new_rumble(ROMSize::KB256, RAMSize::KB64);
+    memory.write(0x0000, 0xA); // Enable RAM
+    memory.write(0x4000, 0x03);
+    memory.write(0xA123, 0x12);
+    memory.write(0x4000, 0x0B); // Bank 3 with the motor on
+    assert_eq_hex!(memory.read(0xA123), 0x12);
+    memory.write(0xA123, 0x34);
+    memory.write(0x4000, 0x03);
+    assert_eq_hex!(memory.read(0xA123), 0x34);
+  }
+
+  #[test]
+  fn ignores_writes_to_0x6000_to_0x7fff() {
+    let mut memory = MBC5::new(ROMSize::KB256, RAMSize::KB64);
+    memory.load_byte(0x8000, 0xAB);
+    memory.write(0x2000, 0x02);
+    memory.write(0x6000, 0x01);
+    memory.write(0x7FFF, 0x00);
+    assert_eq_hex!(memory.read(0x4000), 0xAB);
+  }
 
   #[test]
   fn read_write_ram() {
@@ -102,6 +237,20 @@ mod tests {
     assert_eq_hex!(memory.read(0xBFFF), 0x56);
   }
 
+  #[test]
+  fn save_round_trip() {
+    let mut memory = MBC5::new(ROMSize::KB256, RAMSize::KB64);
+    memory.write(0x0000, 0xA); // Enable RAM
+    memory.write(0x4000, 0x05);
+    memory.write(0xA123, 0x42);
+    let save = memory.export_save(0);
+    assert_eq!(save.len(), RAMSize::KB64.bytes());
+    let mut imported = MBC5::new(ROMSize::KB256, RAMSize::KB64);
+    imported.import_save(&save, 0);
+    imported.write(0x4000, 0x05);
+    assert_eq_hex!(imported.read(0xA123), 0x42);
+  }
+
   #[test]
   fn read_lower_rom() {
     let mut memory = MBC5::new(ROMSize::KB256, RAMSize::KB64);
@@ -142,4 +291,44 @@ mod tests {
     assert_eq_hex!(memory.read(0x5ABC), 0xBB);
     assert_eq_hex!(memory.read(0x7FFF), 0xCC);
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn bank_number_wraps_to_rom_size() {
+    let mut memory = MBC5::new(ROMSize::KB256, RAMSize::KB32);
+    memory.load_byte(0x8123, 0x42);
+    memory.write(0x2000, 0x42);
+    assert_eq_hex!(memory.read(0x4123), 0x42);
+  }
+
+  #[test_case(71, 71; "top bank")]
+  #[test_case(72, 0; "first bank past the end wraps around")]
+  #[test_case(0x7F, 55; "masked to 128 banks before wrapping")]
+  #[test_case(0x87, 7; "masked bits are dropped")]
+  fn bank_number_wraps_on_72_bank_rom(bank: u8, expected_bank: usize) {
+    let mut memory = MBC5::new(ROMSize::MB1_1, RAMSize::KB32);
+    memory.load_byte(0x11C123, 0x42);
+    memory.write(0x2000, bank);
+    assert_eq!(memory.rom_bank(0x4000), expected_bank);
+    assert_eq_hex!(memory.read(0x4123), if expected_bank == 71 { 0x42 } else { 0x00 });
+  }
+
+  #[test]
+  fn bank_number_has_9_bits() {
+    let mut memory = MBC5::new(ROMSize::MB8, RAMSize::KB32);
+    memory.load_byte(0x400123, 0x42);
+    memory.write(0x3000, 0x01);
+    memory.write(0x2000, 0x00);
+    assert_eq!(memory.rom_bank(0x4000), 0x100);
+    assert_eq_hex!(memory.read(0x4123), 0x42);
+  }
+
+  #[test]
+  fn bank_0_can_be_mapped_in_the_switchable_window() {
+    let mut memory = MBC5::new(ROMSize::KB256, RAMSize::KB32);
+    memory.load_byte(0x0123, 0x12);
+    memory.load_byte(0x4123, 0x34);
+    assert_eq_hex!(memory.read(0x4123), 0x34);
+    memory.write(0x2000, 0x00);
+    assert_eq_hex!(memory.read(0x4123), 0x12);
+  }
+}
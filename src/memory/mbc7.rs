@@ -0,0 +1,409 @@
+use serde::{Deserialize, Serialize};
+use crate::memory::mbc::{mask_rom_bank, read_cartridge_block, EmulationMode, Loadable, MBC, SaveTracker, UnmappedAccess};
+use crate::memory::memory::{Memory, ROMSize};
+use crate::util::bit_util::BitUtil;
+
+/// The 93LC56 EEPROM, organised as 128 16-bit words. Commands are shifted in on DI, one bit per rising edge of CLK while
+/// CS is high: a start bit, a 2-bit opcode and an 8-bit address, followed by 16 data bits for writes.
+#[derive(Serialize, Deserialize)]
+struct EEPROM {
+  #[serde(with = "crate::util::serde_util::array")]
+  words: [u16; 128],
+  write_enabled: bool,
+  chip_select: bool,
+  clock: bool,
+  data_in: bool,
+  data_out: bool,
+  command: u32,
+  command_bits: u8,
+  output: u16,
+  output_bits: u8,
+}
+
+impl EEPROM {
+  const COMMAND_BITS: u8 = 11;
+  const COMMAND_WITH_DATA_BITS: u8 = 27;
+
+  pub fn new() -> EEPROM {
+    EEPROM {
+      words: [0xFFFF; 128],
+      write_enabled: false,
+      chip_select: false,
+      clock: false,
+      data_in: false,
+      data_out: true,
+      command: 0,
+      command_bits: 0,
+      output: 0,
+      output_bits: 0,
+    }
+  }
+
+  pub fn read(&self) -> u8 {
+    (if self.chip_select { 0x80 } else { 0x00 }) |
+      (if self.clock { 0x40 } else { 0x00 }) |
+      (if self.data_in { 0x02 } else { 0x00 }) |
+      (if self.data_out { 0x01 } else { 0x00 })
+  }
+
+  /// Returns whether a command that changes the contents completed.
+  pub fn write(&mut self, value: u8) -> bool {
+    let rising_edge = !self.clock && value.get_bit(6);
+    self.chip_select = value.get_bit(7);
+    self.clock = value.get_bit(6);
+    self.data_in = value.get_bit(1);
+    if !self.chip_select {
+      // Deselecting the chip aborts the current command
+      self.command = 0;
+      self.command_bits = 0;
+      self.output_bits = 0;
+      self.data_out = true;
+      return false;
+    }
+    rising_edge && self.clock_in()
+  }
+
+  fn clock_in(&mut self) -> bool {
+    if self.output_bits > 0 {
+      self.data_out = self.output.get_bit(15);
+      self.output <<= 1;
+      self.output_bits -= 1;
+      return false;
+    }
+    if self.command_bits == 0 && !self.data_in {
+      // Waiting for the start bit
+      return false;
+    }
+    self.command = (self.command << 1) | (self.data_in as u32);
+    self.command_bits += 1;
+    if self.command_bits == EEPROM::COMMAND_BITS {
+      self.execute_command()
+    } else if self.command_bits == EEPROM::COMMAND_WITH_DATA_BITS {
+      self.execute_write((self.command >> 16) as u16, self.command as u16)
+    } else {
+      false
+    }
+  }
+
+  /// Runs a command without data and returns whether it erased anything. Writes wait for their 16 data bits.
+  fn execute_command(&mut self) -> bool {
+    let opcode = (self.command >> 8) & 0x03;
+    let address = (self.command & 0x7F) as usize;
+    let mut erased = false;
+    match (opcode, (self.command >> 6) & 0x03) {
+      // READ shifts out a dummy 0 bit, followed by the word
+      (0b10, _) => {
+        self.output = self.words[address];
+        self.output_bits = 16;
+        self.data_out = false;
+      }
+      (0b00, 0b11) => self.write_enabled = true,
+      (0b00, 0b00) => self.write_enabled = false,
+      (0b00, 0b10) => if self.write_enabled {
+        self.words = [0xFFFF; 128];
+        erased = true;
+      },
+      (0b11, _) => if self.write_enabled {
+        self.words[address] = 0xFFFF;
+        erased = true;
+      },
+      // WRITE and WRAL
+      _ => return false
+    }
+    self.command = 0;
+    self.command_bits = 0;
+    erased
+  }
+
+  /// Returns whether the write went through.
+  fn execute_write(&mut self, command: u16, data: u16) -> bool {
+    if self.write_enabled {
+      match (command >> 8) & 0x03 {
+        0b01 => self.words[(command & 0x7F) as usize] = data,
+        _ => self.words = [data; 128]
+      }
+    }
+    self.command = 0;
+    self.command_bits = 0;
+    self.write_enabled
+  }
+}
+
+/// The MBC7 in Kirby Tilt 'n' Tumble. Instead of RAM, it has an accelerometer and an EEPROM, mapped as registers at
+/// 0xA000-0xAFFF when both RAM enable registers are set.
+#[derive(Serialize, Deserialize)]
+pub struct MBC7 {
+  ram_enabled: bool,
+  registers_enabled: bool,
+  rom_bank_address: usize,
+  acceleration: (u16, u16),
+  latched_acceleration: (u16, u16),
+  latch_erased: bool,
+  eeprom: EEPROM,
+  save_tracker: SaveTracker,
+  #[serde(skip)]
+  rom: Vec<u8>,
+  #[serde(skip)]
+  unmapped: UnmappedAccess,
+}
+
+impl MBC7 {
+  const ACCELERATION_CENTER: f32 = 0x81D0 as f32;
+  const ACCELERATION_PER_G: f32 = 0x70 as f32;
+
+  pub fn new(rom_size: ROMSize) -> MBC7 {
+    MBC7 {
+      ram_enabled: false,
+      registers_enabled: false,
+      rom_bank_address: 0x01,
+      acceleration: (0x81D0, 0x81D0),
+      latched_acceleration: (0x8000, 0x8000),
+      latch_erased: false,
+      eeprom: EEPROM::new(),
+      save_tracker: SaveTracker::new(),
+      rom: vec![0; rom_size.bytes()],
+      unmapped: UnmappedAccess::new(EmulationMode::Strict),
+    }
+  }
+
+  fn to_acceleration_value(acceleration: f32) -> u16 {
+    (MBC7::ACCELERATION_CENTER + acceleration * MBC7::ACCELERATION_PER_G).round().clamp(0.0, u16::MAX as f32) as u16
+  }
+}
+
+impl Memory for MBC7 {
+  fn read(&self, address: u16) -> u8 {
+    match address {
+      0x0000..=0x7FFF => self.rom[(self.rom_bank(address) << 14) | (address as usize & 0x3FFF)],
+      0xA000..=0xAFFF if self.ram_enabled && self.registers_enabled => match (address >> 4) & 0x0F {
+        0x2 => self.latched_acceleration.0 as u8,
+        0x3 => (self.latched_acceleration.0 >> 8) as u8,
+        0x4 => self.latched_acceleration.1 as u8,
+        0x5 => (self.latched_acceleration.1 >> 8) as u8,
+        0x6 => 0x00,
+        0x8 => self.eeprom.read(),
+        _ => 0xFF
+      },
+      0xA000..=0xBFFF => 0xFF,
+      _ => self.unmapped.read("MBC7", address)
+    }
+  }
+
+  fn read_block(&self, address: u16, dst: &mut [u8]) {
+    read_cartridge_block(self, &self.rom, address, dst);
+  }
+
+  fn write(&mut self, address: u16, value: u8) {
+    match address {
+      0x0000..=0x1FFF => {
+        self.ram_enabled = value == 0x0A;
+        self.save_tracker.record_ram_enable(self.ram_enabled);
+      }
+      0x2000..=0x3FFF => self.rom_bank_address = (value & 0x7F) as usize,
+      0x4000..=0x5FFF => self.registers_enabled = value == 0x40,
+      0x6000..=0x7FFF => {}
+      0xA000..=0xAFFF if self.ram_enabled && self.registers_enabled => match ((address >> 4) & 0x0F, value) {
+        (0x0, 0x55) => {
+          self.latched_acceleration = (0x8000, 0x8000);
+          self.latch_erased = true;
+        }
+        (0x1, 0xAA) if self.latch_erased => {
+          self.latched_acceleration = self.acceleration;
+          self.latch_erased = false;
+        }
+        (0x8, _) => {
+          let modified = self.eeprom.write(value);
+          if modified {
+            self.save_tracker.record_write();
+          }
+        }
+        _ => {}
+      },
+      0xA000..=0xBFFF => {}
+      _ => self.unmapped.write("MBC7", address)
+    }
+  }
+}
+
+impl MBC for MBC7 {
+  fn rom_bank(&self, address: u16) -> usize {
+    if address < 0x4000 { 0 } else { mask_rom_bank(self.rom_bank_address, &self.rom) }
+  }
+
+  /// The EEPROM, with every word stored little endian.
+  fn export_save(&self, _now: u64) -> Vec<u8> {
+    self.eeprom.words.iter().flat_map(|word| word.to_le_bytes()).collect()
+  }
+
+  fn import_save(&mut self, save: &[u8], _now: u64) {
+    for (word, bytes) in self.eeprom.words.iter_mut().zip(save.chunks_exact(2)) {
+      *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+    }
+  }
+
+  fn set_acceleration(&mut self, x: f32, y: f32) {
+    self.acceleration = (MBC7::to_acceleration_value(x), MBC7::to_acceleration_value(y));
+  }
+
+  fn take_save_dirty(&mut self) -> bool {
+    self.save_tracker.take_dirty()
+  }
+
+  fn set_mode(&mut self, mode: EmulationMode) {
+    self.unmapped = UnmappedAccess::new(mode);
+  }
+
+  fn save_state(&self) -> Vec<u8> {
+    bincode::serialize(self).unwrap()
+  }
+
+  fn load_state(&mut self, state: &[u8]) -> bincode::Result<()> {
+    let loaded: MBC7 = bincode::deserialize(state)?;
+    *self = MBC7 {
+      rom: std::mem::take(&mut self.rom),
+      unmapped: std::mem::take(&mut self.unmapped),
+      ..loaded
+    };
+    Ok(())
+  }
+}
+
+impl Loadable for MBC7 {
+  fn load_byte(&mut self, address: usize, value: u8) {
+    self.rom[address] = value;
+  }
+
+  fn load_bytes(&mut self, address: usize, values: &[u8]) {
+    self.rom.as_mut_slice()[address..(address + values.len())].copy_from_slice(values);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use assert_hex::assert_eq_hex;
+
+  fn enabled_mbc7() -> MBC7 {
+    let mut memory = MBC7::new(ROMSize::KB256);
+    memory.write(0x0000, 0x0A);
+    memory.write(0x4000, 0x40);
+    memory
+  }
+
+  fn read_acceleration(memory: &MBC7) -> (u16, u16) {
+    (u16::from_le_bytes([memory.read(0xA020), memory.read(0xA030)]),
+     u16::from_le_bytes([memory.read(0xA040), memory.read(0xA050)]))
+  }
+
+  /// Clocks in the bits with CS high and returns DO after every rising edge.
+  fn clock_bits(memory: &mut MBC7, bits: &[u8]) -> Vec<u8> {
+    bits.iter().map(|bit| {
+      memory.write(0xA080, 0x80 | (bit << 1));
+      memory.write(0xA080, 0xC0 | (bit << 1));
+      memory.read(0xA080) & 0x01
+    }).collect()
+  }
+
+  fn bits(value: u32, length: usize) -> Vec<u8> {
+    (0..length).rev().map(|bit| ((value >> bit) & 1) as u8).collect()
+  }
+
+  /// A start bit, followed by the opcode and the address.
+  fn command(opcode: u32, address: u32) -> u32 {
+    (0b100 | opcode) << 8 | address
+  }
+
+  fn eeprom_command(memory: &mut MBC7, command: u32, length: usize) -> Vec<u8> {
+    memory.write(0xA080, 0x00);
+    clock_bits(memory, &bits(command, length))
+  }
+
+  #[test]
+  fn registers_need_both_enables() {
+    let mut memory = MBC7::new(ROMSize::KB256);
+    memory.write(0x0000, 0x0A);
+    assert_eq_hex!(memory.read(0xA060), 0xFF);
+    memory.write(0x4000, 0x40);
+    assert_eq_hex!(memory.read(0xA060), 0x00);
+    assert_eq_hex!(memory.read(0xB060), 0xFF);
+  }
+
+  #[test]
+  fn acceleration_is_latched_after_erase() {
+    let mut memory = enabled_mbc7();
+    memory.set_acceleration(1.0, -0.5);
+    assert_eq!(read_acceleration(&memory), (0x8000, 0x8000));
+    memory.write(0xA010, 0xAA);
+    assert_eq!(read_acceleration(&memory), (0x8000, 0x8000));
+    memory.write(0xA000, 0x55);
+    memory.write(0xA010, 0xAA);
+    assert_eq!(read_acceleration(&memory), (0x8240, 0x8198));
+    // Latching again requires another erase
+    memory.set_acceleration(0.0, 0.0);
+    memory.write(0xA010, 0xAA);
+    assert_eq!(read_acceleration(&memory), (0x8240, 0x8198));
+    memory.write(0xA000, 0x55);
+    assert_eq!(read_acceleration(&memory), (0x8000, 0x8000));
+    memory.write(0xA010, 0xAA);
+    assert_eq!(read_acceleration(&memory), (0x81D0, 0x81D0));
+  }
+
+  #[test]
+  fn eeprom_read_after_write() {
+    let mut memory = enabled_mbc7();
+    // WRITE is ignored until EWEN
+    eeprom_command(&mut memory, command(0b01, 0x05) << 16 | 0x1234, 27);
+    let output = eeprom_command(&mut memory, command(0b10, 0x05) << 16, 27);
+    assert_eq!(output[11..], bits(0xFFFF, 16));
+    eeprom_command(&mut memory, command(0b00, 0xC0), 11);
+    eeprom_command(&mut memory, command(0b01, 0x05) << 16 | 0x1234, 27);
+    let output = eeprom_command(&mut memory, command(0b10, 0x05) << 16, 27);
+    assert_eq!(output[10], 0);
+    assert_eq!(output[11..], bits(0x1234, 16));
+    assert_eq!(&memory.export_save(0)[10..12], &[0x34, 0x12]);
+  }
+
+  #[test]
+  fn eeprom_erase() {
+    let mut memory = enabled_mbc7();
+    memory.import_save(&[0x00; 256], 0);
+    eeprom_command(&mut memory, command(0b00, 0xC0), 11);
+    eeprom_command(&mut memory, command(0b11, 0x03), 11);
+    assert_eq!(&memory.export_save(0)[4..8], &[0x00, 0x00, 0xFF, 0xFF]);
+    eeprom_command(&mut memory, command(0b00, 0x80), 11);
+    assert_eq!(memory.export_save(0), vec![0xFF; 256]);
+  }
+
+  #[test]
+  fn save_is_dirty_after_eeprom_writes_when_ram_is_disabled() {
+    let mut memory = enabled_mbc7();
+    eeprom_command(&mut memory, command(0b10, 0x05) << 16, 27);
+    memory.write(0x0000, 0x00);
+    assert!(!memory.take_save_dirty());
+    for modifying_command in [command(0b01, 0x05) << 16 | 0x1234, command(0b00, 0x40) << 16 | 0x5678] {
+      memory.write(0x0000, 0x0A);
+      eeprom_command(&mut memory, command(0b00, 0xC0), 11);
+      eeprom_command(&mut memory, modifying_command, 27);
+      assert!(!memory.take_save_dirty());
+      memory.write(0x0000, 0x00);
+      assert!(memory.take_save_dirty());
+    }
+    for erasing_command in [command(0b11, 0x05), command(0b00, 0x80)] {
+      memory.write(0x0000, 0x0A);
+      eeprom_command(&mut memory, erasing_command, 11);
+      memory.write(0x0000, 0x00);
+      assert!(memory.take_save_dirty());
+    }
+  }
+
+  #[test]
+  fn deselecting_eeprom_aborts_command() {
+    let mut memory = enabled_mbc7();
+    eeprom_command(&mut memory, command(0b00, 0xC0), 11);
+    eeprom_command(&mut memory, command(0b01, 0x05) << 4 | 0x1, 15);
+    eeprom_command(&mut memory, command(0b01, 0x05) << 16 | 0x1234, 27);
+    memory.write(0xA080, 0x00);
+    assert_eq!(&memory.export_save(0)[10..12], &[0x34, 0x12]);
+    assert_eq_hex!(memory.read(0xA080), 0x01);
+  }
+}
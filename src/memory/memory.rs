@@ -1,12 +1,28 @@
 use std::cell::RefCell;
 use std::rc::Rc;
+use serde::{Deserialize, Serialize};
 use crate::MainMemory;
 
 pub trait Memory {
   fn read(&self, address: u16) -> u8;
   fn write(&mut self, address: u16, value: u8);
+
+  /// Reads consecutive bytes starting at the address. Memory backed by an array overrides this to copy them at once.
+  fn read_block(&self, address: u16, dst: &mut [u8]) {
+    read_bytes(self, address, dst);
+  }
+
+  fn write_block(&mut self, address: u16, src: &[u8]) {
+    src.iter().enumerate().for_each(|(offset, byte)| self.write(address.wrapping_add(offset as u16), *byte));
+  }
 }
 
+/// Reads a block a byte at a time, for overrides of read_block that can only copy some blocks at once.
+pub fn read_bytes<M: Memory + ?Sized>(memory: &M, address: u16, dst: &mut [u8]) {
+  dst.iter_mut().enumerate().for_each(|(offset, byte)| *byte = memory.read(address.wrapping_add(offset as u16)));
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum ROMSize {
   KB32,
   KB64,
@@ -17,9 +33,34 @@ pub enum ROMSize {
   MB2,
   MB4,
   MB8,
+  /// 72 banks
+  MB1_1,
+  /// 80 banks
+  MB1_2,
+  /// 96 banks
+  MB1_5,
 }
 
 impl ROMSize {
+  /// The size for the byte at 0x0148 in the cartridge header, if it's valid.
+  pub fn from_byte(byte: u8) -> Option<ROMSize> {
+    match byte {
+      0x00 => Some(ROMSize::KB32),
+      0x01 => Some(ROMSize::KB64),
+      0x02 => Some(ROMSize::KB128),
+      0x03 => Some(ROMSize::KB256),
+      0x04 => Some(ROMSize::KB512),
+      0x05 => Some(ROMSize::MB1),
+      0x06 => Some(ROMSize::MB2),
+      0x07 => Some(ROMSize::MB4),
+      0x08 => Some(ROMSize::MB8),
+      0x52 => Some(ROMSize::MB1_1),
+      0x53 => Some(ROMSize::MB1_2),
+      0x54 => Some(ROMSize::MB1_5),
+      _ => None
+    }
+  }
+
   pub fn bytes(&self) -> usize {
     match self {
       ROMSize::KB32 => 0x8000,
@@ -31,10 +72,14 @@ impl ROMSize {
       ROMSize::MB2 => 0x200000,
       ROMSize::MB4 => 0x400000,
       ROMSize::MB8 => 0x800000,
+      ROMSize::MB1_1 => 0x120000,
+      ROMSize::MB1_2 => 0x140000,
+      ROMSize::MB1_5 => 0x180000,
     }
   }
 }
 
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum RAMSize {
   NotAvailable,
   KB8,
@@ -44,10 +89,22 @@ pub enum RAMSize {
 }
 
 impl RAMSize {
+  /// The size for the byte at 0x0149 in the cartridge header, if it's valid.
+  pub fn from_byte(byte: u8) -> Option<RAMSize> {
+    match byte {
+      0x00 | 0x01 => Some(RAMSize::NotAvailable),
+      0x02 => Some(RAMSize::KB8),
+      0x03 => Some(RAMSize::KB32),
+      0x04 => Some(RAMSize::KB128),
+      0x05 => Some(RAMSize::KB64),
+      _ => None
+    }
+  }
+
   pub fn bytes(&self) -> usize {
     match self {
       RAMSize::NotAvailable => 0,
-      RAMSize::KB8 => 0x8000,
+      RAMSize::KB8 => 0x2000,
       RAMSize::KB32 => 0x8000,
       RAMSize::KB64 => 0x10000,
       RAMSize::KB128 => 0x20000,
@@ -55,7 +112,7 @@ impl RAMSize {
   }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum CGBMode {
   Monochrome,
   Color,
@@ -74,10 +131,28 @@ impl CGBMode {
   }
 }
 
+/// The console being emulated, which determines the register values the boot ROM leaves behind.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum HardwareModel {
+  DMG,
+  CGB,
+}
+
+impl HardwareModel {
+  /// Color cartridges run on a CGB, monochrome ones on a DMG.
+  pub fn from_cgb_mode(cgb_mode: CGBMode) -> HardwareModel {
+    match cgb_mode {
+      CGBMode::Monochrome => HardwareModel::DMG,
+      CGBMode::Color | CGBMode::PGB => HardwareModel::CGB,
+    }
+  }
+}
+
 #[cfg(test)]
 pub mod test {
   use crate::memory::memory::Memory;
 
+  #[derive(Clone)]
   pub struct MockMemory {
     bytes: Vec<u8>,
   }
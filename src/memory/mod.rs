@@ -3,13 +3,21 @@ pub mod main_memory;
 pub mod memory;
 pub mod linear_memory;
 pub mod bank_memory;
+pub mod boot_rom;
+pub mod cartridge_header;
 pub mod mbc;
+pub mod mbc0;
 pub mod mbc1;
 pub mod mbc2;
 pub mod mbc3;
 pub mod mbc5;
+pub mod mbc7;
+pub mod huc1;
+pub mod huc3;
 pub mod vram;
 pub mod wram;
 pub mod stack;
 pub mod cram;
 pub mod oam;
+pub mod rom_info;
+pub mod rom_loader;
@@ -1,5 +1,6 @@
 use std::cell::RefCell;
 use std::rc::Rc;
+use serde::{Deserialize, Serialize};
 use crate::memory::memory::Memory;
 use crate::util::bit_util::BitUtil;
 
@@ -26,6 +27,11 @@ impl ObjectAttributes {
   pub fn palette_index(&self) -> u8 {
     self.0 & 0x7
   }
+
+  /// Selects OBP0 or OBP1 in compatibility mode.
+  pub fn dmg_palette_index(&self) -> u8 {
+    self.0.get_bit(4) as u8
+  }
 }
 
 #[derive(Copy, Clone)]
@@ -45,6 +51,30 @@ impl OAMObject {
       attribute: 0,
     }
   }
+
+  pub fn lcd_y(&self) -> u8 {
+    self.lcd_y
+  }
+
+  pub fn lcd_x(&self) -> u8 {
+    self.lcd_x
+  }
+
+  pub fn tile_index(&self) -> u8 {
+    self.tile_index
+  }
+
+  pub fn attributes(&self) -> ObjectAttributes {
+    ObjectAttributes(self.attribute)
+  }
+}
+
+/// How a 16-bit increment or decrement of an address in 0xFE00-0xFEFF corrupts OAM during the OAM scan.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum OAMCorruption {
+  Write,
+  /// The increment or decrement happened while reading from the address, like in POP.
+  Read,
 }
 
 pub trait OAM {
@@ -52,7 +82,9 @@ pub trait OAM {
   fn get_object(&self, object_index: u8) -> OAMObject;
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct OAMImpl {
+  #[serde(with = "crate::util::serde_util::array")]
   bytes: [u8; 160],
 }
 
@@ -64,6 +96,28 @@ impl OAMImpl {
       bytes: [0; 160]
     }
   }
+
+  /// Corrupts one of the 20 rows of 8 bytes the PPU reads while scanning. The first word of the row is mixed with words
+  /// from the row before it, and the other words are copied from there. The first row is never corrupted.
+  pub fn corrupt(&mut self, row: usize, corruption: OAMCorruption) {
+    if row == 0 {
+      return;
+    }
+    let a = self.word(row, 0);
+    let b = self.word(row - 1, 0);
+    let c = self.word(row - 1, 2);
+    let first_word = match corruption {
+      OAMCorruption::Write => ((a ^ c) & (b ^ c)) ^ c,
+      OAMCorruption::Read => b | (a & c),
+    };
+    self.bytes[8 * row..8 * row + 2].copy_from_slice(&first_word.to_le_bytes());
+    self.bytes.copy_within(8 * row - 6..8 * row, 8 * row + 2);
+  }
+
+  fn word(&self, row: usize, index: usize) -> u16 {
+    let offset = 8 * row + 2 * index;
+    u16::from_le_bytes([self.bytes[offset], self.bytes[offset + 1]])
+  }
 }
 
 impl OAM for OAMImpl {
@@ -92,4 +146,38 @@ impl Memory for OAMImpl {
   fn write(&mut self, address: u16, value: u8) {
     self.bytes[address as usize - OAMImpl::START_ADDRESS] = value;
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use test_case::test_case;
+  use super::*;
+
+  fn row(oam: &OAMImpl, row: u16) -> Vec<u8> {
+    (0..8).map(|offset| oam.read(0xFE00 + 8 * row + offset)).collect()
+  }
+
+  // The first word of row 1 is 0x1234, the first and third word of row 0 are given
+  #[test_case(OAMCorruption::Write, 0xFFFF, 0x0000, [0x34, 0x12]; "write keeps bits where the previous words differ")]
+  #[test_case(OAMCorruption::Read, 0xFFFF, 0x0000, [0xFF, 0xFF]; "read ors in the previous first word")]
+  #[test_case(OAMCorruption::Write, 0xF0F0, 0x00FF, [0xF4, 0x10]; "write with mixed bits")]
+  #[test_case(OAMCorruption::Read, 0xF0F0, 0x00FF, [0xF4, 0xF0]; "read with mixed bits")]
+  fn corruption_mixes_first_word_and_copies_the_rest(corruption: OAMCorruption, b: u16, c: u16, first_word: [u8; 2]) {
+    let mut oam = OAMImpl::new();
+    let [b_low, b_high] = b.to_le_bytes();
+    let [c_low, c_high] = c.to_le_bytes();
+    [b_low, b_high, 0x33, 0x44, c_low, c_high, 0x77, 0x88, 0x34, 0x12, 0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF].iter()
+      .enumerate()
+      .for_each(|(offset, value)| oam.write(0xFE00 + offset as u16, *value));
+    oam.corrupt(1, corruption);
+    assert_eq!(row(&oam, 1), vec![first_word[0], first_word[1], 0x33, 0x44, c_low, c_high, 0x77, 0x88]);
+  }
+
+  #[test]
+  fn first_row_is_never_corrupted() {
+    let mut oam = OAMImpl::new();
+    oam.write(0xFE00, 0x12);
+    oam.corrupt(0, OAMCorruption::Write);
+    assert_eq!(row(&oam, 0), vec![0x12, 0, 0, 0, 0, 0, 0, 0]);
+  }
+}
@@ -0,0 +1,182 @@
+use wasm_bindgen::prelude::*;
+use crate::memory::cartridge_header::{header_checksum, RomError};
+use crate::memory::memory::{RAMSize, ROMSize};
+
+/// What the cartridge header says about a game, for showing it before it's started. Unlike CartridgeHeader, a wrong
+/// header checksum is reported rather than refused.
+#[wasm_bindgen]
+#[derive(Clone, PartialEq, Debug)]
+pub struct RomInfo {
+  title: String,
+  manufacturer_code: String,
+  licensee_code: String,
+  /// 0x80 if the game also runs on the DMG, 0xC0 if it needs a CGB.
+  pub cgb_flag: u8,
+  pub sgb_supported: bool,
+  pub cartridge_type: u8,
+  pub rom_size: usize,
+  pub ram_size: usize,
+  /// 0x00 for Japan, 0x01 for everywhere else.
+  pub destination_code: u8,
+  pub header_checksum_valid: bool,
+}
+
+#[wasm_bindgen]
+impl RomInfo {
+  #[wasm_bindgen(getter)]
+  pub fn title(&self) -> String {
+    self.title.clone()
+  }
+
+  /// Empty for games from before the CGB.
+  #[wasm_bindgen(getter)]
+  pub fn manufacturer_code(&self) -> String {
+    self.manufacturer_code.clone()
+  }
+
+  /// The two character new licensee code, or the old code in hex for older games.
+  #[wasm_bindgen(getter)]
+  pub fn licensee_code(&self) -> String {
+    self.licensee_code.clone()
+  }
+
+  #[wasm_bindgen(getter)]
+  pub fn cartridge_type_name(&self) -> String {
+    cartridge_type_name(self.cartridge_type).to_string()
+  }
+}
+
+impl RomInfo {
+  pub fn parse(rom: &[u8]) -> Result<RomInfo, RomError> {
+    if rom.len() < 0x0150 {
+      return Err(RomError::TooShort(rom.len()));
+    }
+    let rom_size = ROMSize::from_byte(rom[0x0148]).ok_or(RomError::InvalidROMSize(rom[0x0148]))?;
+    let ram_size = RAMSize::from_byte(rom[0x0149]).ok_or(RomError::InvalidRAMSize(rom[0x0149]))?;
+    let cgb_flag = rom[0x0143];
+    // CGB games shortened the title to 15 bytes, and later ones to 11, followed by a 4 character manufacturer code
+    let manufacturer_code = &rom[0x013F..0x0143];
+    let has_manufacturer_code = cgb_flag & 0x80 != 0 && manufacturer_code.iter().all(|byte| byte.is_ascii_uppercase() || byte.is_ascii_digit());
+    let title = match (cgb_flag & 0x80 != 0, has_manufacturer_code) {
+      (_, true) => &rom[0x0134..0x013F],
+      (true, false) => &rom[0x0134..0x0143],
+      (false, false) => &rom[0x0134..0x0144],
+    };
+    let licensee_code = match rom[0x014B] {
+      0x33 => String::from_utf8_lossy(&rom[0x0144..0x0146]).into_owned(),
+      old_code => format!("{:02X}", old_code),
+    };
+    Ok(RomInfo {
+      title: header_text(title),
+      manufacturer_code: if has_manufacturer_code { header_text(manufacturer_code) } else { String::new() },
+      licensee_code,
+      cgb_flag,
+      sgb_supported: rom[0x0146] == 0x03,
+      cartridge_type: rom[0x0147],
+      rom_size: rom_size.bytes(),
+      ram_size: ram_size.bytes(),
+      destination_code: rom[0x014A],
+      header_checksum_valid: rom[0x014D] == header_checksum(rom),
+    })
+  }
+}
+
+/// Header text is ASCII, padded with zeros or spaces.
+fn header_text(bytes: &[u8]) -> String {
+  let end = bytes.iter().position(|byte| *byte == 0).unwrap_or(bytes.len());
+  String::from_utf8_lossy(&bytes[..end]).trim_end().to_string()
+}
+
+pub fn cartridge_type_name(cartridge_type: u8) -> &'static str {
+  match cartridge_type {
+    0x00 => "ROM ONLY",
+    0x01 => "MBC1",
+    0x02 => "MBC1+RAM",
+    0x03 => "MBC1+RAM+BATTERY",
+    0x05 => "MBC2",
+    0x06 => "MBC2+BATTERY",
+    0x08 => "ROM+RAM",
+    0x09 => "ROM+RAM+BATTERY",
+    0x0B => "MMM01",
+    0x0C => "MMM01+RAM",
+    0x0D => "MMM01+RAM+BATTERY",
+    0x0F => "MBC3+TIMER+BATTERY",
+    0x10 => "MBC3+TIMER+RAM+BATTERY",
+    0x11 => "MBC3",
+    0x12 => "MBC3+RAM",
+    0x13 => "MBC3+RAM+BATTERY",
+    0x19 => "MBC5",
+    0x1A => "MBC5+RAM",
+    0x1B => "MBC5+RAM+BATTERY",
+    0x1C => "MBC5+RUMBLE",
+    0x1D => "MBC5+RUMBLE+RAM",
+    0x1E => "MBC5+RUMBLE+RAM+BATTERY",
+    0x20 => "MBC6",
+    0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+    0xFC => "POCKET CAMERA",
+    0xFD => "BANDAI TAMA5",
+    0xFE => "HuC3",
+    0xFF => "HuC1+RAM+BATTERY",
+    _ => "Unknown",
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use test_case::test_case;
+  use super::*;
+
+  fn create_rom(title: &[u8], cgb_flag: u8) -> Vec<u8> {
+    let mut rom = vec![0u8; 0x8000];
+    rom[0x0143] = cgb_flag;
+    // A 16 byte DMG title runs over the CGB flag
+    rom[0x0134..0x0134 + title.len()].copy_from_slice(title);
+    rom[0x014D] = header_checksum(&rom);
+    rom
+  }
+
+  #[test_case(b"TETRIS", 0x00, "TETRIS", ""; "dmg title")]
+  #[test_case(b"SIXTEEN CHARS ..", 0x00, "SIXTEEN CHARS ..", ""; "dmg title uses all 16 bytes")]
+  #[test_case(b"POKEMON YELLOW", 0x80, "POKEMON YELLOW", ""; "cgb title")]
+  #[test_case(b"POKEMON_SLVAAXE", 0x80, "POKEMON_SLV", "AAXE"; "cgb title with manufacturer code")]
+  #[test_case(b"PADDED     ", 0xC0, "PADDED", ""; "trailing spaces are trimmed")]
+  fn parses_title_and_manufacturer_code(title: &[u8], cgb_flag: u8, expected_title: &str, expected_code: &str) {
+    let info = RomInfo::parse(&create_rom(title, cgb_flag)).unwrap();
+    assert_eq!(info.title(), expected_title);
+    assert_eq!(info.manufacturer_code(), expected_code);
+  }
+
+  #[test]
+  fn parses_cartridge_details() {
+    let mut rom = vec![0u8; 0x10000];
+    rom[0x0144..0x0146].copy_from_slice(b"01");
+    rom[0x0146] = 0x03;
+    rom[0x0147] = 0x1B;
+    rom[0x0148] = 0x01;
+    rom[0x0149] = 0x03;
+    rom[0x014A] = 0x01;
+    rom[0x014B] = 0x33;
+    rom[0x014D] = header_checksum(&rom);
+    let info = RomInfo::parse(&rom).unwrap();
+    assert_eq!(info.licensee_code(), "01");
+    assert!(info.sgb_supported);
+    assert_eq!(info.cartridge_type_name(), "MBC5+RAM+BATTERY");
+    assert_eq!((info.rom_size, info.ram_size), (0x10000, 0x8000));
+    assert_eq!(info.destination_code, 0x01);
+    assert!(info.header_checksum_valid);
+  }
+
+  #[test]
+  fn reports_old_licensee_code_and_wrong_checksum() {
+    let mut rom = create_rom(b"TETRIS", 0x00);
+    rom[0x014B] = 0x01;
+    let info = RomInfo::parse(&rom).unwrap();
+    assert_eq!(info.licensee_code(), "01");
+    assert!(!info.header_checksum_valid);
+  }
+
+  #[test]
+  fn rejects_truncated_image() {
+    assert_eq!(RomInfo::parse(&[0u8; 0x100]).err(), Some(RomError::TooShort(0x100)));
+  }
+}
@@ -0,0 +1,92 @@
+use std::borrow::Cow;
+use crate::memory::cartridge_header::RomError;
+
+const ZIP_SIGNATURE: &[u8] = b"PK\x03\x04";
+
+/// ROMs are often distributed zipped. A zip archive is replaced by its first .gb or .gbc file, anything else is
+/// passed through as is. Without the zip feature, archives are passed through too, and refused by the header check.
+pub fn unpack_rom(bytes: &[u8]) -> Result<Cow<'_, [u8]>, RomError> {
+  if !bytes.starts_with(ZIP_SIGNATURE) {
+    return Ok(Cow::Borrowed(bytes));
+  }
+  #[cfg(feature = "zip")]
+  return extract_rom(bytes).map(Cow::Owned);
+  #[cfg(not(feature = "zip"))]
+  Ok(Cow::Borrowed(bytes))
+}
+
+#[cfg(feature = "zip")]
+fn extract_rom(bytes: &[u8]) -> Result<Vec<u8>, RomError> {
+  use std::io::{Cursor, Read};
+  use crate::memory::memory::ROMSize;
+  let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).map_err(|_| RomError::DamagedArchive)?;
+  for index in 0..archive.len() {
+    let file = archive.by_index(index).map_err(|_| RomError::DamagedArchive)?;
+    let name = file.name().to_lowercase();
+    if name.ends_with(".gb") || name.ends_with(".gbc") {
+      // The archive can claim any size, so the read stops one byte past the largest cartridge
+      let limit = ROMSize::MB8.bytes() as u64;
+      if file.size() > limit {
+        return Err(RomError::ROMTooLarge);
+      }
+      let mut rom = vec![];
+      file.take(limit + 1).read_to_end(&mut rom).map_err(|_| RomError::DamagedArchive)?;
+      if rom.len() as u64 > limit {
+        return Err(RomError::ROMTooLarge);
+      }
+      return Ok(rom);
+    }
+  }
+  Err(RomError::NoROMInArchive)
+}
+
+#[cfg(all(test, feature = "zip"))]
+pub mod tests {
+  use std::io::{Cursor, Write};
+  use zip::write::FileOptions;
+  use zip::{CompressionMethod, ZipWriter};
+  use crate::memory::memory::ROMSize;
+  use super::*;
+
+  pub fn create_zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut writer = ZipWriter::new(Cursor::new(vec![]));
+    for (name, contents) in files {
+      writer.start_file(*name, FileOptions::default().compression_method(CompressionMethod::Deflated)).unwrap();
+      writer.write_all(contents).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+  }
+
+  #[test]
+  fn passes_other_files_through() {
+    let rom = [0x00, 0xC3, 0x50, 0x01];
+    assert_eq!(unpack_rom(&rom).unwrap(), Cow::Borrowed(&rom[..]));
+  }
+
+  #[test]
+  fn extracts_first_rom_in_archive() {
+    let zip = create_zip(&[("readme.txt", b"Hello"), ("Game.GBC", &[0x12; 0x100]), ("other.gb", &[0x34; 0x100])]);
+    assert_eq!(unpack_rom(&zip).unwrap().into_owned(), vec![0x12; 0x100]);
+  }
+
+  #[test]
+  fn rejects_archive_without_rom() {
+    let zip = create_zip(&[("readme.txt", b"Hello")]);
+    assert_eq!(unpack_rom(&zip).err(), Some(RomError::NoROMInArchive));
+  }
+
+  #[test]
+  fn rejects_rom_larger_than_largest_cartridge() {
+    let zip = create_zip(&[("game.gb", &vec![0x00; ROMSize::MB8.bytes() + 1])]);
+    assert_eq!(unpack_rom(&zip).err(), Some(RomError::ROMTooLarge));
+    let zip = create_zip(&[("game.gb", &vec![0x00; ROMSize::MB8.bytes()])]);
+    assert_eq!(unpack_rom(&zip).unwrap().len(), ROMSize::MB8.bytes());
+  }
+
+  #[test]
+  fn rejects_damaged_archive() {
+    let mut zip = create_zip(&[("game.gb", &[0x12; 0x100])]);
+    zip.truncate(0x40);
+    assert_eq!(unpack_rom(&zip).err(), Some(RomError::DamagedArchive));
+  }
+}
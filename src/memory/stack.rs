@@ -1,6 +1,9 @@
+use serde::{Deserialize, Serialize};
 use crate::memory::memory::Memory;
 
+#[derive(Serialize, Deserialize)]
 pub struct Stack {
+  #[serde(with = "crate::util::serde_util::array")]
   bytes: [u8; Stack::SIZE],
 }
 
@@ -1,10 +1,15 @@
 use js_sys::Atomics::add;
+use std::ops::Range;
+use serde::{Deserialize, Serialize};
 use crate::memory::memory::Memory;
 
 
 
+#[derive(Serialize, Deserialize)]
 pub struct WRAM {
+  #[serde(with = "crate::util::serde_util::array")]
   bytes: [u8; (8 * WRAM::BANK_SIZE) as usize],
+  /// The bank mapped at 0xD000-0xDFFF, selected by the lower 3 bits of SVBK. Writing 0 selects bank 1.
   bank_index: u8
 }
 
@@ -13,6 +18,7 @@ impl WRAM {
   const END_ADDRESS: u16 = 0xDFFF;
   const BANK_SIZE: u16 = 0x1000;
   const BANK_0_END_ADDRESS: u16 = 0xCFFF;
+  const SWITCHABLE_BANK_START_ADDRESS: u16 = 0xD000;
 
   pub fn new() -> WRAM {
     WRAM {
@@ -20,6 +26,27 @@ impl WRAM {
       bank_index: 1,
     }
   }
+
+  pub fn bank_index(&self) -> u8 {
+    self.bank_index
+  }
+
+  fn switchable_bank_offset(&self, address: u16) -> usize {
+    (self.bank_index as usize * WRAM::BANK_SIZE as usize) + (address - WRAM::SWITCHABLE_BANK_START_ADDRESS) as usize
+  }
+
+  /// The ranges in `bytes` holding a block, split where it crosses from bank 0 into the switchable bank.
+  fn block_ranges(&self, address: u16, length: usize) -> (Range<usize>, Range<usize>) {
+    let start = address as usize;
+    let end = start + length;
+    if address < WRAM::START_ADDRESS || end > WRAM::END_ADDRESS as usize + 1 {
+      panic!("Can't access {} bytes at {:#06x} in WRAM as a block", length, address);
+    }
+    let bank_0_end = end.min(WRAM::SWITCHABLE_BANK_START_ADDRESS as usize).max(start);
+    let bank_0 = (start - WRAM::START_ADDRESS as usize)..(bank_0_end - WRAM::START_ADDRESS as usize);
+    let switchable_offset = self.switchable_bank_offset(address.max(WRAM::SWITCHABLE_BANK_START_ADDRESS));
+    (bank_0, switchable_offset..switchable_offset + end - bank_0_end)
+  }
 }
 
 impl Memory for WRAM {
@@ -28,10 +55,8 @@ impl Memory for WRAM {
       WRAM::START_ADDRESS..=WRAM::BANK_0_END_ADDRESS => {
         self.bytes[(address - WRAM::START_ADDRESS) as usize]
       }
-      WRAM::BANK_0_END_ADDRESS..=WRAM::END_ADDRESS => {
-        self.bytes[(self.bank_index as u16 * WRAM::BANK_SIZE + address - WRAM::BANK_0_END_ADDRESS) as usize]
-      },
-      0xFF70 => self.bank_index,
+      WRAM::SWITCHABLE_BANK_START_ADDRESS..=WRAM::END_ADDRESS => self.bytes[self.switchable_bank_offset(address)],
+      0xFF70 => 0xF8 | self.bank_index,
       _ => panic!("Can't read address {} from WRAM", address)
     }
   }
@@ -41,8 +66,9 @@ impl Memory for WRAM {
       WRAM::START_ADDRESS..=WRAM::BANK_0_END_ADDRESS => {
         self.bytes[(address - WRAM::START_ADDRESS) as usize] = value;
       }
-      WRAM::BANK_0_END_ADDRESS..=WRAM::END_ADDRESS => {
-        self.bytes[(self.bank_index as u16 * WRAM::BANK_SIZE + address - WRAM::BANK_0_END_ADDRESS) as usize] = value;
+      WRAM::SWITCHABLE_BANK_START_ADDRESS..=WRAM::END_ADDRESS => {
+        let offset = self.switchable_bank_offset(address);
+        self.bytes[offset] = value;
       },
       0xFF70 => {
         self.bank_index = value & 0x07;
@@ -53,5 +79,93 @@ impl Memory for WRAM {
       _ => panic!("Can't write to address {} in WRAM", address)
     }
   }
+
+  fn read_block(&self, address: u16, dst: &mut [u8]) {
+    let (bank_0, switchable) = self.block_ranges(address, dst.len());
+    let (bank_0_dst, switchable_dst) = dst.split_at_mut(bank_0.len());
+    bank_0_dst.copy_from_slice(&self.bytes[bank_0]);
+    switchable_dst.copy_from_slice(&self.bytes[switchable]);
+  }
+
+  fn write_block(&mut self, address: u16, src: &[u8]) {
+    let (bank_0, switchable) = self.block_ranges(address, src.len());
+    let (bank_0_src, switchable_src) = src.split_at(bank_0.len());
+    self.bytes[bank_0].copy_from_slice(bank_0_src);
+    self.bytes[switchable].copy_from_slice(switchable_src);
+  }
 }
 
+pub mod checks {
+  use crate::memory::memory::Memory;
+  use super::WRAM;
+
+  pub fn bank_zero_selects_bank_one() -> bool {
+    let mut wram = WRAM::new();
+    wram.write(0xFF70, 0x00);
+    wram.write(0xD000, 0x42);
+    wram.write(0xFF70, 0x01);
+    let bank_1 = wram.read(0xD000);
+    wram.write(0xFF70, 0x09);
+    bank_1 == 0x42 && wram.read(0xFF70) == 0xF9 && wram.read(0xD000) == 0x42
+  }
+
+  /// Only the lower 3 bits of SVBK are backed by a register, the other bits read as 1.
+  pub fn svbk_reads_back_with_upper_bits_set() -> bool {
+    let mut wram = WRAM::new();
+    wram.write(0xFF70, 0x02);
+    let bank_2 = wram.read(0xFF70);
+    wram.write(0xFF70, 0x00);
+    bank_2 == 0xFA && wram.read(0xFF70) == 0xF9
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use assert_hex::assert_eq_hex;
+  use super::*;
+
+  #[test]
+  fn bank_zero_selects_bank_one() {
+    assert!(checks::bank_zero_selects_bank_one());
+  }
+
+  #[test]
+  fn svbk_reads_back_with_upper_bits_set() {
+    assert!(checks::svbk_reads_back_with_upper_bits_set());
+  }
+
+  #[test]
+  fn switchable_banks_span_0xd000_to_0xdfff() {
+    let mut wram = WRAM::new();
+    wram.write(0xFF70, 0x07);
+    wram.write(0xDFFF, 0x12);
+    wram.write(0xD000, 0x34);
+    wram.write(0xFF70, 0x06);
+    assert_eq_hex!(wram.read(0xDFFF), 0x00);
+    assert_eq_hex!(wram.read(0xC000), 0x00);
+    wram.write(0xFF70, 0x07);
+    assert_eq_hex!(wram.read(0xDFFF), 0x12);
+    assert_eq_hex!(wram.read(0xD000), 0x34);
+  }
+
+  #[test]
+  fn blocks_cross_into_the_switchable_bank() {
+    let mut wram = WRAM::new();
+    wram.write(0xFF70, 0x03);
+    wram.write_block(0xCFFE, &[0x01, 0x02, 0x03, 0x04]);
+    assert_eq_hex!(wram.read(0xCFFF), 0x02);
+    assert_eq_hex!(wram.read(0xD000), 0x03);
+    let mut block = [0; 4];
+    wram.read_block(0xCFFE, &mut block);
+    assert_eq!(block, [0x01, 0x02, 0x03, 0x04]);
+    wram.write(0xFF70, 0x01);
+    wram.read_block(0xCFFE, &mut block);
+    assert_eq!(block, [0x01, 0x02, 0x00, 0x00]);
+  }
+
+  #[test]
+  #[should_panic(expected = "Can't access 2 bytes at 0xdfff in WRAM as a block")]
+  fn blocks_beyond_wram_panic() {
+    WRAM::new().read_block(0xDFFF, &mut [0; 2]);
+  }
+}
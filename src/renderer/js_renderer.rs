@@ -0,0 +1,25 @@
+use js_sys::{Array, Function};
+use wasm_bindgen::JsValue;
+use crate::renderer::renderer::{Color, Renderer};
+
+/// Draws through a JS function, called as draw_pixel(x, y, color, drawInBack) with the color as a 15-bit word like the
+/// CGB palettes store them.
+pub struct JSRenderer {
+  draw_pixel: Function,
+}
+
+impl JSRenderer {
+  pub fn new(draw_pixel: Function) -> JSRenderer {
+    JSRenderer {
+      draw_pixel
+    }
+  }
+}
+
+impl Renderer for JSRenderer {
+  fn draw_pixel(&self, x: u8, y: u8, color: Color, draw_in_back: bool) {
+    let arguments = Array::of4(&x.into(), &y.into(), &color.to_word().into(), &draw_in_back.into());
+    // A pixel the frontend failed to draw isn't worth stopping the emulator for
+    let _ = self.draw_pixel.apply(&JsValue::NULL, &arguments);
+  }
+}
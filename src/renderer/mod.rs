@@ -1 +1,2 @@
+pub mod js_renderer;
 pub mod renderer;
\ No newline at end of file
@@ -35,6 +35,20 @@ impl Color {
       blue: ((color_word & 0x7C00) >> 10) as u8
     }
   }
+
+  pub fn to_word(self) -> u16 {
+    (self.red as u16) | ((self.green as u16) << 5) | ((self.blue as u16) << 10)
+  }
+
+  /// The grey of one of the DMG's 4 shades, from 0 (white) to 3 (black).
+  pub fn from_dmg_shade(shade: u8) -> Color {
+    let level = [0x1F, 0x15, 0x0A, 0x00][shade as usize];
+    Color {
+      red: level,
+      green: level,
+      blue: level,
+    }
+  }
 }
 
 #[automock]
@@ -0,0 +1,181 @@
+use std::fmt::{Display, Formatter};
+use std::panic;
+use crate::controllers::timer;
+use crate::cpu::{cpu, interrupts};
+use crate::memory::{cram, mbc1, vram, wram};
+
+/// A small, self-contained check of a single hardware behaviour. The checks live in the `checks` module next to the
+/// code they cover, where the unit tests call them too.
+/// Checks that are known not to be implemented yet are marked as not expected to pass,
+/// so that the scorecard can tell regressions apart from missing features.
+pub struct Check {
+  pub name: &'static str,
+  pub expected_to_pass: bool,
+  run: fn() -> bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Outcome {
+  Passed,
+  Failed,
+  Panicked,
+}
+
+pub struct ScorecardEntry {
+  pub name: &'static str,
+  pub expected_to_pass: bool,
+  pub outcome: Outcome,
+}
+
+impl ScorecardEntry {
+  pub fn is_regression(&self) -> bool {
+    self.expected_to_pass && self.outcome != Outcome::Passed
+  }
+}
+
+pub struct Scorecard {
+  pub entries: Vec<ScorecardEntry>,
+}
+
+impl Scorecard {
+  pub fn run() -> Scorecard {
+    Scorecard {
+      entries: CHECKS.iter().map(|check| ScorecardEntry {
+        name: check.name,
+        expected_to_pass: check.expected_to_pass,
+        outcome: match panic::catch_unwind(check.run) {
+          Ok(true) => Outcome::Passed,
+          Ok(false) => Outcome::Failed,
+          Err(_) => Outcome::Panicked,
+        },
+      }).collect()
+    }
+  }
+
+  pub fn passed(&self) -> usize {
+    self.entries.iter().filter(|entry| entry.outcome == Outcome::Passed).count()
+  }
+
+  pub fn total(&self) -> usize {
+    self.entries.len()
+  }
+
+  pub fn regressions(&self) -> Vec<&ScorecardEntry> {
+    self.entries.iter().filter(|entry| entry.is_regression()).collect()
+  }
+}
+
+impl Display for Scorecard {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}/{} micro-behaviours implemented", self.passed(), self.total())?;
+    let regressions = self.regressions().len();
+    if regressions > 0 {
+      write!(f, ", {} regression(s)", regressions)?;
+    }
+    writeln!(f)?;
+    for entry in &self.entries {
+      let status = match entry.outcome {
+        Outcome::Passed => "PASS",
+        Outcome::Failed => "FAIL",
+        Outcome::Panicked => "PANIC",
+      };
+      let note = match (entry.expected_to_pass, entry.outcome) {
+        _ if entry.is_regression() => " (regression)",
+        (true, _) => "",
+        (false, Outcome::Passed) => " (unexpected pass)",
+        (false, _) => " (expected)",
+      };
+      writeln!(f, "  [{}] {}{}", status, entry.name, note)?;
+    }
+    Ok(())
+  }
+}
+
+const CHECKS: [Check; 21] = [
+  Check { name: "timer/div_increment", expected_to_pass: true, run: timer::checks::read_divider },
+  Check { name: "timer/tima_increment_rate", expected_to_pass: true, run: timer_tima_increment_rate },
+  Check { name: "timer/tima_overflow_interrupt", expected_to_pass: true, run: timer_tima_overflow_interrupt },
+  Check { name: "timer/tma_reload", expected_to_pass: true, run: timer_tma_reload },
+  Check { name: "timer/tima_reload_delay", expected_to_pass: false, run: timer::checks::tima_reload_delay },
+  Check { name: "timer/div_write_falling_edge", expected_to_pass: false, run: timer::checks::div_write_falling_edge },
+  Check { name: "interrupts/priority", expected_to_pass: true, run: interrupts::checks::get_requested_interrupt_returns_highest_priority },
+  Check { name: "interrupts/if_upper_bits", expected_to_pass: true, run: interrupts::checks::interrupt_request_upper_bits_read_as_one },
+  Check { name: "interrupts/ie_all_bits", expected_to_pass: true, run: interrupts::checks::interrupt_enable_upper_bits_do_not_cause_interrupts },
+  Check { name: "cpu/daa_addition", expected_to_pass: true, run: cpu::checks::decimal_adjust_after_addition },
+  Check { name: "cpu/daa_subtraction", expected_to_pass: true, run: cpu::checks::decimal_adjust_after_subtraction },
+  Check { name: "cpu/halt_bug", expected_to_pass: true, run: cpu::checks::halt_bug_reads_next_byte_twice },
+  Check { name: "cpu/ei_delay", expected_to_pass: true, run: cpu::checks::enable_interrupts_takes_effect_after_next_instruction },
+  Check { name: "mbc1/bank_zero_selects_bank_one", expected_to_pass: true, run: mbc1::checks::lower_bank_address_is_never_zero },
+  Check { name: "mbc1/upper_bank_aliasing", expected_to_pass: true, run: mbc1::checks::mode_1_remaps_lower_rom_on_large_carts },
+  Check { name: "mbc1/ram_banking", expected_to_pass: true, run: mbc1::checks::read_write_ram },
+  Check { name: "cram/auto_increment", expected_to_pass: true, run: cram_auto_increment },
+  Check { name: "vram/bank_switching", expected_to_pass: true, run: vram::checks::set_vram_bank },
+  Check { name: "vram/vbk_upper_bits", expected_to_pass: false, run: vram::checks::vbk_upper_bits_read_as_one },
+  Check { name: "wram/bank_zero_selects_bank_one", expected_to_pass: true, run: wram::checks::bank_zero_selects_bank_one },
+  Check { name: "wram/svbk_upper_bits", expected_to_pass: true, run: wram::checks::svbk_reads_back_with_upper_bits_set },
+];
+
+/// The TAC values with the number of M-cycles between TIMA increments.
+const TIMER_RATES: [(u8, usize); 4] = [(0x04, 256), (0x05, 4), (0x06, 16), (0x07, 64)];
+
+fn timer_tima_increment_rate() -> bool {
+  TIMER_RATES.iter().all(|&(tac_register, ticks)| timer::checks::read_tima(tac_register, ticks))
+}
+
+fn timer_tima_overflow_interrupt() -> bool {
+  TIMER_RATES.iter().all(|&(tac_register, ticks)| timer::checks::timer_overflow(tac_register, ticks * 0x100))
+}
+
+fn timer_tma_reload() -> bool {
+  TIMER_RATES.iter().all(|&(tac_register, ticks)| timer::checks::timer_modulo(tac_register, ticks * 0x100))
+}
+
+fn cram_auto_increment() -> bool {
+  cram::checks::writes_color_with_auto_increment(0xFF68, 0xFF69) && cram::checks::writes_color_with_auto_increment(0xFF6A, 0xFF6B)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn scorecard_covers_all_checks() {
+    let scorecard = Scorecard::run();
+    assert_eq!(scorecard.total(), CHECKS.len());
+    scorecard.entries.iter().zip(CHECKS.iter()).for_each(|(entry, check)| {
+      assert_eq!(entry.name, check.name);
+      assert_eq!(entry.expected_to_pass, check.expected_to_pass);
+    });
+  }
+
+  #[test]
+  fn no_check_panics() {
+    let scorecard = Scorecard::run();
+    let panicked: Vec<&str> = scorecard.entries.iter()
+      .filter(|entry| entry.outcome == Outcome::Panicked)
+      .map(|entry| entry.name)
+      .collect();
+    assert!(panicked.is_empty(), "Checks panicked: {:?}", panicked);
+  }
+
+  #[test]
+  fn outcomes_match_expectations() {
+    let scorecard = Scorecard::run();
+    scorecard.entries.iter().for_each(|entry| {
+      assert_eq!(entry.outcome == Outcome::Passed, entry.expected_to_pass, "Unexpected outcome for {}", entry.name);
+    });
+    assert!(scorecard.regressions().is_empty());
+  }
+
+  #[test]
+  fn report_summarizes_results() {
+    let scorecard = Scorecard::run();
+    let report = scorecard.to_string();
+    assert!(report.starts_with(&format!("{}/{} micro-behaviours implemented", scorecard.passed(), scorecard.total())));
+    assert!(report.contains("[PASS] cpu/daa_addition"));
+    assert!(report.contains("[PASS] cpu/halt_bug"));
+    assert!(report.contains("[PASS] cpu/ei_delay"));
+    assert!(report.contains("[FAIL] vram/vbk_upper_bits (expected)"));
+    assert_eq!(report.lines().count(), scorecard.total() + 1);
+  }
+}
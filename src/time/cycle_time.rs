@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+
+/// Converts M-cycles into the real time that passes during them, for cartridge clocks. An M-cycle takes 1/2^20
+/// seconds at single speed, so time that doesn't add up to a full nanosecond yet is carried over and the clock
+/// doesn't drift.
+#[derive(Serialize, Deserialize)]
+pub struct CycleTime {
+  /// In units of 1/2^21 nanoseconds.
+  pending: u64,
+}
+
+impl CycleTime {
+  const HALF_CYCLES_PER_SECOND_SHIFT: u32 = 21;
+
+  pub fn new() -> CycleTime {
+    CycleTime {
+      pending: 0,
+    }
+  }
+
+  /// Returns the nanoseconds that passed during an M-cycle.
+  pub fn elapse(&mut self, double_speed: bool) -> u64 {
+    self.elapse_half_cycles(if double_speed { 1 } else { 2 })
+  }
+
+  /// Returns the nanoseconds that passed during the given number of half M-cycles.
+  pub fn elapse_half_cycles(&mut self, half_cycles: u64) -> u64 {
+    let elapsed_time = self.pending + half_cycles * 1_000_000_000;
+    self.pending = elapsed_time & ((1 << CycleTime::HALF_CYCLES_PER_SECOND_SHIFT) - 1);
+    elapsed_time >> CycleTime::HALF_CYCLES_PER_SECOND_SHIFT
+  }
+
+  pub fn reset(&mut self) {
+    self.pending = 0;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn a_second_of_m_cycles_takes_exactly_a_second() {
+    let mut cycle_time = CycleTime::new();
+    let single_speed: u64 = (0..1_048_576).map(|_| cycle_time.elapse(false)).sum();
+    assert_eq!(single_speed, 1_000_000_000);
+    assert_eq!(cycle_time.pending, 0);
+    let double_speed: u64 = (0..2 * 1_048_576).map(|_| cycle_time.elapse(true)).sum();
+    assert_eq!(double_speed, 1_000_000_000);
+    assert_eq!(cycle_time.pending, 0);
+  }
+}
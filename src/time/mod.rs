@@ -1,2 +1,3 @@
 pub mod time;
 pub mod duration;
+pub mod cycle_time;
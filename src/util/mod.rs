@@ -1,2 +1,3 @@
 pub mod iterator;
-pub mod bit_util;
\ No newline at end of file
+pub mod bit_util;
+pub mod serde_util;
\ No newline at end of file
@@ -0,0 +1,66 @@
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Serde only implements Serialize for arrays of up to 32 elements. Use with `#[serde(with = "array")]`.
+pub mod array {
+  use super::*;
+
+  pub fn serialize<S: Serializer, T: Serialize, const N: usize>(array: &[T; N], serializer: S) -> Result<S::Ok, S::Error> {
+    array.as_slice().serialize(serializer)
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>, T: Deserialize<'de>, const N: usize>(deserializer: D) -> Result<[T; N], D::Error> {
+    let values = Vec::<T>::deserialize(deserializer)?;
+    let length = values.len();
+    values.try_into().map_err(|_| D::Error::invalid_length(length, &"an array of the stored length"))
+  }
+}
+
+/// Like `array`, for arrays of long arrays such as memory banks.
+pub mod nested_array {
+  use super::*;
+
+  pub fn serialize<S: Serializer, T: Serialize, const N: usize, const M: usize>(arrays: &[[T; N]; M], serializer: S) -> Result<S::Ok, S::Error> {
+    arrays.iter().map(|array| array.as_slice()).collect::<Vec<&[T]>>().serialize(serializer)
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>, T: Deserialize<'de>, const N: usize, const M: usize>(deserializer: D) -> Result<[[T; N]; M], D::Error> {
+    let values = Vec::<Vec<T>>::deserialize(deserializer)?;
+    let length = values.len();
+    let arrays = values.into_iter()
+      .map(|array| {
+        let array_length = array.len();
+        array.try_into().map_err(|_| D::Error::invalid_length(array_length, &"an array of the stored length"))
+      })
+      .collect::<Result<Vec<[T; N]>, D::Error>>()?;
+    arrays.try_into().map_err(|_| D::Error::invalid_length(length, &"an array of the stored length"))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[derive(Serialize, Deserialize, PartialEq, Debug)]
+  struct Bytes(#[serde(with = "array")] [u8; 100]);
+
+  #[derive(Serialize, Deserialize, PartialEq, Debug)]
+  struct Banks(#[serde(with = "nested_array")] [[u8; 100]; 2]);
+
+  #[test]
+  fn round_trips_long_arrays() {
+    let bytes = Bytes([0x42; 100]);
+    let serialized = bincode::serialize(&bytes).unwrap();
+    assert_eq!(bincode::deserialize::<Bytes>(&serialized).unwrap(), bytes);
+    let mut truncated = bincode::serialize(&[0x42u8; 99].as_slice()).unwrap();
+    truncated.extend_from_slice(&[0; 8]);
+    assert!(bincode::deserialize::<Bytes>(&truncated).is_err());
+  }
+
+  #[test]
+  fn round_trips_arrays_of_long_arrays() {
+    let banks = Banks([[0x12; 100], [0x34; 100]]);
+    let serialized = bincode::serialize(&banks).unwrap();
+    assert_eq!(bincode::deserialize::<Banks>(&serialized).unwrap(), banks);
+  }
+}